@@ -1,9 +1,17 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::config::{CompressionEffort, EmptyPolicy, MtimePrecision, SymlinkPolicy, SymlinkTargetMode};
+use crate::history::ExportFormat;
 
 #[derive(Parser, Debug)]
 #[command(name = "kibo")]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    /// Fail on keys in .kibo.toml that kibo does not know instead of ignoring them
+    #[arg(long = "strict-config", global = true)]
+    pub strict_config: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -11,78 +19,73 @@ pub struct Cli {
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Save a snapshot of tracked directories and files
-    Save {
-        /// Name for the snapshot
+    Save(Box<SaveArgs>),
+
+    /// Load a previously saved snapshot
+    Load {
+        /// Name of the snapshot to load
         #[arg(value_name = "SNAPSHOT_NAME")]
         name: String,
 
-        /// Automatically overwrite existing snapshot without prompting
-        #[arg(short = 'y', long = "yes")]
-        yes: bool,
-
         /// Show verbose output
         #[arg(short = 'v', long = "verbose")]
         verbose: bool,
 
-        /// Include database snapshot with optional database name (e.g., --include-db or --include-db=mydb)
-        /// If database name is not specified, uses the name from config [database] section
-        #[arg(long = "include-db")]
-        include_db: Option<String>,
+        /// Dry run - show what would be done without actually doing it
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
 
-        /// Force enable progress bar
-        #[arg(long = "progress")]
-        progress: bool,
+        /// Plan the load like a dry run but print only the resulting counts
+        #[arg(long = "report-only", conflicts_with_all = ["verbose", "dry_run", "include_db"])]
+        report_only: bool,
 
-        /// Force disable progress bar
-        #[arg(long = "no-progress", conflicts_with = "progress")]
-        no_progress: bool,
+        /// With --dry-run, also write the planned changes to FILE as JSON
+        #[arg(long = "report", value_name = "FILE", requires = "dry_run")]
+        report: Option<PathBuf>,
 
-        /// Override compression level (0 = no compression, 1-10 = zstd levels)
-        #[arg(long = "compression-level")]
-        compression_level: Option<u32>,
+        /// Include database load (snapshot must include a database dump)
+        #[arg(long = "include-db")]
+        include_db: bool,
 
-        /// Override directories from config (comma-separated, e.g., --directories="dir1,dir2")
-        #[arg(long = "directories", value_delimiter = ',', conflicts_with = "add_directories", require_equals = true)]
-        directories: Option<Vec<String>>,
+        /// Leave files matching this pattern untouched (repeatable, e.g., --exclude="*.cfg")
+        #[arg(long = "exclude", value_name = "GLOB")]
+        exclude: Vec<String>,
 
-        /// Add directories to config (comma-separated, e.g., --add-directories="dir1,dir2")
-        #[arg(long = "add-directories", value_delimiter = ',', require_equals = true)]
-        add_directories: Option<Vec<String>>,
+        /// Move stale files into a timestamped directory below DIR instead of deleting them
+        #[arg(long = "trash", value_name = "DIR")]
+        trash: Option<PathBuf>,
 
-        /// Override file patterns from config (comma-separated, e.g., --files="*.dat,*.o")
-        #[arg(long = "files", value_delimiter = ',', conflicts_with = "add_files", require_equals = true)]
-        files: Option<Vec<String>>,
+        /// Abort before changing anything if tracked paths contain files that are not in the snapshot
+        #[arg(long = "fail-on-extra", conflicts_with = "trash")]
+        fail_on_extra: bool,
 
-        /// Add file patterns to config (comma-separated, e.g., --add-files="*.dat,*.o")
-        #[arg(long = "add-files", value_delimiter = ',', require_equals = true)]
-        add_files: Option<Vec<String>>,
+        /// Rescan the tracked paths after loading and fail if anything still differs from the snapshot
+        #[arg(long = "report-drift", conflicts_with_all = ["dry_run", "report_only"])]
+        report_drift: bool,
 
-        /// Override ignore patterns from config (comma-separated, e.g., --ignore="build/*,*.tmp")
-        #[arg(long = "ignore", value_delimiter = ',', conflicts_with = "add_ignore", require_equals = true)]
-        ignore: Option<Vec<String>>,
+        /// Skip rehashing existing files whose size and mtime match the hash cache
+        #[arg(long = "checksum-cache")]
+        checksum_cache: bool,
 
-        /// Add ignore patterns to config (comma-separated, e.g., --add-ignore="build/*,*.tmp")
-        #[arg(long = "add-ignore", value_delimiter = ',', require_equals = true)]
-        add_ignore: Option<Vec<String>>,
-    },
+        /// Keep locally modified files by renaming them to <path><SUFFIX> before loading (e.g. .local)
+        #[arg(long = "rename-conflicts", value_name = "SUFFIX")]
+        rename_conflicts: Option<String>,
 
-    /// Load a previously saved snapshot
-    Load {
-        /// Name of the snapshot to load
-        #[arg(value_name = "SNAPSHOT_NAME")]
-        name: String,
+        /// Rehash every blob before changing any file, aborting if one is corrupt
+        #[arg(long = "verify-blobs-first")]
+        verify_blobs_first: bool,
 
-        /// Show verbose output
-        #[arg(short = 'v', long = "verbose")]
-        verbose: bool,
+        /// Hash each restored file while writing it, failing if it does not match the snapshot
+        #[arg(long = "verify")]
+        verify: bool,
 
-        /// Dry run - show what would be done without actually doing it
-        #[arg(short = 'n', long = "dry-run")]
-        dry_run: bool,
+        /// Skip files an interrupted `--resume` load already restored, journaling progress in .kibo/load_state.json
+        #[arg(long = "resume", conflicts_with_all = ["dry_run", "report_only"])]
+        resume: bool,
 
-        /// Include database load (snapshot must include a database dump)
-        #[arg(long = "include-db")]
-        include_db: bool,
+        /// Restore into a staging directory, then swap each tracked directory into place at once
+        #[arg(long = "atomic", conflicts_with_all = ["dry_run", "report_only", "resume", "checksum_cache"])]
+        atomic: bool,
 
         /// Force enable progress bar
         #[arg(long = "progress")]
@@ -93,24 +96,153 @@ pub enum Commands {
         no_progress: bool,
     },
 
+    /// Copy single files back from a snapshot without touching the rest of the workspace
+    Restore {
+        /// Name of the snapshot to restore from
+        #[arg(value_name = "SNAPSHOT_NAME")]
+        name: String,
+
+        /// Paths of the files to restore, relative to the workspace root as listed by `show`
+        #[arg(value_name = "PATH", required = true)]
+        paths: Vec<String>,
+
+        /// Show each restored file
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+    },
+
     /// List all saved snapshots
     #[command(alias = "ls")]
     List {
         /// Sort by snapshot name
-        #[arg(long = "name", conflicts_with_all = ["size", "created", "files"])]
+        #[arg(long = "name", conflicts_with_all = ["sort_by_size", "sort_by_created", "sort_by_files"])]
         sort_by_name: bool,
 
         /// Sort by size (largest first)
-        #[arg(long = "size", conflicts_with_all = ["name", "created", "files"])]
+        #[arg(long = "size", conflicts_with_all = ["sort_by_name", "sort_by_created", "sort_by_files"])]
         sort_by_size: bool,
 
         /// Sort by creation date (newest first)
-        #[arg(long = "created", conflicts_with_all = ["name", "size", "files"])]
+        #[arg(long = "created", conflicts_with_all = ["sort_by_name", "sort_by_size", "sort_by_files"])]
         sort_by_created: bool,
 
         /// Sort by number of files (most first)
-        #[arg(long = "files", conflicts_with_all = ["name", "size", "created"])]
+        #[arg(long = "files", conflicts_with_all = ["sort_by_name", "sort_by_size", "sort_by_created"])]
         sort_by_files: bool,
+
+        /// Output as JSON (pretty-printed unless --compact is given)
+        #[arg(long = "json")]
+        json: bool,
+
+        /// Emit single-line JSON, for piping into tools like jq
+        #[arg(long = "compact", requires = "json")]
+        compact: bool,
+
+        /// List snapshots removed with `kibo rm`, from the history log
+        #[arg(long = "deleted", conflicts_with_all = ["sort_by_name", "sort_by_size", "sort_by_created", "sort_by_files", "filters"])]
+        deleted: bool,
+
+        /// List database dumps in .kibo/db_snapshots that no snapshot references, which `kibo prune` removes
+        #[arg(long = "orphaned-dumps", conflicts_with_all = ["sort_by_name", "sort_by_size", "sort_by_created", "sort_by_files", "filters", "deleted"])]
+        orphaned_dumps: bool,
+
+        /// Only list snapshots whose metadata has this value (repeatable, all must match)
+        #[arg(long = "where", value_name = "KEY=VALUE")]
+        filters: Vec<String>,
+    },
+
+    /// Show the contents of a snapshot
+    Show {
+        /// Name of the snapshot to show
+        #[arg(value_name = "SNAPSHOT_NAME")]
+        name: String,
+
+        /// Render files as a directory tree with rolled-up directory sizes
+        #[arg(long = "tree")]
+        tree: bool,
+    },
+
+    /// Compare two snapshots
+    Diff {
+        /// Snapshot to compare from
+        #[arg(value_name = "FROM")]
+        from: String,
+
+        /// Snapshot to compare to
+        #[arg(value_name = "TO")]
+        to: String,
+
+        /// Show a unified diff of the contents of changed text files
+        #[arg(long = "content")]
+        content: bool,
+
+        /// Print only a one-line summary of the changes and the net size change
+        #[arg(long = "stat", conflicts_with = "content")]
+        stat: bool,
+
+        /// Print only the paths of changed files, one per line
+        #[arg(long = "name-only", conflicts_with_all = ["content", "stat", "name_status"])]
+        name_only: bool,
+
+        /// Print only the paths of changed files, each after A (added), D (removed) or M (modified)
+        #[arg(long = "name-status", conflicts_with_all = ["content", "stat"])]
+        name_status: bool,
+
+        /// Print the added, removed and modified files with their sizes, and the net size change, as JSON
+        #[arg(long = "json", conflicts_with_all = ["content", "stat", "name_only", "name_status"])]
+        json: bool,
+
+        /// Write the report to a file instead of stdout
+        #[arg(long = "output", value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Check that all blobs a snapshot references are present in the store
+    Verify {
+        /// Name of the snapshot to verify
+        #[arg(value_name = "SNAPSHOT_NAME", required_unless_present_any = ["manifest", "all", "cross_snapshot"])]
+        name: Option<String>,
+
+        /// Verify a manifest JSON file instead of a saved snapshot
+        #[arg(long = "manifest", value_name = "FILE", conflicts_with = "name")]
+        manifest: Option<PathBuf>,
+
+        /// Verify every saved snapshot
+        #[arg(long = "all", conflicts_with_all = ["name", "manifest"])]
+        all: bool,
+
+        /// Also rehash every blob and check it matches its recorded hash
+        #[arg(long = "deep")]
+        deep: bool,
+
+        /// Only recompute the manifest's content root and compare it with the recorded one,
+        /// without touching the store (combine with --deep to check blobs as well)
+        #[arg(long = "root")]
+        root: bool,
+
+        /// Print the missing and corrupt blob hashes of each snapshot as JSON
+        #[arg(long = "json", conflicts_with = "root")]
+        json: bool,
+
+        /// Check all manifests against each other for hashes recorded with different sizes,
+        /// and for entries whose hash cannot match the entry, without reading the store
+        #[arg(long = "cross-snapshot", conflicts_with_all = ["name", "manifest", "all", "deep", "root"])]
+        cross_snapshot: bool,
+
+        /// Compare the permissions and mtimes of the snapshot's files in the workspace with
+        /// the recorded ones, without reading any content
+        #[arg(long = "workspace-metadata", requires = "name", conflicts_with_all = ["manifest", "all", "deep", "root", "json", "cross_snapshot"])]
+        workspace_metadata: bool,
+
+        /// Apply the recorded permissions and mtimes to the entries that differ
+        #[arg(long = "fix", requires = "workspace_metadata")]
+        fix: bool,
+    },
+
+    /// Check a snapshot against copies of it outside the workspace
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommands,
     },
 
     /// Remove one or more snapshots by name
@@ -120,6 +252,10 @@ pub enum Commands {
         #[arg(value_name = "SNAPSHOT_NAME", required = true)]
         names: Vec<String>,
 
+        /// Also remove snapshots marked with `kibo protect`
+        #[arg(long = "force")]
+        force: bool,
+
         /// Force enable progress bar
         #[arg(long = "progress")]
         progress: bool,
@@ -129,12 +265,54 @@ pub enum Commands {
         no_progress: bool,
     },
 
+    /// Protect snapshots from `kibo rm` and `kibo prune --expire`
+    Protect {
+        /// Names of snapshots to protect
+        #[arg(value_name = "SNAPSHOT_NAME", required = true)]
+        names: Vec<String>,
+    },
+
+    /// Remove the protection set with `kibo protect`
+    Unprotect {
+        /// Names of snapshots to unprotect
+        #[arg(value_name = "SNAPSHOT_NAME", required = true)]
+        names: Vec<String>,
+    },
+
     /// Remove unreferenced blobs from the store
     Prune {
         /// Show verbose output
         #[arg(short = 'v', long = "verbose")]
         verbose: bool,
 
+        /// Also remove snapshots whose retention period has passed
+        #[arg(long = "expire")]
+        expire: bool,
+
+        /// Keep this snapshot even if it has expired (repeatable)
+        #[arg(long = "protect", value_name = "SNAPSHOT_NAME", requires = "expire")]
+        protect: Vec<String>,
+
+        /// Let --expire remove expired snapshots marked with `kibo protect`
+        #[arg(long = "force", requires = "expire")]
+        force: bool,
+
+        /// Rehash every blob that is kept and report damaged ones (slow; damaged blobs are never removed)
+        #[arg(long = "check", alias = "parallel-hash")]
+        check: bool,
+
+        /// Only collect garbage in this store group (see `save --into`)
+        #[arg(long = "group", value_name = "GROUP")]
+        group: Option<String>,
+
+        /// Number of threads scanning the store for unreferenced blobs (default: one per CPU)
+        #[arg(long = "concurrency", value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        concurrency: Option<usize>,
+
+        /// Keep unreferenced blobs until this long after a prune first found them unreferenced, e.g. 24h
+        #[arg(long = "keep-unreferenced-for", value_name = "DURATION")]
+        keep_unreferenced_for: Option<String>,
+
         /// Force enable progress bar
         #[arg(long = "progress")]
         progress: bool,
@@ -144,13 +322,27 @@ pub enum Commands {
         no_progress: bool,
     },
 
+    /// Maintain the blob store
+    Store {
+        #[command(subcommand)]
+        command: StoreCommands,
+    },
+
     /// Initialize a new .kibo.toml configuration file
-    Init,
+    Init {
+        /// Seed the ignore list from the .gitignore in the current directory
+        #[arg(long = "from-gitignore")]
+        from_gitignore: bool,
+    },
 
-    /// Open the .kibo.toml configuration file in vim
-    Config,
+    /// Open the .kibo.toml configuration file in $VISUAL or $EDITOR (vim if neither is set)
+    Config {
+        #[command(subcommand)]
+        command: Option<ConfigCommands>,
+    },
 
     /// View command history log
+    #[command(args_conflicts_with_subcommands = true)]
     History {
         /// Show only the last N entries
         #[arg(long = "last")]
@@ -160,14 +352,598 @@ pub enum Commands {
         #[arg(long = "snapshot")]
         snapshot: Option<String>,
 
+        /// Summarize the entries instead of listing them
+        #[arg(long = "stats")]
+        stats: bool,
+
         /// Output as JSON
         #[arg(long = "json")]
         json: bool,
+
+        #[command(subcommand)]
+        command: Option<HistoryCommands>,
+    },
+
+    /// Check the .kibo directory for inconsistencies
+    Doctor {
+        /// Repair problems that can be fixed automatically
+        #[arg(long = "fix")]
+        fix: bool,
+    },
+
+    /// Re-run save commands recorded in the history log
+    Replay {
+        /// Replay the last N logged saves (defaults to the most recent one)
+        #[arg(long = "last")]
+        last: Option<usize>,
+
+        /// Only replay saves logged at or after this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long = "since")]
+        since: Option<String>,
+
+        /// Only replay saves logged at or before this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long = "until")]
+        until: Option<String>,
+
+        /// Run without asking for confirmation
+        #[arg(short = 'y', long = "yes")]
+        yes: bool,
+    },
+}
+
+/// Arguments for the save command
+#[derive(Args, Debug, Clone)]
+pub struct SaveArgs {
+    /// Name for the snapshot
+    #[arg(value_name = "SNAPSHOT_NAME")]
+    pub name: String,
+
+    /// Automatically overwrite existing snapshot without prompting
+    #[arg(short = 'y', long = "yes")]
+    pub yes: bool,
+
+    /// Fail instead of prompting when a snapshot with this name already exists
+    #[arg(long = "fail-if-exists", conflicts_with = "yes")]
+    pub fail_if_exists: bool,
+
+    /// Show verbose output
+    #[arg(short = 'v', long = "verbose")]
+    pub verbose: bool,
+
+    /// Show what would be saved without hashing or writing anything
+    #[arg(short = 'n', long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Print the dry-run plan as JSON
+    #[arg(long = "json", requires = "dry_run")]
+    pub json: bool,
+
+    /// Include database snapshot with optional database name (e.g., --include-db or --include-db=mydb)
+    /// If database name is not specified, uses the name from config [database] section
+    #[arg(long = "include-db")]
+    pub include_db: Option<String>,
+
+    /// Force enable progress bar
+    #[arg(long = "progress")]
+    pub progress: bool,
+
+    /// Force disable progress bar
+    #[arg(long = "no-progress", conflicts_with = "progress")]
+    pub no_progress: bool,
+
+    /// Write newline-delimited JSON progress events to a file descriptor number or a file path
+    #[arg(long = "progress-json", value_name = "FD|PATH")]
+    pub progress_json: Option<String>,
+
+    /// Print how long hashing, storing and compressing took
+    #[arg(long = "timing")]
+    pub timing: bool,
+
+    /// Also write a copy of the saved manifest to PATH
+    #[arg(long = "output-manifest", value_name = "PATH", conflicts_with = "dry_run")]
+    pub output_manifest: Option<PathBuf>,
+
+    /// Override compression level (0 = no compression, 1-10 = zstd levels)
+    #[arg(long = "compression-level", conflicts_with = "compression_effort")]
+    pub compression_level: Option<u32>,
+
+    /// Compress by preset instead of level: fast, balanced or max (same as `compression_effort`)
+    #[arg(long = "compression-effort", value_name = "EFFORT")]
+    pub compression_effort: Option<CompressionEffort>,
+
+    /// Train a zstd dictionary from the snapshot's small files and compress new blobs with it
+    #[arg(long = "store-compression-dict")]
+    pub store_compression_dict: bool,
+
+    /// Compress reused blobs that an earlier save stored uncompressed (same as `recompress_reused`)
+    #[arg(long = "recompress-reused")]
+    pub recompress_reused: bool,
+
+    /// Report files that duplicate another file in this snapshot, apart from blobs reused from earlier snapshots (same as `dedup_within_run`)
+    #[arg(long = "dedup-within-run")]
+    pub dedup_within_run: bool,
+
+    /// Fsync every stored blob before the manifest is written (same as `durable_writes`)
+    #[arg(long = "fsync")]
+    pub fsync: bool,
+
+    /// Record directories that contain no files (default unless disabled in config)
+    #[arg(long = "include-empty-dirs", conflicts_with = "no_empty_dirs")]
+    pub include_empty_dirs: bool,
+
+    /// Only record directories that contain files
+    #[arg(long = "no-empty-dirs")]
+    pub no_empty_dirs: bool,
+
+    /// Leave out directories with default permissions that hold files, to shrink the manifest (same as `sparse_directories`)
+    #[arg(long = "sparse-directories")]
+    pub sparse_directories: bool,
+
+    /// Do not descend into mount points below the workspace root (like tar --one-file-system)
+    #[arg(long = "one-file-system")]
+    pub one_file_system: bool,
+
+    /// Skip version control metadata directories (.git, .hg, .svn, ...)
+    #[arg(long = "exclude-vcs")]
+    pub exclude_vcs: bool,
+
+    /// Skip files and directories starting with `.` inside tracked directories and `files` pattern matches
+    #[arg(long = "exclude-hidden")]
+    pub exclude_hidden: bool,
+
+    /// Also ignore files matched by the workspace root's .gitignore
+    #[arg(long = "gitignore")]
+    pub gitignore: bool,
+
+    /// Record the git commit checked out in the workspace, and whether tracked files were modified
+    #[arg(long = "include-git-sha")]
+    pub include_git_sha: bool,
+
+    /// Record the value of an environment variable in the snapshot, e.g. --capture-env=CC,CFLAGS
+    /// (repeatable, adds to `capture_env`)
+    #[arg(long = "capture-env", value_name = "VAR", value_delimiter = ',')]
+    pub capture_env: Vec<String>,
+
+    /// Only capture files modified within this long before the save (e.g. 12h, 7d)
+    #[arg(long = "include-newer-than", visible_alias = "exclude-older-than", value_name = "DURATION")]
+    pub include_newer_than: Option<String>,
+
+    /// Leave zero-byte files out of the snapshot (same as `exclude_empty_files`)
+    #[arg(long = "exclude-empty-files")]
+    pub exclude_empty_files: bool,
+
+    /// Accept absolute paths outside the workspace in directories (same as `allow_outside_root`)
+    #[arg(long = "allow-outside-root")]
+    pub allow_outside_root: bool,
+
+    /// Keep this snapshot's blobs in a separate named store group (same as `store_group`)
+    #[arg(long = "into", value_name = "GROUP")]
+    pub into: Option<String>,
+
+    /// Override how symlinks are captured: store the link, follow it to the file's contents, or skip it
+    #[arg(long = "symlink-policy", value_name = "POLICY")]
+    pub symlink_policy: Option<SymlinkPolicy>,
+
+    /// Record stored symlink targets as they are, relative to the link, or absolute (same as `symlink_target_mode`)
+    #[arg(long = "symlink-target-mode", value_name = "MODE")]
+    pub symlink_target_mode: Option<SymlinkTargetMode>,
+
+    /// What to do when no files are captured: fail, warn (default) or ok (same as `on_empty`)
+    #[arg(long = "on-empty", value_name = "POLICY")]
+    pub on_empty: Option<EmptyPolicy>,
+
+    /// Record mtimes to the second or in full, for cross-filesystem copies (same as `mtime_precision`)
+    #[arg(long = "store-mtime-precision", value_name = "PRECISION")]
+    pub store_mtime_precision: Option<MtimePrecision>,
+
+    /// Normalize metadata that varies between saves (creation time, mtimes, toolchain info),
+    /// so that saving the same content twice produces identical manifests
+    #[arg(long = "deterministic", conflicts_with = "retention")]
+    pub deterministic: bool,
+
+    /// Creation time recorded by --deterministic, as RFC 3339 or YYYY-MM-DD
+    /// (default: $SOURCE_DATE_EPOCH, or else the Unix epoch)
+    #[arg(long = "created-at", value_name = "TIME", requires = "deterministic")]
+    pub created_at: Option<String>,
+
+    /// Record a key=value pair in the snapshot's metadata, e.g. --metadata git_sha=1a2b3c (repeatable)
+    #[arg(long = "metadata", value_name = "KEY=VALUE")]
+    pub metadata: Vec<String>,
+
+    /// Expire the snapshot after this long (e.g. 12h, 30d, 2w); see `kibo prune --expire`
+    #[arg(long = "retention", value_name = "DURATION")]
+    pub retention: Option<String>,
+
+    /// Garbage-collect blobs no longer referenced by any snapshot after saving
+    #[arg(long = "prune-after")]
+    pub prune_after: bool,
+
+    /// Warn if the new blobs may not fit on the store's filesystem (same as `check_free_space`)
+    #[arg(long = "check-free-space")]
+    pub check_free_space: bool,
+
+    /// Abort the save if the new blobs may not fit, instead of warning (same as `strict_space`)
+    #[arg(long = "strict-space")]
+    pub strict_space: bool,
+
+    /// Record file hashes without storing any blobs, for a store filled elsewhere (same as `hash_only`)
+    #[arg(long = "hash-only")]
+    pub hash_only: bool,
+
+    /// Have `load` hard-link files with identical content instead of copying each (same as `link_identical`)
+    #[arg(long = "link-identical")]
+    pub link_identical: bool,
+
+    /// Abort the save if it takes longer than this many seconds (same as `max_duration_secs`)
+    #[arg(long = "max-duration", value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_duration: Option<u64>,
+
+    /// Override the store blob count warning threshold from config
+    #[arg(long = "max-blobs-warning", value_name = "COUNT")]
+    pub max_blobs_warning: Option<usize>,
+
+    /// Override directories from config (comma-separated, e.g., --directories="dir1,dir2")
+    #[arg(long = "directories", value_delimiter = ',', conflicts_with = "add_directories", require_equals = true)]
+    pub directories: Option<Vec<String>>,
+
+    /// Add directories to config (comma-separated, e.g., --add-directories="dir1,dir2")
+    #[arg(long = "add-directories", value_delimiter = ',', require_equals = true)]
+    pub add_directories: Option<Vec<String>>,
+
+    /// Override file patterns from config (comma-separated, e.g., --files="*.dat,*.o")
+    #[arg(long = "files", value_delimiter = ',', conflicts_with = "add_files", require_equals = true)]
+    pub files: Option<Vec<String>>,
+
+    /// Add file patterns to config (comma-separated, e.g., --add-files="*.dat,*.o")
+    #[arg(long = "add-files", value_delimiter = ',', require_equals = true)]
+    pub add_files: Option<Vec<String>>,
+
+    /// Override ignore patterns from config (comma-separated, e.g., --ignore="build/*,*.tmp")
+    #[arg(long = "ignore", value_delimiter = ',', conflicts_with = "add_ignore", require_equals = true)]
+    pub ignore: Option<Vec<String>>,
+
+    /// Add ignore patterns to config (comma-separated, e.g., --add-ignore="build/*,*.tmp")
+    #[arg(long = "add-ignore", value_delimiter = ',', require_equals = true)]
+    pub add_ignore: Option<Vec<String>>,
+
+    /// Add patterns whose files are hashed from path, size and mtime without reading content
+    /// (comma-separated, e.g., --assume-unchanged="*.iso,vendor/sdk/*")
+    #[arg(long = "assume-unchanged", value_delimiter = ',', require_equals = true)]
+    pub assume_unchanged: Option<Vec<String>>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommands {
+    /// Rewrite .kibo.toml with every key spelled out and commented, keeping a .bak copy
+    Migrate {
+        /// Print the migrated configuration instead of writing it
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// Check .kibo.toml, failing on invalid values and on keys kibo does not know
+    Validate,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SnapshotCommands {
+    /// Check that a directory, such as a deployed copy, matches a snapshot exactly
+    CompareToDir {
+        /// Name of the snapshot to compare with
+        #[arg(value_name = "SNAPSHOT_NAME")]
+        name: String,
+
+        /// Directory to compare, laid out like the workspace root
+        #[arg(value_name = "DIR")]
+        dir: PathBuf,
+
+        /// Do not report files whose permissions differ from the snapshot
+        #[arg(long = "ignore-permissions")]
+        ignore_permissions: bool,
+
+        /// Force enable progress bars
+        #[arg(long = "progress", conflicts_with = "no_progress")]
+        progress: bool,
+
+        /// Force disable progress bars
+        #[arg(long = "no-progress")]
+        no_progress: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryCommands {
+    /// Write the history log in a structured format for audit pipelines
+    Export {
+        /// Output format: jsonl (one JSON object per line) or syslog (RFC 5424 messages)
+        #[arg(long = "format", value_name = "FORMAT", default_value = "jsonl")]
+        format: ExportFormat,
+
+        /// Write to this file instead of standard output
+        #[arg(long = "output", value_name = "PATH")]
+        output: Option<PathBuf>,
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum StoreCommands {
+    /// Remove empty prefix directories left behind in the store
+    Tidy,
+
+    /// Check that blobs are still read-only, so their content cannot be changed in place
+    VerifyPermissions {
+        /// Make writable blobs read-only again
+        #[arg(long = "fix")]
+        fix: bool,
+    },
+
+    /// Compress blobs that saves at compression level 0 stored uncompressed
+    Recompress {
+        /// Snapshots whose blobs to compress
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        names: Vec<String>,
+
+        /// Compress the blobs of every snapshot
+        #[arg(long = "all")]
+        all: bool,
+
+        /// Compression level to use instead of `compression_level` from config (1-10)
+        #[arg(long = "level", value_name = "LEVEL", value_parser = clap::value_parser!(u32).range(1..=10))]
+        level: Option<u32>,
+    },
+
+    /// Hash the files under a directory and add their content to the store without saving a snapshot
+    ImportBlobs {
+        /// Directory of files to import
+        dir: PathBuf,
+
+        /// Import into this store group instead of the default store (see `save --into`)
+        #[arg(long = "into", value_name = "GROUP")]
+        into: Option<String>,
+    },
+
+    /// Recover a lost or corrupted snapshot manifest from a list of paths and hashes whose blobs are still stored
+    Fsck {
+        /// Name of the snapshot whose manifest to rebuild
+        #[arg(long = "rebuild-manifest", value_name = "NAME")]
+        rebuild_manifest: String,
+
+        /// File list with one `<hash>  <path>` line per file (as `b3sum` prints), or a JSON object of path to hash
+        #[arg(long = "file-list", value_name = "PATH")]
+        file_list: PathBuf,
+
+        /// Look the blobs up in this store group instead of the default store
+        #[arg(long = "group", value_name = "GROUP")]
+        group: Option<String>,
+
+        /// Replace the manifest if one with this name still exists
+        #[arg(long = "force")]
+        force: bool,
+    },
+
+    /// Check the store against `store_max_size_gb`, optionally removing the oldest snapshots until it fits
+    CheckSpace {
+        /// Size limit in GB, instead of `store_max_size_gb` from config
+        #[arg(long = "max-size-gb", value_name = "GB")]
+        max_size_gb: Option<f64>,
+
+        /// Remove expired, then the oldest snapshots until the store fits (same as `auto_prune`)
+        #[arg(long = "auto-prune")]
+        auto_prune: bool,
+    },
+
+    /// Show how much space the store takes up on disk
+    Size {
+        /// Break the size down by snapshot into exclusive and shared blobs
+        #[arg(long = "per-snapshot")]
+        per_snapshot: bool,
+
+        /// Count blobs and their bytes by size range
+        #[arg(long = "histogram")]
+        histogram: bool,
+    },
+
+    /// Report how much space deduplication saves across snapshots
+    DedupReport {
+        /// Number of most-shared blobs to list
+        #[arg(long = "top", value_name = "N", default_value_t = 10)]
+        top: usize,
+    },
+}
+
+impl SaveArgs {
+    /// Build the flag list recorded in the history log for this save
+    pub fn history_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        if self.verbose { flags.push("--verbose".to_string()); }
+        if let Some(ref db_val) = self.include_db {
+            if db_val.is_empty() {
+                flags.push("--include-db".to_string());
+            } else {
+                flags.push(format!("--include-db={}", db_val));
+            }
+        }
+        if self.yes { flags.push("--yes".to_string()); }
+        if self.fail_if_exists { flags.push("--fail-if-exists".to_string()); }
+        if self.prune_after { flags.push("--prune-after".to_string()); }
+        if let Some(ref retention) = self.retention {
+            flags.push(format!("--retention={}", retention));
+        }
+        for pair in &self.metadata {
+            flags.push(format!("--metadata={}", pair));
+        }
+        if self.deterministic { flags.push("--deterministic".to_string()); }
+        if let Some(ref created_at) = self.created_at {
+            flags.push(format!("--created-at={}", created_at));
+        }
+        if let Some(level) = self.compression_level {
+            flags.push(format!("--compression-level={}", level));
+        }
+        if let Some(effort) = self.compression_effort {
+            flags.push(format!("--compression-effort={}", effort.as_str()));
+        }
+        if self.store_compression_dict { flags.push("--store-compression-dict".to_string()); }
+        if self.recompress_reused { flags.push("--recompress-reused".to_string()); }
+        if self.dedup_within_run { flags.push("--dedup-within-run".to_string()); }
+        if self.fsync { flags.push("--fsync".to_string()); }
+        if self.check_free_space { flags.push("--check-free-space".to_string()); }
+        if self.strict_space { flags.push("--strict-space".to_string()); }
+        if self.hash_only { flags.push("--hash-only".to_string()); }
+        if self.link_identical { flags.push("--link-identical".to_string()); }
+        if let Some(seconds) = self.max_duration {
+            flags.push(format!("--max-duration={}", seconds));
+        }
+        if let Some(count) = self.max_blobs_warning {
+            flags.push(format!("--max-blobs-warning={}", count));
+        }
+        if self.include_empty_dirs { flags.push("--include-empty-dirs".to_string()); }
+        if self.no_empty_dirs { flags.push("--no-empty-dirs".to_string()); }
+        if self.sparse_directories { flags.push("--sparse-directories".to_string()); }
+        if self.one_file_system { flags.push("--one-file-system".to_string()); }
+        if self.exclude_vcs { flags.push("--exclude-vcs".to_string()); }
+        if self.exclude_hidden { flags.push("--exclude-hidden".to_string()); }
+        if self.gitignore { flags.push("--gitignore".to_string()); }
+        if self.include_git_sha { flags.push("--include-git-sha".to_string()); }
+        for name in &self.capture_env {
+            flags.push(format!("--capture-env={}", name));
+        }
+        if let Some(ref age) = self.include_newer_than {
+            flags.push(format!("--include-newer-than={}", age));
+        }
+        if self.exclude_empty_files { flags.push("--exclude-empty-files".to_string()); }
+        if self.allow_outside_root { flags.push("--allow-outside-root".to_string()); }
+        if let Some(ref group) = self.into {
+            flags.push(format!("--into={}", group));
+        }
+        if let Some(policy) = self.symlink_policy {
+            flags.push(format!("--symlink-policy={}", policy.as_str()));
+        }
+        if let Some(mode) = self.symlink_target_mode {
+            flags.push(format!("--symlink-target-mode={}", mode.as_str()));
+        }
+        if let Some(policy) = self.on_empty {
+            flags.push(format!("--on-empty={}", policy.as_str()));
+        }
+        if let Some(precision) = self.store_mtime_precision {
+            flags.push(format!("--store-mtime-precision={}", precision.as_str()));
+        }
+        if let Some(ref dirs) = self.directories {
+            flags.push(format!("--directories={}", dirs.join(",")));
+        }
+        if let Some(ref add_dirs) = self.add_directories {
+            flags.push(format!("--add-directories={}", add_dirs.join(",")));
+        }
+        if let Some(ref file_patterns) = self.files {
+            flags.push(format!("--files={}", file_patterns.join(",")));
+        }
+        if let Some(ref add_file_patterns) = self.add_files {
+            flags.push(format!("--add-files={}", add_file_patterns.join(",")));
+        }
+        if let Some(ref ignore_patterns) = self.ignore {
+            flags.push(format!("--ignore={}", ignore_patterns.join(",")));
+        }
+        if let Some(ref add_ignore_patterns) = self.add_ignore {
+            flags.push(format!("--add-ignore={}", add_ignore_patterns.join(",")));
+        }
+        if let Some(ref assume_patterns) = self.assume_unchanged {
+            flags.push(format!("--assume-unchanged={}", assume_patterns.join(",")));
+        }
+        flags
+    }
+
+    /// Reconstruct save arguments from a snapshot name and flags recorded in the history log.
+    /// Flags that the save command does not recognize are dropped and returned separately.
+    pub fn from_history(name: &str, flags: &[String]) -> anyhow::Result<(Self, Vec<String>)> {
+        let command = ReplaySave::command();
+        let known: Vec<&str> = command
+            .get_arguments()
+            .filter_map(|arg| arg.get_long())
+            .collect();
+
+        let mut args = vec!["save".to_string(), name.to_string()];
+        let mut unknown = Vec::new();
+
+        for flag in flags {
+            let flag_name = flag
+                .strip_prefix("--")
+                .map(|f| f.split('=').next().unwrap_or(f));
+
+            match flag_name {
+                // A bare --include-db is logged when the config database name was used
+                Some("include-db") if !flag.contains('=') => args.push("--include-db=".to_string()),
+                Some(n) if known.contains(&n) => args.push(flag.clone()),
+                _ => unknown.push(flag.clone()),
+            }
+        }
+
+        let parsed = ReplaySave::try_parse_from(&args)
+            .map_err(|e| anyhow::anyhow!("Failed to parse logged flags for '{}': {}", name, e))?;
+
+        Ok((parsed.args, unknown))
+    }
+}
+
+/// Parser used to map logged save flags back onto `SaveArgs`
+#[derive(Parser, Debug)]
+struct ReplaySave {
+    #[command(flatten)]
+    args: SaveArgs,
+}
+
 impl Cli {
     pub fn parse_args() -> Self {
         Cli::parse()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_list_compact_requires_json() {
+        assert!(Cli::try_parse_from(["kibo", "list", "--compact"]).is_err());
+        assert!(Cli::try_parse_from(["kibo", "list", "--json", "--compact"]).is_ok());
+    }
+
+    #[test]
+    fn test_list_sort_flags_conflict() {
+        assert!(Cli::try_parse_from(["kibo", "list", "--name", "--size"]).is_err());
+    }
+
+    /// Save flags and a check that must hold both for the parsed arguments and for the
+    /// arguments replayed from their history flags
+    type HistoryCase = (&'static [&'static str], fn(&SaveArgs) -> bool);
+
+    #[test]
+    fn test_save_flags_round_trip_through_history() {
+        let cases: &[HistoryCase] = &[
+            (&["--symlink-policy=follow"], |args| args.symlink_policy == Some(SymlinkPolicy::Follow)),
+            (&["--fail-if-exists"], |args| args.fail_if_exists),
+            (&["--on-empty=fail"], |args| args.on_empty == Some(EmptyPolicy::Fail)),
+            (&["--store-mtime-precision=second"], |args| args.store_mtime_precision == Some(MtimePrecision::Second)),
+            (&["--capture-env=CC,CFLAGS", "--capture-env", "TARGET"], |args| args.capture_env == ["CC", "CFLAGS", "TARGET"]),
+            (&["--metadata", "build=42", "--metadata=url=a=b"], |args| args.metadata == ["build=42", "url=a=b"]),
+        ];
+        for (flags, check) in cases {
+            let cli = Cli::try_parse_from(["kibo", "save", "snap"].iter().chain(flags.iter())).unwrap();
+            let Commands::Save(args) = cli.command else { panic!("expected save") };
+            assert!(check(&args), "parsing {flags:?}");
+
+            let (replayed, unknown) = SaveArgs::from_history("snap", &args.history_flags()).unwrap();
+            assert!(unknown.is_empty(), "replaying {flags:?} left {unknown:?}");
+            assert!(check(&replayed), "replaying {flags:?}");
+        }
+    }
+
+    #[test]
+    fn test_save_rejects_invalid_flags() {
+        assert!(Cli::try_parse_from(["kibo", "save", "snap", "--fail-if-exists", "--yes"]).is_err());
+        assert!(Cli::try_parse_from(["kibo", "save", "snap", "--on-empty=never"]).is_err());
+    }
+}
@@ -7,12 +7,68 @@ pub const KIBO_DIR: &str = ".kibo";
 pub const STORE_DIR: &str = "store";
 pub const MANIFESTS_DIR: &str = "manifests";
 pub const HASH_CACHE_FILE: &str = "hash_cache.json";
+pub const LOAD_STATE_FILE: &str = "load_state.json";
+pub const UNREFERENCED_FILE: &str = "unreferenced.json";
+pub const KEPT_CONFLICTS_FILE: &str = "kept_conflicts.json";
+pub const LOAD_STAGING_DIR: &str = "load_staging";
 pub const HISTORY_LOG_FILE: &str = "history.log";
+pub const GITIGNORE_FILE: &str = ".gitignore";
 
-/// Database configuration for MySQL snapshots
+/// Version control metadata directories skipped when `exclude_vcs` is set
+pub const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn", ".bzr", "_darcs", "CVS"];
+
+/// Top-level config keys with the comment `config migrate` writes above each. Keys are
+/// written in the order of the `Config` fields, not the order of this list.
+const CONFIG_KEYS: &[(&str, &str)] = &[
+    ("directories", "Directories to snapshot"),
+    ("files", "File patterns to snapshot"),
+    ("ignore", "Paths and patterns to skip inside tracked directories"),
+    ("assume_unchanged", "Files hashed by path, size and mtime instead of content"),
+    ("max_snapshot_size_gb", "Warn when a snapshot is larger than this many GB"),
+    ("max_duration_secs", "Abort a save that takes longer than this many seconds"),
+    ("max_blob_count", "Warn when the store holds more blobs than this"),
+    ("store_max_size_gb", "Size limit for the whole store in GB, checked after each save"),
+    ("auto_prune", "Remove the oldest snapshots when the store exceeds store_max_size_gb"),
+    ("keep_unreferenced_for", "Keep unreferenced blobs this long after prune or rm first finds them (e.g. 24h)"),
+    ("compression_level", "Compression level (0 = no compression, 1-10 = zstd levels)"),
+    ("compression_effort", "Compression preset used instead of compression_level: fast, balanced or max"),
+    ("compression_dict", "Train a zstd dictionary from small files on save"),
+    ("recompress_reused", "Compress reused blobs that an earlier save stored uncompressed"),
+    ("dedup_within_run", "Report files that duplicate another file in the same snapshot"),
+    ("durable_writes", "Fsync each stored blob and its directory before the manifest is written"),
+    ("check_free_space", "Check that new blobs fit on the store's filesystem before storing"),
+    ("strict_space", "Abort the save instead of warning when the free space check fails"),
+    ("hash_only", "Record hashes without storing any blobs"),
+    ("link_identical", "Hard-link files with identical content on load"),
+    ("progress", "Show progress bars (default: auto-detect TTY)"),
+    ("include_empty_dirs", "Record directories that contain no files"),
+    ("sparse_directories", "Leave out directories with default permissions that hold files"),
+    ("one_file_system", "Do not descend into mount points below the workspace root"),
+    ("symlink_policy", "How symlinks are captured: store, follow or skip"),
+    ("symlink_target_mode", "How stored symlink targets are recorded: preserve, relative or absolute"),
+    ("exclude_vcs", "Skip version control metadata directories such as .git"),
+    ("exclude_hidden", "Skip files and directories starting with . inside tracked directories and files pattern matches"),
+    ("gitignore", "Add the patterns from the workspace root's .gitignore to the ignore list"),
+    ("include_git_sha", "Record the checked out git commit in each snapshot"),
+    ("capture_env", "Environment variables recorded in each snapshot"),
+    ("on_empty", "What to do when a save captures no files: fail, warn or ok"),
+    ("mtime_precision", "Mtime precision: full or second"),
+    ("include_newer_than", "Only capture files modified within this long before the save (e.g. 7d)"),
+    ("exclude_empty_files", "Leave zero-byte files out of snapshots"),
+    ("allow_outside_root", "Accept absolute paths in directories"),
+    ("store_group", "Store group new snapshots keep their blobs in"),
+    ("pack_small_blobs", "Append small blobs to shared pack files"),
+    ("pack_threshold_kb", "Blobs smaller than this many KB are packed"),
+    ("database", "Database to dump with save --include-db"),
+];
+
+/// Keys of the `[database]` section
+const DATABASE_KEYS: &[&str] = &["db_type", "user", "password", "host", "port", "name", "tables", "single_transaction"];
+
+/// Database configuration for MySQL and PostgreSQL snapshots
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseConfig {
-    /// Database type (only "mysql" supported)
+    /// Database type ("mysql" or "postgres")
     #[serde(default = "default_db_type")]
     pub db_type: String,
 
@@ -52,6 +108,8 @@ fn default_db_port() -> u16 { 3306 }
 fn default_db_name() -> String { "mydb".to_string() }
 fn default_db_tables() -> Vec<String> { vec!["*".to_string()] }
 fn default_db_single_transaction() -> bool { true }
+fn default_pack_threshold_kb() -> u64 { 16 }
+fn default_include_empty_dirs() -> bool { true }
 
 impl Default for DatabaseConfig {
     fn default() -> Self {
@@ -68,6 +126,159 @@ impl Default for DatabaseConfig {
     }
 }
 
+/// How symlinks inside tracked paths are captured by `save`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Store the link itself, recreated as a symlink on load
+    #[default]
+    Store,
+    /// Store the pointed-to file's contents as a regular file
+    Follow,
+    /// Leave symlinks out of the snapshot
+    Skip,
+}
+
+impl SymlinkPolicy {
+    /// Name used in config files and on the command line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SymlinkPolicy::Store => "store",
+            SymlinkPolicy::Follow => "follow",
+            SymlinkPolicy::Skip => "skip",
+        }
+    }
+}
+
+/// How `save` records the targets of stored symlinks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkTargetMode {
+    /// Keep the target exactly as the link has it
+    #[default]
+    Preserve,
+    /// Rewrite absolute targets inside the workspace relative to the link
+    Relative,
+    /// Resolve relative targets to absolute paths
+    Absolute,
+}
+
+impl SymlinkTargetMode {
+    /// Name used in config files and on the command line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SymlinkTargetMode::Preserve => "preserve",
+            SymlinkTargetMode::Relative => "relative",
+            SymlinkTargetMode::Absolute => "absolute",
+        }
+    }
+
+    /// Whether targets are kept as they are
+    pub fn is_preserve(&self) -> bool {
+        *self == SymlinkTargetMode::Preserve
+    }
+}
+
+/// What `save` does when a snapshot ends up with no files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptyPolicy {
+    /// Fail without saving the snapshot
+    Fail,
+    /// Save it and print a note
+    #[default]
+    Warn,
+    /// Save it without comment
+    Ok,
+}
+
+impl EmptyPolicy {
+    /// Name used in config files and on the command line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmptyPolicy::Fail => "fail",
+            EmptyPolicy::Warn => "warn",
+            EmptyPolicy::Ok => "ok",
+        }
+    }
+}
+
+/// How precisely file modification times are recorded and compared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MtimePrecision {
+    /// Keep the nanoseconds the filesystem reports
+    #[default]
+    Full,
+    /// Drop sub-second parts, for files copied between filesystems of different precision
+    Second,
+}
+
+impl MtimePrecision {
+    /// Name used in config files and on the command line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MtimePrecision::Full => "full",
+            MtimePrecision::Second => "second",
+        }
+    }
+
+    /// Sub-second part of an mtime as recorded at this precision
+    pub fn nanos(&self, nanos: u32) -> u32 {
+        match self {
+            MtimePrecision::Full => nanos,
+            MtimePrecision::Second => 0,
+        }
+    }
+
+    /// Whether sub-second parts are kept
+    pub fn is_full(&self) -> bool {
+        *self == MtimePrecision::Full
+    }
+}
+
+/// Compression preset chosen by intent instead of a raw zstd level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionEffort {
+    /// zstd level 1: least CPU, for saves on every build
+    Fast,
+    /// zstd level 3: a good ratio at little CPU cost
+    Balanced,
+    /// zstd level 19 with long-distance matching over a 128 MB window: the best ratio on
+    /// large files with repeats far apart, at many times the CPU of `balanced`
+    Max,
+}
+
+impl CompressionEffort {
+    /// Name used in config files and on the command line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionEffort::Fast => "fast",
+            CompressionEffort::Balanced => "balanced",
+            CompressionEffort::Max => "max",
+        }
+    }
+
+    /// zstd level the preset compresses at
+    pub fn level(&self) -> u32 {
+        match self {
+            CompressionEffort::Fast => 1,
+            CompressionEffort::Balanced => 3,
+            CompressionEffort::Max => 19,
+        }
+    }
+
+    /// Window log to use with long-distance matching, if the preset enables it. Kept at
+    /// 27, the largest window zstd decoders accept without extra settings.
+    pub fn long_distance_window(&self) -> Option<u32> {
+        match self {
+            CompressionEffort::Max => Some(27),
+            CompressionEffort::Fast | CompressionEffort::Balanced => None,
+        }
+    }
+}
+
 /// Configuration loaded from .kibo.toml
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -83,18 +294,154 @@ pub struct Config {
     #[serde(default)]
     pub ignore: Vec<String>,
 
+    /// File patterns whose hash is derived from path, size and mtime instead of content
+    #[serde(default)]
+    pub assume_unchanged: Vec<String>,
+
     /// Optional warning threshold for snapshot size in GB
     #[serde(default)]
     pub max_snapshot_size_gb: Option<f64>,
 
+    /// Abort a save that takes longer than this many seconds
+    #[serde(default)]
+    pub max_duration_secs: Option<u64>,
+
+    /// Optional warning threshold for the number of blobs in the store
+    #[serde(default)]
+    pub max_blob_count: Option<usize>,
+
+    /// Size limit for the whole store in GB, checked after each save
+    #[serde(default)]
+    pub store_max_size_gb: Option<f64>,
+
+    /// Remove the oldest snapshots when the store exceeds `store_max_size_gb`, instead of warning
+    #[serde(default)]
+    pub auto_prune: bool,
+
+    /// Keep blobs that lost their last reference until this long after `prune` or `rm`
+    /// first found them unreferenced (e.g. 24h), so a removed snapshot can still be rebuilt.
+    /// `auto_prune` keeps them too.
+    #[serde(default)]
+    pub keep_unreferenced_for: Option<String>,
+
     /// Compression level (0 = no compression, MVP default)
     #[serde(default)]
     pub compression_level: u32,
 
+    /// Compression preset (fast, balanced or max) used instead of `compression_level`
+    #[serde(default)]
+    pub compression_effort: Option<CompressionEffort>,
+
+    /// Train a zstd dictionary from small files on save and compress new blobs with it
+    #[serde(default)]
+    pub compression_dict: bool,
+
+    /// Compress blobs reused by a save that an earlier save stored uncompressed
+    #[serde(default)]
+    pub recompress_reused: bool,
+
+    /// Report how many files of a save duplicate another file in the same snapshot,
+    /// separately from content reused from earlier snapshots
+    #[serde(default)]
+    pub dedup_within_run: bool,
+
+    /// Fsync each stored blob and its directory before the manifest is written
+    #[serde(default)]
+    pub durable_writes: bool,
+
+    /// Before storing, check that the new blobs fit on the store's filesystem
+    #[serde(default)]
+    pub check_free_space: bool,
+
+    /// Abort the save instead of warning when the free space check fails
+    #[serde(default)]
+    pub strict_space: bool,
+
+    /// Record hashes in the manifest without storing any blobs
+    #[serde(default)]
+    pub hash_only: bool,
+
+    /// Mark snapshots so that `load` hard-links files with identical content
+    #[serde(default)]
+    pub link_identical: bool,
+
     /// Show progress bars (default: auto-detect TTY)
     #[serde(default)]
     pub progress: Option<bool>,
 
+    /// Record directories that contain no files (default: true)
+    #[serde(default = "default_include_empty_dirs")]
+    pub include_empty_dirs: bool,
+
+    /// Leave directories with mode 755 that hold files out of the manifest; load recreates
+    /// them from the file paths. Empty directories and other modes are still recorded.
+    #[serde(default)]
+    pub sparse_directories: bool,
+
+    /// Do not descend into mount points below the workspace root
+    #[serde(default)]
+    pub one_file_system: bool,
+
+    /// How symlinks inside tracked paths are captured (store, follow or skip)
+    #[serde(default)]
+    pub symlink_policy: SymlinkPolicy,
+
+    /// How the targets of stored symlinks are recorded (preserve, relative or absolute)
+    #[serde(default)]
+    pub symlink_target_mode: SymlinkTargetMode,
+
+    /// Skip version control metadata directories such as .git
+    #[serde(default)]
+    pub exclude_vcs: bool,
+
+    /// Skip files and directories whose name starts with `.` inside tracked directories and `files` pattern matches
+    #[serde(default)]
+    pub exclude_hidden: bool,
+
+    /// Add the patterns from the workspace root's .gitignore to the ignore list
+    #[serde(default)]
+    pub gitignore: bool,
+
+    /// Record the git commit checked out in the workspace in each snapshot
+    #[serde(default)]
+    pub include_git_sha: bool,
+
+    /// Environment variables whose values are recorded in each snapshot
+    #[serde(default)]
+    pub capture_env: Vec<String>,
+
+    /// What to do when a save captures no files
+    #[serde(default)]
+    pub on_empty: EmptyPolicy,
+
+    /// Record and compare mtimes to the second instead of the nanosecond
+    #[serde(default)]
+    pub mtime_precision: MtimePrecision,
+
+    /// Only capture files modified within this long before the save (e.g. 12h, 7d)
+    #[serde(default)]
+    pub include_newer_than: Option<String>,
+
+    /// Leave zero-byte files out of snapshots
+    #[serde(default)]
+    pub exclude_empty_files: bool,
+
+    /// Accept absolute paths in `directories`, which may point outside the workspace root
+    #[serde(default)]
+    pub allow_outside_root: bool,
+
+    /// Store group new snapshots keep their blobs in (None = the default store)
+    #[serde(default)]
+    pub store_group: Option<String>,
+
+    /// Append small blobs to shared pack files instead of one file per blob
+    #[serde(default)]
+    pub pack_small_blobs: bool,
+
+    /// Blobs smaller than this size in KB are packed when `pack_small_blobs` is enabled
+    #[serde(default = "default_pack_threshold_kb")]
+    pub pack_threshold_kb: u64,
+
     /// Database configuration (optional)
     #[serde(default)]
     pub database: Option<DatabaseConfig>,
@@ -106,9 +453,42 @@ impl Default for Config {
             directories: Vec::new(),
             files: Vec::new(),
             ignore: Vec::new(),
+            assume_unchanged: Vec::new(),
             max_snapshot_size_gb: None,
+            max_duration_secs: None,
+            max_blob_count: None,
+            store_max_size_gb: None,
+            auto_prune: false,
+            keep_unreferenced_for: None,
             compression_level: 0,
+            compression_effort: None,
+            compression_dict: false,
+            recompress_reused: false,
+            dedup_within_run: false,
+            durable_writes: false,
+            check_free_space: false,
+            strict_space: false,
+            hash_only: false,
+            link_identical: false,
             progress: None,
+            include_empty_dirs: default_include_empty_dirs(),
+            sparse_directories: false,
+            one_file_system: false,
+            symlink_policy: SymlinkPolicy::Store,
+            symlink_target_mode: SymlinkTargetMode::Preserve,
+            exclude_vcs: false,
+            exclude_hidden: false,
+            gitignore: false,
+            include_git_sha: false,
+            capture_env: Vec::new(),
+            on_empty: EmptyPolicy::Warn,
+            mtime_precision: MtimePrecision::Full,
+            include_newer_than: None,
+            exclude_empty_files: false,
+            allow_outside_root: false,
+            store_group: None,
+            pack_small_blobs: false,
+            pack_threshold_kb: default_pack_threshold_kb(),
             database: None,
         }
     }
@@ -129,13 +509,77 @@ impl Config {
         let content = std::fs::read_to_string(config_path)
             .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
 
-        let config: Config = toml::from_str(&content)
+        Self::parse(&content, config_path)
+    }
+
+    /// Load configuration like `load`, but fail on keys kibo does not know instead of
+    /// ignoring them, so that a misspelled key is not silently replaced by its default
+    pub fn load_strict(config_path: &Path) -> Result<Self> {
+        if !config_path.exists() {
+            return Self::load(config_path);
+        }
+
+        let content = std::fs::read_to_string(config_path)
+            .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
+        let unknown = unknown_config_keys(&content)
+            .with_context(|| format!("Failed to parse config file: {}", config_path.display()))?;
+        if !unknown.is_empty() {
+            bail!(
+                "Configuration error: unknown key(s) in {}: {}",
+                config_path.display(),
+                unknown.join(", ")
+            );
+        }
+
+        Self::parse(&content, config_path)
+    }
+
+    /// Parse and validate the content of a config file
+    fn parse(content: &str, config_path: &Path) -> Result<Self> {
+        let config: Config = toml::from_str(content)
             .with_context(|| format!("Failed to parse config file: {}", config_path.display()))?;
 
         config.validate()?;
         Ok(config)
     }
 
+    /// Write the configuration to a TOML file, with every key spelled out
+    pub fn save(&self, config_path: &Path) -> Result<()> {
+        let content = self.to_commented_toml()?;
+        crate::fs_utils::atomic_write(config_path, content.as_bytes())
+            .with_context(|| format!("Failed to write config file: {}", config_path.display()))
+    }
+
+    /// Serialize the configuration with every key that has a value, each preceded by a
+    /// comment describing it. Keys without a value, like an unset size limit, are left out.
+    pub fn to_commented_toml(&self) -> Result<String> {
+        let plain = toml::to_string_pretty(self).context("Failed to serialize config")?;
+
+        let mut content = String::new();
+        let mut in_table = false;
+        for line in plain.lines() {
+            in_table |= line.starts_with('[');
+            let key = if line.starts_with("[database]") {
+                Some("database")
+            }
+            else if in_table {
+                None
+            }
+            else {
+                line.split_once(" = ").map(|(key, _)| key)
+            };
+            if let Some((_, comment)) = key.and_then(|key| CONFIG_KEYS.iter().find(|(known, _)| *known == key)) {
+                if !content.is_empty() && !content.ends_with("\n\n") {
+                    content.push('\n');
+                }
+                content.push_str(&format!("# {}\n", comment));
+            }
+            content.push_str(line);
+            content.push('\n');
+        }
+        Ok(content)
+    }
+
     /// Validate the configuration
     pub fn validate(&self) -> Result<()> {
         if self.directories.is_empty() && self.files.is_empty() {
@@ -155,14 +599,42 @@ impl Config {
                     path
                 );
             }
+            if Path::new(path).is_absolute() && !self.allow_outside_root {
+                bail!(
+                    "Configuration error: path '{}' is absolute, which is only allowed with \
+                     'allow_outside_root = true' or 'save --allow-outside-root'",
+                    path
+                );
+            }
+        }
+
+        if let Some(ref group) = self.store_group {
+            validate_store_group(group)?;
         }
 
+        if let Some(limit_gb) = self.store_max_size_gb
+            && (limit_gb.is_nan() || limit_gb <= 0.0)
+        {
+            bail!("Configuration error: store_max_size_gb must be a positive number of GB, got {}", limit_gb);
+        }
 
         Ok(())
     }
 
+    /// Directories given by absolute path, which are walked directly instead of being matched
+    /// by name inside the workspace
+    pub fn outside_directories(&self) -> impl Iterator<Item = &Path> {
+        self.directories
+            .iter()
+            .map(Path::new)
+            .filter(|path| path.is_absolute())
+    }
+
     /// Validate and warn about compression level
     pub fn validate_compression_level(&self) {
+        if self.compression_effort.is_some() {
+            return;
+        }
         if self.compression_level > 10 {
             eprintln!(
                 "Warning: compression_level {} exceeds maximum allowed (10). Will use level 10.",
@@ -176,9 +648,31 @@ impl Config {
         }
     }
 
-    /// Get the effective compression level (capped at 10)
+    /// Get the effective compression level: the level of the compression effort if one
+    /// is set, otherwise `compression_level` capped at 10
     pub fn effective_compression_level(&self) -> u32 {
-        std::cmp::min(self.compression_level, 10)
+        match self.compression_effort {
+            Some(effort) => effort.level(),
+            None => std::cmp::min(self.compression_level, 10),
+        }
+    }
+
+    /// Merge VCS directories and .gitignore patterns into the ignore list, as enabled by
+    /// `exclude_vcs` and `gitignore`. Returns whether .gitignore rules were applied.
+    pub fn apply_vcs_ignores(&mut self, root: &Path) -> Result<bool> {
+        if self.exclude_vcs {
+            self.ignore.extend(VCS_DIRS.iter().map(|dir| dir.to_string()));
+        }
+
+        let gitignore_path = root.join(GITIGNORE_FILE);
+        if !self.gitignore || !gitignore_path.exists() {
+            return Ok(false);
+        }
+
+        let content = std::fs::read_to_string(&gitignore_path)
+            .with_context(|| format!("Failed to read {}", gitignore_path.display()))?;
+        self.ignore.extend(parse_gitignore(&content));
+        Ok(true)
     }
 
     /// Check if a path should be ignored
@@ -209,6 +703,35 @@ impl Config {
     }
 }
 
+/// Convert .gitignore lines into ignore patterns. Only a subset of the syntax is
+/// understood: literal names, `*` globs and `dir/` entries. Leading and trailing
+/// slashes are dropped, and negated (`!`) and escaped (`\`) lines are skipped.
+pub fn parse_gitignore(content: &str) -> Vec<String> {
+    translate_gitignore(content).0
+}
+
+/// Like `parse_gitignore`, but also returns the lines that could not be translated
+pub fn translate_gitignore(content: &str) -> (Vec<String>, Vec<String>) {
+    let mut patterns = Vec::new();
+    let mut skipped = Vec::new();
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('!') || line.contains('\\') {
+            skipped.push(line.to_string());
+            continue;
+        }
+        let pattern = line.trim_matches('/');
+        if !pattern.is_empty() {
+            patterns.push(pattern.to_string());
+        }
+    }
+
+    (patterns, skipped)
+}
+
 /// Find the repository root by looking for .kibo.toml
 pub fn find_repo_root() -> Result<PathBuf> {
     let current_dir = std::env::current_dir()
@@ -233,6 +756,42 @@ pub fn find_repo_root() -> Result<PathBuf> {
     }
 }
 
+/// Check that a store group name is usable as a directory name
+pub fn validate_store_group(group: &str) -> Result<()> {
+    let valid_chars = group.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if group.is_empty() || group.starts_with('.') || !valid_chars {
+        bail!(
+            "Invalid store group '{}': use letters, digits, '-', '_' and '.', not starting with '.'",
+            group
+        );
+    }
+    Ok(())
+}
+
+/// Keys in a config file that kibo does not know and ignores when loading it, such as
+/// misspelled ones. Keys of the `[database]` section are reported as `database.<key>`.
+pub fn unknown_config_keys(content: &str) -> Result<Vec<String>> {
+    let table: toml::Table = toml::from_str(content).context("Failed to parse config file")?;
+
+    let mut unknown = Vec::new();
+    for (key, value) in &table {
+        if !CONFIG_KEYS.iter().any(|(known, _)| known == key) {
+            unknown.push(key.clone());
+        }
+        else if key == "database"
+            && let Some(database) = value.as_table()
+        {
+            unknown.extend(
+                database
+                    .keys()
+                    .filter(|key| !DATABASE_KEYS.contains(&key.as_str()))
+                    .map(|key| format!("database.{}", key)),
+            );
+        }
+    }
+    Ok(unknown)
+}
+
 /// Get the kibo directory path (.kibo/)
 pub fn get_kibo_dir(root: &Path) -> PathBuf {
     root.join(KIBO_DIR)
@@ -253,12 +812,105 @@ pub fn get_hash_cache_path(root: &Path) -> PathBuf {
     get_kibo_dir(root).join(HASH_CACHE_FILE)
 }
 
+/// Get the path of the journal kept by `load --resume`
+pub fn get_load_state_path(root: &Path) -> PathBuf {
+    get_kibo_dir(root).join(LOAD_STATE_FILE)
+}
+
+/// Get the path where `prune --keep-unreferenced-for` records when blobs became unreferenced
+pub fn get_unreferenced_path(root: &Path) -> PathBuf {
+    get_kibo_dir(root).join(UNREFERENCED_FILE)
+}
+
+/// Get the path where `load --rename-conflicts` records the local versions it kept
+pub fn get_kept_conflicts_path(root: &Path) -> PathBuf {
+    get_kibo_dir(root).join(KEPT_CONFLICTS_FILE)
+}
+
+/// Get the directory `load --atomic` restores into before swapping directories into place
+pub fn get_load_staging_dir(root: &Path) -> PathBuf {
+    get_kibo_dir(root).join(LOAD_STAGING_DIR)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
     use tempfile::TempDir;
 
+    #[test]
+    fn test_config_keys_cover_every_field() {
+        let config = Config {
+            max_snapshot_size_gb: Some(1.0),
+            max_duration_secs: Some(60),
+            max_blob_count: Some(100),
+            store_max_size_gb: Some(2.0),
+            keep_unreferenced_for: Some("24h".to_string()),
+            progress: Some(true),
+            include_newer_than: Some("7d".to_string()),
+            store_group: Some("ci".to_string()),
+            compression_effort: Some(CompressionEffort::Balanced),
+            database: Some(DatabaseConfig::default()),
+            ..Default::default()
+        };
+        let table = toml::Table::try_from(&config).unwrap();
+
+        let serialized: Vec<&str> = table.keys().map(String::as_str).collect();
+        let mut documented: Vec<&str> = CONFIG_KEYS.iter().map(|(key, _)| *key).collect();
+        documented.sort();
+        assert_eq!(serialized, documented);
+
+        let mut database: Vec<&str> = table["database"].as_table().unwrap().keys().map(String::as_str).collect();
+        let mut known = DATABASE_KEYS.to_vec();
+        database.sort();
+        known.sort();
+        assert_eq!(database, known);
+    }
+
+    #[test]
+    fn test_commented_toml_round_trips() {
+        let config = Config {
+            directories: vec!["build".to_string()],
+            compression_level: 3,
+            max_snapshot_size_gb: Some(5.0),
+            database: Some(DatabaseConfig::default()),
+            ..Default::default()
+        };
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join(CONFIG_FILENAME);
+        config.save(&config_path).unwrap();
+
+        let content = fs::read_to_string(&config_path).unwrap();
+        assert!(content.contains("# Compression level (0 = no compression, 1-10 = zstd levels)\ncompression_level = 3\n"));
+        assert!(content.contains("# Database to dump with save --include-db\n[database]\n"));
+        assert!(!content.contains("store_group"), "Unset keys are left out");
+
+        let loaded = Config::load(&config_path).unwrap();
+        assert_eq!(toml::to_string(&loaded).unwrap(), toml::to_string(&config).unwrap());
+        assert!(unknown_config_keys(&content).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_load_strict_rejects_unknown_keys() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join(CONFIG_FILENAME);
+        fs::write(&config_path, "directories = [\"build\"]\ncompresion_level = 9\n").unwrap();
+
+        assert_eq!(Config::load(&config_path).unwrap().compression_level, 0);
+        let err = Config::load_strict(&config_path).unwrap_err();
+        assert!(err.to_string().contains("unknown key(s)"), "{}", err);
+        assert!(err.to_string().contains("compresion_level"), "{}", err);
+
+        fs::write(&config_path, "directories = [\"build\"]\ncompression_level = 9\n").unwrap();
+        assert_eq!(Config::load_strict(&config_path).unwrap().compression_level, 9);
+    }
+
+    #[test]
+    fn test_unknown_config_keys() {
+        let content = "directories = [\"build\"]\ncompresion_level = 3\n\n[database]\nuser = \"me\"\npasword = \"x\"\n";
+        assert_eq!(unknown_config_keys(content).unwrap(), vec!["compresion_level", "database.pasword"]);
+    }
+
     #[test]
     fn test_database_config_defaults() {
         let db_config = DatabaseConfig::default();
@@ -278,9 +930,14 @@ mod tests {
         assert!(config.directories.is_empty());
         assert!(config.files.is_empty());
         assert!(config.ignore.is_empty());
+        assert!(config.assume_unchanged.is_empty());
         assert_eq!(config.max_snapshot_size_gb, None);
+        assert_eq!(config.max_blob_count, None);
         assert_eq!(config.compression_level, 0);
+        assert!(!config.compression_dict);
         assert_eq!(config.progress, None);
+        assert!(!config.pack_small_blobs);
+        assert_eq!(config.pack_threshold_kb, 16);
         assert!(config.database.is_none());
     }
 
@@ -294,6 +951,7 @@ mod tests {
             files = ["*.txt"]
             ignore = ["target", "node_modules"]
             max_snapshot_size_gb = 10.5
+            max_blob_count = 500000
             compression_level = 3
         "#;
         
@@ -304,6 +962,7 @@ mod tests {
         assert_eq!(config.files, vec!["*.txt"]);
         assert_eq!(config.ignore, vec!["target", "node_modules"]);
         assert_eq!(config.max_snapshot_size_gb, Some(10.5));
+        assert_eq!(config.max_blob_count, Some(500000));
         assert_eq!(config.compression_level, 3);
     }
 
@@ -387,6 +1046,46 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains(".."));
     }
 
+    #[test]
+    fn test_validate_store_group() {
+        assert!(validate_store_group("release").is_ok());
+        assert!(validate_store_group("debug-x86_64.v2").is_ok());
+        assert!(validate_store_group("").is_err());
+        assert!(validate_store_group("..").is_err());
+        assert!(validate_store_group("a/b").is_err());
+    }
+
+    #[test]
+    fn test_config_validate_absolute_path_needs_allow_outside_root() {
+        let mut config = Config {
+            directories: vec!["build".to_string(), "/work/out".to_string()],
+            ..Default::default()
+        };
+
+        let result = config.validate();
+        assert!(result.unwrap_err().to_string().contains("allow_outside_root"));
+
+        config.allow_outside_root = true;
+        assert!(config.validate().is_ok());
+        assert_eq!(config.outside_directories().collect::<Vec<_>>(), vec![Path::new("/work/out")]);
+    }
+
+    #[test]
+    fn test_config_validate_store_max_size() {
+        let mut config = Config {
+            directories: vec!["build".to_string()],
+            store_max_size_gb: Some(0.5),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        for limit_gb in [0.0, -1.0, f64::NAN] {
+            config.store_max_size_gb = Some(limit_gb);
+            let result = config.validate();
+            assert!(result.unwrap_err().to_string().contains("store_max_size_gb"));
+        }
+    }
+
     #[test]
     fn test_effective_compression_level_capped_at_10() {
         let config = Config {
@@ -407,6 +1106,16 @@ mod tests {
         assert_eq!(config.effective_compression_level(), 5);
     }
 
+    #[test]
+    fn test_effective_compression_level_from_effort() {
+        let config: Config = toml::from_str("compression_level = 5\ncompression_effort = \"max\"").unwrap();
+        assert_eq!(config.compression_effort, Some(CompressionEffort::Max));
+        assert_eq!(config.effective_compression_level(), 19);
+        assert_eq!(CompressionEffort::Max.long_distance_window(), Some(27));
+        assert_eq!(CompressionEffort::Fast.level(), 1);
+        assert_eq!(CompressionEffort::Fast.long_distance_window(), None);
+    }
+
     #[test]
     fn test_should_ignore_exact_match() {
         let config = Config {
@@ -452,6 +1161,42 @@ mod tests {
         assert!(!config.should_ignore(Path::new("tests")));
     }
 
+    #[test]
+    fn test_parse_gitignore() {
+        let patterns = parse_gitignore("# build output\n/target/\n*.log\n\n!keep.log\nnode_modules/\n.env\n");
+        assert_eq!(patterns, vec!["target", "*.log", "node_modules", ".env"]);
+    }
+
+    #[test]
+    fn test_translate_gitignore() {
+        let (patterns, skipped) = translate_gitignore("/dist/\n!dist/keep\n\\#literal\n*.o\n/\n");
+        assert_eq!(patterns, vec!["dist", "*.o"]);
+        assert_eq!(skipped, vec!["!dist/keep", "\\#literal"]);
+    }
+
+    #[test]
+    fn test_apply_vcs_ignores() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join(GITIGNORE_FILE), "*.log\n").unwrap();
+
+        let mut config = Config {
+            ignore: vec!["tmp".to_string()],
+            exclude_vcs: true,
+            gitignore: true,
+            ..Default::default()
+        };
+        assert!(config.apply_vcs_ignores(temp_dir.path()).unwrap());
+
+        assert!(config.should_ignore(Path::new("src/.git/HEAD")));
+        assert!(config.should_ignore(Path::new("build/debug.log")));
+        assert!(config.should_ignore(Path::new("tmp/scratch")));
+        assert!(!config.should_ignore(Path::new("build/app.o")));
+
+        let mut defaults = Config::default();
+        assert!(!defaults.apply_vcs_ignores(temp_dir.path()).unwrap());
+        assert!(defaults.ignore.is_empty());
+    }
+
     #[test]
     fn test_find_repo_root_in_current_dir() {
         let temp_dir = TempDir::new().unwrap();
@@ -0,0 +1,436 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::file_hash;
+use crate::manifest::{FileEntry, Manifest};
+
+/// How one blob is used across all snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedBlob {
+    pub hash: String,
+    pub size: u64,
+    /// Number of snapshots referencing the blob
+    pub snapshot_count: usize,
+    /// Number of file entries referencing the blob, across all snapshots
+    pub reference_count: usize,
+    /// One of the paths the blob is stored under, for display
+    pub example_path: String,
+}
+
+/// Deduplication statistics for a set of snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct DedupReport {
+    pub snapshot_count: usize,
+    /// Sum of every snapshot's total size, as if each were stored separately
+    pub logical_bytes: u64,
+    /// Size of the distinct blobs referenced by the snapshots, before compression
+    pub unique_bytes: u64,
+    pub unique_blobs: usize,
+    /// The most-shared blobs, by snapshot count and then size
+    pub top_shared: Vec<SharedBlob>,
+}
+
+impl DedupReport {
+    /// Bytes saved by storing shared content only once
+    pub fn saved_bytes(&self) -> u64 {
+        self.logical_bytes.saturating_sub(self.unique_bytes)
+    }
+
+    /// Logical size divided by unique size, 1.0 when nothing is shared
+    pub fn ratio(&self) -> f64 {
+        if self.unique_bytes == 0 {
+            1.0
+        }
+        else {
+            self.logical_bytes as f64 / self.unique_bytes as f64
+        }
+    }
+}
+
+/// What a snapshot costs on disk once deduplication is taken into account
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotFootprint {
+    pub name: String,
+    /// Total size of the snapshot's files, as shown by `kibo list`
+    pub logical_bytes: u64,
+    /// On-disk size of the blobs no other snapshot references, freed by removing this one
+    pub exclusive_bytes: u64,
+    pub exclusive_blobs: usize,
+    /// On-disk size of the blobs this snapshot shares with others
+    pub shared_bytes: u64,
+    pub shared_blobs: usize,
+}
+
+/// Split each snapshot's on-disk size into blobs only it references and blobs it shares,
+/// largest exclusive size first. Blobs are only shared within a store group, and
+/// `stored_size(group, hash)` gives a blob's size on disk (None for a missing blob).
+pub fn snapshot_footprints(
+    snapshots: &[Manifest],
+    stored_size: impl Fn(Option<&str>, &str) -> Option<u64>,
+) -> Vec<SnapshotFootprint> {
+    let distinct: Vec<BTreeSet<&str>> = snapshots
+        .iter()
+        .map(|snapshot| snapshot.files.values().map(|entry| entry.hash.as_str()).collect())
+        .collect();
+
+    let mut snapshot_counts: HashMap<(Option<&str>, &str), usize> = HashMap::new();
+    for (snapshot, hashes) in snapshots.iter().zip(&distinct) {
+        for hash in hashes {
+            *snapshot_counts.entry((snapshot.store_group.as_deref(), hash)).or_default() += 1;
+        }
+    }
+
+    let mut footprints: Vec<SnapshotFootprint> = snapshots
+        .iter()
+        .zip(&distinct)
+        .map(|(snapshot, hashes)| {
+            let group = snapshot.store_group.as_deref();
+            let mut footprint = SnapshotFootprint {
+                name: snapshot.name.clone(),
+                logical_bytes: snapshot.total_size,
+                exclusive_bytes: 0,
+                exclusive_blobs: 0,
+                shared_bytes: 0,
+                shared_blobs: 0,
+            };
+            for hash in hashes {
+                let size = stored_size(group, hash).unwrap_or(0);
+                if snapshot_counts[&(group, *hash)] == 1 {
+                    footprint.exclusive_bytes += size;
+                    footprint.exclusive_blobs += 1;
+                }
+                else {
+                    footprint.shared_bytes += size;
+                    footprint.shared_blobs += 1;
+                }
+            }
+            footprint
+        })
+        .collect();
+
+    footprints.sort_by(|a, b| b.exclusive_bytes.cmp(&a.exclusive_bytes).then(a.name.cmp(&b.name)));
+    footprints
+}
+
+/// One place a hash is recorded, as reported by `verify --cross-snapshot`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HashUse {
+    pub snapshot: String,
+    pub path: String,
+    /// Length of the blob's content: the file size, or the length of a symlink's target
+    pub content_length: u64,
+}
+
+/// A hash recorded with different content lengths, which one blob cannot have. It points at
+/// a corrupt manifest or a hash collision.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HashConflict {
+    pub hash: String,
+    /// The first use of the hash seen with each distinct length
+    pub uses: Vec<HashUse>,
+}
+
+/// A file entry whose hash cannot belong to what the manifest says about the file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InconsistentEntry {
+    pub snapshot: String,
+    pub path: String,
+    pub hash: String,
+    pub reason: String,
+}
+
+/// Result of checking the manifests against each other, without reading the store
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CrossCheck {
+    pub snapshots: usize,
+    /// Number of file entries checked
+    pub entries: usize,
+    pub conflicts: Vec<HashConflict>,
+    pub inconsistent: Vec<InconsistentEntry>,
+}
+
+impl CrossCheck {
+    /// Whether no conflicts or inconsistent entries were found
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty() && self.inconsistent.is_empty()
+    }
+}
+
+/// Length of the blob an entry's hash stands for
+fn content_length(entry: &FileEntry) -> u64 {
+    match entry.symlink_target {
+        Some(ref target) if entry.is_symlink => target.len() as u64,
+        _ => entry.size,
+    }
+}
+
+/// Why an entry's hash cannot match the rest of the entry, if it cannot. Only checks
+/// that need no blob content: symlink targets, assumed hashes and empty files.
+fn entry_inconsistency(path: &str, entry: &FileEntry, empty_hash: &str) -> Option<&'static str> {
+    if entry.is_symlink {
+        return match entry.symlink_target {
+            None => Some("symlink entry has no target"),
+            Some(ref target) if blake3::hash(target.as_bytes()).to_hex().as_str() != entry.hash => {
+                Some("hash does not match the symlink target")
+            }
+            Some(_) => None,
+        };
+    }
+    if entry.assumed {
+        let expected = file_hash::hash_assumed(path, entry.size, entry.mtime_secs, entry.mtime_nanos);
+        return (expected != entry.hash).then_some("assume_unchanged hash does not match the path, size and mtime");
+    }
+    match (entry.size == 0, entry.hash == empty_hash) {
+        (true, false) => Some("empty file recorded with the hash of non-empty content"),
+        (false, true) => Some("non-empty file recorded with the hash of empty content"),
+        _ => None,
+    }
+}
+
+/// Check that every hash means the same content length in every manifest, and that each
+/// entry's hash is consistent with the entry itself, using only the manifests
+pub fn cross_check(snapshots: &[Manifest]) -> CrossCheck {
+    let empty_hash = blake3::hash(b"").to_hex().to_string();
+    let mut uses: BTreeMap<&str, Vec<HashUse>> = BTreeMap::new();
+    let mut check = CrossCheck { snapshots: snapshots.len(), ..CrossCheck::default() };
+
+    for snapshot in snapshots {
+        let files: BTreeMap<&String, &FileEntry> = snapshot.files.iter().collect();
+        for (path, entry) in files {
+            check.entries += 1;
+            if let Some(reason) = entry_inconsistency(path, entry, &empty_hash) {
+                check.inconsistent.push(InconsistentEntry {
+                    snapshot: snapshot.name.clone(),
+                    path: path.clone(),
+                    hash: entry.hash.clone(),
+                    reason: reason.to_string(),
+                });
+            }
+
+            let length = content_length(entry);
+            let seen = uses.entry(entry.hash.as_str()).or_default();
+            if !seen.iter().any(|first| first.content_length == length) {
+                seen.push(HashUse { snapshot: snapshot.name.clone(), path: path.clone(), content_length: length });
+            }
+        }
+    }
+
+    check.conflicts = uses
+        .into_iter()
+        .filter(|(_, uses)| uses.len() > 1)
+        .map(|(hash, uses)| HashConflict { hash: hash.to_string(), uses })
+        .collect();
+    check
+}
+
+/// Build the blob to snapshot reverse index and summarize how much content is shared.
+/// The same hash in two store groups is two blobs, so blobs are keyed by group and hash.
+pub fn dedup_report(snapshots: &[Manifest], top: usize) -> DedupReport {
+    struct Usage<'a> {
+        size: u64,
+        snapshots: BTreeSet<&'a str>,
+        references: usize,
+        example_path: &'a str,
+    }
+
+    let mut index: HashMap<(Option<&str>, &str), Usage> = HashMap::new();
+
+    for snapshot in snapshots {
+        for (path, entry) in &snapshot.files {
+            let key = (snapshot.store_group.as_deref(), entry.hash.as_str());
+            let usage = index.entry(key).or_insert_with(|| Usage {
+                size: entry.size,
+                snapshots: BTreeSet::new(),
+                references: 0,
+                example_path: path,
+            });
+            usage.snapshots.insert(&snapshot.name);
+            usage.references += 1;
+        }
+    }
+
+    let mut shared: Vec<SharedBlob> = index
+        .iter()
+        .filter(|(_, usage)| usage.references > 1)
+        .map(|((_, hash), usage)| SharedBlob {
+            hash: hash.to_string(),
+            size: usage.size,
+            snapshot_count: usage.snapshots.len(),
+            reference_count: usage.references,
+            example_path: usage.example_path.to_string(),
+        })
+        .collect();
+    shared.sort_by(|a, b| {
+        b.snapshot_count.cmp(&a.snapshot_count)
+            .then(b.size.cmp(&a.size))
+            .then(a.hash.cmp(&b.hash))
+    });
+    shared.truncate(top);
+
+    DedupReport {
+        snapshot_count: snapshots.len(),
+        logical_bytes: snapshots.iter().map(|s| s.total_size).sum(),
+        unique_bytes: index.values().map(|usage| usage.size).sum(),
+        unique_blobs: index.len(),
+        top_shared: shared,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::FileEntry;
+
+    fn entry(hash: &str, size: u64) -> FileEntry {
+        FileEntry {
+            hash: hash.to_string(),
+            size,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
+        }
+    }
+
+    #[test]
+    fn test_dedup_report() {
+        let mut first = Manifest::new("first".to_string());
+        first.add_file("lib.a".to_string(), entry("lib", 1000));
+        first.add_file("app.bin".to_string(), entry("app1", 300));
+        first.add_file("copy/lib.a".to_string(), entry("lib", 1000));
+
+        let mut second = Manifest::new("second".to_string());
+        second.add_file("lib.a".to_string(), entry("lib", 1000));
+        second.add_file("app.bin".to_string(), entry("app2", 400));
+
+        let report = dedup_report(&[first, second], 10);
+
+        assert_eq!(report.snapshot_count, 2);
+        assert_eq!(report.logical_bytes, 3700);
+        assert_eq!(report.unique_bytes, 1700);
+        assert_eq!(report.unique_blobs, 3);
+        assert_eq!(report.saved_bytes(), 2000);
+
+        assert_eq!(report.top_shared.len(), 1);
+        let lib = &report.top_shared[0];
+        assert_eq!(lib.hash, "lib");
+        assert_eq!(lib.snapshot_count, 2);
+        assert_eq!(lib.reference_count, 3);
+    }
+
+    #[test]
+    fn test_dedup_report_counts_store_groups_apart() {
+        let mut first = Manifest::new("first".to_string());
+        first.add_file("lib.a".to_string(), entry("lib", 1000));
+        first.total_size = 1000;
+
+        let mut grouped = Manifest::new("grouped".to_string());
+        grouped.add_file("lib.a".to_string(), entry("lib", 1000));
+        grouped.total_size = 1000;
+        grouped.store_group = Some("debug".to_string());
+
+        let report = dedup_report(&[first, grouped], 10);
+
+        assert_eq!(report.unique_blobs, 2);
+        assert_eq!(report.unique_bytes, 2000);
+        assert_eq!(report.saved_bytes(), 0);
+        assert!(report.top_shared.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_footprints() {
+        let mut first = Manifest::new("first".to_string());
+        first.add_file("lib.a".to_string(), entry("lib", 1000));
+        first.add_file("copy/lib.a".to_string(), entry("lib", 1000));
+        first.add_file("app.bin".to_string(), entry("app1", 300));
+        first.total_size = 2300;
+
+        let mut second = Manifest::new("second".to_string());
+        second.add_file("lib.a".to_string(), entry("lib", 1000));
+        second.add_file("app.bin".to_string(), entry("app2", 400));
+        second.total_size = 1400;
+
+        // The same content in another store group is stored separately
+        let mut grouped = Manifest::new("grouped".to_string());
+        grouped.add_file("lib.a".to_string(), entry("lib", 1000));
+        grouped.store_group = Some("debug".to_string());
+
+        // On-disk sizes after compression; app2 is missing from the store
+        let stored_size = |_: Option<&str>, hash: &str| match hash {
+            "lib" => Some(100),
+            "app1" => Some(30),
+            _ => None,
+        };
+        let footprints = snapshot_footprints(&[first, second, grouped], stored_size);
+
+        let names: Vec<&str> = footprints.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["grouped", "first", "second"]);
+        assert_eq!(footprints[0].exclusive_bytes, 100);
+        assert_eq!(footprints[0].shared_blobs, 0);
+
+        let first = &footprints[1];
+        assert_eq!(first.logical_bytes, 2300);
+        assert_eq!((first.exclusive_bytes, first.exclusive_blobs), (30, 1));
+        assert_eq!((first.shared_bytes, first.shared_blobs), (100, 1));
+
+        let second = &footprints[2];
+        assert_eq!((second.exclusive_bytes, second.exclusive_blobs), (0, 1));
+        assert_eq!((second.shared_bytes, second.shared_blobs), (100, 1));
+    }
+
+    #[test]
+    fn test_cross_check() {
+        let empty_hash = blake3::hash(b"").to_hex().to_string();
+        let link_hash = blake3::hash(b"lib.so.1").to_hex().to_string();
+
+        let mut first = Manifest::new("first".to_string());
+        first.add_file("lib.a".to_string(), entry("lib", 1000));
+        first.add_file(".stamp".to_string(), entry(&empty_hash, 0));
+        first.add_file("lib.so".to_string(), FileEntry {
+            is_symlink: true,
+            symlink_target: Some("lib.so.1".to_string()),
+            ..entry(&link_hash, 0)
+        });
+
+        let mut second = Manifest::new("second".to_string());
+        second.add_file("lib.a".to_string(), entry("lib", 1000));
+        // A regular file with the same content as the symlink's target is not a conflict
+        second.add_file("link.txt".to_string(), entry(&link_hash, 8));
+
+        let check = cross_check(&[first.clone(), second.clone()]);
+        assert_eq!(check.snapshots, 2);
+        assert_eq!(check.entries, 5);
+        assert!(check.is_clean(), "{:?}", check);
+
+        second.add_file("copy/lib.a".to_string(), entry("lib", 999));
+        second.add_file("empty.o".to_string(), entry("lib2", 0));
+        second.add_file("bad.so".to_string(), FileEntry {
+            is_symlink: true,
+            symlink_target: Some("elsewhere".to_string()),
+            ..entry(&link_hash, 0)
+        });
+        let check = cross_check(&[first, second]);
+
+        assert_eq!(check.conflicts.len(), 2);
+        let lib = check.conflicts.iter().find(|c| c.hash == "lib").unwrap();
+        let lengths: Vec<u64> = lib.uses.iter().map(|u| u.content_length).collect();
+        assert_eq!(lengths, [1000, 999]);
+        assert_eq!(lib.uses[1].path, "copy/lib.a");
+
+        let paths: Vec<&str> = check.inconsistent.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["bad.so", "empty.o"]);
+    }
+
+    #[test]
+    fn test_dedup_report_empty() {
+        let report = dedup_report(&[], 10);
+        assert_eq!(report.logical_bytes, 0);
+        assert_eq!(report.saved_bytes(), 0);
+        assert_eq!(report.ratio(), 1.0);
+        assert!(report.top_shared.is_empty());
+    }
+}
@@ -0,0 +1,490 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::file_hash::{TEXT_PROBE_SIZE, is_text};
+use crate::manifest::{FileEntry, Manifest, format_size};
+use crate::store::Store;
+
+/// Number of unchanged lines shown around each change in a content diff
+const CONTEXT_LINES: usize = 3;
+/// Upper bound on the line comparison table, to keep content diffs of huge files cheap
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// A difference in a single path between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<'a> {
+    Added { path: &'a str, entry: &'a FileEntry },
+    Removed { path: &'a str, entry: &'a FileEntry },
+    Modified { path: &'a str, old: &'a FileEntry, new: &'a FileEntry },
+}
+
+impl Change<'_> {
+    /// Path the change applies to
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added { path, .. } | Change::Removed { path, .. } | Change::Modified { path, .. } => path,
+        }
+    }
+
+    /// Git-style status letter, as printed by `diff --name-status`
+    pub fn status(&self) -> char {
+        match self {
+            Change::Added { .. } => 'A',
+            Change::Removed { .. } => 'D',
+            Change::Modified { .. } => 'M',
+        }
+    }
+}
+
+/// Compare the files of two manifests, sorted by path
+pub fn diff_manifests<'a>(old: &'a Manifest, new: &'a Manifest) -> Vec<Change<'a>> {
+    let paths: BTreeSet<&String> = old.files.keys().chain(new.files.keys()).collect();
+
+    paths
+        .into_iter()
+        .filter_map(|path| match (old.files.get(path), new.files.get(path)) {
+            (None, Some(entry)) => Some(Change::Added { path, entry }),
+            (Some(entry), None) => Some(Change::Removed { path, entry }),
+            (Some(old), Some(new)) if old.hash != new.hash || old.is_symlink != new.is_symlink => {
+                Some(Change::Modified { path, old, new })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Counts of a diff and how much the snapshot grew or shrank, for `diff --stat`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DiffStat {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+    /// Total size of the new versions minus the total size of the old ones
+    pub net_bytes: i64,
+}
+
+impl DiffStat {
+    pub fn from_changes(changes: &[Change]) -> Self {
+        let mut stat = Self::default();
+        for change in changes {
+            match change {
+                Change::Added { entry, .. } => {
+                    stat.added += 1;
+                    stat.net_bytes += entry.size as i64;
+                }
+                Change::Removed { entry, .. } => {
+                    stat.removed += 1;
+                    stat.net_bytes -= entry.size as i64;
+                }
+                Change::Modified { old, new, .. } => {
+                    stat.modified += 1;
+                    stat.net_bytes += new.size as i64 - old.size as i64;
+                }
+            }
+        }
+        stat
+    }
+}
+
+impl fmt::Display for DiffStat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} file(s) changed, +{} added, -{} removed, ~{} modified, net {}{}",
+            self.added + self.removed + self.modified,
+            self.added,
+            self.removed,
+            self.modified,
+            if self.net_bytes < 0 { '-' } else { '+' },
+            format_size(self.net_bytes.unsigned_abs())
+        )
+    }
+}
+
+/// A changed path in a `diff --json` report. Sizes and symlink targets are only given for
+/// the sides the path exists on.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChangedFile {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_size: Option<u64>,
+    /// Size of the new version minus the size of the old one
+    pub size_delta: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_symlink_target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_symlink_target: Option<String>,
+}
+
+impl ChangedFile {
+    fn new(path: &str, old: Option<&FileEntry>, new: Option<&FileEntry>) -> Self {
+        let size = |entry: Option<&FileEntry>| entry.map(|entry| entry.size);
+        let target = |entry: Option<&FileEntry>| entry.and_then(|entry| entry.symlink_target.clone());
+        Self {
+            path: path.to_string(),
+            old_size: size(old),
+            new_size: size(new),
+            size_delta: size(new).unwrap_or(0) as i64 - size(old).unwrap_or(0) as i64,
+            old_symlink_target: target(old),
+            new_symlink_target: target(new),
+        }
+    }
+}
+
+/// The changes between two snapshots, for `diff --json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffReport {
+    pub from: String,
+    pub to: String,
+    pub added: Vec<ChangedFile>,
+    pub removed: Vec<ChangedFile>,
+    pub modified: Vec<ChangedFile>,
+    pub old_total_size: u64,
+    pub new_total_size: u64,
+    /// Total size of the new snapshot minus the total size of the old one
+    pub net_bytes: i64,
+}
+
+impl DiffReport {
+    pub fn new(old: &Manifest, new: &Manifest, changes: &[Change]) -> Self {
+        let mut report = Self {
+            from: old.name.clone(),
+            to: new.name.clone(),
+            added: Vec::new(),
+            removed: Vec::new(),
+            modified: Vec::new(),
+            old_total_size: old.total_size,
+            new_total_size: new.total_size,
+            net_bytes: new.total_size as i64 - old.total_size as i64,
+        };
+        for change in changes {
+            match change {
+                Change::Added { path, entry } => report.added.push(ChangedFile::new(path, None, Some(entry))),
+                Change::Removed { path, entry } => report.removed.push(ChangedFile::new(path, Some(entry), None)),
+                Change::Modified { path, old, new } => {
+                    report.modified.push(ChangedFile::new(path, Some(old), Some(new)))
+                }
+            }
+        }
+        report
+    }
+}
+
+/// Read a file's blob for diffing, or None if it is binary. Files recorded as text when
+/// they were saved are not sampled again; others are only read in full if their start
+/// looks like text.
+pub fn read_text_blob(store: &Store, entry: &FileEntry) -> Result<Option<String>> {
+    if !entry.is_text {
+        let mut probe = Probe(Vec::with_capacity(TEXT_PROBE_SIZE));
+        let copied = store.copy_blob_to_writer(&entry.hash, &mut probe);
+        // A full probe stops the copy with an error on purpose
+        if probe.0.len() < TEXT_PROBE_SIZE {
+            copied?;
+        }
+        if !is_text(&probe.0) {
+            return Ok(None);
+        }
+    }
+
+    let mut data = Vec::new();
+    store.copy_blob_to_writer(&entry.hash, &mut data)?;
+    Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+}
+
+/// Keeps the first `TEXT_PROBE_SIZE` bytes written to it, then accepts no more, so a copy
+/// into it stops early
+struct Probe(Vec<u8>);
+
+impl std::io::Write for Probe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let wanted = buf.len().min(TEXT_PROBE_SIZE - self.0.len());
+        self.0.extend_from_slice(&buf[..wanted]);
+        Ok(wanted)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// Produce a unified diff between two texts, or None if they are too large to compare
+pub fn unified_diff(old_label: &str, new_label: &str, old: &str, new: &str) -> Option<String> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let edits = diff_lines(&old_lines, &new_lines)?;
+
+    let mut out = format!("--- {}\n+++ {}\n", old_label, new_label);
+    for (start, end) in hunk_ranges(&edits) {
+        let old_before = edits[..start].iter().filter(|e| !matches!(e, Edit::Insert(_))).count();
+        let new_before = edits[..start].iter().filter(|e| !matches!(e, Edit::Delete(_))).count();
+        let hunk = &edits[start..end];
+        let old_count = hunk.iter().filter(|e| !matches!(e, Edit::Insert(_))).count();
+        let new_count = hunk.iter().filter(|e| !matches!(e, Edit::Delete(_))).count();
+
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_before, old_count),
+            hunk_range(new_before, new_count)
+        ));
+        for edit in hunk {
+            match edit {
+                Edit::Equal(line) => out.push_str(&format!(" {}\n", line)),
+                Edit::Delete(line) => out.push_str(&format!("-{}\n", line)),
+                Edit::Insert(line) => out.push_str(&format!("+{}\n", line)),
+            }
+        }
+    }
+
+    Some(out)
+}
+
+/// Format a hunk header range; empty ranges point at the line before them
+fn hunk_range(lines_before: usize, count: usize) -> String {
+    if count == 0 {
+        format!("{},0", lines_before)
+    }
+    else {
+        format!("{},{}", lines_before + 1, count)
+    }
+}
+
+/// Line-level edit script based on the longest common subsequence
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Option<Vec<Edit<'a>>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    let (n, m) = (old_mid.len(), new_mid.len());
+
+    if (n + 1).saturating_mul(m + 1) > MAX_DIFF_CELLS {
+        return None;
+    }
+
+    // lcs[i][j] = length of the LCS of old_mid[i..] and new_mid[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            }
+            else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits: Vec<Edit> = old[..prefix].iter().map(|l| Edit::Equal(l)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_mid[i] == new_mid[j] {
+            edits.push(Edit::Equal(old_mid[i]));
+            i += 1;
+            j += 1;
+        }
+        else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            edits.push(Edit::Delete(old_mid[i]));
+            i += 1;
+        }
+        else {
+            edits.push(Edit::Insert(new_mid[j]));
+            j += 1;
+        }
+    }
+    edits.extend(old[old.len() - suffix..].iter().map(|l| Edit::Equal(l)));
+
+    Some(edits)
+}
+
+/// Group changed edits into hunks, each padded with up to CONTEXT_LINES of unchanged lines
+fn hunk_ranges(edits: &[Edit]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+    for (index, edit) in edits.iter().enumerate() {
+        if matches!(edit, Edit::Equal(_)) {
+            continue;
+        }
+
+        let start = index.saturating_sub(CONTEXT_LINES);
+        let end = (index + CONTEXT_LINES + 1).min(edits.len());
+
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(hash: &str, size: u64) -> FileEntry {
+        FileEntry {
+            hash: hash.to_string(),
+            size,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
+        }
+    }
+
+    #[test]
+    fn test_read_text_blob() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let store = Store::with_compression(temp_dir.path(), 3);
+        store.init().unwrap();
+        let stored = |content: &[u8]| {
+            let source = temp_dir.path().join("source");
+            std::fs::write(&source, content).unwrap();
+            let hash = crate::file_hash::hash_file(&source).unwrap();
+            store.store_file(&source, &hash).unwrap();
+            entry(&hash, content.len() as u64)
+        };
+
+        let text = "line\n".repeat(TEXT_PROBE_SIZE);
+        assert_eq!(read_text_blob(&store, &stored(text.as_bytes())).unwrap().as_deref(), Some(text.as_str()));
+        assert_eq!(read_text_blob(&store, &stored(b"short\n")).unwrap().as_deref(), Some("short\n"));
+
+        let mut binary = vec![0u8; TEXT_PROBE_SIZE * 4];
+        binary.extend_from_slice(b"text");
+        assert_eq!(read_text_blob(&store, &stored(&binary)).unwrap(), None);
+
+        // Files saved as text are read without sampling them again
+        let marked = FileEntry { is_text: true, ..stored(b"\x00marked") };
+        assert_eq!(read_text_blob(&store, &marked).unwrap().as_deref(), Some("\u{0}marked"));
+        assert!(read_text_blob(&store, &entry(&"f".repeat(64), 1)).is_err());
+    }
+
+    #[test]
+    fn test_diff_manifests() {
+        let mut old = Manifest::new("old".to_string());
+        old.add_file("same.txt".to_string(), entry("h1", 1));
+        old.add_file("changed.txt".to_string(), entry("h2", 2));
+        old.add_file("removed.txt".to_string(), entry("h3", 3));
+
+        let mut new = Manifest::new("new".to_string());
+        new.add_file("same.txt".to_string(), entry("h1", 1));
+        new.add_file("changed.txt".to_string(), entry("h4", 4));
+        new.add_file("added.txt".to_string(), entry("h5", 5));
+
+        let changes = diff_manifests(&old, &new);
+        let paths: Vec<&str> = changes.iter().map(|c| c.path()).collect();
+        assert_eq!(paths, vec!["added.txt", "changed.txt", "removed.txt"]);
+        assert!(matches!(changes[0], Change::Added { .. }));
+        assert!(matches!(changes[1], Change::Modified { .. }));
+        assert!(matches!(changes[2], Change::Removed { .. }));
+
+        let statuses: String = changes.iter().map(Change::status).collect();
+        assert_eq!(statuses, "AMD");
+    }
+
+    #[test]
+    fn test_diff_stat() {
+        let mut old = Manifest::new("old".to_string());
+        old.add_file("changed.txt".to_string(), entry("h2", 2048));
+        old.add_file("removed.txt".to_string(), entry("h3", 1024));
+
+        let mut new = Manifest::new("new".to_string());
+        new.add_file("changed.txt".to_string(), entry("h4", 1024));
+        new.add_file("added.txt".to_string(), entry("h5", 512));
+
+        let stat = DiffStat::from_changes(&diff_manifests(&old, &new));
+        assert_eq!(stat, DiffStat { added: 1, removed: 1, modified: 1, net_bytes: -1536 });
+        assert_eq!(stat.to_string(), "3 file(s) changed, +1 added, -1 removed, ~1 modified, net -1.50 KB");
+
+        let stat = DiffStat::from_changes(&diff_manifests(&new, &old));
+        assert_eq!(stat.to_string(), "3 file(s) changed, +1 added, -1 removed, ~1 modified, net +1.50 KB");
+    }
+
+    #[test]
+    fn test_diff_report() {
+        let mut old = Manifest::new("old".to_string());
+        old.add_file("changed.txt".to_string(), entry("h2", 2048));
+        old.add_file("removed.txt".to_string(), entry("h3", 1024));
+        old.add_file("link".to_string(), entry("h6", 10));
+
+        let mut new = Manifest::new("new".to_string());
+        new.add_file("changed.txt".to_string(), entry("h4", 1024));
+        new.add_file("added.txt".to_string(), entry("h5", 512));
+        new.add_file("link".to_string(), FileEntry {
+            is_symlink: true,
+            symlink_target: Some("target".to_string()),
+            ..entry("h7", 6)
+        });
+
+        let report = DiffReport::new(&old, &new, &diff_manifests(&old, &new));
+        assert_eq!((report.from.as_str(), report.to.as_str()), ("old", "new"));
+        assert_eq!(report.added.len(), 1);
+        assert_eq!(report.removed[0].old_size, Some(1024));
+        assert_eq!(report.removed[0].new_size, None);
+        assert_eq!(report.modified[0].size_delta, -1024);
+        assert_eq!(report.modified[1].path, "link");
+        assert_eq!(report.modified[1].old_symlink_target, None);
+        assert_eq!(report.modified[1].new_symlink_target.as_deref(), Some("target"));
+        assert_eq!(report.net_bytes, 1542 - 3082);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json["added"][0].get("old_size").is_none());
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n";
+        let new = "a\nb\nc\nD\ne\nf\ng\nh\ni\nj\nk\nl\nm\n";
+
+        let diff = unified_diff("a/file", "b/file", old, new).unwrap();
+
+        assert_eq!(diff, "\
+--- a/file
++++ b/file
+@@ -1,7 +1,7 @@
+ a
+ b
+ c
+-d
++D
+ e
+ f
+ g
+@@ -10,3 +10,4 @@
+ j
+ k
+ l
++m
+");
+    }
+
+    #[test]
+    fn test_unified_diff_from_empty() {
+        let diff = unified_diff("a/file", "b/file", "", "new\n").unwrap();
+        assert_eq!(diff, "--- a/file\n+++ b/file\n@@ -0,0 +1,1 @@\n+new\n");
+    }
+
+    #[test]
+    fn test_unified_diff_merges_nearby_changes() {
+        let diff = unified_diff("a", "b", "1\n2\n3\n4\n5\n", "x\n2\n3\n4\ny\n").unwrap();
+        assert_eq!(diff.matches("@@ -").count(), 1);
+    }
+}
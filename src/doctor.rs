@@ -0,0 +1,351 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::config::{get_kibo_dir, get_store_dir};
+use crate::manifest::{Manifest, format_size, list_snapshots};
+use crate::store::{Store, make_read_only};
+
+const DB_SNAPSHOTS_DIR: &str = "db_snapshots";
+
+/// A database dump file that no snapshot references
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OrphanedDump {
+    pub filename: String,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Find the `.sql` files in `.kibo/db_snapshots` whose names are not in `referenced`, sorted by name
+pub fn find_orphaned_db_dumps(root: &Path, referenced: &HashSet<String>) -> Result<Vec<OrphanedDump>> {
+    let db_dumps_dir = get_kibo_dir(root).join(DB_SNAPSHOTS_DIR);
+    let mut orphans = Vec::new();
+    if !db_dumps_dir.exists() {
+        return Ok(orphans);
+    }
+
+    for entry in fs::read_dir(&db_dumps_dir)? {
+        let entry = entry?;
+        let filename = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_file() && filename.ends_with(".sql") && !referenced.contains(&filename) {
+            orphans.push(OrphanedDump {
+                filename,
+                path: entry.path(),
+                size: entry.metadata()?.len(),
+            });
+        }
+    }
+    orphans.sort_by(|a, b| a.filename.cmp(&b.filename));
+    Ok(orphans)
+}
+
+/// Database dump file names referenced by the given snapshots
+pub fn referenced_db_dumps(snapshots: &[Manifest]) -> HashSet<String> {
+    snapshots.iter().filter_map(|s| s.db_dump_filename.clone()).collect()
+}
+
+/// A problem found in the .kibo directory
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// A database dump not referenced by any snapshot
+    OrphanedDbDump { filename: String, size: u64 },
+    /// A snapshot whose database dump file no longer exists
+    MissingDbDump { snapshot: String, filename: String },
+    /// A temporary file left behind by an interrupted write
+    LeftoverTempFile { path: PathBuf, size: u64 },
+    /// A snapshot referencing blobs that are not in the store
+    MissingBlobs { snapshot: String, count: usize },
+    /// A blob that has become writable, so its content could be changed in place
+    WritableBlob { path: PathBuf },
+}
+
+impl Issue {
+    /// Describe the problem for display
+    pub fn describe(&self) -> String {
+        match self {
+            Issue::OrphanedDbDump { filename, size } => {
+                format!("Database dump '{}' ({}) is not referenced by any snapshot", filename, format_size(*size))
+            }
+            Issue::MissingDbDump { snapshot, filename } => {
+                format!("Snapshot '{}' references missing database dump '{}'", snapshot, filename)
+            }
+            Issue::LeftoverTempFile { path, size } => {
+                format!("Leftover temporary file {} ({})", path.display(), format_size(*size))
+            }
+            Issue::MissingBlobs { snapshot, count } => {
+                format!("Snapshot '{}' is missing {} blob(s) from the store", snapshot, count)
+            }
+            Issue::WritableBlob { path } => {
+                format!("Blob {} is writable; blobs should be read-only", path.display())
+            }
+        }
+    }
+
+    /// Whether `fix` can repair this problem
+    pub fn is_fixable(&self) -> bool {
+        !matches!(self, Issue::MissingBlobs { .. })
+    }
+
+    /// Repair the problem, returning a description of what was done
+    pub fn fix(&self, root: &Path) -> Result<String> {
+        match self {
+            Issue::OrphanedDbDump { filename, .. } => {
+                let path = get_kibo_dir(root).join(DB_SNAPSHOTS_DIR).join(filename);
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove database dump: {}", path.display()))?;
+                Ok(format!("Removed unreferenced database dump '{}'", filename))
+            }
+            Issue::MissingDbDump { snapshot, filename } => {
+                let mut manifest = Manifest::load(root, snapshot)?;
+                manifest.db_dump_filename = None;
+                manifest.save(root)?;
+                Ok(format!(
+                    "Cleared database dump reference from snapshot '{}' (was '{}')",
+                    snapshot, filename
+                ))
+            }
+            Issue::LeftoverTempFile { path, .. } => {
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    let _ = fs::set_permissions(path, fs::Permissions::from_mode(0o644));
+                }
+                fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove temporary file: {}", path.display()))?;
+                Ok(format!("Removed temporary file {}", path.display()))
+            }
+            Issue::MissingBlobs { snapshot, .. } => {
+                anyhow::bail!(
+                    "Missing blobs in snapshot '{}' cannot be repaired automatically; re-save or remove it",
+                    snapshot
+                )
+            }
+            Issue::WritableBlob { path } => {
+                make_read_only(path)?;
+                Ok(format!("Made blob {} read-only", path.display()))
+            }
+        }
+    }
+}
+
+/// Scan the .kibo directory for inconsistencies
+pub fn diagnose(root: &Path) -> Result<Vec<Issue>> {
+    let snapshots = list_snapshots(root)?;
+    let mut issues = Vec::new();
+
+    let db_dumps_dir = get_kibo_dir(root).join(DB_SNAPSHOTS_DIR);
+
+    for snapshot in &snapshots {
+        if let Some(ref filename) = snapshot.db_dump_filename
+            && !db_dumps_dir.join(filename).exists()
+        {
+            issues.push(Issue::MissingDbDump {
+                snapshot: snapshot.name.clone(),
+                filename: filename.clone(),
+            });
+        }
+
+        let store = Store::new(root).in_group(snapshot.store_group.as_deref());
+        let missing = snapshot
+            .files
+            .values()
+            .filter(|entry| !store.has_blob(&entry.hash))
+            .count();
+        if missing > 0 {
+            issues.push(Issue::MissingBlobs {
+                snapshot: snapshot.name.clone(),
+                count: missing,
+            });
+        }
+    }
+
+    issues.extend(
+        find_orphaned_db_dumps(root, &referenced_db_dumps(&snapshots))?
+            .into_iter()
+            .map(|dump| Issue::OrphanedDbDump { filename: dump.filename, size: dump.size }),
+    );
+
+    issues.extend(find_temp_files(root));
+
+    let groups = Store::group_names(root)?;
+    let stores = std::iter::once(None).chain(groups.iter().map(|group| Some(group.as_str())));
+    for group in stores {
+        let writable = Store::new(root).in_group(group).writable_blobs()?;
+        issues.extend(writable.into_iter().map(|path| Issue::WritableBlob { path }));
+    }
+
+    Ok(issues)
+}
+
+/// Find temporary files left by interrupted blob copies (`*.tmp` in the store)
+/// and atomic writes (`.kibo_tmp_*` anywhere in .kibo)
+fn find_temp_files(root: &Path) -> Vec<Issue> {
+    let store_dir = get_store_dir(root);
+
+    WalkDir::new(get_kibo_dir(root))
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy();
+            name.starts_with(".kibo_tmp_")
+                || (e.path().starts_with(&store_dir) && name.ends_with(".tmp"))
+        })
+        .map(|e| Issue::LeftoverTempFile {
+            path: e.path().to_path_buf(),
+            size: e.metadata().map(|m| m.len()).unwrap_or(0),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::FileEntry;
+    use tempfile::TempDir;
+
+    fn db_dumps_dir(root: &Path) -> PathBuf {
+        let dir = get_kibo_dir(root).join(DB_SNAPSHOTS_DIR);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_diagnose_clean() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        Manifest::new("snap".to_string()).save(root).unwrap();
+
+        assert!(diagnose(root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_find_orphaned_db_dumps() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        assert!(find_orphaned_db_dumps(root, &HashSet::new()).unwrap().is_empty());
+
+        let dir = db_dumps_dir(root);
+        for name in ["kept.sql", "old-b.sql", "old-a.sql", "notes.txt"] {
+            fs::write(dir.join(name), b"-- dump").unwrap();
+        }
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.db_dump_filename = Some("kept.sql".to_string());
+
+        let orphans = find_orphaned_db_dumps(root, &referenced_db_dumps(&[manifest])).unwrap();
+        let names: Vec<&str> = orphans.iter().map(|dump| dump.filename.as_str()).collect();
+        assert_eq!(names, vec!["old-a.sql", "old-b.sql"]);
+        assert_eq!(orphans[0].path, dir.join("old-a.sql"));
+        assert_eq!(orphans[0].size, 7);
+    }
+
+    #[test]
+    fn test_orphaned_db_dump() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let dump = db_dumps_dir(root).join("old-mydb.sql");
+        fs::write(&dump, b"-- dump").unwrap();
+
+        let issues = diagnose(root).unwrap();
+        assert_eq!(issues, vec![Issue::OrphanedDbDump { filename: "old-mydb.sql".to_string(), size: 7 }]);
+
+        issues[0].fix(root).unwrap();
+        assert!(!dump.exists());
+    }
+
+    #[test]
+    fn test_missing_db_dump() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.db_dump_filename = Some("snap-mydb.sql".to_string());
+        manifest.save(root).unwrap();
+
+        let issues = diagnose(root).unwrap();
+        assert_eq!(issues.len(), 1);
+        assert!(matches!(issues[0], Issue::MissingDbDump { .. }));
+
+        let message = issues[0].fix(root).unwrap();
+        assert!(message.contains("snap-mydb.sql"));
+        assert!(Manifest::load(root, "snap").unwrap().db_dump_filename.is_none());
+        assert!(diagnose(root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_leftover_temp_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        let blob_tmp = get_store_dir(root).join("ab").join("cdef.tmp");
+        fs::create_dir_all(blob_tmp.parent().unwrap()).unwrap();
+        fs::write(&blob_tmp, b"partial").unwrap();
+
+        let atomic_tmp = get_kibo_dir(root).join("manifests").join(".kibo_tmp_1_2");
+        fs::create_dir_all(atomic_tmp.parent().unwrap()).unwrap();
+        fs::write(&atomic_tmp, b"{").unwrap();
+
+        let issues = diagnose(root).unwrap();
+        assert_eq!(issues.len(), 2);
+
+        for issue in &issues {
+            assert!(issue.is_fixable());
+            issue.fix(root).unwrap();
+        }
+        assert!(!blob_tmp.exists());
+        assert!(!atomic_tmp.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_writable_blob() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let blob = root.join("blob.txt");
+        fs::write(&blob, b"content").unwrap();
+
+        let store = Store::new(root).in_group(Some("debug"));
+        store.init().unwrap();
+        store.store_file(&blob, "ab12").unwrap();
+        let blob_path = store.blob_path("ab12");
+        fs::set_permissions(&blob_path, fs::Permissions::from_mode(0o664)).unwrap();
+
+        let issues = diagnose(root).unwrap();
+        assert_eq!(issues, vec![Issue::WritableBlob { path: blob_path.clone() }]);
+        assert!(issues[0].is_fixable());
+        issues[0].fix(root).unwrap();
+        assert!(diagnose(root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_missing_blobs_not_fixable() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.add_file("build/a.o".to_string(), FileEntry {
+            hash: "deadbeef".to_string(),
+            size: 4,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
+        });
+        manifest.save(root).unwrap();
+
+        let issues = diagnose(root).unwrap();
+        assert_eq!(issues, vec![Issue::MissingBlobs { snapshot: "snap".to_string(), count: 1 }]);
+        assert!(!issues[0].is_fixable());
+        assert!(issues[0].fix(root).is_err());
+    }
+}
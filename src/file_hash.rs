@@ -6,16 +6,21 @@ use std::io::{BufReader, Read};
 use std::path::Path;
 use std::time::SystemTime;
 
-use crate::config::get_hash_cache_path;
+use crate::config::{MtimePrecision, get_hash_cache_path};
 use crate::fs_utils;
 
 /// Buffer size for reading files during hashing (64KB)
 const HASH_BUFFER_SIZE: usize = 64 * 1024;
+/// Only the first few KB are sampled when deciding if a file is text
+pub(crate) const TEXT_PROBE_SIZE: usize = 8 * 1024;
 
 /// Hash cache to avoid re-hashing unchanged files
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct HashCache {
     entries: HashMap<String, CacheEntry>,
+    /// With second precision, mtimes that differ only below the second still match
+    #[serde(skip)]
+    mtime_precision: MtimePrecision,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +29,9 @@ struct CacheEntry {
     mtime_secs: i64,
     mtime_nanos: u32,
     hash: String,
+    /// Whether the file looked like text, once a save has sampled it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    is_text: Option<bool>,
 }
 
 impl HashCache {
@@ -31,9 +39,16 @@ impl HashCache {
     pub fn new() -> Self {
         Self {
             entries: HashMap::new(),
+            mtime_precision: MtimePrecision::Full,
         }
     }
 
+    /// Compare mtimes at the given precision
+    pub fn with_mtime_precision(mut self, mtime_precision: MtimePrecision) -> Self {
+        self.mtime_precision = mtime_precision;
+        self
+    }
+
     /// Load the hash cache from disk
     pub fn load(root: &Path) -> Result<Self> {
         let cache_path = get_hash_cache_path(root);
@@ -70,20 +85,23 @@ impl HashCache {
 
     /// Look up a cached hash if the file hasn't changed
     pub fn get(&self, path: &Path, size: u64, mtime: SystemTime) -> Option<String> {
+        self.lookup(path, size, mtime).map(|entry| entry.hash.clone())
+    }
+
+    /// Look up whether an unchanged file looked like text, if that was recorded
+    pub fn get_text(&self, path: &Path, size: u64, mtime: SystemTime) -> Option<bool> {
+        self.lookup(path, size, mtime).and_then(|entry| entry.is_text)
+    }
+
+    fn lookup(&self, path: &Path, size: u64, mtime: SystemTime) -> Option<&CacheEntry> {
         let path_str = path.to_string_lossy().to_string();
-        
-        if let Some(entry) = self.entries.get(&path_str) {
-            let (mtime_secs, mtime_nanos) = system_time_to_secs_nanos(mtime);
-            
-            if entry.size == size 
-                && entry.mtime_secs == mtime_secs 
-                && entry.mtime_nanos == mtime_nanos 
-            {
-                return Some(entry.hash.clone());
-            }
-        }
-        
-        None
+        let entry = self.entries.get(&path_str)?;
+        let (mtime_secs, mtime_nanos) = system_time_to_secs_nanos(mtime);
+
+        let matches = entry.size == size
+            && entry.mtime_secs == mtime_secs
+            && self.mtime_precision.nanos(entry.mtime_nanos) == self.mtime_precision.nanos(mtime_nanos);
+        matches.then_some(entry)
     }
 
     /// Insert a new hash into the cache
@@ -96,8 +114,16 @@ impl HashCache {
             mtime_secs,
             mtime_nanos,
             hash,
+            is_text: None,
         });
     }
+
+    /// Record whether a cached file looked like text
+    pub fn set_text(&mut self, path: &Path, is_text: bool) {
+        if let Some(entry) = self.entries.get_mut(path.to_string_lossy().as_ref()) {
+            entry.is_text = Some(is_text);
+        }
+    }
 }
 
 /// Convert SystemTime to seconds and nanoseconds since UNIX_EPOCH
@@ -114,12 +140,18 @@ fn system_time_to_secs_nanos(time: SystemTime) -> (i64, u32) {
 
 /// Compute the blake3 hash of a file
 pub fn hash_file(path: &Path) -> Result<String> {
+    hash_file_and_probe(path).map(|(hash, _)| hash)
+}
+
+/// Compute the blake3 hash of a file and whether it looks like text, in one read
+pub fn hash_file_and_probe(path: &Path) -> Result<(String, bool)> {
     let file = File::open(path)
         .with_context(|| format!("Failed to open file for hashing: {}", path.display()))?;
     
     let mut reader = BufReader::with_capacity(HASH_BUFFER_SIZE, file);
     let mut hasher = blake3::Hasher::new();
     let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+    let mut sample = Vec::new();
 
     loop {
         let bytes_read = reader.read(&mut buffer)
@@ -130,9 +162,45 @@ pub fn hash_file(path: &Path) -> Result<String> {
         }
         
         hasher.update(&buffer[..bytes_read]);
+        let wanted = TEXT_PROBE_SIZE.saturating_sub(sample.len());
+        sample.extend_from_slice(&buffer[..bytes_read.min(wanted)]);
     }
 
-    Ok(hasher.finalize().to_hex().to_string())
+    Ok((hasher.finalize().to_hex().to_string(), is_text(&sample)))
+}
+
+/// Derive a hash from a file's path, size and mtime without reading its content.
+/// Used for files configured as assume-unchanged; the result is not a content hash.
+pub fn hash_assumed(relative_path: &str, size: u64, mtime_secs: i64, mtime_nanos: u32) -> String {
+    let key = format!("assumed\0{}\0{}\0{}.{}", relative_path, size, mtime_secs, mtime_nanos);
+    blake3::hash(key.as_bytes()).to_hex().to_string()
+}
+
+/// Heuristic text detection: a file is text if its start contains no null bytes and is
+/// valid UTF-8. A multi-byte character cut off at the end of the sample is allowed.
+pub fn is_text(data: &[u8]) -> bool {
+    let sample = &data[..data.len().min(TEXT_PROBE_SIZE)];
+    if sample.contains(&0) {
+        return false;
+    }
+
+    match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// Sample the start of a file to decide if it is text
+pub fn file_is_text(path: &Path) -> Result<bool> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open file: {}", path.display()))?;
+
+    let mut sample = Vec::with_capacity(TEXT_PROBE_SIZE);
+    file.take(TEXT_PROBE_SIZE as u64)
+        .read_to_end(&mut sample)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+
+    Ok(is_text(&sample))
 }
 
 /// Hash the content of a symlink (the target path, not the file it points to)
@@ -140,10 +208,12 @@ pub fn hash_symlink(path: &Path) -> Result<String> {
     let target = fs::read_link(path)
         .with_context(|| format!("Failed to read symlink: {}", path.display()))?;
     
-    let target_str = target.to_string_lossy();
-    let hash = blake3::hash(target_str.as_bytes());
-    
-    Ok(hash.to_hex().to_string())
+    Ok(hash_symlink_target(&target.to_string_lossy()))
+}
+
+/// Hash a symlink target string, as stored for a symlink entry
+pub fn hash_symlink_target(target: &str) -> String {
+    blake3::hash(target.as_bytes()).to_hex().to_string()
 }
 
 #[cfg(test)]
@@ -209,6 +279,22 @@ mod tests {
         assert_eq!(retrieved, None);
     }
 
+    #[test]
+    fn test_hash_cache_second_precision() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("test.txt");
+        let mtime = UNIX_EPOCH + Duration::from_secs(1234567890);
+        let copied = mtime + Duration::from_nanos(500);
+
+        let mut cache = HashCache::new();
+        cache.insert(&file_path, 100, mtime, "abc123".to_string());
+        assert_eq!(cache.get(&file_path, 100, copied), None);
+
+        let cache = cache.with_mtime_precision(MtimePrecision::Second);
+        assert_eq!(cache.get(&file_path, 100, copied), Some("abc123".to_string()));
+        assert_eq!(cache.get(&file_path, 100, mtime + Duration::from_secs(1)), None);
+    }
+
     #[test]
     fn test_hash_cache_get_miss_no_entry() {
         let cache = HashCache::new();
@@ -396,6 +482,44 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_is_text() {
+        assert!(is_text(b"key = value\n"));
+        assert!(is_text(b""));
+        assert!(is_text("caf\u{e9}\n".as_bytes()));
+        assert!(!is_text(b"\x7fELF\x00\x01"));
+        assert!(!is_text(b"caf\xe9\n"));
+
+        // A multi-byte character split by the sample boundary is still text
+        let mut data = "a".repeat(TEXT_PROBE_SIZE - 1).into_bytes();
+        data.extend_from_slice("\u{e9}".as_bytes());
+        assert!(is_text(&data));
+    }
+
+    #[test]
+    fn test_file_is_text() {
+        let temp_dir = TempDir::new().unwrap();
+        let text = temp_dir.path().join("notes.txt");
+        fs::write(&text, b"hello\n").unwrap();
+        let binary = temp_dir.path().join("app.bin");
+        fs::write(&binary, b"\x00\x01\x02").unwrap();
+
+        assert!(file_is_text(&text).unwrap());
+        assert!(!file_is_text(&binary).unwrap());
+        assert_eq!(hash_file_and_probe(&text).unwrap(), (hash_file(&text).unwrap(), true));
+        assert!(!hash_file_and_probe(&binary).unwrap().1);
+    }
+
+    #[test]
+    fn test_hash_assumed_depends_on_metadata() {
+        let hash = hash_assumed("build/big.iso", 100, 1000, 5);
+
+        assert_eq!(hash, hash_assumed("build/big.iso", 100, 1000, 5));
+        assert_ne!(hash, hash_assumed("build/other.iso", 100, 1000, 5));
+        assert_ne!(hash, hash_assumed("build/big.iso", 101, 1000, 5));
+        assert_ne!(hash, hash_assumed("build/big.iso", 100, 1001, 5));
+    }
+
     #[test]
     fn test_hash_cache_update_entry() {
         let mut cache = HashCache::new();
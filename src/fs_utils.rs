@@ -1,7 +1,9 @@
 use anyhow::{Context, Result};
+use std::collections::BTreeSet;
 use std::fs::{self, File, Permissions};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
 
 #[cfg(unix)]
 use std::os::unix::fs::{PermissionsExt};
@@ -113,6 +115,231 @@ pub fn set_file_mtime(path: &Path, mtime_secs: i64, mtime_nanos: u32) -> Result<
     Ok(())
 }
 
+/// Get the id of the device holding a path, without following symlinks
+#[cfg(unix)]
+pub fn device_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::symlink_metadata(path).ok().map(|m| m.dev())
+}
+
+#[cfg(not(unix))]
+pub fn device_id(_path: &Path) -> Option<u64> {
+    // Filesystem boundaries are not detected on Windows
+    None
+}
+
+/// Check if a path is on the given device. Always true when no device is given or the
+/// path's device cannot be determined.
+pub fn on_device(path: &Path, device: Option<u64>) -> bool {
+    let Some(expected) = device else {
+        return true;
+    };
+    device_id(path).is_none_or(|actual| actual == expected)
+}
+
+/// Find the files matching an absolute glob pattern, such as one built from a `files`
+/// entry. The tree is walked from the pattern's literal prefix, following directory
+/// symlinks except those leading back to one of their own ancestors: a self-referential
+/// link would otherwise yield the same files again at every level, and several such links
+/// in one directory multiply the paths to walk at each level. Those links are added to
+/// `loops`. Entries for which `keep` returns false are skipped, and not descended into.
+pub fn match_files(
+    pattern: &str,
+    loops: &mut BTreeSet<PathBuf>,
+    mut keep: impl FnMut(&walkdir::DirEntry) -> bool,
+) -> Result<Vec<PathBuf>, glob::PatternError> {
+    let matcher = glob::Pattern::new(pattern)?;
+    let options = glob::MatchOptions { require_literal_separator: true, ..Default::default() };
+
+    let parts: Vec<&str> = pattern.split('/').collect();
+    let literal = parts.iter().take_while(|part| !part.contains(['*', '?', '['])).count();
+    let base = match parts[..literal].join("/") {
+        base if base.is_empty() => "/".to_string(),
+        base => base,
+    };
+    let rest = &parts[literal..];
+    let max_depth = if rest.contains(&"**") { usize::MAX } else { rest.len() };
+
+    let mut matches = Vec::new();
+    for entry in walkdir::WalkDir::new(&base).follow_links(true).max_depth(max_depth).into_iter().filter_entry(|e| keep(e)) {
+        let path = match entry {
+            Ok(entry) if entry.file_type().is_dir() => continue,
+            Ok(entry) => entry.into_path(),
+            Err(err) => match (err.loop_ancestor(), err.path()) {
+                (Some(_), Some(link)) => {
+                    loops.insert(link.to_path_buf());
+                    continue;
+                },
+                // A dangling symlink cannot be followed, but is still a match
+                (None, Some(path)) if path.symlink_metadata().is_ok_and(|m| m.is_symlink()) => path.to_path_buf(),
+                _ => continue,
+            },
+        };
+        if matcher.matches_path_with(&path, options) {
+            matches.push(path);
+        }
+    }
+    Ok(matches)
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> Result<Option<u64>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("Invalid path: {}", path.display()))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is NUL-terminated and `stat` is valid for writes of a `statvfs`
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to query free space: {}", path.display()));
+    }
+    // SAFETY: `statvfs` succeeded, so it filled in `stat`
+    let stat = unsafe { stat.assume_init() };
+    // The field types vary between platforms
+    let (blocks, block_size): (u64, u64) = (stat.f_bavail as _, stat.f_frsize as _);
+    Ok(Some(blocks.saturating_mul(block_size)))
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path: &Path) -> Result<Option<u64>> {
+    // Free space is not queried on Windows
+    Ok(None)
+}
+
+/// Check if a path (or a symlink itself) was last modified before `cutoff`. False when
+/// the modification time cannot be read.
+pub fn modified_before(path: &Path, cutoff: SystemTime) -> bool {
+    fs::symlink_metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|mtime| mtime < cutoff)
+}
+
+/// Check if a walk entry below the walk's root is hidden, i.e. its name starts with `.`.
+/// The root itself never counts, so a tracked directory such as `.build` is still walked.
+pub fn is_hidden_entry(entry: &walkdir::DirEntry) -> bool {
+    entry.depth() > 0 && entry.file_name().as_encoded_bytes().starts_with(b".")
+}
+
+/// Check if a path is a regular file with no content (symlinks are never empty files)
+pub fn is_empty_file(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.len() == 0)
+}
+
+/// Express `path` relative to `base` without touching the filesystem, using `..` for the
+/// parts of `base` that `path` is not below. Both paths are expected to be absolute.
+pub fn relative_to(base: &Path, path: &Path) -> PathBuf {
+    if let Ok(relative) = path.strip_prefix(base) {
+        return relative.to_path_buf();
+    }
+
+    let base_components: Vec<Component> = base.components().collect();
+    let path_components: Vec<Component> = path.components().collect();
+    let common = base_components
+        .iter()
+        .zip(&path_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base_components.len() {
+        relative.push("..");
+    }
+    for component in &path_components[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+/// Join a relative path onto `base`, folding `..` components without touching the filesystem
+pub fn resolve_relative(base: &Path, relative_path: &str) -> PathBuf {
+    let mut resolved = base.to_path_buf();
+    for component in Path::new(relative_path).components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => {}
+            other => resolved.push(other),
+        }
+    }
+    resolved
+}
+
+/// Compile glob patterns, failing on the first invalid one
+pub fn compile_patterns(patterns: &[String]) -> Result<Vec<glob::Pattern>> {
+    patterns
+        .iter()
+        .map(|p| glob::Pattern::new(p).with_context(|| format!("Invalid pattern: {}", p)))
+        .collect()
+}
+
+/// Check if a relative path matches any of the patterns. Patterns containing a '/' match
+/// the whole relative path; other patterns match the file name anywhere in the tree.
+pub fn matches_any_pattern(relative_path: &Path, patterns: &[glob::Pattern]) -> bool {
+    let path_str = relative_path.to_string_lossy();
+    let file_name = relative_path.file_name().map(|n| n.to_string_lossy());
+
+    patterns.iter().any(|pattern| {
+        if pattern.as_str().contains('/') {
+            pattern.matches(&path_str)
+        }
+        else {
+            file_name.as_ref().is_some_and(|name| pattern.matches(name))
+        }
+    })
+}
+
+/// Move a file or symlink, creating the destination's parent directories. Falls back
+/// to copy and remove when the destination is on another filesystem.
+pub fn move_file(src: &Path, dst: &Path) -> Result<()> {
+    if let Some(parent) = dst.parent() {
+        ensure_dir(parent)?;
+    }
+
+    match fs::rename(src, dst) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to move {} to {}", src.display(), dst.display()));
+        }
+    }
+
+    if src.is_symlink() {
+        let target = fs::read_link(src)
+            .with_context(|| format!("Failed to read symlink: {}", src.display()))?;
+        create_symlink(&target, dst)?;
+    }
+    else {
+        fs::copy(src, dst)
+            .with_context(|| format!("Failed to copy {} to {}", src.display(), dst.display()))?;
+    }
+
+    fs::remove_file(src)
+        .with_context(|| format!("Failed to remove {} after copying it", src.display()))?;
+    Ok(())
+}
+
+/// Flush a file's data to disk
+pub fn sync_file(path: &Path) -> Result<()> {
+    File::open(path)
+        .and_then(|file| file.sync_all())
+        .with_context(|| format!("Failed to sync file: {}", path.display()))
+}
+
+/// Flush a directory's entries to disk, so files created or renamed in it survive a crash.
+/// Directories cannot be opened for syncing on Windows, where this does nothing.
+pub fn sync_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    File::open(path)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync directory: {}", path.display()))?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
 
 /// Ensure a directory exists, creating it if necessary
 pub fn ensure_dir(path: &Path) -> Result<()> {
@@ -129,6 +356,32 @@ mod tests {
     use std::fs;
     use tempfile::TempDir;
 
+    #[test]
+    fn test_relative_to_and_resolve_relative() {
+        let root = Path::new("/work/repo");
+
+        assert_eq!(relative_to(root, Path::new("/work/repo/build/a.o")), PathBuf::from("build/a.o"));
+        assert_eq!(relative_to(root, Path::new("/work/out/a.o")), PathBuf::from("../out/a.o"));
+        assert_eq!(relative_to(root, Path::new("/opt/cache")), PathBuf::from("../../opt/cache"));
+
+        assert_eq!(resolve_relative(root, "build/a.o"), PathBuf::from("/work/repo/build/a.o"));
+        assert_eq!(resolve_relative(root, "../out/a.o"), PathBuf::from("/work/out/a.o"));
+        assert_eq!(resolve_relative(root, "../../opt/cache"), PathBuf::from("/opt/cache"));
+    }
+
+    #[test]
+    fn test_move_file_creates_parent_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("stale.o");
+        let dst = temp_dir.path().join("trash/build/stale.o");
+        fs::write(&src, b"stale").unwrap();
+
+        move_file(&src, &dst).unwrap();
+
+        assert!(!src.exists());
+        assert_eq!(fs::read(&dst).unwrap(), b"stale");
+    }
+
     #[test]
     fn test_atomic_write_creates_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -376,4 +629,78 @@ mod tests {
             assert!(file_path.exists(), "File for thread {} doesn't exist", i);
         }
     }
+
+    #[test]
+    fn test_matches_any_pattern() {
+        let patterns = compile_patterns(&["*.cfg".to_string(), "build/f1".to_string()]).unwrap();
+
+        assert!(matches_any_pattern(Path::new("build/sub/local.cfg"), &patterns));
+        assert!(matches_any_pattern(Path::new("build/f1"), &patterns));
+        assert!(!matches_any_pattern(Path::new("build/f10"), &patterns));
+        assert!(!matches_any_pattern(Path::new("other/f1"), &patterns));
+    }
+
+    #[test]
+    fn test_compile_patterns_invalid() {
+        assert!(compile_patterns(&["[".to_string()]).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_on_device() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("file.txt");
+        fs::write(&file, b"data").unwrap();
+
+        let device = device_id(temp_dir.path());
+        assert!(device.is_some());
+        assert!(on_device(&file, device));
+        assert!(!on_device(&file, device.map(|d| d.wrapping_add(1))));
+        assert!(on_device(&file, None));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_available_space() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(available_space(temp_dir.path()).unwrap().is_some_and(|bytes| bytes > 0));
+        assert!(available_space(&temp_dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_match_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let sub = root.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        fs::create_dir_all(root.join("elsewhere")).unwrap();
+        fs::write(root.join("top.cfg"), b"").unwrap();
+        fs::write(sub.join("a.cfg"), b"").unwrap();
+        fs::write(sub.join("a.txt"), b"").unwrap();
+        fs::write(root.join("elsewhere/b.cfg"), b"").unwrap();
+        std::os::unix::fs::symlink("..", sub.join("up")).unwrap();
+        std::os::unix::fs::symlink("..", sub.join("up2")).unwrap();
+        std::os::unix::fs::symlink("../elsewhere", sub.join("link")).unwrap();
+
+        let mut loops = BTreeSet::new();
+        let mut found = match_files(&format!("{}/**/*.cfg", root.display()), &mut loops, |_| true).unwrap();
+        found.sort();
+        assert_eq!(found, vec![
+            root.join("elsewhere/b.cfg"),
+            sub.join("a.cfg"),
+            sub.join("link/b.cfg"),
+            root.join("top.cfg"),
+        ]);
+        assert_eq!(loops.into_iter().collect::<Vec<_>>(), vec![sub.join("up"), sub.join("up2")]);
+
+        let mut loops = BTreeSet::new();
+        let found = match_files(&format!("{}/sub/*.txt", root.display()), &mut loops, |_| true).unwrap();
+        assert_eq!(found, vec![sub.join("a.txt")]);
+        let found = match_files(&format!("{}/*.cfg", root.display()), &mut loops, |_| true).unwrap();
+        assert_eq!(found, vec![root.join("top.cfg")]);
+        let found = match_files(&format!("{}/**/*.cfg", root.display()), &mut loops, |e| e.file_name() != "sub").unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(match_files("[", &mut loops, |_| true).is_err());
+    }
 }
\ No newline at end of file
@@ -1,5 +1,5 @@
 use anyhow::{Context, Result};
-use chrono::Utc;
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
@@ -18,6 +18,24 @@ pub struct HistoryEntry {
     pub snapshot: Option<String>,
     /// Flags used (e.g., "--include-db", "--yes")
     pub flags: Vec<String>,
+    /// User who ran the command, if known (not recorded by older versions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// Flag-style token under which `to_line` records the user, so older logs still parse
+const USER_TOKEN: &str = "--user=";
+
+/// Syslog priority of exported entries: facility user (1), severity notice (5)
+const SYSLOG_PRIORITY: u8 = 13;
+
+/// Structured formats `kibo history export` writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// One JSON object per line
+    Jsonl,
+    /// RFC 5424 syslog messages
+    Syslog,
 }
 
 impl HistoryEntry {
@@ -29,6 +47,7 @@ impl HistoryEntry {
             command: command.to_uppercase(),
             snapshot: snapshot.map(|s| s.to_string()),
             flags,
+            user: current_user(),
         }
     }
 
@@ -43,6 +62,10 @@ impl HistoryEntry {
         for flag in &self.flags {
             parts.push(flag.clone());
         }
+
+        if let Some(ref user) = self.user {
+            parts.push(format!("{}{}", USER_TOKEN, user));
+        }
         
         parts.join(" ")
     }
@@ -64,16 +87,69 @@ impl HistoryEntry {
             (None, 2)
         };
 
-        let flags = parts[flags_start..].iter().map(|s| s.to_string()).collect();
+        let mut flags: Vec<String> = parts[flags_start..].iter().map(|s| s.to_string()).collect();
+        let user = match flags.last().and_then(|flag| flag.strip_prefix(USER_TOKEN)) {
+            Some(user) => {
+                let user = user.to_string();
+                flags.pop();
+                Some(user)
+            },
+            None => None,
+        };
 
         Some(Self {
             timestamp,
             command,
             snapshot,
             flags,
+            user,
         })
     }
 
+    /// Parse the entry timestamp, if it is valid RFC 3339
+    pub fn parsed_timestamp(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(&self.timestamp)
+            .ok()
+            .map(|t| t.with_timezone(&Utc))
+    }
+
+    /// Format entry as an RFC 5424 syslog message, with the snapshot, flags and user as
+    /// structured data
+    pub fn to_syslog(&self, hostname: &str) -> String {
+        // RFC 5424 allows at most microseconds
+        let timestamp = self
+            .parsed_timestamp()
+            .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Micros, true))
+            .unwrap_or_else(|| "-".to_string());
+
+        let mut data = String::from("[kibo@32473");
+        let params = [
+            ("snapshot", self.snapshot.clone()),
+            ("flags", (!self.flags.is_empty()).then(|| self.flags.join(" "))),
+            ("user", self.user.clone()),
+        ];
+        for (name, value) in params {
+            if let Some(value) = value {
+                data.push_str(&format!(" {}=\"{}\"", name, escape_param_value(&value)));
+            }
+        }
+        data.push(']');
+
+        let mut message = vec![self.command.clone()];
+        message.extend(self.snapshot.clone());
+        message.extend(self.flags.iter().cloned());
+
+        format!(
+            "<{}>1 {} {} kibo - {} {} {}",
+            SYSLOG_PRIORITY,
+            timestamp,
+            hostname,
+            self.command,
+            data,
+            message.join(" ")
+        )
+    }
+
     /// Format entry for display
     pub fn display(&self) -> String {
         let mut result = format!("{} {:8}", self.timestamp, self.command);
@@ -93,6 +169,67 @@ impl HistoryEntry {
     }
 }
 
+/// Escape the characters RFC 5424 reserves inside structured data values
+fn escape_param_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Name of the user running kibo, from `$USER` or `$USERNAME`, falling back to the numeric
+/// user ID. Whitespace is replaced so the name stays one token in the log.
+fn current_user() -> Option<String> {
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|user| !user.is_empty());
+    #[cfg(unix)]
+    // SAFETY: `getuid` has no preconditions and cannot fail
+    let user = user.or_else(|| Some(unsafe { libc::getuid() }.to_string()));
+    user.map(|user| user.split_whitespace().collect::<Vec<_>>().join("_"))
+}
+
+/// Name of this machine for syslog messages, or `-` (the syslog nil value) if unknown
+fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buffer = [0u8; 256];
+        // SAFETY: the buffer is valid for writes of its length
+        if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } == 0 {
+            let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+            if let Ok(name) = std::str::from_utf8(&buffer[..end])
+                && !name.is_empty()
+            {
+                return name.to_string();
+            }
+        }
+    }
+    std::env::var("COMPUTERNAME")
+        .ok()
+        .filter(|name| !name.is_empty() && name.is_ascii() && !name.contains(' '))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Render history entries in a structured format, one entry per line
+pub fn export_history(entries: &[HistoryEntry], format: ExportFormat) -> Result<String> {
+    let hostname = hostname();
+    let mut output = String::new();
+    for entry in entries {
+        let line = match format {
+            ExportFormat::Jsonl => serde_json::to_string(entry)?,
+            ExportFormat::Syslog => entry.to_syslog(&hostname),
+        };
+        output.push_str(&line);
+        output.push('\n');
+    }
+    Ok(output)
+}
+
 /// Log a history entry to the history file
 pub fn log_entry(root: &Path, entry: &HistoryEntry) {
     let history_path = root.join(config::KIBO_DIR).join(config::HISTORY_LOG_FILE);
@@ -154,6 +291,144 @@ pub fn filter_by_snapshot(entries: Vec<HistoryEntry>, snapshot: &str) -> Vec<His
         .collect()
 }
 
+/// Filter history entries by command name (case-insensitive)
+pub fn filter_by_command(entries: Vec<HistoryEntry>, command: &str) -> Vec<HistoryEntry> {
+    let command = command.to_uppercase();
+    entries
+        .into_iter()
+        .filter(|e| e.command == command)
+        .collect()
+}
+
+/// Filter history entries to those logged within an inclusive time range
+pub fn filter_by_time_range(
+    entries: Vec<HistoryEntry>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+) -> Vec<HistoryEntry> {
+    entries
+        .into_iter()
+        .filter(|e| {
+            let Some(timestamp) = e.parsed_timestamp() else {
+                return false;
+            };
+            since.is_none_or(|s| timestamp >= s) && until.is_none_or(|u| timestamp <= u)
+        })
+        .collect()
+}
+
+/// Parse a user-supplied time bound, accepting RFC 3339 or a plain YYYY-MM-DD date (midnight UTC)
+pub fn parse_time_bound(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .with_context(|| format!("Invalid time '{}': expected RFC 3339 or YYYY-MM-DD", value))?;
+
+    Ok(date.and_hms_opt(0, 0, 0).expect("midnight is a valid time").and_utc())
+}
+
+/// A snapshot removed with `kibo rm`, reconstructed from the history log
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeletedSnapshot {
+    pub name: String,
+    /// Time of the most recent removal
+    pub deleted_at: DateTime<Utc>,
+    /// Whether a snapshot with the same name was saved after the removal
+    pub resaved: bool,
+}
+
+/// Find removed snapshots in the history, newest removal first
+pub fn deleted_snapshots(entries: Vec<HistoryEntry>) -> Vec<DeletedSnapshot> {
+    let saves = filter_by_command(entries.clone(), "SAVE");
+    let mut deleted: Vec<DeletedSnapshot> = Vec::new();
+
+    for entry in filter_by_command(entries, "RM") {
+        let (Some(name), Some(deleted_at)) = (entry.snapshot.as_ref(), entry.parsed_timestamp()) else {
+            continue;
+        };
+
+        match deleted.iter_mut().find(|d| &d.name == name) {
+            Some(existing) if existing.deleted_at >= deleted_at => {}
+            Some(existing) => existing.deleted_at = deleted_at,
+            None => deleted.push(DeletedSnapshot {
+                name: name.clone(),
+                deleted_at,
+                resaved: false,
+            }),
+        }
+    }
+
+    for snapshot in &mut deleted {
+        snapshot.resaved = saves.iter().any(|save| {
+            save.snapshot.as_deref() == Some(snapshot.name.as_str())
+                && save.parsed_timestamp().is_some_and(|t| t > snapshot.deleted_at)
+        });
+    }
+
+    deleted.sort_by_key(|d| std::cmp::Reverse(d.deleted_at));
+    deleted
+}
+
+/// A value and how many history entries it appeared in
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Tally {
+    pub name: String,
+    pub count: usize,
+}
+
+/// Aggregate view of the history log
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryStats {
+    pub total: usize,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    /// Entries per command, most frequent first
+    pub commands: Vec<Tally>,
+    /// Snapshot names saved most often
+    pub top_snapshots: Vec<Tally>,
+    /// Days (UTC) with the most entries
+    pub busiest_days: Vec<Tally>,
+}
+
+/// Count occurrences, most frequent first and ties by name, keeping at most `limit`
+fn tally(values: impl Iterator<Item = String>, limit: usize) -> Vec<Tally> {
+    let mut counts: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+
+    let mut tallies: Vec<Tally> = counts
+        .into_iter()
+        .map(|(name, count)| Tally { name, count })
+        .collect();
+    tallies.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    tallies.truncate(limit);
+    tallies
+}
+
+/// Summarize the history: counts per command, the most saved snapshots and the busiest
+/// days, listing at most `top` snapshots and days
+pub fn history_stats(entries: &[HistoryEntry], top: usize) -> HistoryStats {
+    let timestamps: Vec<DateTime<Utc>> = entries.iter().filter_map(HistoryEntry::parsed_timestamp).collect();
+
+    HistoryStats {
+        total: entries.len(),
+        first: timestamps.iter().min().copied(),
+        last: timestamps.iter().max().copied(),
+        commands: tally(entries.iter().map(|e| e.command.clone()), usize::MAX),
+        top_snapshots: tally(
+            filter_by_command(entries.to_vec(), "SAVE").into_iter().filter_map(|e| e.snapshot),
+            top,
+        ),
+        busiest_days: tally(
+            timestamps.iter().map(|t| t.date_naive().format("%Y-%m-%d").to_string()),
+            top,
+        ),
+    }
+}
+
 /// Get last N entries
 pub fn take_last(entries: Vec<HistoryEntry>, n: usize) -> Vec<HistoryEntry> {
     let len = entries.len();
@@ -196,12 +471,72 @@ mod tests {
             command: "SAVE".to_string(),
             snapshot: Some("snapshot1".to_string()),
             flags: vec!["--include-db".to_string()],
+            user: None,
         };
         
         let line = entry.to_line();
         assert_eq!(line, "2026-01-01T12:00:00Z SAVE snapshot1 --include-db");
     }
 
+    #[test]
+    fn test_history_entry_user_round_trips() {
+        let entry = HistoryEntry {
+            timestamp: "2026-01-01T12:00:00Z".to_string(),
+            command: "PRUNE".to_string(),
+            snapshot: None,
+            flags: vec!["--expire".to_string()],
+            user: Some("alice".to_string()),
+        };
+
+        let line = entry.to_line();
+        assert_eq!(line, "2026-01-01T12:00:00Z PRUNE --expire --user=alice");
+        let parsed = HistoryEntry::from_line(&line).unwrap();
+        assert_eq!(parsed.snapshot, None);
+        assert_eq!(parsed.flags, vec!["--expire"]);
+        assert_eq!(parsed.user.as_deref(), Some("alice"));
+
+        // Lines written before users were recorded have none
+        let old = HistoryEntry::from_line("2026-01-01T12:00:00Z SAVE s1 --yes").unwrap();
+        assert_eq!(old.flags, vec!["--yes"]);
+        assert_eq!(old.user, None);
+    }
+
+    #[test]
+    fn test_history_entry_to_syslog() {
+        let entry = HistoryEntry {
+            timestamp: "2026-01-01T12:00:00.123456789+00:00".to_string(),
+            command: "SAVE".to_string(),
+            snapshot: Some("nightly".to_string()),
+            flags: vec!["--metadata=note=\"a]b\"".to_string()],
+            user: Some("ci".to_string()),
+        };
+
+        assert_eq!(
+            entry.to_syslog("build-01"),
+            "<13>1 2026-01-01T12:00:00.123456Z build-01 kibo - SAVE \
+             [kibo@32473 snapshot=\"nightly\" flags=\"--metadata=note=\\\"a\\]b\\\"\" user=\"ci\"] \
+             SAVE nightly --metadata=note=\"a]b\""
+        );
+
+        let bare = HistoryEntry { snapshot: None, flags: vec![], user: None, ..entry };
+        assert!(bare.to_syslog("-").ends_with("kibo - SAVE [kibo@32473] SAVE"));
+    }
+
+    #[test]
+    fn test_export_history_jsonl() {
+        let entries = vec![
+            HistoryEntry::from_line("2026-01-01T12:00:00Z SAVE s1 --user=alice").unwrap(),
+            HistoryEntry::from_line("2026-01-02T12:00:00Z RM s1").unwrap(),
+        ];
+
+        let output = export_history(&entries, ExportFormat::Jsonl).unwrap();
+        let lines: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["user"], "alice");
+        assert_eq!(lines[1]["command"], "RM");
+        assert!(lines[1].get("user").is_none());
+    }
+
     #[test]
     fn test_history_entry_to_line_without_snapshot() {
         let entry = HistoryEntry {
@@ -209,6 +544,7 @@ mod tests {
             command: "LIST".to_string(),
             snapshot: None,
             flags: vec![],
+            user: None,
         };
         
         let line = entry.to_line();
@@ -263,6 +599,7 @@ mod tests {
             command: "SAVE".to_string(),
             snapshot: Some("snapshot1".to_string()),
             flags: vec!["--include-db".to_string()],
+            user: None,
         };
         
         let display = entry.display();
@@ -279,6 +616,7 @@ mod tests {
             command: "LIST".to_string(),
             snapshot: None,
             flags: vec![],
+            user: None,
         };
         
         let display = entry.display();
@@ -347,18 +685,21 @@ mod tests {
                 command: "SAVE".to_string(),
                 snapshot: Some("snapshot1".to_string()),
                 flags: vec![],
+                user: None,
             },
             HistoryEntry {
                 timestamp: "2026-01-01T13:00:00Z".to_string(),
                 command: "SAVE".to_string(),
                 snapshot: Some("snapshot2".to_string()),
                 flags: vec![],
+                user: None,
             },
             HistoryEntry {
                 timestamp: "2026-01-01T14:00:00Z".to_string(),
                 command: "LOAD".to_string(),
                 snapshot: Some("snapshot1".to_string()),
                 flags: vec![],
+                user: None,
             },
         ];
         
@@ -376,6 +717,7 @@ mod tests {
                 command: "SAVE".to_string(),
                 snapshot: Some("snapshot1".to_string()),
                 flags: vec![],
+                user: None,
             },
         ];
         
@@ -391,6 +733,7 @@ mod tests {
                 command: "LIST".to_string(),
                 snapshot: None,
                 flags: vec![],
+                user: None,
             },
         ];
         
@@ -398,6 +741,136 @@ mod tests {
         assert!(filtered.is_empty());
     }
 
+    #[test]
+    fn test_filter_by_command() {
+        let entries = vec![
+            HistoryEntry::new("save", Some("s1"), vec![]),
+            HistoryEntry::new("load", Some("s1"), vec![]),
+            HistoryEntry::new("save", Some("s2"), vec![]),
+        ];
+
+        let filtered = filter_by_command(entries, "save");
+        assert_eq!(filtered.len(), 2);
+        assert_eq!(filtered[1].snapshot, Some("s2".to_string()));
+    }
+
+    #[test]
+    fn test_filter_by_time_range() {
+        let entries = vec![
+            HistoryEntry {
+                timestamp: "2026-01-01T12:00:00Z".to_string(),
+                command: "SAVE".to_string(),
+                snapshot: Some("s1".to_string()),
+                flags: vec![],
+                user: None,
+            },
+            HistoryEntry {
+                timestamp: "2026-01-02T12:00:00Z".to_string(),
+                command: "SAVE".to_string(),
+                snapshot: Some("s2".to_string()),
+                flags: vec![],
+                user: None,
+            },
+            HistoryEntry {
+                timestamp: "2026-01-03T12:00:00Z".to_string(),
+                command: "SAVE".to_string(),
+                snapshot: Some("s3".to_string()),
+                flags: vec![],
+                user: None,
+            },
+        ];
+
+        let since = parse_time_bound("2026-01-02").unwrap();
+        let until = parse_time_bound("2026-01-02T23:59:59Z").unwrap();
+        let filtered = filter_by_time_range(entries, Some(since), Some(until));
+
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].snapshot, Some("s2".to_string()));
+    }
+
+    #[test]
+    fn test_parse_time_bound_invalid() {
+        assert!(parse_time_bound("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_deleted_snapshots() {
+        let entry = |timestamp: &str, command: &str, snapshot: &str| HistoryEntry {
+            timestamp: timestamp.to_string(),
+            command: command.to_string(),
+            snapshot: Some(snapshot.to_string()),
+            flags: vec![],
+            user: None,
+        };
+        let entries = vec![
+            entry("2026-01-01T12:00:00Z", "SAVE", "s1"),
+            entry("2026-01-01T13:00:00Z", "SAVE", "s2"),
+            entry("2026-01-02T12:00:00Z", "RM", "s1"),
+            entry("2026-01-03T12:00:00Z", "RM", "s2"),
+            entry("2026-01-04T12:00:00Z", "SAVE", "s1"),
+            entry("2026-01-05T12:00:00Z", "LOAD", "s2"),
+        ];
+
+        let deleted = deleted_snapshots(entries);
+
+        assert_eq!(deleted.len(), 2);
+        assert_eq!(deleted[0].name, "s2");
+        assert!(!deleted[0].resaved);
+        assert_eq!(deleted[1].name, "s1");
+        assert!(deleted[1].resaved);
+        assert_eq!(deleted[1].deleted_at, parse_time_bound("2026-01-02T12:00:00Z").unwrap());
+    }
+
+    #[test]
+    fn test_history_stats() {
+        let entry = |timestamp: &str, command: &str, snapshot: &str| HistoryEntry {
+            timestamp: timestamp.to_string(),
+            command: command.to_string(),
+            snapshot: Some(snapshot.to_string()),
+            flags: vec![],
+            user: None,
+        };
+        let entries = vec![
+            entry("2026-01-01T12:00:00Z", "SAVE", "nightly"),
+            entry("2026-01-01T13:00:00Z", "SAVE", "feature"),
+            entry("2026-01-01T14:00:00Z", "LOAD", "nightly"),
+            entry("2026-01-02T12:00:00Z", "SAVE", "nightly"),
+            entry("2026-01-03T12:00:00Z", "RM", "feature"),
+        ];
+
+        let stats = history_stats(&entries, 1);
+
+        assert_eq!(stats.total, 5);
+        assert_eq!(stats.first, Some(parse_time_bound("2026-01-01T12:00:00Z").unwrap()));
+        assert_eq!(stats.last, Some(parse_time_bound("2026-01-03T12:00:00Z").unwrap()));
+        let commands: Vec<(&str, usize)> = stats.commands.iter().map(|t| (t.name.as_str(), t.count)).collect();
+        assert_eq!(commands, vec![("SAVE", 3), ("LOAD", 1), ("RM", 1)]);
+        assert_eq!(stats.top_snapshots, vec![Tally { name: "nightly".to_string(), count: 2 }]);
+        assert_eq!(stats.busiest_days, vec![Tally { name: "2026-01-01".to_string(), count: 3 }]);
+    }
+
+    #[test]
+    fn test_deleted_snapshots_keeps_latest_removal() {
+        let entry = |timestamp: &str, command: &str| HistoryEntry {
+            timestamp: timestamp.to_string(),
+            command: command.to_string(),
+            snapshot: Some("s1".to_string()),
+            flags: vec![],
+            user: None,
+        };
+        let entries = vec![
+            entry("2026-01-01T12:00:00Z", "RM"),
+            entry("2026-01-02T12:00:00Z", "SAVE"),
+            entry("2026-01-03T12:00:00Z", "RM"),
+        ];
+
+        let deleted = deleted_snapshots(entries);
+
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].deleted_at, parse_time_bound("2026-01-03").unwrap() + chrono::Duration::hours(12));
+        assert!(!deleted[0].resaved);
+    }
+
     #[test]
     fn test_take_last_all_entries() {
         let entries = vec![
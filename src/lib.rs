@@ -1,21 +1,39 @@
 mod cli;
 mod config;
+mod dedup;
+mod diff;
+mod doctor;
 mod file_hash;
 mod fs_utils;
 mod history;
 mod manifest;
+mod pack;
 mod progress;
+mod recovery;
 mod load;
+mod load_journal;
+mod load_staging;
 mod snapshot;
 mod store;
+mod tree;
+mod unreferenced;
 
 // Re-export public APIs
-pub use cli::{Cli, Commands};
-pub use config::{Config, DatabaseConfig, find_repo_root, CONFIG_FILENAME, KIBO_DIR, HISTORY_LOG_FILE};
+pub use cli::{Cli, Commands, ConfigCommands, HistoryCommands, SaveArgs, SnapshotCommands, StoreCommands};
+pub use config::{Config, DatabaseConfig, EmptyPolicy, MtimePrecision, SymlinkTargetMode, find_repo_root, CONFIG_FILENAME, GITIGNORE_FILE, KIBO_DIR, HISTORY_LOG_FILE, translate_gitignore, unknown_config_keys, validate_store_group};
+pub use dedup::{
+    CrossCheck, DedupReport, HashConflict, HashUse, InconsistentEntry, SharedBlob, SnapshotFootprint, cross_check,
+    dedup_report, snapshot_footprints,
+};
+pub use diff::{Change, ChangedFile, DiffReport, DiffStat, diff_manifests, read_text_blob, unified_diff};
+pub use doctor::{Issue, OrphanedDump, diagnose, find_orphaned_db_dumps, referenced_db_dumps};
 pub use file_hash::HashCache;
-pub use history::{HistoryEntry, log_entry, read_history, filter_by_snapshot, take_last};
-pub use manifest::{FileEntry, DirectoryEntry, Manifest, list_snapshots, format_size};
-pub use progress::{ProgressConfig, ByteProgress, ItemProgress, Spinner, Timer};
-pub use load::{load_snapshot, LoadStats};
-pub use snapshot::{create_snapshot};
-pub use store::Store;
+pub use history::{DeletedSnapshot, ExportFormat, HistoryEntry, export_history, HistoryStats, Tally, deleted_snapshots, history_stats, log_entry, read_history, filter_by_snapshot, filter_by_command, filter_by_time_range, parse_time_bound, take_last};
+pub use manifest::{FileEntry, DirectoryEntry, Manifest, SnapshotSummary, list_snapshots, format_size, format_lifetime, parse_metadata, parse_retention};
+pub use progress::{ProgressConfig, ProgressEvents, ByteProgress, ItemProgress, Spinner, Timer};
+pub use recovery::{parse_file_list, rebuild_manifest};
+pub use load::{check_drift, check_metadata, load_snapshot, restore_files, verify_report, verify_snapshot, verify_snapshot_contents, DriftReport, LoadOptions, LoadStats, MetadataDrift, VerifyReport};
+pub use snapshot::{create_snapshot, plan_snapshot, PlannedFile, SaveTiming, SnapshotPlan};
+pub use store::{BlobCheck, ImportedBlobs, Recompressed, SizeBucket, Store, make_read_only, size_histogram};
+pub use tree::render_tree;
+pub use unreferenced::UnreferencedBlobs;
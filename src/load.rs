@@ -1,32 +1,91 @@
 use anyhow::{Context, Result, bail};
 use glob;
 use rayon::prelude::*;
-use std::collections::{HashMap, HashSet};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use walkdir::WalkDir;
 
-use crate::file_hash;
+use crate::config::{SymlinkPolicy, get_kept_conflicts_path};
+use crate::file_hash::{self, HashCache};
 use crate::fs_utils;
-use crate::manifest::Manifest;
+use crate::load_journal::LoadJournal;
+use crate::load_staging::{self, LoadStaging};
+use crate::manifest::{IMPLIED_DIRECTORY_MODE, Manifest};
 use crate::progress::{ProgressConfig, ByteProgress};
-use crate::store::Store;
+use crate::store::{STORE_FORMAT_VERSION, Store};
+
+/// Options controlling how a snapshot is loaded
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    pub verbose: bool,
+    pub dry_run: bool,
+    /// Glob patterns for files to leave untouched (neither restored nor deleted as stale)
+    pub exclude: Vec<String>,
+    /// Move stale files into a timestamped directory below this one instead of deleting them
+    pub trash: Option<PathBuf>,
+    /// Trust the hash cache for existing files whose size and mtime match, instead of rehashing them
+    pub checksum_cache: bool,
+    /// Plan the load like a dry run, without narrating each step
+    pub report_only: bool,
+    /// Keep locally modified files by renaming them to `<path><suffix>` before they are overwritten
+    pub rename_conflicts: Option<String>,
+    /// Journal restored files, and skip the files an interrupted load already restored
+    pub resume: bool,
+    /// Rehash every blob before touching the workspace, so a corrupt store aborts the load cleanly
+    pub verify_blobs_first: bool,
+    /// Hash each restored file as it is written, and fail if it does not match the snapshot
+    pub verify: bool,
+    /// Restore into a staging directory and swap the tracked directories into place at the end
+    pub atomic: bool,
+    /// Abort before changing anything if tracked paths hold files that are not in the snapshot
+    pub fail_on_extra: bool,
+    /// Compare the workspace with the snapshot again once the load is done
+    pub report_drift: bool,
+}
+
+impl LoadOptions {
+    /// Whether nothing should be written, as in a dry run or a report-only load
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run || self.report_only
+    }
+
+    /// Whether steps are described as they are planned or performed
+    fn narrate(&self) -> bool {
+        self.verbose || self.dry_run
+    }
+}
 
 /// Load a snapshot to the tracked directories
 pub fn load_snapshot(
     root: &Path,
     name: &str,
-    verbose: bool,
-    dry_run: bool,
+    options: &LoadOptions,
     progress_config: ProgressConfig,
 ) -> Result<LoadStats> {
     use crate::progress::Timer;
     let timer = Timer::new();
+    let verbose = options.verbose;
+    let dry_run = options.is_dry_run();
     
-    let manifest = Manifest::load(root, name)?;
+    let mut manifest = Manifest::load(root, name)?;
+    check_store_format(&manifest)?;
+    check_outside_base(root, &manifest)?;
+    let atomic_roots = if options.atomic && !dry_run { Some(atomic_roots(root, &manifest)?) } else { None };
+
+    let exclude = fs_utils::compile_patterns(&options.exclude)?;
+    let excluded = apply_exclude_patterns(&mut manifest, &exclude);
+    if excluded > 0 && options.narrate() {
+        if dry_run {
+            println!("[DRY RUN] Excluding {} file(s) matching {:?}", excluded, options.exclude);
+        } else {
+            eprintln!("Excluding {} file(s) matching {:?}", excluded, options.exclude);
+        }
+    }
 
-    if dry_run {
+    if options.dry_run {
         println!("[DRY RUN] Loading snapshot '{}' ({} files, {})", name, manifest.file_count, manifest.human_size());
         println!("\nTracked directories: {:?}", manifest.tracked_directories);
         println!("Tracked file patterns: {:?}", manifest.tracked_files);
@@ -42,7 +101,7 @@ pub fn load_snapshot(
         );
     }
 
-    let store = Store::new(root);
+    let store = Store::open(root, manifest.store_group.as_deref())?;
 
     let setup_spinner = if !verbose && !dry_run {
         Some(crate::progress::Spinner::new(progress_config, "Preparing load"))
@@ -51,7 +110,7 @@ pub fn load_snapshot(
         None
     };
 
-    if verbose || dry_run {
+    if options.narrate() {
         if dry_run {
             println!("\n[DRY RUN] Verifying snapshot integrity");
         } else {
@@ -59,8 +118,15 @@ pub fn load_snapshot(
         }
     }
     verify_snapshot(&manifest, &store)?;
+    if options.verify_blobs_first {
+        if options.narrate() {
+            eprintln!("Rehashing blobs before touching the workspace");
+        }
+        verify_snapshot_contents(&manifest, &store)
+            .context("Load aborted before changing any files")?;
+    }
 
-    if verbose || dry_run {
+    if options.narrate() {
         if dry_run {
             println!("[DRY RUN] Tracked directories: {:?}", manifest.tracked_directories);
             println!("[DRY RUN] Tracked files: {:?}", manifest.tracked_files);
@@ -72,26 +138,96 @@ pub fn load_snapshot(
 
     let stats = Arc::new(Mutex::new(LoadStats::default()));
 
-    cleanup_stale_files(root, &manifest, verbose, dry_run, stats.clone())?;
+    // With `--atomic`, everything up to the swap happens in the staging directory
+    let mut staging = match atomic_roots {
+        Some(roots) => {
+            if options.narrate() {
+                eprintln!("Staging {} tracked director{}", roots.len(), if roots.len() == 1 { "y" } else { "ies" });
+            }
+            Some(LoadStaging::prepare(root, roots)?)
+        },
+        None => None,
+    };
+    let workspace = staging.as_ref().map_or(root, LoadStaging::dir);
+
+    let trash_dir = match options.trash {
+        Some(ref trash) => Some(trash_dir_for(trash, name)?),
+        None => None,
+    };
+    let kept = read_kept_conflicts(root)?;
+    cleanup_stale_files(workspace, &manifest, &exclude, &kept, trash_dir.as_deref(), options, stats.clone())?;
 
-    cleanup_empty_directories(root, &manifest, verbose, dry_run)?;
+    cleanup_empty_directories(workspace, &manifest, options)?;
     
-    restore_directories(root, &manifest, verbose, dry_run)?;
+    restore_directories(workspace, &manifest, options)?;
+    if manifest.sparse_directories {
+        restore_implied_directories(workspace, &manifest, options)?;
+    }
 
     if let Some(spinner) = setup_spinner {
         spinner.finish();
     }
 
-    load_files(root, &manifest, &store, verbose, dry_run, progress_config, stats.clone())?;
+    let hash_cache = if options.checksum_cache {
+        Some(Mutex::new(
+            HashCache::load(root)
+                .unwrap_or_else(|_| HashCache::new())
+                .with_mtime_precision(manifest.mtime_precision),
+        ))
+    }
+    else {
+        None
+    };
+
+    let journal = if options.resume && !dry_run {
+        Some(LoadJournal::open(root, &manifest)?)
+    }
+    else {
+        None
+    };
+    if let Some(ref journal) = journal {
+        let resumed = skip_restored_files(root, &mut manifest, journal);
+        if resumed > 0 {
+            eprintln!("Resuming interrupted load: skipping {} file(s) already restored", resumed);
+        }
+        let mut s = stats.lock().unwrap();
+        s.resumed = resumed;
+        s.files_loaded += resumed;
+    }
+
+    let records = LoadRecords { hash_cache: hash_cache.as_ref(), journal: journal.as_ref() };
+    load_files(workspace, &manifest, &store, options, progress_config, &records, stats.clone())?;
+    if let Some(ref mut staging) = staging {
+        if options.narrate() {
+            eprintln!("Swapping restored directories into place");
+        }
+        staging.swap_into(root)?;
+    }
+    drop(staging);
+    if !dry_run {
+        LoadJournal::remove(root)?;
+    }
 
-    let stats = Arc::try_unwrap(stats)
+    let mut stats = Arc::try_unwrap(stats)
         .expect("Stats still has references")
         .into_inner()
         .expect("Stats mutex poisoned");
+    if stats.removed > 0 {
+        stats.trash_dir = trash_dir;
+    }
+    if !dry_run {
+        record_kept_conflicts(root, &kept, &stats.renamed_files)?;
+    }
+    if options.report_drift && !dry_run {
+        // The manifest above may have lost the files a resumed load skipped
+        let mut manifest = Manifest::load(root, name)?;
+        apply_exclude_patterns(&mut manifest, &exclude);
+        stats.drift = Some(check_drift(root, &manifest, &exclude, progress_config)?);
+    }
 
     let elapsed = timer.elapsed_string();
     
-    if dry_run {
+    if options.dry_run {
         println!(
             "\n[DRY RUN] Would load: {} files ({} to copy, {} unchanged, {} symlinks, {} removed)",
             stats.files_loaded,
@@ -108,6 +244,13 @@ pub fn load_snapshot(
             }
         }
         
+        if !stats.renamed_files.is_empty() {
+            println!("\nLocal versions to keep:");
+            for file in &stats.renamed_files {
+                println!("  - {}", file);
+            }
+        }
+        
         if !stats.unchanged_files.is_empty() {
             println!("\nFiles unchanged:");
             for file in &stats.unchanged_files {
@@ -168,22 +311,247 @@ pub fn load_snapshot(
             }
         }
     }
-    else {
+    else if !options.report_only {
         println!("Load completed in {}", elapsed);
     }
 
     Ok(stats)
 }
 
-/// Clean up stale files within tracked paths
+/// Copy single files of a snapshot back into the workspace with their recorded permissions
+/// and mtimes. Unlike `load_snapshot`, no other file is touched: nothing is removed and
+/// files that were not requested keep whatever content they have. Returns the paths restored.
+pub fn restore_files(root: &Path, name: &str, paths: &[String], verbose: bool) -> Result<Vec<String>> {
+    let manifest = Manifest::load(root, name)?;
+    check_store_format(&manifest)?;
+    check_outside_base(root, &manifest)?;
+
+    let mut requested = Vec::new();
+    let mut not_found = Vec::new();
+    for path in paths {
+        let path = path.strip_prefix("./").unwrap_or(path);
+        match manifest.files.get_key_value(path) {
+            Some(file) => requested.push(file),
+            None => not_found.push(path),
+        }
+    }
+    if !not_found.is_empty() {
+        bail!("Not in snapshot '{}': {}", name, not_found.join(", "));
+    }
+
+    let store = Store::open(root, manifest.store_group.as_deref())?;
+    let missing: Vec<&str> = requested
+        .iter()
+        .filter(|(_, entry)| !store.has_blob(&entry.hash))
+        .map(|(path, _)| path.as_str())
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "Blobs missing from the store for: {}{}",
+            missing.join(", "),
+            if manifest.hash_only { " (the snapshot was saved with --hash-only)" } else { "" }
+        );
+    }
+
+    let mut restored = Vec::new();
+    for (relative_path, entry) in requested {
+        let dest_path = fs_utils::resolve_relative(root, relative_path);
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+
+        // Replace rather than write through a symlink or hard link, which would change other files
+        if dest_path.symlink_metadata().is_ok() {
+            fs::remove_file(&dest_path)
+                .with_context(|| format!("Failed to replace: {}", relative_path))?;
+        }
+        if entry.is_symlink {
+            let target_path = store.retrieve_symlink_target(&entry.hash)?;
+            fs_utils::create_symlink(&target_path, &dest_path)?;
+        }
+        else {
+            store.copy_blob_to_file(&entry.hash, &dest_path)
+                .with_context(|| format!("Failed to copy blob for: {}", relative_path))?;
+            #[cfg(unix)]
+            fs_utils::set_file_mode(&dest_path, entry.mode)?;
+            fs_utils::set_file_mtime(&dest_path, entry.mtime_secs, entry.mtime_nanos)?;
+        }
+
+        if verbose {
+            eprintln!("  Restored: {}", relative_path);
+        }
+        restored.push(relative_path.clone());
+    }
+
+    Ok(restored)
+}
+
+/// Directories that `--atomic` swaps as a whole, relative to the root: the tracked directories
+/// found in the workspace and those the snapshot's paths lie in, without nested ones
+fn atomic_roots(root: &Path, manifest: &Manifest) -> Result<Vec<PathBuf>> {
+    if !manifest.tracked_files.is_empty() {
+        bail!("--atomic only restores snapshots whose tracked paths are all directories, but this one tracks files {:?}", manifest.tracked_files);
+    }
+    if let Some(dir) = manifest.tracked_directories.iter().find(|dir| Path::new(dir).is_absolute()) {
+        bail!("--atomic cannot swap '{}', which is outside the workspace", dir);
+    }
+
+    let mut found: BTreeSet<PathBuf> = find_tracked_directory_roots(root, manifest)
+        .into_iter()
+        .filter_map(|dir| dir.strip_prefix(root).ok().map(Path::to_path_buf))
+        .collect();
+    let paths = || manifest.files.keys().chain(manifest.directories.keys()).map(Path::new);
+    for path in paths() {
+        let mut current = PathBuf::new();
+        for component in path.components() {
+            current.push(component);
+            if manifest.tracked_directories.iter().any(|dir| component.as_os_str() == dir.as_str() || current == Path::new(dir)) {
+                found.insert(current);
+                break;
+            }
+        }
+    }
+
+    let roots: Vec<PathBuf> = found
+        .iter()
+        .filter(|dir| !found.iter().any(|other| other != *dir && dir.starts_with(other)))
+        .cloned()
+        .collect();
+    load_staging::check_roots(&roots)?;
+    if let Some(path) = paths().find(|path| !roots.iter().any(|dir| path.starts_with(dir))) {
+        bail!("--atomic cannot tell which tracked directory '{}' belongs to", path.display());
+    }
+
+    Ok(roots)
+}
+
+/// Drop excluded files from the manifest so they are not restored
+/// Returns the number of manifest entries removed
+fn apply_exclude_patterns(manifest: &mut Manifest, exclude: &[glob::Pattern]) -> usize {
+    if exclude.is_empty() {
+        return 0;
+    }
+
+    let excluded: Vec<String> = manifest
+        .files
+        .keys()
+        .filter(|path| fs_utils::matches_any_pattern(Path::new(path), exclude))
+        .cloned()
+        .collect();
+
+    for path in &excluded {
+        if let Some(entry) = manifest.files.remove(path) {
+            manifest.total_size -= entry.size;
+            manifest.file_count -= 1;
+        }
+    }
+
+    excluded.len()
+}
+
+/// Drop the files an interrupted load restored from the manifest, so they are neither
+/// rehashed nor copied again. Returns the number of files dropped.
+fn skip_restored_files(root: &Path, manifest: &mut Manifest, journal: &LoadJournal) -> usize {
+    if journal.resumed() == 0 {
+        return 0;
+    }
+
+    let restored: Vec<String> = manifest
+        .files
+        .par_iter()
+        .filter(|(relative_path, entry)| journal.is_restored(root, relative_path, entry))
+        .map(|(relative_path, _)| relative_path.clone())
+        .collect();
+    for path in &restored {
+        if let Some(entry) = manifest.files.remove(path) {
+            manifest.total_size -= entry.size;
+            manifest.file_count -= 1;
+        }
+    }
+
+    restored.len()
+}
+
+/// Refuse snapshots whose blobs were written in a store format this binary does not know,
+/// rather than restoring files from blobs it would misread
+fn check_store_format(manifest: &Manifest) -> Result<()> {
+    if manifest.store_format_version > STORE_FORMAT_VERSION {
+        bail!(
+            "Snapshot '{}' was saved by kibo {} with store format version {}, but this kibo ({}) only supports up to version {}; upgrade kibo to load it",
+            manifest.name,
+            manifest.kibo_version,
+            manifest.store_format_version,
+            env!("CARGO_PKG_VERSION"),
+            STORE_FORMAT_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Files a snapshot captured outside the workspace are stored relative to the workspace root
+/// it was saved from, so they can only be put back from that same root
+fn check_outside_base(root: &Path, manifest: &Manifest) -> Result<()> {
+    let Some(ref base) = manifest.outside_base else {
+        return Ok(());
+    };
+
+    let base_path = Path::new(base);
+    if !base_path.exists() {
+        bail!(
+            "Snapshot '{}' tracks directories outside the workspace relative to {}, which no longer exists",
+            manifest.name,
+            base
+        );
+    }
+    if fs::canonicalize(base_path)? != fs::canonicalize(root)? {
+        bail!(
+            "Snapshot '{}' tracks directories outside the workspace and was saved from {}; load it from there",
+            manifest.name,
+            base
+        );
+    }
+    Ok(())
+}
+
+/// Pick a fresh trash directory for this load: `<trash>/<timestamp>-<snapshot>`
+fn trash_dir_for(trash: &Path, name: &str) -> Result<PathBuf> {
+    let trash = std::path::absolute(trash)
+        .with_context(|| format!("Invalid trash directory: {}", trash.display()))?;
+    Ok(trash.join(format!("{}-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"), name)))
+}
+
+/// Delete a stale file, or move it below the trash directory keeping its relative path
+fn remove_stale_file(file_path: &Path, relative_path: &str, trash: Option<&Path>) -> Result<()> {
+    match trash {
+        Some(trash) => {
+            // Files outside the workspace drop their `..` components so they stay inside the trash
+            let trash_path: PathBuf = Path::new(relative_path)
+                .components()
+                .filter(|component| matches!(component, std::path::Component::Normal(_)))
+                .collect();
+            fs_utils::move_file(file_path, &trash.join(trash_path))
+                .with_context(|| format!("Failed to move stale file to trash: {}", file_path.display()))
+        }
+        None => fs::remove_file(file_path)
+            .with_context(|| format!("Failed to delete stale file: {}", file_path.display())),
+    }
+}
+
+/// Clean up stale files within tracked paths, moving them to `trash` if given. With
+/// `fail_on_extra`, the load fails instead if there are any.
 fn cleanup_stale_files(
     root: &Path,
     manifest: &Manifest,
-    verbose: bool,
-    dry_run: bool,
+    exclude: &[glob::Pattern],
+    kept: &BTreeSet<String>,
+    trash: Option<&Path>,
+    options: &LoadOptions,
     stats: Arc<Mutex<LoadStats>>,
 ) -> Result<()> {
-    if verbose || dry_run {
+    let dry_run = options.is_dry_run();
+
+    if options.narrate() {
         if dry_run {
             println!("\n[DRY RUN] Would clean up stale files in tracked paths");
         } else {
@@ -191,10 +559,70 @@ fn cleanup_stale_files(
         }
     }
 
+    // Earlier loads' trash directories sit next to this one's, and are no more stale than it
+    let trash_root = trash.and_then(Path::parent);
+    let stale_files = find_stale_files(root, manifest, exclude, kept, trash_root, options.verbose)?;
+    if options.fail_on_extra && !stale_files.is_empty() {
+        let mut extra_files: Vec<&str> = stale_files.iter().map(|(_, relative_path)| relative_path.as_str()).collect();
+        extra_files.sort();
+        bail!(
+            "Load aborted before changing any files: {} file(s) in tracked paths are not in the snapshot:\n  {}",
+            extra_files.len(),
+            extra_files.join("\n  ")
+        );
+    }
+
+    let (action, dry_run_action) = if trash.is_some() {
+        ("Moving stale file to trash", "Would move stale file to trash")
+    }
+    else {
+        ("Deleting stale file", "Would delete stale file")
+    };
+
+    for (file_path, relative_path) in &stale_files {
+        if options.narrate() {
+            if dry_run {
+                println!("    [DRY RUN] {}: {}", dry_run_action, relative_path);
+            } else {
+                eprintln!("    {}: {}", action, relative_path);
+            }
+        }
+
+        if !dry_run {
+            remove_stale_file(file_path, relative_path, trash)?;
+        }
+
+        let mut s = stats.lock().unwrap();
+        s.removed += 1;
+        s.removed_files.push(relative_path.clone());
+    }
+
+    if options.narrate() && !stale_files.is_empty() {
+        if dry_run {
+            println!("  [DRY RUN] Would delete {} stale files", stale_files.len());
+        } else {
+            eprintln!("  Deleted {} stale files", stale_files.len());
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the files within tracked paths that are not in the snapshot, as (absolute path,
+/// path relative to the workspace root) pairs. Files in `trash` and the local versions
+/// in `kept` are not included.
+fn find_stale_files(
+    root: &Path,
+    manifest: &Manifest,
+    exclude: &[glob::Pattern],
+    kept: &BTreeSet<String>,
+    trash: Option<&Path>,
+    verbose: bool,
+) -> Result<Vec<(PathBuf, String)>> {
     let manifest_files: HashSet<PathBuf> = manifest
         .files
         .keys()
-        .map(|p| root.join(p))
+        .map(|p| fs_utils::resolve_relative(root, p))
         .collect();
 
     // Determine directories to scan by finding all directories in the workspace
@@ -219,7 +647,21 @@ fn cleanup_stale_files(
         }
     }
 
-    let mut deleted_count = 0;
+    // Symlinks were deliberately left out of the snapshot, so they are not stale
+    let keep_symlinks = manifest.symlink_policy == SymlinkPolicy::Skip;
+    // Likewise for anything below a mount point when the snapshot stayed on one filesystem
+    let root_device = root_device_for(root, manifest);
+    // Never treat files already moved to a trash inside the workspace as stale
+    let in_trash = |path: &Path| trash.is_some_and(|trash| path.starts_with(trash));
+    // Files too old to be captured by `--include-newer-than` were left out, not removed
+    let too_old = |path: &Path| {
+        manifest.modified_after.is_some_and(|cutoff| fs_utils::modified_before(path, cutoff.into()))
+    };
+    // As were empty files when the snapshot was saved with `--exclude-empty-files`
+    let empty_excluded = |path: &Path| manifest.exclude_empty_files && fs_utils::is_empty_file(path);
+
+    let mut stale_files = Vec::new();
+    let mut found = HashSet::new();
 
     for dir_path in directories_to_scan {
         if !dir_path.exists() {
@@ -234,51 +676,41 @@ fn cleanup_stale_files(
         for entry in WalkDir::new(&dir_path)
             .into_iter()
             .filter_entry(|e| {
-                if e.file_name() == ".kibo" {
+                if e.file_name() == ".kibo" || in_trash(e.path()) {
+                    return false;
+                }
+                // Hidden entries were left out of the snapshot, not removed
+                if manifest.exclude_hidden && fs_utils::is_hidden_entry(e) {
                     return false;
                 }
 
-                if let Ok(rel_path) = e.path().strip_prefix(root) {
-                    if manifest.should_ignore(rel_path) {
-                        return false;
-                    }
+                let rel_path = fs_utils::relative_to(root, e.path());
+                if manifest.should_ignore(&rel_path) || fs_utils::matches_any_pattern(&rel_path, exclude) {
+                    return false;
                 }
-                true
+                fs_utils::on_device(e.path(), root_device)
             })
             .filter_map(|e| e.ok())
             .filter(|e| !e.file_type().is_dir())
+            .filter(|e| !(keep_symlinks && e.path_is_symlink()))
+            .filter(|e| !too_old(e.path()) && !empty_excluded(e.path()))
         {
             let file_path = entry.path();
 
             if !manifest_files.contains(file_path) {
-                
-                if verbose || dry_run {
-                    let rel_path = file_path.strip_prefix(root).unwrap_or(file_path);
-                    if dry_run {
-                        println!("    [DRY RUN] Would delete stale file: {}", rel_path.display());
-                    } else {
-                        eprintln!("    Deleting stale file: {}", rel_path.display());
-                    }
-                }
-                
-                let relative_path = file_path.strip_prefix(root)
-                    .unwrap_or(file_path)
+                let relative_path = fs_utils::relative_to(root, file_path)
                     .to_string_lossy()
                     .to_string();
-                
-                if !dry_run {
-                    fs::remove_file(file_path)
-                        .with_context(|| format!("Failed to delete stale file: {}", file_path.display()))?;
+
+                if !kept.contains(&relative_path) && found.insert(file_path.to_path_buf()) {
+                    stale_files.push((file_path.to_path_buf(), relative_path));
                 }
-                
-                let mut s = stats.lock().unwrap();
-                s.removed += 1;
-                s.removed_files.push(relative_path);
-                deleted_count += 1;
             }
         }
     }
 
+    // Saves skip paths through a symlink loop; removing one would remove the file it loops back to
+    let mut loops = BTreeSet::new();
     for file_pattern in &manifest.tracked_files {
         let full_pattern = if file_pattern.starts_with("./") {
             let pattern_without_prefix = &file_pattern[2..];
@@ -301,10 +733,18 @@ fn cleanup_stale_files(
             }
         };
 
-        if let Ok(paths) = glob::glob(&full_pattern) {
-            for entry in paths.filter_map(Result::ok) {
+        let keep = |e: &walkdir::DirEntry| e.file_name() != ".kibo" && !(manifest.exclude_hidden && fs_utils::is_hidden_entry(e));
+        if let Ok(paths) = fs_utils::match_files(&full_pattern, &mut loops, keep) {
+            for entry in paths {
                 if entry.is_file() && !manifest_files.contains(&entry) {
-                    if entry.starts_with(root.join(".kibo")) {
+                    if keep_symlinks && entry.is_symlink() {
+                        continue;
+                    }
+                    if !fs_utils::on_device(&entry, root_device) {
+                        continue;
+                    }
+
+                    if entry.starts_with(root.join(".kibo")) || in_trash(&entry) || too_old(&entry) || empty_excluded(&entry) {
                         continue;
                     }
 
@@ -315,54 +755,35 @@ fn cleanup_stale_files(
                         }
                         continue;
                     }
-
-                    if verbose || dry_run {
-                        let rel_path = entry.strip_prefix(root).unwrap_or(&entry);
-                        if dry_run {
-                            println!("    [DRY RUN] Would delete stale file: {}", rel_path.display());
-                        } else {
-                            eprintln!("    Deleting stale file: {}", rel_path.display());
+                    if fs_utils::matches_any_pattern(relative_path, exclude) {
+                        if verbose {
+                            eprintln!("    Skipping excluded file: {}", relative_path.display());
                         }
+                        continue;
                     }
-                    
-                    let relative_path = entry.strip_prefix(root)
-                        .unwrap_or(&entry)
-                        .to_string_lossy()
-                        .to_string();
-                    
-                    if !dry_run {
-                        fs::remove_file(&entry)
-                            .with_context(|| format!("Failed to delete stale file: {}", entry.display()))?;
+
+                    let relative_path = relative_path.to_string_lossy().to_string();
+                    if !kept.contains(&relative_path) && found.insert(entry.clone()) {
+                        stale_files.push((entry, relative_path));
                     }
-                    
-                    let mut s = stats.lock().unwrap();
-                    s.removed += 1;
-                    s.removed_files.push(relative_path);
-                    deleted_count += 1;
                 }
             }
         }
     }
 
-    if (verbose || dry_run) && deleted_count > 0 {
-        if dry_run {
-            println!("  [DRY RUN] Would delete {} stale files", deleted_count);
-        } else {
-            eprintln!("  Deleted {} stale files", deleted_count);
-        }
-    }
-
-    Ok(())
+    Ok(stale_files)
 }
 
 /// Clean up empty directories that are not required by the snapshot
 fn cleanup_empty_directories(
     root: &Path,
     manifest: &Manifest,
-    verbose: bool,
-    dry_run: bool,
+    options: &LoadOptions,
 ) -> Result<()> {
-    if verbose || dry_run {
+    let verbose = options.verbose;
+    let dry_run = options.is_dry_run();
+
+    if options.narrate() {
         if dry_run {
             println!("\n[DRY RUN] Would clean up empty directories");
         } else {
@@ -373,11 +794,11 @@ fn cleanup_empty_directories(
     let mut required_dirs: HashSet<PathBuf> = HashSet::new();
     
     for file_path in manifest.files.keys() {
-        let full_path = root.join(file_path);
+        let full_path = fs_utils::resolve_relative(root, file_path);
         let mut current = full_path.as_path();
         
         while let Some(parent) = current.parent() {
-            if parent == root || !parent.starts_with(root) {
+            if root.starts_with(parent) {
                 break;
             }
             
@@ -387,6 +808,7 @@ fn cleanup_empty_directories(
     }
 
     let directories_to_scan: HashSet<PathBuf> = find_tracked_directory_roots(root, manifest);
+    let root_device = root_device_for(root, manifest);
 
     let mut all_dirs: Vec<PathBuf> = Vec::new();
     
@@ -397,6 +819,9 @@ fn cleanup_empty_directories(
         
         for entry in WalkDir::new(&scan_root)
             .into_iter()
+            .filter_entry(|e| {
+                fs_utils::on_device(e.path(), root_device) && !(manifest.exclude_hidden && fs_utils::is_hidden_entry(e))
+            })
             .filter_map(|e| e.ok())
             .filter(|e| e.file_type().is_dir())
         {
@@ -425,7 +850,7 @@ fn cleanup_empty_directories(
 
         if let Ok(mut entries) = fs::read_dir(&dir_path) {
             if entries.next().is_none() {
-                if verbose || dry_run {
+                if options.narrate() {
                     let rel_path = dir_path.strip_prefix(root).unwrap_or(&dir_path);
                     if dry_run {
                         println!("    [DRY RUN] Would delete empty directory: {}", rel_path.display());
@@ -450,7 +875,7 @@ fn cleanup_empty_directories(
         }
     }
 
-    if (verbose || dry_run) && deleted_count > 0 {
+    if options.narrate() && deleted_count > 0 {
         if dry_run {
             println!("  [DRY RUN] Would delete {} empty directories", deleted_count);
         } else {
@@ -461,17 +886,27 @@ fn cleanup_empty_directories(
     Ok(())
 }
 
-/// Load all files from manifest
+/// State about workspace files kept up to date while files are restored
+struct LoadRecords<'a> {
+    /// Hashes of restored files, with `--checksum-cache`
+    hash_cache: Option<&'a Mutex<HashCache>>,
+    /// Paths restored so far, with `--resume`
+    journal: Option<&'a LoadJournal>,
+}
+
 fn load_files(
     root: &Path,
     manifest: &Manifest,
     store: &Store,
-    verbose: bool,
-    dry_run: bool,
+    options: &LoadOptions,
     progress_config: ProgressConfig,
+    records: &LoadRecords,
     stats: Arc<Mutex<LoadStats>>,
 ) -> Result<()> {
-    if verbose || dry_run {
+    let hash_cache = records.hash_cache;
+    let dry_run = options.is_dry_run();
+
+    if options.narrate() {
         if dry_run {
             println!("\n[DRY RUN] Would load files from snapshot");
         } else {
@@ -479,66 +914,259 @@ fn load_files(
         }
     }
 
-    let existing_files = scan_existing_files_in_manifest(root, manifest, progress_config)?;
+    let existing = scan_existing_files_in_manifest(root, manifest, progress_config, hash_cache)?;
+    let plan = LoadPlan {
+        keep_as: kept_conflict_names(root, manifest, &existing, options.rename_conflicts.as_deref()),
+        existing,
+        link_to: if manifest.link_identical { identical_file_links(manifest) } else { HashMap::new() },
+    };
 
-    let total_bytes = manifest.total_size;
-    let progress = ByteProgress::new(total_bytes, progress_config);
+    // Unchanged files are counted apart, so the bar and its ETA only cover the copy work
+    let progress = ByteProgress::new(bytes_to_copy(manifest, &plan.existing), progress_config);
 
-    let entries: Vec<(&String, &crate::manifest::FileEntry)> = manifest
+    // Files linked to an identical file are restored once that file is in place
+    let (linked, entries): (Vec<(&String, &crate::manifest::FileEntry)>, Vec<_>) = manifest
         .files
         .iter()
-        .collect();
+        .partition(|(relative_path, _)| plan.link_to.contains_key(*relative_path));
 
-    let results: Vec<Result<()>> = entries
-        .par_iter()
-        .map(|(relative_path, entry)| {
-            load_single_file(
-                root,
-                relative_path,
-                entry,
-                &existing_files,
-                store,
-                stats.clone(),
-                verbose,
-                dry_run,
-                &progress,
-            )
+    let results: Vec<Result<()>> = [entries, linked]
+        .iter()
+        .flat_map(|entries| {
+            entries
+                .par_iter()
+                .map(|(relative_path, entry)| {
+                    load_single_file(
+                        root,
+                        relative_path,
+                        entry,
+                        &plan,
+                        store,
+                        stats.clone(),
+                        options,
+                        &progress,
+                    )
+                    .and_then(|()| match records.journal {
+                        Some(journal) => journal.record(relative_path),
+                        None => Ok(()),
+                    })
+                })
+                .collect::<Vec<_>>()
         })
         .collect();
 
+    // Keep what was restored before a failure, so that `--resume` can skip it
+    if let Some(journal) = records.journal {
+        journal.flush()?;
+    }
     for result in results {
         result?;
     }
 
     progress.finish();
 
+    if let Some(hash_cache) = hash_cache
+        && !dry_run
+    {
+        let mut hash_cache = hash_cache.lock().expect("Hash cache mutex poisoned");
+
+        // Restored files now match the snapshot, so the next load or save can skip hashing them
+        let s = stats.lock().unwrap();
+        for relative_path in &s.copied_files {
+            let path = root.join(relative_path);
+            if let Ok(metadata) = fs::symlink_metadata(&path)
+                && metadata.is_file()
+                && let Ok(mtime) = metadata.modified()
+            {
+                hash_cache.insert(&path, metadata.len(), mtime, manifest.files[relative_path].hash.clone());
+            }
+        }
+
+        hash_cache.save(root)?;
+    }
+
     Ok(())
 }
 
-/// Scan existing files mentioned in manifest and compute their hashes
-fn scan_existing_files_in_manifest(
+/// Total size of the manifest's files that differ from the workspace and must be restored
+fn bytes_to_copy(manifest: &Manifest, existing: &HashMap<String, String>) -> u64 {
+    manifest
+        .files
+        .iter()
+        .filter(|(relative_path, entry)| existing.get(*relative_path) != Some(&entry.hash))
+        .map(|(_, entry)| entry.size)
+        .sum()
+}
+
+/// What is known about the workspace before files are restored
+struct LoadPlan {
+    /// Hashes of the manifest's files that already exist in the workspace
+    existing: HashMap<String, String>,
+    /// Files to hard-link to an identical file of the snapshot instead of copying
+    link_to: HashMap<String, String>,
+    /// Locally modified files to rename aside with `--rename-conflicts`, and their new names
+    keep_as: HashMap<String, String>,
+}
+
+/// Pick the names that `--rename-conflicts` keeps the local versions of modified files as:
+/// `<path><suffix>`, or `<path><suffix>.<n>` if that name is taken in the workspace or by
+/// a file of the snapshot
+fn kept_conflict_names(
     root: &Path,
     manifest: &Manifest,
-    progress_config: ProgressConfig,
-) -> Result<HashMap<String, String>> {
-    let spinner = crate::progress::Spinner::new(progress_config, "Scanning existing files");
+    existing: &HashMap<String, String>,
+    suffix: Option<&str>,
+) -> HashMap<String, String> {
+    let Some(suffix) = suffix else {
+        return HashMap::new();
+    };
 
-    let files: Vec<String> = manifest.files.keys().cloned().collect();
+    let mut taken: HashSet<String> = manifest.files.keys().cloned().collect();
+    let mut names = HashMap::new();
+    for (relative_path, entry) in &manifest.files {
+        let is_conflict = !entry.is_symlink
+            && existing.get(relative_path).is_some_and(|hash| hash != &entry.hash)
+            && root.join(relative_path).symlink_metadata().is_ok_and(|m| m.is_file());
+        if !is_conflict {
+            continue;
+        }
+        let name = std::iter::once(format!("{}{}", relative_path, suffix))
+            .chain((1..).map(|n| format!("{}{}.{}", relative_path, suffix, n)))
+            .find(|name| !taken.contains(name) && root.join(name).symlink_metadata().is_err())
+            .expect("Candidate names are unbounded");
+        taken.insert(name.clone());
+        names.insert(relative_path.clone(), name);
+    }
+    names
+}
 
-    let hashes: Vec<(String, String)> = files
-        .par_iter()
-        .filter_map(|relative_path| {
-            let path = root.join(relative_path);
-            // Use symlink_metadata to check existence without following symlinks
-            if path.symlink_metadata().is_err() {
-                return None;
-            }
-            
-            // Check if it's a symlink and hash accordingly
-            let hash = if path.symlink_metadata().ok()?.is_symlink() {
-                file_hash::hash_symlink(&path).ok()?
-            } else {
-                file_hash::hash_file(&path).ok()?
+/// Local versions kept by `--rename-conflicts` in earlier loads. They are in no snapshot,
+/// but later loads must not remove them as stale.
+fn read_kept_conflicts(root: &Path) -> Result<BTreeSet<String>> {
+    let path = get_kept_conflicts_path(root);
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse kept local versions: {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read kept local versions: {}", path.display())),
+    }
+}
+
+/// Record the local versions kept by this load along with the earlier ones that still exist
+fn record_kept_conflicts(root: &Path, kept: &BTreeSet<String>, renamed: &[String]) -> Result<()> {
+    let still_kept: BTreeSet<String> = kept
+        .iter()
+        .chain(renamed)
+        .filter(|relative_path| root.join(relative_path).symlink_metadata().is_ok())
+        .cloned()
+        .collect();
+    if &still_kept == kept {
+        return Ok(());
+    }
+
+    let path = get_kept_conflicts_path(root);
+    if still_kept.is_empty() {
+        return fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove kept local versions: {}", path.display()));
+    }
+    let content = serde_json::to_string_pretty(&still_kept)?;
+    fs_utils::atomic_write(&path, content.as_bytes())
+        .with_context(|| format!("Failed to write kept local versions: {}", path.display()))
+}
+
+/// Map every regular file to the first file (by path) with the same content, mode and mtime.
+/// Linked files share one inode, so files whose mtimes differ are kept apart. Empty and
+/// `assume_unchanged` files are never linked.
+fn identical_file_links(manifest: &Manifest) -> HashMap<String, String> {
+    let mut paths: Vec<&String> = manifest
+        .files
+        .iter()
+        .filter(|(_, entry)| !entry.is_symlink && !entry.assumed && entry.size > 0)
+        .map(|(path, _)| path)
+        .collect();
+    paths.sort();
+
+    let mut first: HashMap<(&str, u32, i64, u32), &String> = HashMap::new();
+    let mut links = HashMap::new();
+    for path in paths {
+        let entry = &manifest.files[path];
+        #[cfg(unix)]
+        let mode = entry.mode;
+        #[cfg(not(unix))]
+        let mode = 0;
+        let key = (entry.hash.as_str(), mode, entry.mtime_secs, entry.mtime_nanos);
+        match first.get(&key) {
+            Some(source) => {
+                links.insert(path.clone(), (*source).clone());
+            },
+            None => {
+                first.insert(key, path);
+            },
+        }
+    }
+    links
+}
+
+/// Replace `dest` with a hard link to `source`. Returns false, leaving the file to be
+/// copied, if `source` is not a regular file or the link cannot be made (e.g. across
+/// file systems).
+fn link_identical_file(source: &Path, dest: &Path) -> bool {
+    if !source.symlink_metadata().is_ok_and(|m| m.is_file()) {
+        return false;
+    }
+    if dest.symlink_metadata().is_ok() && fs::remove_file(dest).is_err() {
+        return false;
+    }
+    fs::hard_link(source, dest).is_ok()
+}
+
+/// Device of the workspace root if the snapshot was saved without crossing mount points
+fn root_device_for(root: &Path, manifest: &Manifest) -> Option<u64> {
+    if manifest.one_file_system {
+        fs_utils::device_id(root)
+    }
+    else {
+        None
+    }
+}
+
+/// Scan existing files mentioned in manifest and compute their hashes. With a hash cache,
+/// regular files whose size and mtime match a cached entry are not read.
+fn scan_existing_files_in_manifest(
+    root: &Path,
+    manifest: &Manifest,
+    progress_config: ProgressConfig,
+    hash_cache: Option<&Mutex<HashCache>>,
+) -> Result<HashMap<String, String>> {
+    let spinner = crate::progress::Spinner::new(progress_config, "Scanning existing files");
+
+    let files: Vec<String> = manifest.files.keys().cloned().collect();
+
+    let hashes: Vec<(String, String)> = files
+        .par_iter()
+        .filter_map(|relative_path| {
+            let path = root.join(relative_path);
+            // Use symlink_metadata to check existence without following symlinks
+            if path.symlink_metadata().is_err() {
+                return None;
+            }
+            
+            // Check if it's a symlink and hash accordingly
+            let metadata = path.symlink_metadata().ok()?;
+            let entry = &manifest.files[relative_path];
+            let hash = if metadata.is_symlink() && manifest.symlink_policy == SymlinkPolicy::Follow && !entry.is_symlink {
+                // Followed links were saved as their target's contents
+                file_hash::hash_file(&path).ok()?
+            } else if metadata.is_symlink() {
+                file_hash::hash_symlink(&path).ok()?
+            } else if entry.assumed {
+                let mtime = filetime::FileTime::from_last_modification_time(&metadata);
+                let nanos = manifest.mtime_precision.nanos(mtime.nanoseconds());
+                file_hash::hash_assumed(relative_path, metadata.len(), mtime.unix_seconds(), nanos)
+            } else if let Some(hash_cache) = hash_cache {
+                hash_file_cached(&path, &metadata, hash_cache)?
+            } else {
+                file_hash::hash_file(&path).ok()?
             };
             Some((relative_path.clone(), hash))
         })
@@ -548,18 +1176,34 @@ fn scan_existing_files_in_manifest(
     Ok(hashes.into_iter().collect())
 }
 
+/// Hash a regular file through the hash cache, updating the cache on a miss
+fn hash_file_cached(path: &Path, metadata: &fs::Metadata, hash_cache: &Mutex<HashCache>) -> Option<String> {
+    let mtime = metadata.modified().ok()?;
+
+    let cached = hash_cache.lock().expect("Hash cache mutex poisoned").get(path, metadata.len(), mtime);
+    if let Some(hash) = cached {
+        return Some(hash);
+    }
+
+    let hash = file_hash::hash_file(path).ok()?;
+    hash_cache.lock().expect("Hash cache mutex poisoned").insert(path, metadata.len(), mtime, hash.clone());
+    Some(hash)
+}
+
 /// Load a single file
 fn load_single_file(
     root: &Path,
     relative_path: &str,
     entry: &crate::manifest::FileEntry,
-    existing_files: &HashMap<String, String>,
+    plan: &LoadPlan,
     store: &Store,
     stats: Arc<Mutex<LoadStats>>,
-    verbose: bool,
-    dry_run: bool,
+    options: &LoadOptions,
     progress: &ByteProgress,
 ) -> Result<()> {
+    let existing_files = &plan.existing;
+    let verbose = options.verbose;
+    let dry_run = options.is_dry_run();
     let dest_path = root.join(relative_path);
 
     if !dry_run {
@@ -573,7 +1217,7 @@ fn load_single_file(
             // Check if symlink already exists with correct target
             let needs_restore = if let Some(existing_hash) = existing_files.get(relative_path) {
                 if existing_hash == &entry.hash {
-                    if verbose || dry_run {
+                    if options.narrate() {
                         if dry_run {
                             println!("  [DRY RUN] Symlink unchanged: {}", relative_path);
                         } else {
@@ -586,7 +1230,7 @@ fn load_single_file(
                     s.files_loaded += 1;
                     s.unchanged_files.push(relative_path.to_string());
                     
-                    progress.inc(entry.size);
+                    progress.skip();
                     
                     false
                 } else {
@@ -626,7 +1270,7 @@ fn load_single_file(
     else {
         let needs_copy = if let Some(existing_hash) = existing_files.get(relative_path) {
             if existing_hash == &entry.hash {
-                if verbose || dry_run {
+                if options.narrate() {
                     if dry_run {
                         println!("  [DRY RUN] Unchanged: {}", relative_path);
                     } else {
@@ -639,7 +1283,7 @@ fn load_single_file(
                 s.files_loaded += 1;
                 s.unchanged_files.push(relative_path.to_string());
                 
-                progress.inc(entry.size);
+                progress.skip();
                 
                 false
             }
@@ -651,17 +1295,64 @@ fn load_single_file(
             true
         };
 
+        // A regular file that differs from the snapshot is renamed aside instead of overwritten
+        let conflict_path = plan.keep_as.get(relative_path).filter(|_| needs_copy).cloned();
+
+        let link_source = plan.link_to.get(relative_path);
         if needs_copy {
             if dry_run {
                 if verbose {
-                    println!("  [DRY RUN] Would load: {}", relative_path);
+                    match link_source {
+                        Some(source) => println!("  [DRY RUN] Would link: {} -> {}", relative_path, source),
+                        None => println!("  [DRY RUN] Would load: {}", relative_path),
+                    }
+                    if let Some(aside) = &conflict_path {
+                        println!("  [DRY RUN] Would keep local version as: {}", aside);
+                    }
                 }
             } else {
-                store.copy_blob_to_file(&entry.hash, &dest_path)
-                    .with_context(|| format!("Failed to copy blob for: {}", relative_path))?;
-                
-                if verbose {
-                    eprintln!("  Loaded: {}", relative_path);
+                // Replace rather than write through a symlink, which could modify its target
+                if dest_path.symlink_metadata().is_ok_and(|m| m.is_symlink()) {
+                    fs::remove_file(&dest_path)?;
+                }
+                else if let Some(aside) = &conflict_path {
+                    fs::rename(&dest_path, root.join(aside))
+                        .with_context(|| format!("Failed to keep local version of: {}", relative_path))?;
+                    if verbose {
+                        eprintln!("  Kept local version: {} -> {}", relative_path, aside);
+                    }
+                }
+
+                // The file may be a hard link (from --link-identical, or a staged copy of the live
+                // file), which must not be written through
+                if dest_path.symlink_metadata().is_ok() {
+                    fs::remove_file(&dest_path)?;
+                }
+
+                if let Some(source) = link_source
+                    && link_identical_file(&root.join(source), &dest_path)
+                {
+                    stats.lock().unwrap().linked += 1;
+                    if verbose {
+                        eprintln!("  Linked: {} -> {}", relative_path, source);
+                    }
+                }
+                else {
+                    if store.ensure_read_only(&entry.hash)? {
+                        stats.lock().unwrap().writable_blobs += 1;
+                    }
+                    // Assumed entries have no content hash to check against
+                    let copied = if options.verify && !entry.assumed {
+                        store.copy_blob_to_file_verified(&entry.hash, &dest_path)
+                    }
+                    else {
+                        store.copy_blob_to_file(&entry.hash, &dest_path)
+                    };
+                    copied.with_context(|| format!("Failed to copy blob for: {}", relative_path))?;
+
+                    if verbose {
+                        eprintln!("  Loaded: {}", relative_path);
+                    }
                 }
             }
             
@@ -669,6 +1360,10 @@ fn load_single_file(
             s.copies += 1;
             s.files_loaded += 1;
             s.copied_files.push(relative_path.to_string());
+            if let Some(aside) = conflict_path {
+                s.renamed += 1;
+                s.renamed_files.push(aside);
+            }
             
             progress.inc(entry.size);
         }
@@ -690,14 +1385,16 @@ fn load_single_file(
 fn restore_directories(
     root: &Path,
     manifest: &Manifest,
-    verbose: bool,
-    dry_run: bool,
+    options: &LoadOptions,
 ) -> Result<()> {
+    let verbose = options.verbose;
+    let dry_run = options.is_dry_run();
+
     if manifest.directories.is_empty() {
         return Ok(());
     }
     
-    if verbose || dry_run {
+    if options.narrate() {
         if dry_run {
             println!("\n[DRY RUN] Would restore {} directories", manifest.directories.len());
         } else {
@@ -744,8 +1441,68 @@ fn restore_directories(
     Ok(())
 }
 
+/// Directories a sparse manifest left out: the parents of its files inside the workspace
+/// that have no entry of their own, shallowest first. Only tracked directories and the
+/// directories below them count, since the parents of tracked directories and of `files`
+/// pattern matches are never recorded.
+fn implied_directories(manifest: &Manifest) -> Vec<String> {
+    let in_tracked_directory = |dir: &Path| {
+        dir.components()
+            .any(|component| manifest.tracked_directories.iter().any(|tracked| component.as_os_str() == tracked.as_str()))
+    };
+
+    let mut implied = BTreeSet::new();
+    for file_path in manifest.files.keys() {
+        let path = Path::new(file_path);
+        if !path.components().all(|component| matches!(component, Component::Normal(_))) {
+            continue;
+        }
+        implied.extend(
+            path.ancestors()
+                .skip(1)
+                .filter(|dir| !dir.as_os_str().is_empty() && in_tracked_directory(dir))
+                .map(|dir| dir.to_string_lossy().to_string())
+                .filter(|dir| !manifest.directories.contains_key(dir)),
+        );
+    }
+
+    let mut implied: Vec<String> = implied.into_iter().collect();
+    implied.sort_by_key(|dir| dir.matches('/').count());
+    implied
+}
+
+/// Create the directories a sparse manifest left out and give them `IMPLIED_DIRECTORY_MODE`.
+/// Their mtimes were not recorded, so they are left as they are.
+fn restore_implied_directories(
+    root: &Path,
+    manifest: &Manifest,
+    options: &LoadOptions,
+) -> Result<()> {
+    for relative_path in implied_directories(manifest) {
+        let dir_path = root.join(&relative_path);
+
+        if options.is_dry_run() {
+            if options.verbose && !dir_path.exists() {
+                println!("  [DRY RUN] Would create directory: {}", relative_path);
+            }
+            continue;
+        }
+
+        if !dir_path.exists() {
+            fs::create_dir_all(&dir_path)
+                .with_context(|| format!("Failed to create directory: {}", dir_path.display()))?;
+            if options.verbose {
+                eprintln!("  Created directory: {}", relative_path);
+            }
+        }
+        fs_utils::set_file_mode(&dir_path, IMPLIED_DIRECTORY_MODE)?;
+    }
+
+    Ok(())
+}
+
 /// Verify that all blobs in the manifest exist in the store
-fn verify_snapshot(manifest: &Manifest, store: &Store) -> Result<()> {
+pub fn verify_snapshot(manifest: &Manifest, store: &Store) -> Result<()> {
     let mut missing = Vec::new();
 
     for (path, entry) in &manifest.files {
@@ -758,28 +1515,288 @@ fn verify_snapshot(manifest: &Manifest, store: &Store) -> Result<()> {
         let sample: Vec<_> = missing.iter().take(5).collect();
         bail!(
             "Snapshot is incomplete: {} files missing from store.\n\
-             Sample: {:?}{}",
+             Sample: {:?}{}{}",
             missing.len(),
             sample,
-            if missing.len() > 5 { " ..." } else { "" }
+            if missing.len() > 5 { " ..." } else { "" },
+            if manifest.hash_only {
+                "\nThe snapshot was saved with --hash-only, so its blobs must be stored by another save first."
+            }
+            else {
+                ""
+            }
         );
     }
 
     Ok(())
 }
 
+/// Result of checking one snapshot's blobs, as printed by `verify --json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VerifyReport {
+    pub snapshot: String,
+    /// Number of distinct blobs checked
+    pub checked: usize,
+    /// Blobs that are present (and, with a deep check, match their hash)
+    pub ok: usize,
+    /// Hashes of blobs not in the store
+    pub missing: Vec<String>,
+    /// Hashes of blobs whose content no longer matches; only filled by a deep check
+    pub corrupt: Vec<String>,
+    /// Why the snapshot's blobs could not be checked at all, e.g. its store is missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl VerifyReport {
+    /// Report for a snapshot whose store could not be opened, so no blob was checked
+    pub fn unavailable(manifest: &Manifest, error: &anyhow::Error) -> Self {
+        Self {
+            snapshot: manifest.name.clone(),
+            checked: 0,
+            ok: 0,
+            missing: Vec::new(),
+            corrupt: Vec::new(),
+            error: Some(format!("{:#}", error)),
+        }
+    }
+
+    /// Whether any blob is missing or corrupt, or the blobs could not be checked
+    pub fn is_damaged(&self) -> bool {
+        !self.missing.is_empty() || !self.corrupt.is_empty() || self.error.is_some()
+    }
+}
+
+/// Check every distinct blob of a snapshot is in the store, rehashing the ones derived from
+/// content when `deep` is set. Unlike `verify_snapshot`, problems are collected, not returned
+/// as an error.
+pub fn verify_report(manifest: &Manifest, store: &Store, deep: bool) -> VerifyReport {
+    let hashes: BTreeSet<&str> = manifest.files.values().map(|entry| entry.hash.as_str()).collect();
+    // Assumed-unchanged hashes are not derived from content, so they can only be checked for presence
+    let rehash: BTreeSet<&str> = manifest.files
+        .values()
+        .filter(|entry| deep && !entry.assumed)
+        .map(|entry| entry.hash.as_str())
+        .collect();
+
+    let check = store.check_blobs(&rehash.iter().copied().collect::<Vec<_>>());
+    let mut missing = check.missing;
+    missing.extend(
+        hashes
+            .iter()
+            .filter(|hash| !rehash.contains(*hash) && !store.has_blob(hash))
+            .map(|hash| hash.to_string())
+    );
+    missing.sort();
+
+    VerifyReport {
+        snapshot: manifest.name.clone(),
+        checked: hashes.len(),
+        ok: hashes.len() - missing.len() - check.corrupt.len(),
+        missing,
+        corrupt: check.corrupt,
+        error: None,
+    }
+}
+
+/// Rehash every blob in the manifest and check it matches the hash it is stored under.
+/// Assumed-unchanged entries are skipped since their hash is not derived from content.
+/// Returns the number of blobs rehashed.
+pub fn verify_snapshot_contents(manifest: &Manifest, store: &Store) -> Result<usize> {
+    let entries: Vec<(&String, &crate::manifest::FileEntry)> = manifest.files
+        .iter()
+        .filter(|(_, entry)| !entry.assumed)
+        .collect();
+
+    let mut corrupt: Vec<String> = entries
+        .par_iter()
+        .filter(|(_, entry)| {
+            !store.content_hash(&entry.hash).is_ok_and(|actual| actual == entry.hash)
+        })
+        .map(|(path, _)| (*path).clone())
+        .collect();
+
+    if !corrupt.is_empty() {
+        corrupt.sort();
+        let sample: Vec<_> = corrupt.iter().take(5).collect();
+        bail!(
+            "Snapshot is corrupt: {} blobs do not match their hash.\n\
+             Sample: {:?}{}",
+            corrupt.len(),
+            sample,
+            if corrupt.len() > 5 { " ..." } else { "" }
+        );
+    }
+
+    Ok(entries.len())
+}
+
 /// Statistics about a load operation
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 pub struct LoadStats {
     pub files_loaded: usize,
     pub copies: usize,
     pub unchanged: usize,
     pub symlinks: usize,
     pub removed: usize,
+    /// Locally modified files renamed aside by `--rename-conflicts`
+    pub renamed: usize,
     pub copied_files: Vec<String>,
     pub unchanged_files: Vec<String>,
     pub symlink_files: Vec<String>,
     pub removed_files: Vec<String>,
+    /// New paths of the files renamed aside
+    pub renamed_files: Vec<String>,
+    /// Blobs found writable while loading, which were made read-only again
+    pub writable_blobs: usize,
+    /// Copies made as hard links to an identical file, for snapshots saved with `--link-identical`
+    pub linked: usize,
+    /// Files skipped because an interrupted `--resume` load had already restored them
+    pub resumed: usize,
+    /// Where removed files were moved, when loading with a trash directory
+    pub trash_dir: Option<PathBuf>,
+    /// What still differs from the snapshot after the load, with `--report-drift`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drift: Option<DriftReport>,
+}
+
+/// Differences between the workspace and a snapshot, found by `check_drift`
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct DriftReport {
+    /// Files in the snapshot that are not in the workspace
+    pub missing: Vec<String>,
+    /// Files whose content differs from the snapshot
+    pub modified: Vec<String>,
+    /// Files whose permissions differ from the snapshot
+    pub permissions: Vec<String>,
+    /// Files in tracked paths that are not in the snapshot
+    pub extra: Vec<String>,
+}
+
+impl DriftReport {
+    /// Whether the workspace matches the snapshot
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.modified.is_empty() && self.permissions.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// An entry whose permissions or mtime in the workspace differ from the snapshot,
+/// found by `check_metadata`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataDrift {
+    /// Path relative to the workspace root
+    pub path: String,
+    /// Recorded and current permission bits, when they differ
+    pub mode: Option<(u32, u32)>,
+    /// Recorded and current mtime as (seconds, nanoseconds), when they differ
+    pub mtime: Option<((i64, u32), (i64, u32))>,
+}
+
+impl MetadataDrift {
+    /// Apply the recorded permissions and mtime again
+    pub fn fix(&self, root: &Path) -> Result<()> {
+        let path = root.join(&self.path);
+        if let Some((recorded, _)) = self.mode {
+            fs_utils::set_file_mode(&path, recorded)?;
+        }
+        if let Some(((secs, nanos), _)) = self.mtime {
+            fs_utils::set_file_mtime(&path, secs, nanos)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the permissions and mtimes of the snapshot's files, and the permissions of its
+/// directories, with the workspace without reading any content. Symlinks and entries
+/// missing from the workspace are skipped. Directory mtimes are not compared, as writing
+/// files into a directory after it was restored changes them.
+pub fn check_metadata(root: &Path, manifest: &Manifest) -> Result<Vec<MetadataDrift>> {
+    let mut drift = Vec::new();
+
+    for (relative_path, entry) in &manifest.files {
+        if entry.is_symlink {
+            continue;
+        }
+        let Ok(metadata) = fs::symlink_metadata(root.join(relative_path)) else {
+            continue;
+        };
+
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            let current = metadata.permissions().mode() & 0o7777;
+            (current != entry.mode & 0o7777).then_some((entry.mode & 0o7777, current))
+        };
+        #[cfg(not(unix))]
+        let mode = None;
+
+        let mtime = filetime::FileTime::from_last_modification_time(&metadata);
+        let current = (mtime.unix_seconds(), manifest.mtime_precision.nanos(mtime.nanoseconds()));
+        let recorded = (entry.mtime_secs, entry.mtime_nanos);
+        let mtime = (current != recorded).then_some((recorded, current));
+
+        if mode.is_some() || mtime.is_some() {
+            drift.push(MetadataDrift { path: relative_path.clone(), mode, mtime });
+        }
+    }
+
+    #[cfg(unix)]
+    for (relative_path, entry) in &manifest.directories {
+        use std::os::unix::fs::PermissionsExt;
+        let Ok(metadata) = fs::metadata(root.join(relative_path)) else {
+            continue;
+        };
+        let current = metadata.permissions().mode() & 0o7777;
+        if current != entry.mode & 0o7777 {
+            drift.push(MetadataDrift {
+                path: relative_path.clone(),
+                mode: Some((entry.mode & 0o7777, current)),
+                mtime: None,
+            });
+        }
+    }
+
+    drift.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(drift)
+}
+
+/// Rescan the workspace and compare it with a snapshot: rehash each of its files, compare
+/// their permissions, and look for files in tracked paths that a load would remove
+pub fn check_drift(
+    root: &Path,
+    manifest: &Manifest,
+    exclude: &[glob::Pattern],
+    progress_config: ProgressConfig,
+) -> Result<DriftReport> {
+    let existing = scan_existing_files_in_manifest(root, manifest, progress_config, None)?;
+    let mut report = DriftReport::default();
+
+    for (relative_path, entry) in &manifest.files {
+        match existing.get(relative_path) {
+            None => report.missing.push(relative_path.clone()),
+            Some(hash) if hash != &entry.hash => report.modified.push(relative_path.clone()),
+            Some(_) => {
+                #[cfg(unix)]
+                if !entry.is_symlink {
+                    use std::os::unix::fs::PermissionsExt;
+                    let mode = fs::metadata(root.join(relative_path))?.permissions().mode();
+                    if mode & 0o7777 != entry.mode & 0o7777 {
+                        report.permissions.push(relative_path.clone());
+                    }
+                }
+            },
+        }
+    }
+
+    let kept = read_kept_conflicts(root)?;
+    report.extra = find_stale_files(root, manifest, exclude, &kept, None, false)?
+        .into_iter()
+        .map(|(_, relative_path)| relative_path)
+        .collect();
+    for paths in [&mut report.missing, &mut report.modified, &mut report.permissions, &mut report.extra] {
+        paths.sort();
+    }
+    Ok(report)
 }
 
 /// Find tracked directory roots by scanning the workspace for directories whose
@@ -792,6 +1809,14 @@ fn find_tracked_directory_roots(root: &Path, manifest: &Manifest) -> HashSet<Pat
         return found;
     }
 
+    // Directories outside the workspace are tracked by absolute path
+    found.extend(
+        manifest.tracked_directories
+            .iter()
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+    );
+
     let tracked: HashSet<&str> = manifest
         .tracked_directories
         .iter()
@@ -867,6 +1892,8 @@ mod tests {
             symlink_target: None,
             mtime_secs: 0,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         manifest.add_file("test.txt".to_string(), entry);
         
@@ -893,6 +1920,8 @@ mod tests {
             symlink_target: None,
             mtime_secs: 0,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         manifest.add_file("test.txt".to_string(), entry);
         
@@ -901,6 +1930,107 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("incomplete"));
     }
 
+    #[test]
+    fn test_verify_snapshot_contents_detects_corruption() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        
+        let store = Store::with_compression(root, 3);
+        store.init().unwrap();
+        
+        let mut manifest = Manifest::new("test".to_string());
+        for (name, content) in [("good.txt", "good"), ("bad.txt", "bad")] {
+            let path = root.join(name);
+            File::create(&path).unwrap().write_all(content.as_bytes()).unwrap();
+            let hash = file_hash::hash_file(&path).unwrap();
+            store.store_file(&path, &hash).unwrap();
+            manifest.add_file(name.to_string(), FileEntry {
+                hash,
+                size: content.len() as u64,
+                #[cfg(unix)]
+                mode: 0o644,
+                is_symlink: false,
+                symlink_target: None,
+                mtime_secs: 0,
+                mtime_nanos: 0,
+                assumed: false,
+                is_text: false,
+            });
+        }
+        
+        assert_eq!(verify_snapshot_contents(&manifest, &store).unwrap(), 2);
+        
+        let bad_blob = store.blob_path(&manifest.files["bad.txt"].hash);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&bad_blob, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        fs::write(&bad_blob, b"tampered").unwrap();
+        
+        let err = verify_snapshot_contents(&manifest, &store).unwrap_err().to_string();
+        assert!(err.contains("1 blobs"));
+        assert!(err.contains("bad.txt"));
+        
+        // Assumed entries are not content-hashed, so they are never reported
+        manifest.files.get_mut("bad.txt").unwrap().assumed = true;
+        assert_eq!(verify_snapshot_contents(&manifest, &store).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_verify_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let store = Store::new(root);
+        store.init().unwrap();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        for (name, content) in [("good.txt", "good"), ("bad.txt", "bad"), ("gone.txt", "gone")] {
+            let path = root.join(name);
+            fs::write(&path, content).unwrap();
+            let hash = file_hash::hash_file(&path).unwrap();
+            store.store_file(&path, &hash).unwrap();
+            manifest.add_file(name.to_string(), FileEntry {
+                hash,
+                size: content.len() as u64,
+                #[cfg(unix)]
+                mode: 0o644,
+                is_symlink: false,
+                symlink_target: None,
+                mtime_secs: 0,
+                mtime_nanos: 0,
+                assumed: false,
+                is_text: false,
+            });
+        }
+
+        let bad_blob = store.blob_path(&manifest.files["bad.txt"].hash);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&bad_blob, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        fs::write(&bad_blob, b"tampered").unwrap();
+        let gone_hash = manifest.files["gone.txt"].hash.clone();
+        fs::remove_file(store.blob_path(&gone_hash)).unwrap();
+
+        let shallow = verify_report(&manifest, &store, false);
+        assert_eq!((shallow.checked, shallow.ok), (3, 2));
+        assert_eq!(shallow.missing, vec![gone_hash.clone()]);
+        assert!(shallow.corrupt.is_empty());
+
+        let deep = verify_report(&manifest, &store, true);
+        assert_eq!((deep.checked, deep.ok), (3, 1));
+        assert_eq!(deep.missing, vec![gone_hash]);
+        assert_eq!(deep.corrupt, vec![manifest.files["bad.txt"].hash.clone()]);
+        assert!(deep.is_damaged());
+        assert!(!serde_json::to_value(&deep).unwrap().as_object().unwrap().contains_key("error"));
+
+        let unavailable = VerifyReport::unavailable(&manifest, &anyhow::anyhow!("Store group 'debug' is not initialized"));
+        assert!(unavailable.is_damaged());
+        assert_eq!(serde_json::to_value(&unavailable).unwrap()["error"], "Store group 'debug' is not initialized");
+    }
+
     #[test]
     fn test_verify_snapshot_empty_manifest() {
         let temp_dir = TempDir::new().unwrap();
@@ -968,18 +2098,228 @@ mod tests {
             symlink_target: None,
             mtime_secs: 0,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         manifest.add_file("config.txt".to_string(), entry);
         
         // should NOT delete subdir/config.txt because ./ pattern only matches root
         let stats = Arc::new(Mutex::new(LoadStats::default()));
-        cleanup_stale_files(root, &manifest, false, false, stats).unwrap();
+        cleanup_stale_files(root, &manifest, &[], &BTreeSet::new(), None, &LoadOptions::default(), stats).unwrap();
         
         assert!(root_config.exists(), "Root config.txt should exist");
         
         assert!(subdir_config.exists(), "Subdirectory config.txt should be preserved");
     }
 
+    #[test]
+    fn test_apply_exclude_patterns() {
+        let mut manifest = Manifest::new("test".to_string());
+        for (path, size) in [("build/app.o", 10), ("build/local.cfg", 5), ("build/sub/other.cfg", 3)] {
+            let entry = FileEntry {
+                hash: path.to_string(),
+                size,
+                #[cfg(unix)]
+                mode: 0o644,
+                is_symlink: false,
+                symlink_target: None,
+                mtime_secs: 0,
+                mtime_nanos: 0,
+                assumed: false,
+                is_text: false,
+            };
+            manifest.add_file(path.to_string(), entry);
+        }
+
+        let exclude = fs_utils::compile_patterns(&["*.cfg".to_string()]).unwrap();
+        let excluded = apply_exclude_patterns(&mut manifest, &exclude);
+
+        assert_eq!(excluded, 2);
+        assert_eq!(manifest.file_count, 1);
+        assert_eq!(manifest.total_size, 10);
+        assert!(manifest.files.contains_key("build/app.o"));
+    }
+
+    #[test]
+    fn test_cleanup_preserves_excluded_files() {
+        let temp_dir = TempDir::new().unwrap();
+        // Workspace roots starting with '.' are skipped when finding tracked directories
+        let root = &temp_dir.path().join("workspace");
+        
+        fs::create_dir_all(root.join("build")).unwrap();
+        let local_config = root.join("build/local.cfg");
+        File::create(&local_config).unwrap().write_all(b"local").unwrap();
+        let stale = root.join("build/stale.o");
+        File::create(&stale).unwrap().write_all(b"stale").unwrap();
+        
+        let mut manifest = Manifest::new("test".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        let exclude = fs_utils::compile_patterns(&["build/local.cfg".to_string()]).unwrap();
+        
+        let stats = Arc::new(Mutex::new(LoadStats::default()));
+        cleanup_stale_files(root, &manifest, &exclude, &BTreeSet::new(), None, &LoadOptions::default(), stats).unwrap();
+        
+        assert!(local_config.exists(), "Excluded file should be preserved");
+        assert!(!stale.exists(), "Stale file should be deleted");
+    }
+
+    #[test]
+    fn test_cleanup_keeps_files_older_than_snapshot_cutoff() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = &temp_dir.path().join("workspace");
+        
+        fs::create_dir_all(root.join("build")).unwrap();
+        let old_artifact = root.join("build/old.o");
+        File::create(&old_artifact).unwrap().write_all(b"old").unwrap();
+        filetime::set_file_mtime(&old_artifact, filetime::FileTime::from_unix_time(1_000_000_000, 0)).unwrap();
+        let stale = root.join("build/stale.o");
+        File::create(&stale).unwrap().write_all(b"stale").unwrap();
+        
+        let mut manifest = Manifest::new("test".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec!["*.o".to_string()]);
+        manifest.modified_after = Some(manifest.created_at - chrono::Duration::days(1));
+        
+        let stats = Arc::new(Mutex::new(LoadStats::default()));
+        cleanup_stale_files(root, &manifest, &[], &BTreeSet::new(), None, &LoadOptions::default(), stats).unwrap();
+        
+        assert!(old_artifact.exists(), "Files older than the cutoff were never captured, so they stay");
+        assert!(!stale.exists(), "Newer files missing from the snapshot are still stale");
+    }
+
+    #[test]
+    fn test_cleanup_keeps_empty_files_when_excluded() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = &temp_dir.path().join("workspace");
+
+        fs::create_dir_all(root.join("build")).unwrap();
+        let stamp = root.join("build/.stamp");
+        File::create(&stamp).unwrap();
+        let stale = root.join("build/stale.o");
+        File::create(&stale).unwrap().write_all(b"stale").unwrap();
+
+        let mut manifest = Manifest::new("test".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        manifest.exclude_empty_files = true;
+
+        let stats = Arc::new(Mutex::new(LoadStats::default()));
+        cleanup_stale_files(root, &manifest, &[], &BTreeSet::new(), None, &LoadOptions::default(), stats).unwrap();
+
+        assert!(stamp.exists(), "Empty files were never captured, so they stay");
+        assert!(!stale.exists());
+    }
+
+    #[test]
+    fn test_cleanup_keeps_hidden_entries_when_excluded() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = &temp_dir.path().join("workspace");
+
+        fs::create_dir_all(root.join("build/.deps")).unwrap();
+        fs::create_dir_all(root.join("build/.empty")).unwrap();
+        let deps = root.join("build/.deps/app.d");
+        File::create(&deps).unwrap().write_all(b"deps").unwrap();
+        let stale = root.join("build/stale.o");
+        File::create(&stale).unwrap().write_all(b"stale").unwrap();
+
+        fs::create_dir_all(root.join("conf")).unwrap();
+        let hidden_cfg = root.join("conf/.local.cfg");
+        File::create(&hidden_cfg).unwrap().write_all(b"local").unwrap();
+
+        let mut manifest = Manifest::new("test".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec!["**/*.cfg".to_string()]);
+        manifest.exclude_hidden = true;
+
+        let stats = Arc::new(Mutex::new(LoadStats::default()));
+        cleanup_stale_files(root, &manifest, &[], &BTreeSet::new(), None, &LoadOptions::default(), stats).unwrap();
+        cleanup_empty_directories(root, &manifest, &LoadOptions::default()).unwrap();
+
+        assert!(deps.exists(), "Hidden files were never captured, so they stay");
+        assert!(hidden_cfg.exists(), "Hidden files are not matched by files patterns either");
+        assert!(root.join("build/.empty").exists());
+        assert!(!stale.exists());
+    }
+
+    #[test]
+    fn test_cleanup_moves_stale_files_to_trash() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = &temp_dir.path().join("workspace");
+        
+        fs::create_dir_all(root.join("build/obj")).unwrap();
+        let stale = root.join("build/obj/stale.o");
+        File::create(&stale).unwrap().write_all(b"stale").unwrap();
+        // A trash inside a tracked directory must not be cleaned up itself
+        let trash = root.join("build/.trash/run");
+        fs::create_dir_all(&trash).unwrap();
+        let trashed_earlier = trash.join("old.o");
+        File::create(&trashed_earlier).unwrap().write_all(b"old").unwrap();
+        
+        let mut manifest = Manifest::new("test".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        
+        let stats = Arc::new(Mutex::new(LoadStats::default()));
+        cleanup_stale_files(root, &manifest, &[], &BTreeSet::new(), Some(&trash), &LoadOptions::default(), stats.clone()).unwrap();
+        
+        assert!(!stale.exists(), "Stale file should be moved away");
+        assert_eq!(fs::read(trash.join("build/obj/stale.o")).unwrap(), b"stale");
+        assert!(trashed_earlier.exists(), "Files in the trash should be left alone");
+        assert_eq!(stats.lock().unwrap().removed, 1);
+
+        // A later load trashes into a new directory, and leaves the earlier ones alone
+        let later = root.join("build/.trash/later");
+        File::create(&stale).unwrap().write_all(b"stale again").unwrap();
+        let stats = Arc::new(Mutex::new(LoadStats::default()));
+        cleanup_stale_files(root, &manifest, &[], &BTreeSet::new(), Some(&later), &LoadOptions::default(), stats.clone()).unwrap();
+
+        assert_eq!(stats.lock().unwrap().removed_files, vec!["build/obj/stale.o"]);
+        assert_eq!(fs::read(later.join("build/obj/stale.o")).unwrap(), b"stale again");
+        assert_eq!(fs::read(trash.join("build/obj/stale.o")).unwrap(), b"stale");
+        assert!(trashed_earlier.exists());
+        assert!(!later.join("build/.trash").exists(), "Earlier trash should not be nested in the new one");
+    }
+
+    #[test]
+    fn test_cleanup_fails_on_extra_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = &temp_dir.path().join("workspace");
+
+        fs::create_dir_all(root.join("build/obj")).unwrap();
+        let stale = root.join("build/obj/stale.o");
+        File::create(&stale).unwrap().write_all(b"stale").unwrap();
+
+        let mut manifest = Manifest::new("test".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+
+        let options = LoadOptions { fail_on_extra: true, ..Default::default() };
+        let stats = Arc::new(Mutex::new(LoadStats::default()));
+        let error = cleanup_stale_files(root, &manifest, &[], &BTreeSet::new(), None, &options, stats.clone()).unwrap_err();
+
+        assert!(error.to_string().contains("build/obj/stale.o"), "The extra files should be listed: {}", error);
+        assert!(stale.exists(), "Extra files should be left in place");
+        assert_eq!(stats.lock().unwrap().removed, 0);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_cleanup_preserves_symlinks_when_skipped() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = &temp_dir.path().join("workspace");
+        
+        fs::create_dir_all(root.join("build")).unwrap();
+        let link = root.join("build/current");
+        std::os::unix::fs::symlink("/nonexistent", &link).unwrap();
+        let stale = root.join("build/stale.o");
+        File::create(&stale).unwrap().write_all(b"stale").unwrap();
+        
+        let mut manifest = Manifest::new("test".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        manifest.symlink_policy = SymlinkPolicy::Skip;
+        
+        let stats = Arc::new(Mutex::new(LoadStats::default()));
+        cleanup_stale_files(root, &manifest, &[], &BTreeSet::new(), None, &LoadOptions::default(), stats).unwrap();
+        
+        assert!(link.symlink_metadata().is_ok(), "Skipped symlink should be preserved");
+        assert!(!stale.exists(), "Stale file should be deleted");
+    }
+
     #[test]
     fn test_cleanup_recursive_pattern_deletes_all_matches() {
         let temp_dir = TempDir::new().unwrap();
@@ -1005,12 +2345,14 @@ mod tests {
             symlink_target: None,
             mtime_secs: 0,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         manifest.add_file("config.txt".to_string(), entry);
         
         // SHOULD delete subdir/config.txt because recursive pattern matches it
         let stats = Arc::new(Mutex::new(LoadStats::default()));
-        cleanup_stale_files(root, &manifest, false, false, stats).unwrap();
+        cleanup_stale_files(root, &manifest, &[], &BTreeSet::new(), None, &LoadOptions::default(), stats).unwrap();
         
         assert!(root_config.exists(), "Root config.txt should exist");
         
@@ -1044,11 +2386,13 @@ mod tests {
             symlink_target: None,
             mtime_secs: 0,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         manifest.add_file("data1.bin".to_string(), entry);
         
         let stats = Arc::new(Mutex::new(LoadStats::default()));
-        cleanup_stale_files(root, &manifest, false, false, stats).unwrap();
+        cleanup_stale_files(root, &manifest, &[], &BTreeSet::new(), None, &LoadOptions::default(), stats).unwrap();
         
         // data1.bin should exist (in manifest)
         assert!(root_data1.exists(), "data1.bin should exist");
@@ -1088,11 +2432,13 @@ mod tests {
             symlink_target: None,
             mtime_secs: 0,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         manifest.add_file("data/file1.txt".to_string(), entry);
         
         let stats = Arc::new(Mutex::new(LoadStats::default()));
-        cleanup_stale_files(root, &manifest, false, false, stats).unwrap();
+        cleanup_stale_files(root, &manifest, &[], &BTreeSet::new(), None, &LoadOptions::default(), stats).unwrap();
         
         // file1.txt should exist (in manifest)
         assert!(root_file1.exists(), "data/file1.txt should exist");
@@ -1133,11 +2479,13 @@ mod tests {
             symlink_target: None,
             mtime_secs: 0,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         manifest_root_only.add_file("Makefile".to_string(), entry);
         
         let stats = Arc::new(Mutex::new(LoadStats::default()));
-        cleanup_stale_files(root, &manifest_root_only, false, false, stats).unwrap();
+        cleanup_stale_files(root, &manifest_root_only, &[], &BTreeSet::new(), None, &LoadOptions::default(), stats).unwrap();
         
         // With ./ pattern: subdirectory Makefiles should NOT be deleted
         assert!(root_makefile.exists(), "Root Makefile should exist");
@@ -1176,6 +2524,8 @@ mod tests {
             symlink_target: None,
             mtime_secs: 0,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         manifest.add_file("regular.txt".to_string(), regular_entry);
         
@@ -1189,10 +2539,12 @@ mod tests {
             symlink_target: Some("target.txt".to_string()),
             mtime_secs: 0,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         manifest.add_file("link.txt".to_string(), symlink_entry);
         
-        let existing = scan_existing_files_in_manifest(root, &manifest, ProgressConfig::Auto).unwrap();
+        let existing = scan_existing_files_in_manifest(root, &manifest, ProgressConfig::Auto, None).unwrap();
         
         // Both files should be in the map
         assert_eq!(existing.len(), 2, "Should detect both regular file and symlink");
@@ -1200,6 +2552,702 @@ mod tests {
         assert_eq!(existing.get("link.txt"), Some(&symlink_hash), "Symlink hash should match");
     }
 
+    #[test]
+    fn test_bytes_to_copy_skips_unchanged_files() {
+        let mut manifest = Manifest::new("test".to_string());
+        for (path, hash, size) in [("same.o", "aaa", 100), ("changed.o", "bbb", 20), ("new.o", "ccc", 3)] {
+            manifest.add_file(path.to_string(), FileEntry {
+                hash: hash.to_string(),
+                size,
+                #[cfg(unix)]
+                mode: 0o644,
+                is_symlink: false,
+                symlink_target: None,
+                mtime_secs: 0,
+                mtime_nanos: 0,
+                assumed: false,
+                is_text: false,
+            });
+        }
+        let existing = HashMap::from([
+            ("same.o".to_string(), "aaa".to_string()),
+            ("changed.o".to_string(), "old".to_string()),
+        ]);
+
+        assert_eq!(bytes_to_copy(&manifest, &existing), 23);
+    }
+
+    #[test]
+    fn test_scan_existing_uses_hash_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        
+        let path = root.join("app.o");
+        fs::write(&path, b"original").unwrap();
+        let original_hash = file_hash::hash_file(&path).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        let mtime = filetime::FileTime::from_last_modification_time(&metadata);
+        
+        let mut manifest = Manifest::new("test".to_string());
+        manifest.add_file("app.o".to_string(), FileEntry {
+            hash: original_hash.clone(),
+            size: 8,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: mtime.unix_seconds(),
+            mtime_nanos: mtime.nanoseconds(),
+            assumed: false,
+            is_text: false,
+        });
+        
+        let mut cache = HashCache::new();
+        cache.insert(&path, metadata.len(), metadata.modified().unwrap(), original_hash.clone());
+        let cache = Mutex::new(cache);
+        
+        // Change the content but keep size and mtime, so only a real read could notice
+        fs::write(&path, b"modified").unwrap();
+        filetime::set_file_mtime(&path, mtime).unwrap();
+        
+        let cached = scan_existing_files_in_manifest(root, &manifest, ProgressConfig::ForceDisable, Some(&cache)).unwrap();
+        assert_eq!(cached.get("app.o"), Some(&original_hash), "Cache hit should skip reading the file");
+        
+        let rehashed = scan_existing_files_in_manifest(root, &manifest, ProgressConfig::ForceDisable, None).unwrap();
+        assert_ne!(rehashed.get("app.o"), Some(&original_hash), "Without the cache the file is rehashed");
+    }
+
+    #[test]
+    fn test_load_snapshot_outside_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        let outside = temp_dir.path().join("out");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("app.o"), b"object").unwrap();
+
+        let config = crate::config::Config {
+            directories: vec![outside.to_string_lossy().to_string()],
+            allow_outside_root: true,
+            ..Default::default()
+        };
+        let manifest = crate::snapshot::create_snapshot(&root, "snap", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        manifest.save(&root).unwrap();
+
+        fs::write(outside.join("app.o"), b"rebuilt").unwrap();
+        fs::write(outside.join("stale.o"), b"stale").unwrap();
+
+        let stats = load_snapshot(&root, "snap", &LoadOptions::default(), ProgressConfig::ForceDisable).unwrap();
+
+        assert_eq!(stats.removed_files, vec!["../out/stale.o"]);
+        assert_eq!(fs::read(outside.join("app.o")).unwrap(), b"object");
+        assert!(!outside.join("stale.o").exists());
+
+        // The same snapshot cannot be put back from another workspace root
+        let other_root = temp_dir.path().join("other");
+        fs::create_dir_all(&other_root).unwrap();
+        let result = check_outside_base(&other_root, &manifest);
+        assert!(result.unwrap_err().to_string().contains("load it from there"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_load_sparse_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("build/obj")).unwrap();
+        fs::create_dir_all(root.join("build/private")).unwrap();
+        fs::create_dir_all(root.join("build/empty")).unwrap();
+        fs::write(root.join("build/obj/a.o"), b"object").unwrap();
+        fs::write(root.join("build/private/key"), b"secret").unwrap();
+        fs::set_permissions(root.join("build"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(root.join("build/obj"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(root.join("build/private"), fs::Permissions::from_mode(0o700)).unwrap();
+        // Parents of tracked directories and of pattern matches are never recorded, sparse or not
+        fs::create_dir_all(root.join("src/x/build")).unwrap();
+        fs::write(root.join("src/x/build/gen.h"), b"header").unwrap();
+        fs::create_dir_all(root.join("secrets")).unwrap();
+        fs::write(root.join("secrets/db.cfg"), b"password").unwrap();
+        fs::set_permissions(root.join("src"), fs::Permissions::from_mode(0o750)).unwrap();
+        fs::set_permissions(root.join("secrets"), fs::Permissions::from_mode(0o700)).unwrap();
+
+        let config = crate::config::Config {
+            directories: vec!["build".to_string()],
+            files: vec!["secrets/*.cfg".to_string()],
+            sparse_directories: true,
+            ..Default::default()
+        };
+        let manifest = crate::snapshot::create_snapshot(root, "snap", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        manifest.save(root).unwrap();
+
+        let mut recorded: Vec<&str> = manifest.directories.keys().map(String::as_str).collect();
+        recorded.sort();
+        assert_eq!(recorded, vec!["build/empty", "build/private"]);
+        assert_eq!(implied_directories(&manifest), vec!["build", "build/obj", "src/x/build"]);
+
+        fs::remove_dir_all(root.join("build")).unwrap();
+        load_snapshot(root, "snap", &LoadOptions::default(), ProgressConfig::ForceDisable).unwrap();
+
+        assert_eq!(fs::read(root.join("build/obj/a.o")).unwrap(), b"object");
+        assert!(root.join("build/empty").is_dir());
+        let mode = |path: &str| fs::metadata(root.join(path)).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode("build"), 0o755);
+        assert_eq!(mode("build/obj"), 0o755);
+        assert_eq!(mode("build/private"), 0o700);
+        assert_eq!(mode("src"), 0o750);
+        assert_eq!(mode("secrets"), 0o700);
+    }
+
+    #[test]
+    fn test_restore_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("build/obj")).unwrap();
+        fs::write(root.join("build/app"), b"binary").unwrap();
+        fs::write(root.join("build/obj/a.o"), b"object").unwrap();
+
+        let config = crate::config::Config { directories: vec!["build".to_string()], ..Default::default() };
+        let manifest = crate::snapshot::create_snapshot(root, "snap", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        manifest.save(root).unwrap();
+
+        fs::remove_dir_all(root.join("build/obj")).unwrap();
+        fs::write(root.join("build/app"), b"rebuilt").unwrap();
+        fs::write(root.join("build/new.o"), b"extra").unwrap();
+
+        // A hard-linked twin of a restored file keeps its content
+        fs::hard_link(root.join("build/app"), root.join("build/app.twin")).unwrap();
+
+        let restored = restore_files(root, "snap", &["./build/obj/a.o".to_string(), "build/app".to_string()], false).unwrap();
+        assert_eq!(restored, vec!["build/obj/a.o", "build/app"]);
+        assert_eq!(fs::read(root.join("build/app")).unwrap(), b"binary");
+        assert_eq!(fs::read(root.join("build/app.twin")).unwrap(), b"rebuilt");
+        assert_eq!(fs::read(root.join("build/obj/a.o")).unwrap(), b"object");
+        let mtime = filetime::FileTime::from_last_modification_time(&fs::metadata(root.join("build/obj/a.o")).unwrap());
+        assert_eq!(mtime.unix_seconds(), manifest.files["build/obj/a.o"].mtime_secs);
+
+        // Everything else stays as it is
+        assert!(root.join("build/new.o").exists());
+
+        fs::write(root.join("build/obj/a.o"), b"rebuilt").unwrap();
+        let err = restore_files(root, "snap", &["build/obj/a.o".to_string(), "build/gone".to_string()], false).unwrap_err();
+        assert!(err.to_string().contains("build/gone"), "{}", err);
+        assert_eq!(fs::read(root.join("build/obj/a.o")).unwrap(), b"rebuilt");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_metadata() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("build")).unwrap();
+        fs::write(root.join("build/app"), b"binary").unwrap();
+        fs::write(root.join("build/lib.a"), b"archive").unwrap();
+        fs_utils::set_file_mode(&root.join("build/app"), 0o755).unwrap();
+        fs_utils::set_file_mode(&root.join("build/lib.a"), 0o644).unwrap();
+
+        let config = crate::config::Config { directories: vec!["build".to_string()], ..Default::default() };
+        let manifest = crate::snapshot::create_snapshot(root, "snap", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        assert!(check_metadata(root, &manifest).unwrap().is_empty());
+
+        fs_utils::set_file_mode(&root.join("build/app"), 0o700).unwrap();
+        fs_utils::set_file_mtime(&root.join("build/lib.a"), 1_000_000_000, 0).unwrap();
+
+        let drift = check_metadata(root, &manifest).unwrap();
+        assert_eq!(drift.len(), 2);
+        assert_eq!(drift[0].path, "build/app");
+        assert_eq!(drift[0].mode, Some((0o755, 0o700)));
+        assert!(drift[0].mtime.is_none());
+        assert_eq!(drift[1].path, "build/lib.a");
+        assert!(drift[1].mode.is_none());
+        assert_eq!(drift[1].mtime.unwrap().1, (1_000_000_000, 0));
+
+        for entry in &drift {
+            entry.fix(root).unwrap();
+        }
+        assert!(check_metadata(root, &manifest).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_load_snapshot_newer_store_format() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        Store::new(root).init().unwrap();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.store_format_version = STORE_FORMAT_VERSION + 1;
+        manifest.save(root).unwrap();
+
+        let result = load_snapshot(root, "snap", &LoadOptions::default(), ProgressConfig::ForceDisable);
+        assert!(result.unwrap_err().to_string().contains("upgrade kibo"));
+
+        manifest.store_format_version = STORE_FORMAT_VERSION;
+        assert!(check_store_format(&manifest).is_ok());
+    }
+
+    #[test]
+    fn test_load_snapshot_report_only_writes_nothing() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        let build = root.join("build");
+        fs::create_dir_all(&build).unwrap();
+
+        let store = Store::new(&root);
+        store.init().unwrap();
+
+        let source = root.join("source.o");
+        fs::write(&source, b"object").unwrap();
+        let hash = file_hash::hash_file(&source).unwrap();
+        store.store_file(&source, &hash).unwrap();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        manifest.add_file("build/app.o".to_string(), FileEntry {
+            hash,
+            size: 6,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
+        });
+        manifest.save(&root).unwrap();
+        fs::write(build.join("stale.o"), b"stale").unwrap();
+
+        let options = LoadOptions { report_only: true, ..LoadOptions::default() };
+        let stats = load_snapshot(&root, "snap", &options, ProgressConfig::ForceDisable).unwrap();
+
+        assert_eq!(stats.copies, 1);
+        assert_eq!(stats.removed, 1);
+        assert!(!build.join("app.o").exists());
+        assert!(build.join("stale.o").exists());
+
+        let report = serde_json::to_value(&stats).unwrap();
+        assert_eq!(report["copies"], 1);
+        assert_eq!(report["copied_files"][0], "build/app.o");
+        assert_eq!(report["removed_files"][0], "build/stale.o");
+    }
+
+    #[test]
+    fn test_load_snapshot_verify_blobs_first() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        let build = root.join("build");
+        fs::create_dir_all(&build).unwrap();
+
+        let store = Store::new(&root);
+        store.init().unwrap();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        for (name, content) in [("good.o", "good"), ("bad.o", "bad")] {
+            let source = root.join(name);
+            fs::write(&source, content).unwrap();
+            let hash = file_hash::hash_file(&source).unwrap();
+            store.store_file(&source, &hash).unwrap();
+            manifest.add_file(format!("build/{}", name), FileEntry {
+                hash,
+                size: content.len() as u64,
+                #[cfg(unix)]
+                mode: 0o644,
+                is_symlink: false,
+                symlink_target: None,
+                mtime_secs: 0,
+                mtime_nanos: 0,
+                assumed: false,
+                is_text: false,
+            });
+        }
+        manifest.save(&root).unwrap();
+        fs::write(build.join("stale.o"), b"stale").unwrap();
+
+        let bad_blob = store.blob_path(&manifest.files["build/bad.o"].hash);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&bad_blob, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        fs::write(&bad_blob, b"tampered").unwrap();
+
+        let options = LoadOptions { verify_blobs_first: true, ..LoadOptions::default() };
+        let err = load_snapshot(&root, "snap", &options, ProgressConfig::ForceDisable).unwrap_err();
+        assert!(format!("{:#}", err).contains("build/bad.o"));
+
+        // Nothing was restored or cleaned up
+        assert!(!build.join("good.o").exists());
+        assert!(build.join("stale.o").exists());
+    }
+
+    #[test]
+    fn test_load_snapshot_verify() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build")).unwrap();
+
+        let store = Store::new(&root);
+        store.init().unwrap();
+
+        let source = root.join("source");
+        fs::write(&source, b"content").unwrap();
+        let hash = file_hash::hash_file(&source).unwrap();
+        store.store_file(&source, &hash).unwrap();
+        fs::remove_file(&source).unwrap();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        manifest.add_file("build/app.o".to_string(), FileEntry {
+            hash: hash.clone(),
+            size: 7,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
+        });
+        manifest.save(&root).unwrap();
+
+        let options = LoadOptions { verify: true, ..LoadOptions::default() };
+        load_snapshot(&root, "snap", &options, ProgressConfig::ForceDisable).unwrap();
+        assert_eq!(fs::read(root.join("build/app.o")).unwrap(), b"content");
+
+        let blob = store.blob_path(&hash);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&blob, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        fs::write(&blob, b"tampered").unwrap();
+        fs::remove_file(root.join("build/app.o")).unwrap();
+
+        let err = load_snapshot(&root, "snap", &options, ProgressConfig::ForceDisable).unwrap_err();
+        assert!(format!("{:#}", err).contains("build/app.o"), "{:#}", err);
+        assert!(!root.join("build/app.o").exists());
+
+        // Without --verify the corrupt content is restored as it is
+        load_snapshot(&root, "snap", &LoadOptions::default(), ProgressConfig::ForceDisable).unwrap();
+        assert_eq!(fs::read(root.join("build/app.o")).unwrap(), b"tampered");
+    }
+
+    #[test]
+    fn test_load_snapshot_keeps_renamed_conflicts() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        let build = root.join("build");
+        fs::create_dir_all(&build).unwrap();
+
+        let store = Store::new(&root);
+        store.init().unwrap();
+
+        let source = root.join("source");
+        fs::write(&source, "snapshot").unwrap();
+        let hash = file_hash::hash_file(&source).unwrap();
+        store.store_file(&source, &hash).unwrap();
+        fs::remove_file(&source).unwrap();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        manifest.add_file("build/app.cfg".to_string(), FileEntry {
+            hash,
+            size: 8,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: true,
+        });
+        manifest.save(&root).unwrap();
+
+        let options = LoadOptions { rename_conflicts: Some(".local".to_string()), ..LoadOptions::default() };
+        for edit in ["first edits", "second edits"] {
+            fs::write(build.join("app.cfg"), edit).unwrap();
+            load_snapshot(&root, "snap", &options, ProgressConfig::ForceDisable).unwrap();
+        }
+        assert_eq!(fs::read(build.join("app.cfg")).unwrap(), b"snapshot");
+        assert_eq!(fs::read(build.join("app.cfg.local")).unwrap(), b"first edits");
+        assert_eq!(fs::read(build.join("app.cfg.local.1")).unwrap(), b"second edits");
+
+        // Later loads neither remove the kept versions nor report them
+        let options = LoadOptions { report_drift: true, ..LoadOptions::default() };
+        let stats = load_snapshot(&root, "snap", &options, ProgressConfig::ForceDisable).unwrap();
+        assert_eq!(stats.removed, 0);
+        assert_eq!(stats.drift, Some(DriftReport::default()));
+        assert!(build.join("app.cfg.local").exists());
+
+        // Once a kept version is gone it is forgotten, and the name becomes stale again
+        fs::remove_file(build.join("app.cfg.local.1")).unwrap();
+        load_snapshot(&root, "snap", &LoadOptions::default(), ProgressConfig::ForceDisable).unwrap();
+        assert_eq!(read_kept_conflicts(&root).unwrap(), BTreeSet::from(["build/app.cfg.local".to_string()]));
+    }
+
+    #[test]
+    fn test_load_snapshot_report_drift() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        let build = root.join("build");
+        fs::create_dir_all(&build).unwrap();
+
+        let store = Store::new(&root);
+        store.init().unwrap();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        for (name, content) in [("app", "app"), ("core.a", "core"), ("gone.a", "gone")] {
+            let source = root.join("source");
+            fs::write(&source, content).unwrap();
+            let hash = file_hash::hash_file(&source).unwrap();
+            store.store_file(&source, &hash).unwrap();
+            manifest.add_file(format!("build/{}", name), FileEntry {
+                hash,
+                size: content.len() as u64,
+                #[cfg(unix)]
+                mode: 0o644,
+                is_symlink: false,
+                symlink_target: None,
+                mtime_secs: 0,
+                mtime_nanos: 0,
+                assumed: false,
+                is_text: false,
+            });
+        }
+        manifest.save(&root).unwrap();
+        fs::remove_file(root.join("source")).unwrap();
+        fs::write(build.join("stale.o"), b"stale").unwrap();
+
+        let options = LoadOptions { report_drift: true, ..LoadOptions::default() };
+        let stats = load_snapshot(&root, "snap", &options, ProgressConfig::ForceDisable).unwrap();
+        assert_eq!(stats.drift, Some(DriftReport::default()));
+
+        fs::write(build.join("app"), b"changed").unwrap();
+        fs::remove_file(build.join("gone.a")).unwrap();
+        fs::write(build.join("extra.o"), b"extra").unwrap();
+        #[cfg(unix)]
+        fs_utils::set_file_mode(&build.join("core.a"), 0o600).unwrap();
+
+        let drift = check_drift(&root, &manifest, &[], ProgressConfig::ForceDisable).unwrap();
+        assert!(!drift.is_clean());
+        assert_eq!(drift.modified, vec!["build/app"]);
+        assert_eq!(drift.missing, vec!["build/gone.a"]);
+        assert_eq!(drift.extra, vec!["build/extra.o"]);
+        #[cfg(unix)]
+        assert_eq!(drift.permissions, vec!["build/core.a"]);
+    }
+
+    #[test]
+    fn test_check_drift_against_other_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let deployed = temp_dir.path().join("deployed");
+        fs::create_dir_all(deployed.join("build")).unwrap();
+        fs::write(deployed.join("build/app"), b"app").unwrap();
+        fs::write(deployed.join("build/extra.o"), b"extra").unwrap();
+        #[cfg(unix)]
+        fs_utils::set_file_mode(&deployed.join("build/app"), 0o644).unwrap();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        for (name, content) in [("app", "app"), ("lib.a", "lib")] {
+            manifest.add_file(format!("build/{}", name), FileEntry {
+                hash: blake3::hash(content.as_bytes()).to_hex().to_string(),
+                size: content.len() as u64,
+                #[cfg(unix)]
+                mode: 0o644,
+                is_symlink: false,
+                symlink_target: None,
+                mtime_secs: 0,
+                mtime_nanos: 0,
+                assumed: false,
+                is_text: false,
+            });
+        }
+
+        let drift = check_drift(&deployed, &manifest, &[], ProgressConfig::ForceDisable).unwrap();
+        assert!(drift.modified.is_empty());
+        assert!(drift.permissions.is_empty());
+        assert_eq!(drift.missing, vec!["build/lib.a"]);
+        assert_eq!(drift.extra, vec!["build/extra.o"]);
+    }
+
+    #[test]
+    fn test_load_snapshot_atomic() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        let build = root.join("build");
+        fs::create_dir_all(&build).unwrap();
+
+        let store = Store::new(&root);
+        store.init().unwrap();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        for (name, content) in [("app", "new app"), ("lib/core.a", "core")] {
+            let source = root.join("source");
+            fs::write(&source, content).unwrap();
+            let hash = file_hash::hash_file(&source).unwrap();
+            store.store_file(&source, &hash).unwrap();
+            manifest.add_file(format!("build/{}", name), FileEntry {
+                hash,
+                size: content.len() as u64,
+                #[cfg(unix)]
+                mode: 0o644,
+                is_symlink: false,
+                symlink_target: None,
+                mtime_secs: 0,
+                mtime_nanos: 0,
+                assumed: false,
+                is_text: false,
+            });
+        }
+        manifest.save(&root).unwrap();
+        fs::remove_file(root.join("source")).unwrap();
+
+        fs::write(build.join("app"), b"old app").unwrap();
+        fs::write(build.join("stale.o"), b"stale").unwrap();
+        let old_app = root.join("old_app");
+        fs::hard_link(build.join("app"), &old_app).unwrap();
+
+        let options = LoadOptions { atomic: true, ..LoadOptions::default() };
+        let stats = load_snapshot(&root, "snap", &options, ProgressConfig::ForceDisable).unwrap();
+        assert_eq!(stats.copies, 2);
+        assert_eq!(stats.removed, 1);
+
+        assert_eq!(fs::read(build.join("app")).unwrap(), b"new app");
+        assert_eq!(fs::read(build.join("lib/core.a")).unwrap(), b"core");
+        assert!(!build.join("stale.o").exists());
+        assert_eq!(fs::read(&old_app).unwrap(), b"old app", "Staged hard links are replaced, not written through");
+        assert!(!crate::config::get_load_staging_dir(&root).exists());
+
+        // Snapshots that track individual files cannot be swapped as directories
+        manifest.set_tracked_paths(vec!["build".to_string()], vec!["Makefile".to_string()]);
+        manifest.save(&root).unwrap();
+        let err = load_snapshot(&root, "snap", &options, ProgressConfig::ForceDisable).unwrap_err();
+        assert!(err.to_string().contains("--atomic"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_load_snapshot_links_identical_files() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        let build = root.join("build");
+        fs::create_dir_all(&build).unwrap();
+
+        let store = Store::new(&root);
+        store.init().unwrap();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.set_tracked_paths(vec!["build".to_string()], vec![]);
+        manifest.link_identical = true;
+        let files = [
+            ("a.so", "lib", 0o644, 0),
+            ("b.so", "lib", 0o644, 0),
+            ("c.so", "lib", 0o755, 0),
+            ("d.so", "other", 0o644, 0),
+            ("e.so", "lib", 0o644, 1_000_000),
+        ];
+        for (name, content, mode, mtime_secs) in files {
+            let source = root.join(name);
+            fs::write(&source, content).unwrap();
+            let hash = file_hash::hash_file(&source).unwrap();
+            store.store_file(&source, &hash).unwrap();
+            manifest.add_file(format!("build/{}", name), FileEntry {
+                hash,
+                size: content.len() as u64,
+                mode,
+                is_symlink: false,
+                symlink_target: None,
+                mtime_secs,
+                mtime_nanos: 0,
+                assumed: false,
+                is_text: false,
+            });
+        }
+        manifest.save(&root).unwrap();
+
+        let stats = load_snapshot(&root, "snap", &LoadOptions::default(), ProgressConfig::ForceDisable).unwrap();
+        assert_eq!(stats.copies, 5);
+        assert_eq!(stats.linked, 1);
+
+        let inode = |name: &str| fs::metadata(build.join(name)).unwrap().ino();
+        assert_eq!(inode("a.so"), inode("b.so"));
+        assert_ne!(inode("a.so"), inode("c.so"), "Files with a different mode are copied");
+        assert_ne!(inode("a.so"), inode("e.so"), "Files with a different mtime are copied");
+        assert_eq!(fs::metadata(build.join("a.so")).unwrap().mtime(), 0);
+        assert_eq!(fs::metadata(build.join("e.so")).unwrap().mtime(), 1_000_000);
+        assert_eq!(fs::read(build.join("b.so")).unwrap(), b"lib");
+        assert_eq!(fs::read(build.join("d.so")).unwrap(), b"other");
+
+        // Without the flag every file is copied
+        fs::remove_dir_all(&build).unwrap();
+        manifest.link_identical = false;
+        manifest.save(&root).unwrap();
+        let stats = load_snapshot(&root, "snap", &LoadOptions::default(), ProgressConfig::ForceDisable).unwrap();
+        assert_eq!(stats.linked, 0);
+        assert_ne!(inode("a.so"), inode("b.so"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_load_does_not_write_through_linked_duplicates() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        let build = root.join("build");
+        fs::create_dir_all(&build).unwrap();
+
+        let store = Store::new(&root);
+        store.init().unwrap();
+
+        let blob = |content: &str| {
+            let source = root.join("source");
+            fs::write(&source, content).unwrap();
+            let hash = file_hash::hash_file(&source).unwrap();
+            store.store_file(&source, &hash).unwrap();
+            FileEntry {
+                hash,
+                size: content.len() as u64,
+                mode: 0o644,
+                is_symlink: false,
+                symlink_target: None,
+                mtime_secs: 0,
+                mtime_nanos: 0,
+                assumed: false,
+                is_text: true,
+            }
+        };
+        let same = blob("lib");
+        let changed = blob("new lib");
+
+        let mut first = Manifest::new("first".to_string());
+        first.set_tracked_paths(vec!["build".to_string()], vec![]);
+        first.link_identical = true;
+        first.add_file("build/a.so".to_string(), same.clone());
+        first.add_file("build/b.so".to_string(), same.clone());
+        first.save(&root).unwrap();
+
+        let mut second = Manifest::new("second".to_string());
+        second.set_tracked_paths(vec!["build".to_string()], vec![]);
+        second.add_file("build/a.so".to_string(), same);
+        second.add_file("build/b.so".to_string(), changed);
+        second.save(&root).unwrap();
+
+        let stats = load_snapshot(&root, "first", &LoadOptions::default(), ProgressConfig::ForceDisable).unwrap();
+        assert_eq!(stats.linked, 1);
+        load_snapshot(&root, "second", &LoadOptions::default(), ProgressConfig::ForceDisable).unwrap();
+
+        assert_eq!(fs::read(build.join("a.so")).unwrap(), b"lib");
+        assert_eq!(fs::read(build.join("b.so")).unwrap(), b"new lib");
+    }
+
     #[test]
     #[cfg(unix)]
     fn test_symlink_unchanged_not_recreated() {
@@ -1241,12 +3289,14 @@ mod tests {
             symlink_target: Some("target.txt".to_string()),
             mtime_secs: 0,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         manifest.add_file("link.txt".to_string(), entry);
         
         // Create existing_files map with the symlink
         let existing_files: HashMap<String, String> = 
-            scan_existing_files_in_manifest(root, &manifest, ProgressConfig::Auto).unwrap();
+            scan_existing_files_in_manifest(root, &manifest, ProgressConfig::Auto, None).unwrap();
         
         // Load the file (should skip because unchanged)
         let stats = Arc::new(Mutex::new(LoadStats::default()));
@@ -1256,11 +3306,10 @@ mod tests {
             root,
             "link.txt",
             manifest.files.get("link.txt").unwrap(),
-            &existing_files,
+            &LoadPlan { existing: existing_files, link_to: HashMap::new(), keep_as: HashMap::new() },
             &store,
             stats.clone(),
-            false,
-            false,
+            &LoadOptions::default(),
             &progress,
         ).unwrap();
         
@@ -1315,12 +3364,14 @@ mod tests {
             symlink_target: Some("new_target.txt".to_string()),
             mtime_secs: 0,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         manifest.add_file("link.txt".to_string(), entry);
         
         // existing_files will have the OLD hash
         let existing_files: HashMap<String, String> = 
-            scan_existing_files_in_manifest(root, &manifest, ProgressConfig::Auto).unwrap();
+            scan_existing_files_in_manifest(root, &manifest, ProgressConfig::Auto, None).unwrap();
         
         // Load the file (should recreate because changed)
         let stats = Arc::new(Mutex::new(LoadStats::default()));
@@ -1330,11 +3381,10 @@ mod tests {
             root,
             "link.txt",
             manifest.files.get("link.txt").unwrap(),
-            &existing_files,
+            &LoadPlan { existing: existing_files, link_to: HashMap::new(), keep_as: HashMap::new() },
             &store,
             stats.clone(),
-            false,
-            false,
+            &LoadOptions::default(),
             &progress,
         ).unwrap();
         
@@ -1348,5 +3398,59 @@ mod tests {
         assert_eq!(final_stats.unchanged, 0, "Should not count as unchanged");
         assert_eq!(final_stats.symlink_files.len(), 1, "Should be in symlink list");
     }
+
+    #[test]
+    fn test_load_single_file_renames_conflicts() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let store = Store::new(root);
+        store.init().unwrap();
+
+        let snapshot_file = root.join("snapshot.cfg");
+        fs::write(&snapshot_file, b"snapshot").unwrap();
+        let hash = file_hash::hash_file(&snapshot_file).unwrap();
+        store.store_file(&snapshot_file, &hash).unwrap();
+
+        let mut manifest = Manifest::new("test".to_string());
+        for path in ["app.cfg", "new.cfg"] {
+            manifest.add_file(path.to_string(), FileEntry {
+                hash: hash.clone(),
+                size: 8,
+                #[cfg(unix)]
+                mode: 0o644,
+                is_symlink: false,
+                symlink_target: None,
+                mtime_secs: 0,
+                mtime_nanos: 0,
+                assumed: false,
+                is_text: true,
+            });
+        }
+        fs::write(root.join("app.cfg"), b"local edits").unwrap();
+
+        let existing = scan_existing_files_in_manifest(root, &manifest, ProgressConfig::ForceDisable, None).unwrap();
+        let plan = LoadPlan {
+            keep_as: kept_conflict_names(root, &manifest, &existing, Some(".local")),
+            existing,
+            link_to: HashMap::new(),
+        };
+        let stats = Arc::new(Mutex::new(LoadStats::default()));
+        let progress = ByteProgress::new(0, ProgressConfig::ForceDisable);
+        let options = LoadOptions { rename_conflicts: Some(".local".to_string()), ..Default::default() };
+
+        for path in ["app.cfg", "new.cfg"] {
+            load_single_file(root, path, &manifest.files[path], &plan, &store, stats.clone(), &options, &progress).unwrap();
+        }
+
+        assert_eq!(fs::read(root.join("app.cfg")).unwrap(), b"snapshot");
+        assert_eq!(fs::read(root.join("app.cfg.local")).unwrap(), b"local edits");
+        assert_eq!(fs::read(root.join("new.cfg")).unwrap(), b"snapshot");
+        assert!(!root.join("new.cfg.local").exists(), "Files that did not exist have nothing to keep");
+
+        let final_stats = stats.lock().unwrap();
+        assert_eq!(final_stats.copies, 2);
+        assert_eq!(final_stats.renamed, 1);
+        assert_eq!(final_stats.renamed_files, vec!["app.cfg.local"]);
+    }
 }
 
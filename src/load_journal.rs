@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::get_load_state_path;
+use crate::fs_utils;
+use crate::manifest::{FileEntry, Manifest};
+
+/// How often the journal is written while files are being restored
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What is written to `.kibo/load_state.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LoadState {
+    snapshot: String,
+    /// Content root of the snapshot, so a snapshot re-saved under the same name is not resumed
+    content_root: String,
+    /// Paths restored so far
+    completed: BTreeSet<String>,
+}
+
+/// Journal of the files a `load --resume` has restored, so that an interrupted load can skip
+/// them when run again instead of rehashing them
+#[derive(Debug)]
+pub struct LoadJournal {
+    path: PathBuf,
+    state: Mutex<(LoadState, Instant)>,
+    /// Number of paths taken over from an interrupted load
+    resumed: usize,
+}
+
+impl LoadJournal {
+    /// Pick up the journal left by an interrupted load of the same snapshot, or start a new one
+    pub fn open(root: &Path, manifest: &Manifest) -> Result<Self> {
+        let path = get_load_state_path(root);
+        let content_root = manifest.content_root.clone().unwrap_or_else(|| manifest.compute_content_root());
+
+        let previous = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str::<LoadState>(&content).ok(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read load journal: {}", path.display())),
+        };
+        let completed = previous
+            .filter(|state| state.snapshot == manifest.name && state.content_root == content_root)
+            .map(|state| state.completed)
+            .unwrap_or_default();
+
+        Ok(Self {
+            path,
+            resumed: completed.len(),
+            state: Mutex::new((
+                LoadState { snapshot: manifest.name.clone(), content_root, completed },
+                Instant::now(),
+            )),
+        })
+    }
+
+    /// Number of paths the interrupted load had restored
+    pub fn resumed(&self) -> usize {
+        self.resumed
+    }
+
+    /// Whether a path was restored by the interrupted load and still looks the way it was
+    /// left, judging by its metadata alone
+    pub fn is_restored(&self, root: &Path, relative_path: &str, entry: &FileEntry) -> bool {
+        let state = self.state.lock().expect("Load journal mutex poisoned");
+        if !state.0.completed.contains(relative_path) {
+            return false;
+        }
+
+        let path = root.join(relative_path);
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            return false;
+        };
+        if entry.is_symlink {
+            return metadata.is_symlink()
+                && fs::read_link(&path).is_ok_and(|target| {
+                    entry.symlink_target.as_deref() == Some(target.to_string_lossy().as_ref())
+                });
+        }
+
+        let mtime = filetime::FileTime::from_last_modification_time(&metadata);
+        metadata.is_file()
+            && metadata.len() == entry.size
+            && mtime.unix_seconds() == entry.mtime_secs
+            && mtime.nanoseconds() == entry.mtime_nanos
+    }
+
+    /// Record a restored path, writing the journal if it has not been written for a while
+    pub fn record(&self, relative_path: &str) -> Result<()> {
+        let mut state = self.state.lock().expect("Load journal mutex poisoned");
+        state.0.completed.insert(relative_path.to_string());
+        if state.1.elapsed() >= FLUSH_INTERVAL {
+            self.write(&state.0)?;
+            state.1 = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Write the journal now, e.g. before giving up on a failed load
+    pub fn flush(&self) -> Result<()> {
+        let mut state = self.state.lock().expect("Load journal mutex poisoned");
+        self.write(&state.0)?;
+        state.1 = Instant::now();
+        Ok(())
+    }
+
+    fn write(&self, state: &LoadState) -> Result<()> {
+        let content = serde_json::to_string(state).context("Failed to serialize load journal")?;
+        fs_utils::atomic_write(&self.path, content.as_bytes())
+            .with_context(|| format!("Failed to write load journal: {}", self.path.display()))
+    }
+
+    /// Remove the journal of a load that completed
+    pub fn remove(root: &Path) -> Result<()> {
+        let path = get_load_state_path(root);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove load journal: {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(size: u64) -> FileEntry {
+        FileEntry {
+            hash: "abc123".to_string(),
+            size,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: 1_000_000_000,
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
+        }
+    }
+
+    #[test]
+    fn test_load_journal_resume() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join(".kibo")).unwrap();
+        fs::write(root.join("done.o"), b"done").unwrap();
+        filetime::set_file_mtime(root.join("done.o"), filetime::FileTime::from_unix_time(1_000_000_000, 0)).unwrap();
+
+        let mut manifest = Manifest::new("snap".to_string());
+        manifest.add_file("done.o".to_string(), entry(4));
+        manifest.add_file("todo.o".to_string(), entry(4));
+
+        let journal = LoadJournal::open(root, &manifest).unwrap();
+        assert_eq!(journal.resumed(), 0);
+        journal.record("done.o").unwrap();
+        journal.flush().unwrap();
+
+        let journal = LoadJournal::open(root, &manifest).unwrap();
+        assert_eq!(journal.resumed(), 1);
+        assert!(journal.is_restored(root, "done.o", &entry(4)));
+        assert!(!journal.is_restored(root, "todo.o", &entry(4)));
+        // A file changed since it was restored is loaded again
+        assert!(!journal.is_restored(root, "done.o", &entry(5)));
+
+        // A different snapshot does not pick up the journal
+        let other = Manifest::new("other".to_string());
+        assert_eq!(LoadJournal::open(root, &other).unwrap().resumed(), 0);
+
+        LoadJournal::remove(root).unwrap();
+        assert!(!get_load_state_path(root).exists());
+        LoadJournal::remove(root).unwrap();
+    }
+}
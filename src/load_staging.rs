@@ -0,0 +1,260 @@
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::config::get_load_staging_dir;
+use crate::fs_utils;
+
+/// Staging copy of the tracked directories that `load --atomic` restores into. The restored
+/// directories are then renamed into the workspace one after another, so each one changes
+/// from its old to its new state at once.
+#[derive(Debug)]
+pub struct LoadStaging {
+    /// Where the snapshot is restored, laid out like the workspace
+    dir: PathBuf,
+    /// Where the replaced directories are moved during the swap
+    replaced_dir: PathBuf,
+    /// Directories swapped as a whole, relative to the workspace root
+    roots: Vec<PathBuf>,
+    /// Set when a replaced directory could not be put back, so it must outlive the staging
+    keep: bool,
+}
+
+impl LoadStaging {
+    /// Set up the staging directory with a copy of each root that exists in the workspace.
+    /// Files are hard-linked where possible, so staging costs little space or time; the load
+    /// must then replace staged files instead of writing to them.
+    pub fn prepare(root: &Path, roots: Vec<PathBuf>) -> Result<Self> {
+        let base = get_load_staging_dir(root);
+        let staging = Self { dir: base.join("new"), replaced_dir: base.join("old"), roots, keep: false };
+
+        // Replaced directories are only left behind when they could not be put back
+        if staging.replaced_dir.exists() {
+            bail!(
+                "A previous load left original directories in {}; move them back into the workspace or remove it first",
+                staging.replaced_dir.display()
+            );
+        }
+        // Left behind by a load that was killed before it could clean up
+        if base.exists() {
+            fs::remove_dir_all(&base)
+                .with_context(|| format!("Failed to remove old staging directory: {}", base.display()))?;
+        }
+        fs::create_dir_all(&staging.dir)
+            .with_context(|| format!("Failed to create staging directory: {}", staging.dir.display()))?;
+
+        for relative_root in &staging.roots {
+            let live = root.join(relative_root);
+            if live.is_dir() {
+                stage_tree(&live, &staging.dir.join(relative_root))
+                    .with_context(|| format!("Failed to stage: {}", relative_root.display()))?;
+            }
+        }
+
+        Ok(staging)
+    }
+
+    /// Directory to restore the snapshot into instead of the workspace root
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Move every restored root into the workspace in place of the old one. If a rename
+    /// fails, the roots already swapped are put back as they were. Any that cannot be put
+    /// back are kept in the staging directory instead of being removed with it.
+    pub fn swap_into(&mut self, root: &Path) -> Result<()> {
+        let mut swapped: Vec<&Path> = Vec::new();
+
+        for relative_root in &self.roots {
+            if let Err(e) = self.swap_root(root, relative_root) {
+                let undo = [(relative_root.as_path(), false)].into_iter().chain(swapped.iter().rev().map(|done| (*done, true)));
+                for (done, completed) in undo {
+                    if let Err(restore_error) = self.restore_root(root, done, completed) {
+                        eprintln!("Warning: Failed to put back '{}': {:#}", done.display(), restore_error);
+                        self.keep = true;
+                    }
+                }
+                if self.keep {
+                    eprintln!("The original directories are kept in: {}", self.replaced_dir.display());
+                }
+                return Err(e).with_context(|| format!("Failed to swap restored directory into place: {}", relative_root.display()));
+            }
+            swapped.push(relative_root);
+        }
+
+        Ok(())
+    }
+
+    fn swap_root(&self, root: &Path, relative_root: &Path) -> Result<()> {
+        let live = root.join(relative_root);
+        let staged = self.dir.join(relative_root);
+        let replaced = self.replaced_dir.join(relative_root);
+
+        if live.symlink_metadata().is_ok() {
+            create_parent(&replaced)?;
+            fs::rename(&live, &replaced)?;
+        }
+        if staged.symlink_metadata().is_ok() {
+            create_parent(&live)?;
+            fs::rename(&staged, &live)?;
+        }
+        Ok(())
+    }
+
+    /// Undo `swap_root`: remove the restored directory if it was moved into place, and move
+    /// the replaced one back
+    fn restore_root(&self, root: &Path, relative_root: &Path, completed: bool) -> Result<()> {
+        let live = root.join(relative_root);
+        let replaced = self.replaced_dir.join(relative_root);
+
+        if completed && live.symlink_metadata().is_ok() {
+            fs::remove_dir_all(&live)?;
+        }
+        if replaced.symlink_metadata().is_ok() && live.symlink_metadata().is_err() {
+            fs::rename(&replaced, &live)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LoadStaging {
+    /// Remove the staging directory, whether the load succeeded or not, unless it holds
+    /// directories that could not be put back
+    fn drop(&mut self) {
+        if self.keep {
+            let _ = fs::remove_dir_all(&self.dir);
+            return;
+        }
+        if let Some(base) = self.dir.parent() {
+            let _ = fs::remove_dir_all(base);
+        }
+    }
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+/// Recreate the tree at `source` under `dest`: directories with their permissions, symlinks
+/// as symlinks, and files as hard links, or copies where a link cannot be made. Fails on a
+/// mount point below `source`: it could not be staged, and once the live tree is swapped out
+/// and removed with the staging directory, the mounted filesystem's contents would go too.
+fn stage_tree(source: &Path, dest: &Path) -> Result<()> {
+    let device = fs_utils::device_id(source);
+    for entry in WalkDir::new(source).follow_links(false) {
+        let entry = entry?;
+        let path = entry.path();
+        let target = dest.join(path.strip_prefix(source).unwrap_or(path));
+        let file_type = entry.file_type();
+
+        if !fs_utils::on_device(path, device) {
+            bail!("'{}' is a mount point; --atomic cannot swap a directory that contains one", path.display());
+        }
+        if file_type.is_dir() {
+            fs::create_dir_all(&target)?;
+            fs::set_permissions(&target, entry.metadata()?.permissions())?;
+        }
+        else if file_type.is_symlink() {
+            fs_utils::create_symlink(&fs::read_link(path)?, &target)?;
+        }
+        else if fs::hard_link(path, &target).is_err() {
+            fs::copy(path, &target)
+                .with_context(|| format!("Failed to copy: {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Check that no root is the workspace root itself or inside `.kibo`, which cannot be swapped
+pub fn check_roots(roots: &[PathBuf]) -> Result<()> {
+    for relative_root in roots {
+        if relative_root.as_os_str().is_empty() || relative_root.starts_with(crate::config::KIBO_DIR) {
+            bail!("--atomic cannot swap '{}' as a tracked directory", relative_root.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_swap_replaces_roots_and_cleans_up() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("build/sub")).unwrap();
+        fs::write(root.join("build/sub/lib.a"), b"old").unwrap();
+        fs::write(root.join("build/keep.txt"), b"keep").unwrap();
+
+        let mut staging = LoadStaging::prepare(root, vec![PathBuf::from("build"), PathBuf::from("out")]).unwrap();
+        let staged = staging.dir().join("build/sub/lib.a");
+        assert_eq!(fs::read(&staged).unwrap(), b"old");
+
+        // Replace the staged file rather than writing through the hard link
+        fs::remove_file(&staged).unwrap();
+        fs::write(&staged, b"new").unwrap();
+        fs::create_dir_all(staging.dir().join("out")).unwrap();
+        fs::write(staging.dir().join("out/app"), b"app").unwrap();
+        assert_eq!(fs::read(root.join("build/sub/lib.a")).unwrap(), b"old", "The workspace is untouched until the swap");
+
+        staging.swap_into(root).unwrap();
+        assert_eq!(fs::read(root.join("build/sub/lib.a")).unwrap(), b"new");
+        assert_eq!(fs::read(root.join("build/keep.txt")).unwrap(), b"keep");
+        assert_eq!(fs::read(root.join("out/app")).unwrap(), b"app");
+
+        drop(staging);
+        assert!(!get_load_staging_dir(root).exists());
+    }
+
+    #[test]
+    fn test_dropping_unswapped_staging_leaves_workspace() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("build")).unwrap();
+        fs::write(root.join("build/lib.a"), b"old").unwrap();
+
+        let staging = LoadStaging::prepare(root, vec![PathBuf::from("build")]).unwrap();
+        fs::remove_dir_all(staging.dir().join("build")).unwrap();
+        drop(staging);
+
+        assert_eq!(fs::read(root.join("build/lib.a")).unwrap(), b"old");
+        assert!(!get_load_staging_dir(root).exists());
+    }
+
+    #[test]
+    fn test_failed_put_back_keeps_originals() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("build")).unwrap();
+        fs::write(root.join("build/lib.a"), b"old").unwrap();
+
+        let mut staging = LoadStaging::prepare(root, vec![PathBuf::from("build"), PathBuf::from("a/out")]).unwrap();
+        // The restored build cannot be removed as a directory to put the old one back, after
+        // a/out fails to move into place under a file
+        fs::remove_dir_all(staging.dir().join("build")).unwrap();
+        fs::write(staging.dir().join("build"), b"new").unwrap();
+        fs::create_dir_all(staging.dir().join("a/out")).unwrap();
+        fs::write(root.join("a"), b"not a directory").unwrap();
+
+        assert!(staging.swap_into(root).is_err());
+        let original = staging.replaced_dir.join("build/lib.a");
+        drop(staging);
+
+        assert_eq!(fs::read(&original).unwrap(), b"old");
+        let err = LoadStaging::prepare(root, vec![PathBuf::from("build")]).unwrap_err();
+        assert!(err.to_string().contains("original directories"), "{}", err);
+    }
+
+    #[test]
+    fn test_check_roots() {
+        assert!(check_roots(&[PathBuf::from("build"), PathBuf::from("a/out")]).is_ok());
+        assert!(check_roots(&[PathBuf::new()]).is_err());
+        assert!(check_roots(&[PathBuf::from(".kibo")]).is_err());
+    }
+}
@@ -1,17 +1,21 @@
 use anyhow::{Context, Result};
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 use kibo::{
-    Cli, Commands,
-    Config, DatabaseConfig,
-    HistoryEntry, log_entry, read_history, filter_by_snapshot, take_last,
-    Manifest, list_snapshots, format_size,
-    load_snapshot,
-    create_snapshot,
-    Store,
+    Cli, Commands, ConfigCommands, HistoryCommands, SaveArgs, SnapshotCommands, StoreCommands,
+    Config, DatabaseConfig, EmptyPolicy,
+    ExportFormat, HistoryEntry, log_entry, read_history, filter_by_snapshot, filter_by_command, filter_by_time_range,
+    parse_time_bound, take_last, deleted_snapshots, history_stats, HistoryStats,
+    Manifest, list_snapshots, format_size, format_lifetime, parse_metadata, parse_retention, render_tree,
+    dedup_report,
+    Change, diff_manifests, read_text_blob, unified_diff,
+    load_snapshot, verify_report, verify_snapshot, verify_snapshot_contents, LoadOptions,
+    create_snapshot, plan_snapshot, SaveTiming,
+    BlobCheck, Store,
     find_repo_root,
-    CONFIG_FILENAME,
-    ProgressConfig, Timer, ItemProgress,
+    CONFIG_FILENAME, GITIGNORE_FILE, translate_gitignore,
+    diagnose,
+    ProgressConfig, ProgressEvents, Timer, ItemProgress,
 };
 
 fn main() {
@@ -24,101 +28,333 @@ fn main() {
 fn run() -> Result<()> {
     let cli = Cli::parse_args();
 
-    if matches!(cli.command, Commands::Init) {
-        return cmd_init();
+    if let Commands::Init { from_gitignore } = cli.command {
+        return cmd_init(from_gitignore);
     }
 
     let root = find_repo_root()?;
 
-    if matches!(cli.command, Commands::Config) {
-        return cmd_config(&root);
+    if let Commands::Config { command } = cli.command {
+        return match command {
+            Some(ConfigCommands::Migrate { dry_run }) => cmd_config_migrate(&root, dry_run),
+            Some(ConfigCommands::Validate) => cmd_config_validate(&root),
+            None => cmd_config(&root),
+        };
+    }
+    if let Commands::Doctor { fix } = cli.command {
+        return cmd_doctor(&root, fix);
     }
     if matches!(cli.command, Commands::History { .. }) {
-        let Commands::History { last, snapshot, json } = cli.command else { unreachable!() };
-        return cmd_history(&root, last, snapshot, json);
+        let Commands::History { last, snapshot, stats, json, command } = cli.command else { unreachable!() };
+        if let Some(HistoryCommands::Export { format, output }) = command {
+            return cmd_history_export(&root, format, output.as_deref());
+        }
+        return cmd_history(&root, last, snapshot, stats, json);
     }
 
     let config_path = root.join(CONFIG_FILENAME);
-    let config = Config::load(&config_path)?;
+    let config = if cli.strict_config { Config::load_strict(&config_path)? } else { Config::load(&config_path)? };
 
     match cli.command {
-        Commands::Save { 
-            name, 
-            yes, 
-            verbose, 
-            include_db, 
-            progress, 
-            no_progress,
-            compression_level,
-            directories,
-            add_directories,
-            files,
-            add_files,
-            ignore,
-            add_ignore,
-        } => {
+        Commands::Save(args) => {
+            let progress_config = ProgressConfig::from_flags(args.progress, args.no_progress, config.progress);
+            let effective_config = effective_save_config(&config, &args)?;
+            cmd_save(&root, &args, &effective_config, progress_config, &config)?
+        },
+        Commands::Load { name, verbose, dry_run, report_only, report, include_db, exclude, trash, fail_on_extra, report_drift, checksum_cache, rename_conflicts, verify_blobs_first, verify, resume, atomic, progress, no_progress } => {
             let progress_config = ProgressConfig::from_flags(progress, no_progress, config.progress);
-            
-            let mut effective_config = config.clone();
-            
-            if let Some(level) = compression_level {
-                effective_config.compression_level = level;
-            }
-            
-            if let Some(ref dirs) = directories {
-                effective_config.directories = dirs.clone();
-            } else if let Some(ref add_dirs) = add_directories {
-                effective_config.directories.extend(add_dirs.iter().cloned());
-            }
-            
-            if let Some(ref file_patterns) = files {
-                effective_config.files = file_patterns.clone();
-            } else if let Some(ref add_file_patterns) = add_files {
-                effective_config.files.extend(add_file_patterns.iter().cloned());
-            }
-            
-            if let Some(ref ignore_patterns) = ignore {
-                effective_config.ignore = ignore_patterns.clone();
-            } else if let Some(ref add_ignore_patterns) = add_ignore {
-                effective_config.ignore.extend(add_ignore_patterns.iter().cloned());
-            }
-            
-            if effective_config.directories.is_empty() && effective_config.files.is_empty() {
-                anyhow::bail!(
-                    "Configuration error: both 'directories' and 'files' lists are empty.\n\
-                     You must specify at least one directory or file pattern to snapshot."
-                );
+            let options = LoadOptions { verbose, dry_run, exclude, trash, checksum_cache, report_only, rename_conflicts, resume, verify_blobs_first, verify, atomic, fail_on_extra, report_drift };
+            cmd_load(&root, &name, &options, report.as_deref(), include_db, &config, progress_config)?
+        },
+        Commands::Restore { name, paths, verbose } => {
+            cmd_restore(&root, &name, &paths, verbose)?
+        },
+        Commands::List { deleted: true, json, compact, .. } => {
+            cmd_list_deleted(&root, json, compact)?
+        },
+        Commands::List { orphaned_dumps: true, json, compact, .. } => {
+            cmd_list_orphaned_dumps(&root, json, compact)?
+        },
+        Commands::List { sort_by_name, sort_by_size, sort_by_created, sort_by_files, json, compact, filters, .. } => {
+            let order = if sort_by_name {
+                ListOrder::Name
+            }
+            else if sort_by_size {
+                ListOrder::Size
+            }
+            else if sort_by_created {
+                ListOrder::Created
             }
-            effective_config.validate_compression_level();
-            
-            cmd_save(&root, &name, &effective_config, yes, verbose, include_db, progress_config, &config, 
-                     compression_level, &directories, &add_directories, &files, &add_files, &ignore, &add_ignore)?
+            else if sort_by_files {
+                ListOrder::Files
+            }
+            else {
+                ListOrder::Default
+            };
+            cmd_list(&root, order, json, compact, &filters)?
         },
-        Commands::Load { name, verbose, dry_run, include_db, progress, no_progress } => {
-            let progress_config = ProgressConfig::from_flags(progress, no_progress, config.progress);
-            cmd_load(&root, &name, verbose, dry_run, include_db, &config, progress_config)?
+        Commands::Show { name, tree } => {
+            cmd_show(&root, &name, tree)?
+        },
+        Commands::Diff { from, to, content, stat, name_only, name_status, json, output } => {
+            let format = if json {
+                DiffFormat::Json
+            }
+            else if stat {
+                DiffFormat::Stat
+            }
+            else if name_only {
+                DiffFormat::NameOnly
+            }
+            else if name_status {
+                DiffFormat::NameStatus
+            }
+            else {
+                DiffFormat::Full { content }
+            };
+            cmd_diff(&root, &from, &to, format, output.as_deref())?
+        },
+        Commands::Verify { cross_snapshot: true, json, .. } => {
+            cmd_verify_cross_snapshot(&root, json)?
+        },
+        Commands::Verify { name: Some(name), workspace_metadata: true, fix, .. } => {
+            cmd_verify_workspace_metadata(&root, &name, fix)?
+        },
+        Commands::Verify { name, manifest, all, deep, root: check_root, json, .. } => {
+            cmd_verify(&root, name.as_deref(), manifest.as_deref(), all, deep, check_root, json)?
         },
-        Commands::List { sort_by_name, sort_by_size, sort_by_created, sort_by_files } => {
-            cmd_list(&root, sort_by_name, sort_by_size, sort_by_created, sort_by_files)?
+        Commands::Snapshot { command: SnapshotCommands::CompareToDir { name, dir, ignore_permissions, progress, no_progress } } => {
+            let progress_config = ProgressConfig::from_flags(progress, no_progress, config.progress);
+            cmd_snapshot_compare_to_dir(&root, &name, &dir, ignore_permissions, progress_config)?
         },
-        Commands::Remove { names, progress, no_progress } => {
+        Commands::Remove { names, force, progress, no_progress } => {
             let progress_config = ProgressConfig::from_flags(progress, no_progress, config.progress);
-            cmd_remove(&root, &names, progress_config)?
+            cmd_remove(&root, &names, force, config.keep_unreferenced_for.as_deref(), progress_config)?
+        },
+        Commands::Protect { names } => {
+            cmd_protect(&root, &names, true)?
         },
-        Commands::Prune { verbose, progress, no_progress } => {
+        Commands::Unprotect { names } => {
+            cmd_protect(&root, &names, false)?
+        },
+        Commands::Prune { verbose, expire, protect, force, check, group, concurrency, keep_unreferenced_for, progress, no_progress } => {
             let progress_config = ProgressConfig::from_flags(progress, no_progress, config.progress);
-            cmd_prune(&root, verbose, progress_config)?
+            let expiry = expire.then_some(Expiry { protect, force });
+            let collect = Collect {
+                check,
+                group: group.as_deref(),
+                concurrency,
+                keep_unreferenced_for: keep_unreferenced_for.as_deref().or(config.keep_unreferenced_for.as_deref()),
+            };
+            cmd_prune(&root, verbose, expiry, &collect, progress_config)?
+        },
+        Commands::Store { command: StoreCommands::Tidy } => {
+            cmd_store_tidy(&root)?
+        },
+        Commands::Store { command: StoreCommands::VerifyPermissions { fix } } => {
+            cmd_store_verify_permissions(&root, fix)?
+        },
+        Commands::Store { command: StoreCommands::Recompress { names, all, level } } => {
+            cmd_store_recompress(&root, &config, &names, all, level)?
+        },
+        Commands::Store { command: StoreCommands::ImportBlobs { dir, into } } => {
+            cmd_store_import_blobs(&root, &config, &dir, into.as_deref())?
+        },
+        Commands::Store { command: StoreCommands::Fsck { rebuild_manifest, file_list, group, force } } => {
+            cmd_store_fsck(&root, &config, &rebuild_manifest, &file_list, group.as_deref(), force)?
         },
-        Commands::Init => unreachable!(), // Already handled above
-        Commands::Config => unreachable!(), // Handled above
+        Commands::Store { command: StoreCommands::CheckSpace { max_size_gb, auto_prune } } => {
+            cmd_store_check_space(&root, &config, max_size_gb, auto_prune)?
+        },
+        Commands::Store { command: StoreCommands::Size { per_snapshot, histogram } } => {
+            cmd_store_size(&root, per_snapshot, histogram)?
+        },
+        Commands::Store { command: StoreCommands::DedupReport { top } } => {
+            cmd_store_dedup_report(&root, top)?
+        },
+        Commands::Replay { last, since, until, yes } => {
+            cmd_replay(&root, last, since, until, yes, &config)?
+        },
+        Commands::Init { .. } => unreachable!(), // Already handled above
+        Commands::Config { .. } => unreachable!(), // Handled above
         Commands::History { .. } => unreachable!(), // Handled above
+        Commands::Doctor { .. } => unreachable!(), // Handled above
     }
 
     Ok(())
 }
 
+/// Apply save command overrides on top of the loaded configuration
+fn effective_save_config(config: &Config, args: &SaveArgs) -> Result<Config> {
+    let mut effective_config = config.clone();
+    
+    if let Some(level) = args.compression_level {
+        effective_config.compression_level = level;
+        effective_config.compression_effort = None;
+    }
+
+    if let Some(effort) = args.compression_effort {
+        effective_config.compression_effort = Some(effort);
+    }
+    
+    if args.store_compression_dict {
+        effective_config.compression_dict = true;
+    }
+    
+    if args.recompress_reused {
+        effective_config.recompress_reused = true;
+    }
+
+    if args.dedup_within_run {
+        effective_config.dedup_within_run = true;
+    }
+    
+    if args.fsync {
+        effective_config.durable_writes = true;
+    }
+    
+    if args.check_free_space || args.strict_space {
+        effective_config.check_free_space = true;
+    }
+    if args.strict_space {
+        effective_config.strict_space = true;
+    }
+    if args.hash_only {
+        effective_config.hash_only = true;
+    }
+    if args.link_identical {
+        effective_config.link_identical = true;
+    }
+    
+    if let Some(count) = args.max_blobs_warning {
+        effective_config.max_blob_count = Some(count);
+    }
+    
+    if let Some(seconds) = args.max_duration {
+        effective_config.max_duration_secs = Some(seconds);
+    }
+    
+    if args.include_empty_dirs {
+        effective_config.include_empty_dirs = true;
+    } else if args.no_empty_dirs {
+        effective_config.include_empty_dirs = false;
+    }
+
+    if args.sparse_directories {
+        effective_config.sparse_directories = true;
+    }
+    
+    if args.one_file_system {
+        effective_config.one_file_system = true;
+    }
+    
+    if args.exclude_vcs {
+        effective_config.exclude_vcs = true;
+    }
+
+    if args.exclude_hidden {
+        effective_config.exclude_hidden = true;
+    }
+    
+    if args.gitignore {
+        effective_config.gitignore = true;
+    }
+    
+    if args.include_git_sha {
+        effective_config.include_git_sha = true;
+    }
+    for name in &args.capture_env {
+        if !effective_config.capture_env.contains(name) {
+            effective_config.capture_env.push(name.clone());
+        }
+    }
+    if let Some(name) = effective_config.capture_env.iter().find(|name| name.is_empty() || name.contains('=')) {
+        anyhow::bail!("Invalid environment variable name in capture_env: '{}'", name);
+    }
+    
+    if let Some(ref age) = args.include_newer_than {
+        effective_config.include_newer_than = Some(age.clone());
+    }
+    if let Some(ref age) = effective_config.include_newer_than {
+        parse_retention(age).context("Invalid include_newer_than")?;
+    }
+    
+    if args.exclude_empty_files {
+        effective_config.exclude_empty_files = true;
+    }
+    
+    if args.allow_outside_root {
+        effective_config.allow_outside_root = true;
+    }
+    
+    if let Some(ref group) = args.into {
+        effective_config.store_group = Some(group.clone());
+    }
+    
+    if let Some(policy) = args.symlink_policy {
+        effective_config.symlink_policy = policy;
+    }
+
+    if let Some(mode) = args.symlink_target_mode {
+        effective_config.symlink_target_mode = mode;
+    }
+    
+    if let Some(precision) = args.store_mtime_precision {
+        effective_config.mtime_precision = precision;
+    }
+    if let Some(policy) = args.on_empty {
+        effective_config.on_empty = policy;
+    }
+    
+    if let Some(ref dirs) = args.directories {
+        effective_config.directories = dirs.clone();
+    } else if let Some(ref add_dirs) = args.add_directories {
+        effective_config.directories.extend(add_dirs.iter().cloned());
+    }
+    
+    if let Some(ref file_patterns) = args.files {
+        effective_config.files = file_patterns.clone();
+    } else if let Some(ref add_file_patterns) = args.add_files {
+        effective_config.files.extend(add_file_patterns.iter().cloned());
+    }
+    
+    if let Some(ref ignore_patterns) = args.ignore {
+        effective_config.ignore = ignore_patterns.clone();
+    } else if let Some(ref add_ignore_patterns) = args.add_ignore {
+        effective_config.ignore.extend(add_ignore_patterns.iter().cloned());
+    }
+    
+    if let Some(ref assume_patterns) = args.assume_unchanged {
+        effective_config.assume_unchanged.extend(assume_patterns.iter().cloned());
+    }
+    
+    effective_config.validate()?;
+    effective_config.validate_compression_level();
+
+    Ok(effective_config)
+}
+
+/// The `ignore` list of the `kibo init` template, replaced by `--from-gitignore`
+const DEFAULT_IGNORE_LIST: &str = r#"ignore = [
+    "*.log",
+    "*.tmp",
+    ".cache",
+    "temp",
+]
+"#;
+
+/// Render patterns as the `ignore` array of a generated config
+fn render_ignore_list(patterns: &[String]) -> String {
+    let mut list = format!("# Seeded from {}\nignore = [\n", GITIGNORE_FILE);
+    for pattern in patterns {
+        list.push_str(&format!("    {},\n", toml::Value::String(pattern.clone())));
+    }
+    list.push_str("]\n");
+    list
+}
+
 /// Initialize a new .kibo.toml configuration file
-fn cmd_init() -> Result<()> {
+fn cmd_init(from_gitignore: bool) -> Result<()> {
     use std::io::Write;
     
     let config_path = std::path::Path::new(CONFIG_FILENAME);
@@ -131,6 +367,19 @@ fn cmd_init() -> Result<()> {
         );
     }
 
+    let gitignore_patterns = if from_gitignore {
+        let content = std::fs::read_to_string(GITIGNORE_FILE)
+            .with_context(|| format!("Failed to read {} in the current directory", GITIGNORE_FILE))?;
+        let (patterns, skipped) = translate_gitignore(&content);
+        for line in &skipped {
+            eprintln!("Warning: Skipping {} line that kibo cannot express: {}", GITIGNORE_FILE, line);
+        }
+        Some(patterns)
+    }
+    else {
+        None
+    };
+
     // Default configuration content
     let default_config = r#"# Kibo Configuration
 # 
@@ -165,10 +414,37 @@ ignore = [
     "temp",
 ]
 
+# Files to hash from path, size and mtime instead of content (optional)
+# Only for large files that never change: content edits that keep size and mtime go unnoticed
+# assume_unchanged = ["*.iso", "vendor/sdk/*.tar"]
+
 # Maximum snapshot size warning threshold in GB (optional)
 # A warning will be shown if a snapshot exceeds this size
 max_snapshot_size_gb = 10.0
 
+# Size limit for the whole store in GB (optional)
+# Checked after every save and by 'kibo store check-space'; a warning is shown if the store exceeds it
+# store_max_size_gb = 50.0
+
+# Remove expired, then the oldest unprotected snapshots until the store fits (optional)
+# auto_prune = true
+
+# Keep unreferenced blobs this long before prune or rm removes them (optional)
+# The clock starts when a prune or rm first finds a blob unreferenced
+# auto_prune honours it too, and stops removing snapshots once a removal leaves blobs it keeps
+# Can be overridden with 'kibo prune --keep-unreferenced-for'
+# keep_unreferenced_for = "24h"
+
+# Abort a save that runs longer than this many seconds (optional)
+# Stops a misconfigured tracked directory from stalling a CI pipeline
+# Can be overridden with --max-duration
+# max_duration_secs = 600
+
+# Maximum number of blobs in the store before warning (optional)
+# Millions of small files can exhaust filesystem inodes
+# Can be overridden with --max-blobs-warning
+# max_blob_count = 1000000
+
 # Compression level (zstd)
 # 0  = No compression (fastest)
 # 1  = Fast compression
@@ -178,6 +454,49 @@ max_snapshot_size_gb = 10.0
 # Range: 0–10
 compression_level = 0
 
+# Compression preset used instead of compression_level (optional)
+# "fast"     = zstd level 1, least CPU
+# "balanced" = zstd level 3
+# "max"      = zstd level 19 with long-distance matching; best ratio on large files, many times slower
+# Can be overridden with --compression-effort
+# compression_effort = "balanced"
+
+# Train a zstd dictionary from small files on each save (optional)
+# Improves compression of many small, similar files; needs compression_level > 0
+# Can be enabled with --store-compression-dict
+# compression_dict = true
+
+# Compress blobs reused by a save that an earlier save stored uncompressed (optional)
+# Upgrades a store built at compression_level = 0 as files are saved again
+# Can be enabled with --recompress-reused
+# recompress_reused = true
+
+# Report files that duplicate another file in the same snapshot (optional)
+# Counted apart from blobs reused from earlier snapshots
+# Can be enabled with --dedup-within-run
+# dedup_within_run = true
+
+# Fsync every stored blob before the manifest is written (optional)
+# Slower, but a crash or power loss right after a save cannot leave it with truncated blobs
+# Can be enabled with --fsync
+# durable_writes = true
+
+# Check that a save's new blobs fit on the store's filesystem before storing them (optional)
+# Warns by default; with strict_space the save is aborted instead
+# Can be enabled with --check-free-space and --strict-space
+# check_free_space = true
+# strict_space = true
+
+# Record file hashes without storing blobs, when another machine fills a shared store (optional)
+# Such snapshots can only be loaded once every blob they reference is in the store
+# Can be enabled with --hash-only
+# hash_only = true
+
+# Have load hard-link files with identical content instead of copying each one (optional)
+# Linked files share their content, so editing one changes all of them
+# Can be enabled with --link-identical
+# link_identical = true
+
 # Show progress bars (optional)
 # true  = Always show progress bars
 # false = Never show progress bars
@@ -185,6 +504,98 @@ compression_level = 0
 # Can be overridden with --progress or --no-progress flags
 # progress = true
 
+# Record directories that contain no files (optional, default: true)
+# Set to false to only capture directories holding files; load then removes empty ones
+# Can be overridden with --include-empty-dirs or --no-empty-dirs
+# include_empty_dirs = false
+
+# Leave directories with mode 755 that hold files out of the manifest (optional)
+# Load recreates them from the file paths with mode 755, without restoring their mtimes
+# Empty directories and directories with other permissions are still recorded
+# Can be enabled with --sparse-directories
+# sparse_directories = true
+
+# Do not descend into mount points such as network shares or loop-mounted images (optional)
+# Entries on a different filesystem than the workspace root are skipped, like tar --one-file-system
+# Can be enabled with --one-file-system
+# one_file_system = true
+
+# How symlinks inside tracked paths are captured (optional)
+# "store"  = Save the link itself and recreate it on load (default)
+# "follow" = Save the pointed-to file's contents as a regular file
+# "skip"   = Leave symlinks out of the snapshot
+# Can be overridden with --symlink-policy
+# symlink_policy = "store"
+
+# How the targets of stored symlinks are recorded (optional)
+# "preserve" = Keep each target as the link has it (default)
+# "relative" = Rewrite absolute targets inside the workspace relative to the link, so they survive a move
+# "absolute" = Resolve relative targets to absolute paths
+# Can be overridden with --symlink-target-mode
+# symlink_target_mode = "preserve"
+
+# What a save does when it captures no files (optional)
+# "fail" = Exit with an error and keep any existing snapshot of the same name
+# "warn" = Save the empty snapshot and print a note (default)
+# "ok"   = Save the empty snapshot without comment
+# Can be overridden with --on-empty
+# on_empty = "warn"
+
+# How precisely file mtimes are recorded and compared (optional)
+# "full"   = Nanoseconds, as reported by the filesystem (default)
+# "second" = Whole seconds, so copies between filesystems of different precision still match
+# Can be overridden with --store-mtime-precision
+# mtime_precision = "full"
+
+# Skip version control metadata directories (.git, .hg, .svn, .bzr, _darcs, CVS) (optional)
+# Can be enabled with --exclude-vcs
+# exclude_vcs = true
+
+# Skip files and directories starting with '.' inside tracked directories and files pattern matches (optional)
+# Tracked directories that are hidden themselves, such as '.build', are still captured
+# Can be enabled with --exclude-hidden
+# exclude_hidden = true
+
+# Also ignore files matched by the workspace root's .gitignore (optional)
+# Supports literal names, * globs and dir/ entries; negated (!) lines are skipped
+# Can be enabled with --gitignore
+# gitignore = true
+
+# Record the git commit checked out in the workspace in each snapshot (optional)
+# Can be enabled with --include-git-sha
+# include_git_sha = true
+
+# Environment variables whose values are recorded in each snapshot (optional)
+# Variables that are not set are left out; more can be added with --capture-env
+# capture_env = ["CC", "CFLAGS", "TARGET"]
+
+# Only capture files modified within this long before the save (optional)
+# A later load leaves the older files alone instead of removing them
+# Can be set with --include-newer-than (alias --exclude-older-than)
+# include_newer_than = "7d"
+
+# Leave zero-byte files, such as build stamps, out of snapshots (optional)
+# A later load leaves empty files alone instead of removing them
+# Can be enabled with --exclude-empty-files
+# exclude_empty_files = true
+
+# Accept absolute paths in 'directories', for build outputs that live outside the workspace (optional)
+# Such a directory is walked as given instead of being matched by name, and a snapshot that
+# contains one can only be loaded from the workspace it was saved in
+# Can be enabled with --allow-outside-root
+# allow_outside_root = true
+
+# Keep blobs in a separate named store group, e.g. to prune debug and release builds apart (optional)
+# Each group lives in .kibo/store/groups/<name> and shares no blobs with the default store
+# Can be set with --into
+# store_group = "release"
+
+# Pack small blobs into shared pack files (optional)
+# Reduces inode usage for workspaces with many small files
+# Blobs smaller than pack_threshold_kb are appended to .kibo/store/packs/
+# pack_small_blobs = true
+# pack_threshold_kb = 16
+
 # Database configuration (optional)
 # Uncomment to enable database snapshots with --include-db flag
 # The [database] section provides connection settings for database snapshots.
@@ -193,7 +604,7 @@ compression_level = 0
 #   - With value (e.g., --include-db=mydb): uses specified database name
 # SQL dump files are auto-generated per snapshot and stored in .kibo/db_snapshots/
 # [database]
-# type = "mysql"
+# db_type = "mysql"  # or "postgres" (then set port = 5432)
 # user = "root"
 # password = ""
 # host = "localhost"
@@ -203,10 +614,18 @@ compression_level = 0
 # single_transaction = true  # Ensures consistent InnoDB snapshots without locking
 "#;
 
+    let config_content = match gitignore_patterns {
+        Some(ref patterns) => default_config.replacen(DEFAULT_IGNORE_LIST, &render_ignore_list(patterns), 1),
+        None => default_config.to_string(),
+    };
+
     let mut file = std::fs::File::create(config_path)?;
-    file.write_all(default_config.as_bytes())?;
+    file.write_all(config_content.as_bytes())?;
 
     println!("Created '{}'", CONFIG_FILENAME);
+    if let Some(ref patterns) = gitignore_patterns {
+        println!("Seeded 'ignore' with {} pattern(s) from {}", patterns.len(), GITIGNORE_FILE);
+    }
     println!("\nNext steps:");
     println!("  1. Edit {}", CONFIG_FILENAME);
     println!("  2. Run 'kibo save <name>' to create your first snapshot");
@@ -217,27 +636,37 @@ compression_level = 0
 /// Save a snapshot
 fn cmd_save(
     root: &std::path::Path, 
-    name: &str, 
+    args: &SaveArgs, 
     config: &Config, 
-    yes: bool, 
-    verbose: bool, 
-    include_db: Option<String>, 
     progress_config: ProgressConfig, 
     base_config: &Config,
-    compression_level: Option<u32>,
-    directories: &Option<Vec<String>>,
-    add_directories: &Option<Vec<String>>,
-    files: &Option<Vec<String>>,
-    add_files: &Option<Vec<String>>,
-    ignore: &Option<Vec<String>>,
-    add_ignore: &Option<Vec<String>>,
 ) -> Result<()> {
+    let name = args.name.as_str();
+    let yes = args.yes;
+    let verbose = args.verbose;
+    let include_db = args.include_db.as_ref();
     let timer = Timer::new();
     
     validate_snapshot_name(name)?;
+    if args.fail_if_exists && Manifest::exists(root, name) {
+        anyhow::bail!("Snapshot '{}' already exists; remove it first or save under another name (--fail-if-exists)", name);
+    }
+    if Manifest::load(root, name).is_ok_and(|existing| existing.protected) {
+        anyhow::bail!("Snapshot '{}' is protected; run 'kibo unprotect {}' first to overwrite it", name, name);
+    }
+
+    let retention = args.retention.as_deref().map(parse_retention).transpose()?;
+    let metadata = args.metadata.iter().map(|pair| parse_metadata(pair)).collect::<Result<HashMap<_, _>>>()?;
+    let deterministic_created_at = if args.deterministic {
+        Some(deterministic_created_at(args.created_at.as_deref(), config)?)
+    }
+    else {
+        None
+    };
+    let progress_events = args.progress_json.as_deref().map(ProgressEvents::open).transpose()?;
 
     // Determine database name if --include-db is specified
-    let db_name_to_dump = if let Some(ref db_flag_value) = include_db {
+    let db_name_to_dump = if let Some(db_flag_value) = include_db {
         // --include-db flag was used
         if db_flag_value.is_empty() {
             // --include-db without value: use config database name
@@ -268,7 +697,12 @@ fn cmd_save(
         None
     };
 
-    if Manifest::exists(root, name) {
+    if args.dry_run {
+        return print_save_plan(root, name, config, args, db_name_to_dump.as_deref());
+    }
+
+    let replacing = Manifest::exists(root, name);
+    if replacing {
         if !yes {
             use std::io::{self, Write};
             print!("Snapshot '{}' already exists. Overwrite with current workspace state? [y/N] ", name);
@@ -283,31 +717,14 @@ fn cmd_save(
                 return Ok(());
             }
         }
-
-        println!("Removing existing snapshot '{}'", name);
-        
-        // Load the old manifest to get the old database dump filename
-        if let Ok(old_manifest) = Manifest::load(root, name) {
-            if let Some(ref old_db_filename) = old_manifest.db_dump_filename {
-                let old_dump_path = root.join(".kibo").join("db_snapshots").join(old_db_filename);
-                if old_dump_path.exists() {
-                    if let Err(e) = std::fs::remove_file(&old_dump_path) {
-                        eprintln!("Warning: Failed to delete old database dump {}: {}", old_db_filename, e);
-                    } 
-                    else if verbose {
-                        eprintln!("Deleted old database dump: {}", old_db_filename);
-                    }
-                }
-            }
-        }
-        
-        Manifest::delete(root, name)?;
     }
+    // The old snapshot is only removed once the new one has been taken
+    let replaced = if replacing { Manifest::load(root, name).ok() } else { None };
 
     // Dump database if --include-db is specified
-    let db_dump_filename = if let Some(db_name) = db_name_to_dump {
+    let db_dump_filename = if let Some(ref db_name) = db_name_to_dump {
         if let Some(ref db_config) = base_config.database {
-            Some(dump_database(root, name, &db_name, db_config, verbose)?)
+            Some(dump_database(root, name, db_name, db_config, verbose)?)
         }
         else {
             None
@@ -317,11 +734,69 @@ fn cmd_save(
         None
     };
 
-    let mut manifest = create_snapshot(root, name, config, verbose, progress_config)?;
+    let mut timing = args.timing.then(SaveTiming::default);
+    let mut manifest = create_snapshot(
+        root,
+        name,
+        config,
+        verbose,
+        progress_config,
+        progress_events.as_ref(),
+        timing.as_mut(),
+    )?;
+
+    if manifest.file_count == 0 && config.on_empty == EmptyPolicy::Fail {
+        if let Some(ref dump_filename) = db_dump_filename {
+            let _ = std::fs::remove_file(root.join(".kibo").join("db_snapshots").join(dump_filename));
+        }
+        anyhow::bail!(
+            "Snapshot '{}' captured no files and was not saved (on_empty = fail); \
+             check that the build produced its artifacts and that `directories` and `files` match them",
+            name
+        );
+    }
+    if replacing {
+        remove_replaced_snapshot(root, name, replaced.as_ref(), db_dump_filename.as_deref(), verbose)?;
+    }
+
     manifest.db_dump_filename = db_dump_filename.clone();
+    manifest.db_name = db_dump_filename.as_ref().and(db_name_to_dump);
+    manifest.metadata = metadata;
+    if let Some(ref replaced) = replaced {
+        manifest.record_replaced(replaced);
+    }
+    if let Some(created_at) = deterministic_created_at {
+        manifest.normalize_volatile(created_at);
+    }
+    if let Some(retention) = retention {
+        manifest.set_retention(retention);
+    }
     
-    // Save manifest now that db_dump_filename is set
+    // Save manifest now that db_dump_filename and expire_at are set
     manifest.save(root)?;
+    if let Some(ref path) = args.output_manifest {
+        manifest.save_to_path(path)?;
+    }
+
+    let prune_stats = if args.prune_after {
+        let collect = Collect { keep_unreferenced_for: config.keep_unreferenced_for.as_deref(), ..Default::default() };
+        Some(prune_unreferenced(root, verbose, &collect, progress_config.should_show_progress())?)
+    }
+    else {
+        None
+    };
+
+    let store_limit = match config.store_max_size_gb {
+        Some(limit_gb) => Some(enforce_store_limit(
+            root,
+            limit_gb,
+            config.auto_prune,
+            Some(name),
+            config.keep_unreferenced_for.as_deref(),
+            verbose,
+        )?),
+        None => None,
+    };
 
     let store = Store::new(root);
     let disk_size = store.total_size().unwrap_or(0);
@@ -333,61 +808,175 @@ fn cmd_save(
     
     if manifest.file_count == 0 {
         println!("  Files: 0");
-        println!("  Note: An empty snapshot represents an intentionally clean artifact state and can be loaded just like any other snapshot.");
+        if config.on_empty == EmptyPolicy::Warn {
+            println!("  Note: An empty snapshot represents an intentionally clean artifact state and can be loaded just like any other snapshot.");
+        }
     } 
     else {
         println!("  Files: {}", manifest.file_count);
     }
     
+    let assumed = manifest.assumed_count();
+    if assumed > 0 {
+        println!("  Assumed unchanged: {} (content not hashed)", assumed);
+    }
+    if manifest.hash_only {
+        println!("  Blobs: not stored (hash-only)");
+    }
+    
     println!("  Size of snapshot: {}", manifest.human_size());
     println!("  Size of store: {}", format_size(disk_size));
+    if let Some(expire_at) = manifest.expire_at {
+        println!("  Expires: {}", expire_at.format("%Y-%m-%d %H:%M:%S"));
+    }
+    if let Some(ref path) = args.output_manifest {
+        println!("  Manifest copy: {}", path.display());
+    }
+    if let Some(stats) = prune_stats {
+        println!(
+            "  Pruned: {} blob(s) (freed {}), {} database dump(s) (freed {})",
+            stats.blobs_removed,
+            format_size(stats.blobs_freed),
+            stats.db_dumps_removed,
+            format_size(stats.db_dumps_freed)
+        );
+    }
+    if let Some(ref check) = store_limit {
+        if !check.removed.is_empty() {
+            println!("  Auto-pruned: {} (freed {})", check.removed.join(", "), format_size(check.freed));
+        }
+        if check.is_over() {
+            eprintln!(
+                "Warning: Store size ({}) exceeds store_max_size_gb ({}); {}",
+                format_size(check.size),
+                format_size(check.limit),
+                if check.stalled { "removed snapshots' blobs are kept by keep_unreferenced_for" } else { "remove snapshots or set auto_prune = true" }
+            );
+        }
+    }
     println!("  Time: {}", timer.elapsed_string());
+    if let Some(timing) = timing {
+        println!("  Timing ({} worker thread(s)):", timing.threads);
+        println!(
+            "    Scan:  {:.2}s ({:.2}s reading and hashing, summed across threads)",
+            timing.scan.as_secs_f64(),
+            timing.hashing.as_secs_f64()
+        );
+        println!(
+            "    Store: {:.2}s ({:.2}s compressing)",
+            timing.store.as_secs_f64(),
+            timing.compression.as_secs_f64()
+        );
+    }
+
+    if let Some(ref events) = progress_events {
+        events.done();
+    }
     
     // Log to history
-    let mut flags = Vec::new();
-    if verbose { flags.push("--verbose".to_string()); }
-    if let Some(ref db_val) = include_db {
-        if db_val.is_empty() {
-            flags.push("--include-db".to_string());
-        } else {
-            flags.push(format!("--include-db={}", db_val));
-        }
+    let entry = HistoryEntry::new("SAVE", Some(name), args.history_flags());
+    log_entry(root, &entry);
+
+    Ok(())
+}
+
+/// Creation time for `save --deterministic`: `--created-at`, else `$SOURCE_DATE_EPOCH`,
+/// else the Unix epoch. Fails on settings whose results depend on when the save runs.
+fn deterministic_created_at(created_at: Option<&str>, config: &Config) -> Result<chrono::DateTime<chrono::Utc>> {
+    if !config.assume_unchanged.is_empty() {
+        anyhow::bail!("--deterministic cannot be combined with assume_unchanged, whose hashes depend on mtimes");
     }
-    if yes { flags.push("--yes".to_string()); }
-    if let Some(level) = compression_level {
-        flags.push(format!("--compression-level={}", level));
+    if config.include_newer_than.is_some() {
+        anyhow::bail!("--deterministic cannot be combined with include_newer_than, whose cutoff depends on the time of the save");
     }
-    if let Some(dirs) = directories {
-        flags.push(format!("--directories={}", dirs.join(",")));
+
+    if let Some(created_at) = created_at {
+        return parse_time_bound(created_at);
     }
-    if let Some(add_dirs) = add_directories {
-        flags.push(format!("--add-directories={}", add_dirs.join(",")));
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) => {
+            let seconds: i64 = value
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid SOURCE_DATE_EPOCH '{}': expected seconds since the Unix epoch", value))?;
+            chrono::DateTime::from_timestamp(seconds, 0)
+                .with_context(|| format!("SOURCE_DATE_EPOCH '{}' is out of range", value))
+        }
+        Err(_) => Ok(chrono::DateTime::UNIX_EPOCH),
     }
-    if let Some(file_patterns) = files {
-        flags.push(format!("--files={}", file_patterns.join(",")));
+}
+
+/// Show what `save` would capture without hashing or writing anything
+fn print_save_plan(
+    root: &std::path::Path,
+    name: &str,
+    config: &Config,
+    args: &SaveArgs,
+    db_name: Option<&str>,
+) -> Result<()> {
+    let plan = plan_snapshot(root, name, config, args.verbose)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+        return Ok(());
     }
-    if let Some(add_file_patterns) = add_files {
-        flags.push(format!("--add-files={}", add_file_patterns.join(",")));
+
+    println!("[DRY RUN] Would create snapshot '{}'", plan.would_create);
+    if Manifest::exists(root, name) {
+        println!("  Replaces the existing snapshot '{}'", name);
     }
-    if let Some(ignore_patterns) = ignore {
-        flags.push(format!("--ignore={}", ignore_patterns.join(",")));
+    println!("  Files: {} ({})", plan.files.len(), format_size(plan.total_size));
+    println!("  Directories: {}", plan.directories);
+    if let Some(db_name) = db_name {
+        println!("  Database: would dump '{}'", db_name);
     }
-    if let Some(add_ignore_patterns) = add_ignore {
-        flags.push(format!("--add-ignore={}", add_ignore_patterns.join(",")));
+
+    if !plan.files.is_empty() {
+        println!("\nFiles to save:");
+        for file in &plan.files {
+            println!("  - {} ({})", file.path, format_size(file.size));
+        }
     }
-    let entry = HistoryEntry::new("SAVE", Some(name), flags);
-    log_entry(root, &entry);
 
     Ok(())
 }
 
 /// Load a snapshot
-fn cmd_load(root: &std::path::Path, name: &str, verbose: bool, dry_run: bool, include_db: bool, config: &Config, progress_config: ProgressConfig) -> Result<()> {
+fn cmd_load(
+    root: &std::path::Path,
+    name: &str,
+    options: &LoadOptions,
+    report: Option<&std::path::Path>,
+    include_db: bool,
+    config: &Config,
+    progress_config: ProgressConfig,
+) -> Result<()> {
+    let verbose = options.verbose;
+    let dry_run = options.dry_run;
+
+    if let Some(ref suffix) = options.rename_conflicts
+        && (suffix.is_empty() || suffix.contains(['/', '\\']))
+    {
+        anyhow::bail!("Invalid --rename-conflicts suffix '{}': expected a non-empty suffix without path separators", suffix);
+    }
 
     // Load manifest first to check for database dump
     let manifest = Manifest::load(root, name)?;
 
-    let stats = load_snapshot(root, name, verbose, dry_run, progress_config)?;
+    let stats = load_snapshot(root, name, options, progress_config)?;
+
+    if options.report_only {
+        println!("files_loaded: {}", stats.files_loaded);
+        println!("copies: {}", stats.copies);
+        println!("unchanged: {}", stats.unchanged);
+        println!("symlinks: {}", stats.symlinks);
+        println!("removed: {}", stats.removed);
+        println!("renamed: {}", stats.renamed);
+        if let Some(ref trash_dir) = stats.trash_dir {
+            println!("trash_dir: {}", trash_dir.display());
+        }
+        return Ok(());
+    }
 
     if dry_run {
         println!("\n[DRY RUN] Would load snapshot '{}'", name);
@@ -396,6 +985,17 @@ fn cmd_load(root: &std::path::Path, name: &str, verbose: bool, dry_run: bool, in
             "  {} to copy, {} unchanged, {} symlinks, {} removed",
             stats.copies, stats.unchanged, stats.symlinks, stats.removed
         );
+        if let Some(ref trash_dir) = stats.trash_dir {
+            println!("  Removed files would be moved to: {}", trash_dir.display());
+        }
+        if stats.renamed > 0 {
+            println!("  Local versions that would be kept: {}", stats.renamed);
+        }
+        if let Some(path) = report {
+            std::fs::write(path, serde_json::to_string_pretty(&stats)?)
+                .with_context(|| format!("Failed to write load report: {}", path.display()))?;
+            println!("  Wrote load plan to {}", path.display());
+        }
     }
     else {
         println!("\nSnapshot '{}' loaded successfully", name);
@@ -404,6 +1004,28 @@ fn cmd_load(root: &std::path::Path, name: &str, verbose: bool, dry_run: bool, in
             "  {} copied, {} unchanged, {} symlinks, {} removed",
             stats.copies, stats.unchanged, stats.symlinks, stats.removed
         );
+        if stats.linked > 0 {
+            println!("  Hard-linked to an identical file: {}", stats.linked);
+        }
+        if stats.resumed > 0 {
+            println!("  Already restored by the interrupted load: {}", stats.resumed);
+        }
+        if let Some(ref trash_dir) = stats.trash_dir {
+            println!("  Removed files moved to: {}", trash_dir.display());
+        }
+        if stats.writable_blobs > 0 {
+            eprintln!(
+                "Warning: {} blob(s) in the store were writable and have been made read-only again; \
+                 run 'kibo verify --deep' to check that their content is intact",
+                stats.writable_blobs
+            );
+        }
+        if stats.renamed > 0 {
+            println!("  Local versions kept: {}", stats.renamed);
+            for file in &stats.renamed_files {
+                println!("    {}", file);
+            }
+        }
     }
 
     if include_db {
@@ -426,163 +1048,1495 @@ fn cmd_load(root: &std::path::Path, name: &str, verbose: bool, dry_run: bool, in
         let mut flags = Vec::new();
         if verbose { flags.push("--verbose".to_string()); }
         if include_db { flags.push("--include-db".to_string()); }
+        for pattern in &options.exclude {
+            flags.push(format!("--exclude={}", pattern));
+        }
+        if let Some(ref trash) = options.trash {
+            flags.push(format!("--trash={}", trash.display()));
+        }
+        if options.fail_on_extra { flags.push("--fail-on-extra".to_string()); }
+        if options.checksum_cache { flags.push("--checksum-cache".to_string()); }
+        if let Some(ref suffix) = options.rename_conflicts {
+            flags.push(format!("--rename-conflicts={}", suffix));
+        }
+        if options.verify_blobs_first { flags.push("--verify-blobs-first".to_string()); }
+        if options.verify { flags.push("--verify".to_string()); }
+        if options.resume { flags.push("--resume".to_string()); }
+        if options.atomic { flags.push("--atomic".to_string()); }
+        if options.report_drift { flags.push("--report-drift".to_string()); }
         let entry = HistoryEntry::new("LOAD", Some(name), flags);
         log_entry(root, &entry);
     }
 
+    if let Some(ref drift) = stats.drift {
+        print_drift(drift);
+        if !drift.is_clean() {
+            anyhow::bail!("Workspace does not match snapshot '{}' after loading", name);
+        }
+    }
+
     Ok(())
 }
 
-/// List all snapshots
-fn cmd_list(root: &std::path::Path, sort_by_name: bool, sort_by_size: bool, sort_by_created: bool, sort_by_files: bool) -> Result<()> {
-    let mut snapshots = list_snapshots(root)?;
+/// Copy the given files back from a snapshot, leaving everything else alone
+fn cmd_restore(root: &std::path::Path, name: &str, paths: &[String], verbose: bool) -> Result<()> {
+    let restored = kibo::restore_files(root, name, paths, verbose)?;
+    println!("Restored {} file(s) from snapshot '{}'", restored.len(), name);
 
-    if snapshots.is_empty() {
-        println!("No snapshots found.");
-        println!("\nCreate a snapshot with: kibo save <name>");
-        return Ok(());
+    log_entry(root, &HistoryEntry::new("RESTORE", Some(name), restored));
+    Ok(())
+}
+
+/// Print what `load --report-drift` found still differing from the snapshot
+fn print_drift(drift: &kibo::DriftReport) {
+    if drift.is_clean() {
+        println!("Drift check: workspace matches the snapshot");
+        return;
+    }
+
+    println!("Drift check: differences remain after loading");
+    print_drift_paths(drift);
+}
+
+/// List each differing path after its kind of difference
+fn print_drift_paths(drift: &kibo::DriftReport) {
+    let groups = [
+        ("missing", &drift.missing),
+        ("modified", &drift.modified),
+        ("permissions", &drift.permissions),
+        ("extra", &drift.extra),
+    ];
+    for (label, paths) in groups {
+        for path in paths {
+            println!("  {:<12} {}", label, path);
+        }
+    }
+}
+
+/// Compare a directory outside the workspace with a snapshot, failing on any difference
+fn cmd_snapshot_compare_to_dir(
+    root: &std::path::Path,
+    name: &str,
+    dir: &std::path::Path,
+    ignore_permissions: bool,
+    progress_config: ProgressConfig,
+) -> Result<()> {
+    if !dir.is_dir() {
+        anyhow::bail!("Not a directory: {}", dir.display());
+    }
+    let manifest = Manifest::load(root, name)?;
+    if manifest.outside_base.is_some() {
+        anyhow::bail!(
+            "Snapshot '{}' tracks directories outside the workspace, so it cannot be compared with another directory",
+            name
+        );
     }
 
-    if sort_by_name {
-        snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut drift = kibo::check_drift(dir, &manifest, &[], progress_config)?;
+    if ignore_permissions {
+        drift.permissions.clear();
     }
-    else if sort_by_size {
-        snapshots.sort_by(|a, b| b.total_size.cmp(&a.total_size)); // Largest first
+
+    if drift.is_clean() {
+        println!("{} matches snapshot '{}' ({} files)", dir.display(), name, manifest.file_count);
+        return Ok(());
     }
-    else if sort_by_created {
-        snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at)); // Newest first
-    } 
-    else if sort_by_files {
-        snapshots.sort_by(|a, b| b.file_count.cmp(&a.file_count)); // Most first
+
+    print_drift_paths(&drift);
+    anyhow::bail!(
+        "{} differs from snapshot '{}': {} missing, {} modified, {} with different permissions, {} extra",
+        dir.display(),
+        name,
+        drift.missing.len(),
+        drift.modified.len(),
+        drift.permissions.len(),
+        drift.extra.len()
+    );
+}
+
+/// Output selected by the `kibo diff` flags
+enum DiffFormat {
+    /// Every changed file, with unified diffs of text files when `content` is set
+    Full { content: bool },
+    Stat,
+    NameOnly,
+    NameStatus,
+    Json,
+}
+
+/// Sort order selected by the `kibo list` flags
+enum ListOrder {
+    /// Order from list_snapshots (newest first by creation time)
+    Default,
+    Name,
+    Size,
+    Created,
+    Files,
+}
+
+/// List all snapshots
+fn cmd_list(
+    root: &std::path::Path, 
+    order: ListOrder,
+    json: bool, 
+    compact: bool,
+    filters: &[String],
+) -> Result<()> {
+    let filters = filters.iter().map(|filter| parse_metadata(filter)).collect::<Result<Vec<_>>>()?;
+    let mut snapshots = list_snapshots(root)?;
+
+    if snapshots.is_empty() && !json {
+        println!("No snapshots found.");
+        println!("\nCreate a snapshot with: kibo save <name>");
+        return Ok(());
+    }
+
+    snapshots.retain(|snapshot| snapshot.matches_metadata(&filters));
+    if snapshots.is_empty() && !json {
+        println!("No snapshots match {}.", filters.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(", "));
+        return Ok(());
+    }
+
+    match order {
+        ListOrder::Name => snapshots.sort_by(|a, b| a.name.cmp(&b.name)),
+        ListOrder::Size => snapshots.sort_by(|a, b| b.total_size.cmp(&a.total_size)), // Largest first
+        ListOrder::Created => snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at)), // Newest first
+        ListOrder::Files => snapshots.sort_by(|a, b| b.file_count.cmp(&a.file_count)), // Most first
+        ListOrder::Default => {}
+    }
+
+    if json {
+        let summaries: Vec<_> = snapshots.iter().map(Manifest::summary).collect();
+        let json_output = if compact {
+            serde_json::to_string(&summaries)?
+        }
+        else {
+            serde_json::to_string_pretty(&summaries)?
+        };
+        println!("{}", json_output);
+        return Ok(());
+    }
+
+    println!("Snapshots ({}):\n", snapshots.len());
+    println!(
+        "{:<20} {:<20} {:>10} {:>8} {:>10}",
+        "NAME", "CREATED", "SIZE", "FILES", "EXPIRES"
+    );
+    println!("{}", "-".repeat(71));
+
+    let now = chrono::Utc::now();
+    for snapshot in &snapshots {
+        let expires = snapshot
+            .expires_in(now)
+            .map(format_lifetime)
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:<20} {:<20} {:>10} {:>8} {:>10}",
+            snapshot.name,
+            snapshot.created_at.format("%Y-%m-%d %H:%M:%S"),
+            snapshot.human_size(),
+            snapshot.file_count,
+            expires
+        );
+    }
+
+    let store = Store::new(root);
+    if let Ok(total_size) = store.total_size() {
+        if let Ok(blob_count) = count_all_blobs(root) {
+            println!("\nStore: {} blobs, {} on disk", blob_count, format_size(total_size));
+        }
+    }
+
+    Ok(())
+}
+
+/// List snapshots removed with `kibo rm`, as recorded in the history log
+fn cmd_list_deleted(root: &std::path::Path, json: bool, compact: bool) -> Result<()> {
+    let deleted = deleted_snapshots(read_history(root)?);
+
+    if json {
+        let json_output = if compact {
+            serde_json::to_string(&deleted)?
+        }
+        else {
+            serde_json::to_string_pretty(&deleted)?
+        };
+        println!("{}", json_output);
+        return Ok(());
+    }
+
+    if deleted.is_empty() {
+        println!("No deleted snapshots found in history.");
+        return Ok(());
+    }
+
+    println!("Deleted snapshots ({}):\n", deleted.len());
+    println!("{:<20} {:<20} {:>9}", "NAME", "DELETED", "RE-SAVED");
+    println!("{}", "-".repeat(51));
+
+    for snapshot in &deleted {
+        println!(
+            "{:<20} {:<20} {:>9}",
+            snapshot.name,
+            snapshot.deleted_at.format("%Y-%m-%d %H:%M:%S"),
+            if snapshot.resaved { "yes" } else { "no" }
+        );
+    }
+
+    Ok(())
+}
+
+/// List database dumps that no snapshot references, which `kibo prune` would remove
+fn cmd_list_orphaned_dumps(root: &std::path::Path, json: bool, compact: bool) -> Result<()> {
+    let orphans = kibo::find_orphaned_db_dumps(root, &kibo::referenced_db_dumps(&list_snapshots(root)?))?;
+
+    if json {
+        let json_output = if compact {
+            serde_json::to_string(&orphans)?
+        }
+        else {
+            serde_json::to_string_pretty(&orphans)?
+        };
+        println!("{}", json_output);
+        return Ok(());
+    }
+
+    if orphans.is_empty() {
+        println!("No orphaned database dumps found.");
+        return Ok(());
+    }
+
+    println!("Orphaned database dumps ({}):\n", orphans.len());
+    println!("{:<50} {:>10}", "FILE", "SIZE");
+    println!("{}", "-".repeat(61));
+
+    for dump in &orphans {
+        println!("{:<50} {:>10}", dump.filename, format_size(dump.size));
+    }
+
+    let total: u64 = orphans.iter().map(|dump| dump.size).sum();
+    println!("\nTotal: {} (removed by 'kibo prune')", format_size(total));
+
+    Ok(())
+}
+
+/// Remove the snapshot a save is replacing, along with its database dump
+fn remove_replaced_snapshot(
+    root: &std::path::Path,
+    name: &str,
+    replaced: Option<&Manifest>,
+    new_dump_filename: Option<&str>,
+    verbose: bool,
+) -> Result<()> {
+    println!("Removing existing snapshot '{}'", name);
+
+    // A dump taken within the same second has the same name, and is the new snapshot's
+    if let Some(old_db_filename) = replaced.and_then(|old| old.db_dump_filename.as_deref())
+        && Some(old_db_filename) != new_dump_filename
+    {
+        let old_dump_path = root.join(".kibo").join("db_snapshots").join(old_db_filename);
+        if old_dump_path.exists() {
+            if let Err(e) = std::fs::remove_file(&old_dump_path) {
+                eprintln!("Warning: Failed to delete old database dump {}: {}", old_db_filename, e);
+            }
+            else if verbose {
+                eprintln!("Deleted old database dump: {}", old_db_filename);
+            }
+        }
+    }
+
+    Manifest::delete(root, name)
+}
+
+/// Show the contents of a snapshot
+fn cmd_show(root: &std::path::Path, name: &str, tree: bool) -> Result<()> {
+    let manifest = Manifest::load(root, name)?;
+
+    println!("Snapshot: {}", manifest.name);
+    println!("Created:  {}", manifest.created_at.format("%Y-%m-%d %H:%M:%S"));
+    println!(
+        "Size:     {} ({} files, {} directories)",
+        manifest.human_size(),
+        manifest.file_count,
+        manifest.directories.len()
+    );
+    if let Some(ref db_dump) = manifest.db_dump_filename {
+        println!("Database: {}", db_dump);
+    }
+    if let Some(ref group) = manifest.store_group {
+        println!("Group:    {}", group);
+    }
+    if manifest.protected {
+        println!("Status:   protected");
+    }
+    if manifest.hash_only {
+        println!("Blobs:    not stored by this save (hash-only)");
+    }
+    if manifest.link_identical {
+        println!("Load:     identical files are hard-linked");
+    }
+    if manifest.sparse_directories {
+        println!("Directories: only recorded when empty or not mode 755");
+    }
+    if !manifest.symlink_target_mode.is_preserve() {
+        println!("Symlinks: targets recorded {}", manifest.symlink_target_mode.as_str());
+    }
+    match (manifest.compression_level, manifest.compression_algorithm.as_deref()) {
+        (Some(0), _) => println!("Compress: none"),
+        (Some(level), algorithm) => match manifest.compression_effort {
+            Some(effort) => println!("Compress: {} level {} (effort {})", algorithm.unwrap_or("zstd"), level, effort.as_str()),
+            None => println!("Compress: {} level {}", algorithm.unwrap_or("zstd"), level),
+        },
+        (None, _) => {},
+    }
+    if let Some(ref revision) = manifest.vcs_revision {
+        println!("Revision: {}{}", revision, if manifest.vcs_dirty { " (uncommitted changes)" } else { "" });
+    }
+    if let Some(ref previous_root) = manifest.previous_content_root {
+        match manifest.previous_created_at {
+            Some(previous_created_at) => println!(
+                "Replaced: snapshot with root {} saved on {}",
+                previous_root,
+                previous_created_at.format("%Y-%m-%d %H:%M:%S")
+            ),
+            None => println!("Replaced: snapshot with root {}", previous_root),
+        }
+    }
+    if !manifest.metadata.is_empty() {
+        let metadata: BTreeSet<_> = manifest.metadata.iter().collect();
+        println!("Metadata:");
+        for (key, value) in metadata {
+            println!("  {} = {}", key, value);
+        }
+    }
+    if !manifest.environment.is_empty() {
+        println!("Environment:");
+        for (name, value) in &manifest.environment {
+            println!("  {}={}", name, value);
+        }
+    }
+
+    let assumed = manifest.assumed_count();
+    if assumed > 0 {
+        eprintln!(
+            "Warning: {} file(s) were hashed from path, size and mtime (assume_unchanged); their content was not verified",
+            assumed
+        );
+    }
+
+    println!();
+    if tree {
+        for line in render_tree(&manifest) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    let mut paths: Vec<&String> = manifest.files.keys().collect();
+    paths.sort();
+    for path in paths {
+        let entry = &manifest.files[path];
+        if entry.is_symlink {
+            println!("{:>10}  {} -> {}", "", path, entry.symlink_target.as_deref().unwrap_or("?"));
+        }
+        else {
+            println!("{:>10}  {}", format_size(entry.size), path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Compare two snapshots, optionally including content diffs of changed text files
+fn cmd_diff(
+    root: &std::path::Path,
+    from: &str,
+    to: &str,
+    format: DiffFormat,
+    output: Option<&std::path::Path>,
+) -> Result<()> {
+    use std::fmt::Write;
+
+    let old = Manifest::load(root, from)?;
+    let new = Manifest::load(root, to)?;
+    let old_store = Store::new(root).in_group(old.store_group.as_deref());
+    let new_store = Store::new(root).in_group(new.store_group.as_deref());
+
+    let changes = diff_manifests(&old, &new);
+    let mut report = String::new();
+    let content = match format {
+        DiffFormat::Full { content } => content,
+        DiffFormat::Stat => {
+            writeln!(report, "{}", kibo::DiffStat::from_changes(&changes))?;
+            return write_diff_report(&report, output);
+        }
+        DiffFormat::Json => {
+            writeln!(report, "{}", serde_json::to_string_pretty(&kibo::DiffReport::new(&old, &new, &changes))?)?;
+            return write_diff_report(&report, output);
+        }
+        DiffFormat::NameOnly | DiffFormat::NameStatus => {
+            for change in &changes {
+                if matches!(format, DiffFormat::NameStatus) {
+                    write!(report, "{}\t", change.status())?;
+                }
+                writeln!(report, "{}", change.path())?;
+            }
+            return write_diff_report(&report, output);
+        }
+    };
+    writeln!(report, "Comparing '{}' -> '{}'\n", from, to)?;
+
+    let (mut added, mut removed, mut modified) = (0, 0, 0);
+    for change in &changes {
+        match change {
+            Change::Added { path, entry } => {
+                added += 1;
+                writeln!(report, "  + {} ({})", path, format_size(entry.size))?;
+            }
+            Change::Removed { path, entry } => {
+                removed += 1;
+                writeln!(report, "  - {} ({})", path, format_size(entry.size))?;
+            }
+            Change::Modified { path, old: old_entry, new: new_entry } => {
+                modified += 1;
+                if old_entry.is_symlink || new_entry.is_symlink {
+                    writeln!(
+                        report,
+                        "  M {} (symlink: {} -> {})",
+                        path,
+                        old_entry.symlink_target.as_deref().unwrap_or("<file>"),
+                        new_entry.symlink_target.as_deref().unwrap_or("<file>")
+                    )?;
+                    continue;
+                }
+
+                writeln!(report, "  M {} changed ({} bytes -> {} bytes)", path, old_entry.size, new_entry.size)?;
+                if !content {
+                    continue;
+                }
+
+                // A hash-only or pruned snapshot has no blobs to compare; report it and go on
+                let texts = read_text_blob(&old_store, old_entry)
+                    .and_then(|old_text| Ok((old_text, read_text_blob(&new_store, new_entry)?)));
+                let (old_text, new_text) = match texts {
+                    Ok(texts) => texts,
+                    Err(e) => {
+                        writeln!(report, "    Content unavailable: {:#}", e)?;
+                        continue;
+                    }
+                };
+                let (Some(old_text), Some(new_text)) = (old_text, new_text) else {
+                    writeln!(report, "    Binary file changed ({} bytes)", new_entry.size)?;
+                    continue;
+                };
+
+                match unified_diff(&format!("a/{}", path), &format!("b/{}", path), &old_text, &new_text) {
+                    Some(diff) => {
+                        writeln!(report)?;
+                        report.push_str(&diff);
+                        writeln!(report)?;
+                    }
+                    None => writeln!(report, "    Too large for a content diff")?,
+                }
+            }
+        }
+    }
+
+    if changes.is_empty() {
+        writeln!(report, "No differences.")?;
+    }
+    else {
+        let net_bytes = new.total_size as i64 - old.total_size as i64;
+        writeln!(
+            report,
+            "\n{} change(s): {} added, {} removed, {} modified, net {}{}",
+            changes.len(), added, removed, modified,
+            if net_bytes < 0 { '-' } else { '+' },
+            format_size(net_bytes.unsigned_abs())
+        )?;
+    }
+
+    write_diff_report(&report, output)
+}
+
+/// Print a diff report, or write it to `output`
+fn write_diff_report(report: &str, output: Option<&std::path::Path>) -> Result<()> {
+    match output {
+        Some(path) => {
+            std::fs::write(path, report)
+                .with_context(|| format!("Failed to write diff report: {}", path.display()))?;
+            println!("Wrote diff report to {}", path.display());
+        }
+        None => print!("{}", report),
+    }
+
+    Ok(())
+}
+
+/// Check a snapshot, a manifest file or every snapshot against the store
+fn cmd_verify(
+    root: &std::path::Path,
+    name: Option<&str>,
+    manifest_path: Option<&std::path::Path>,
+    all: bool,
+    deep: bool,
+    check_root: bool,
+    json: bool,
+) -> Result<()> {
+    let manifests = match (name, manifest_path) {
+        _ if all => list_snapshots(root)?,
+        (_, Some(path)) => vec![Manifest::load_from_path(path)?],
+        (Some(name), None) => vec![Manifest::load(root, name)?],
+        (None, None) => anyhow::bail!("Specify a snapshot name, --manifest <FILE> or --all"),
+    };
+
+    if json {
+        // A missing store only fails the snapshots that use it, not the whole report
+        let reports: Vec<_> = manifests
+            .iter()
+            .map(|manifest| match Store::open(root, manifest.store_group.as_deref()) {
+                Ok(store) => verify_report(manifest, &store, deep),
+                Err(e) => kibo::VerifyReport::unavailable(manifest, &e),
+            })
+            .collect();
+        if all {
+            println!("{}", serde_json::to_string_pretty(&reports)?);
+        }
+        else {
+            println!("{}", serde_json::to_string_pretty(&reports[0])?);
+        }
+
+        let damaged: Vec<&str> = reports.iter().filter(|r| r.is_damaged()).map(|r| r.snapshot.as_str()).collect();
+        if !damaged.is_empty() {
+            anyhow::bail!("Missing or corrupt blobs, or no store, in {} snapshot(s): {}", damaged.len(), damaged.join(", "));
+        }
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for manifest in &manifests {
+        match verify_manifest(root, manifest, deep, check_root) {
+            Ok(()) => {}
+            Err(e) if all => {
+                eprintln!("Error: {:#}", e);
+                failed += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} snapshot(s) failed verification", failed, manifests.len());
+    }
+
+    Ok(())
+}
+
+/// Compare the permissions and mtimes of a snapshot's entries in the workspace with the
+/// recorded ones, and with `fix` apply the recorded ones again
+fn cmd_verify_workspace_metadata(root: &std::path::Path, name: &str, fix: bool) -> Result<()> {
+    let manifest = Manifest::load(root, name)?;
+    println!("Checking metadata of snapshot '{}' ({} files)...", manifest.name, manifest.file_count);
+
+    let drift = kibo::check_metadata(root, &manifest)?;
+    if drift.is_empty() {
+        println!("Permissions and mtimes match the snapshot.");
+        return Ok(());
+    }
+
+    let format_mtime = |(secs, nanos): (i64, u32)| {
+        chrono::DateTime::from_timestamp(secs, nanos)
+            .map_or_else(|| secs.to_string(), |time| time.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true))
+    };
+    for entry in &drift {
+        if let Some((recorded, current)) = entry.mode {
+            println!("  permissions  {} (recorded {:o}, now {:o})", entry.path, recorded, current);
+        }
+        if let Some((recorded, current)) = entry.mtime {
+            println!("  mtime        {} (recorded {}, now {})", entry.path, format_mtime(recorded), format_mtime(current));
+        }
+        if fix {
+            entry.fix(root)?;
+        }
+    }
+
+    if !fix {
+        anyhow::bail!(
+            "{} {} from snapshot '{}'; run 'kibo verify {} --workspace-metadata --fix' to apply the recorded metadata",
+            drift.len(),
+            if drift.len() == 1 { "entry differs" } else { "entries differ" },
+            name,
+            name
+        );
+    }
+    println!("\nApplied the recorded metadata to {} entr{}.", drift.len(), if drift.len() == 1 { "y" } else { "ies" });
+
+    Ok(())
+}
+
+/// Check every manifest against the others, without reading the store
+fn cmd_verify_cross_snapshot(root: &std::path::Path, json: bool) -> Result<()> {
+    let check = kibo::cross_check(&list_snapshots(root)?);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&check)?);
+    }
+    else {
+        println!("Checked {} file entries across {} snapshot(s).", check.entries, check.snapshots);
+        for conflict in &check.conflicts {
+            println!("\nHash {} is recorded with different sizes:", conflict.hash);
+            for hash_use in &conflict.uses {
+                println!("  {:>10}  {}: {}", format_size(hash_use.content_length), hash_use.snapshot, hash_use.path);
+            }
+        }
+        if !check.inconsistent.is_empty() {
+            println!("\nEntries whose hash does not fit the entry:");
+            for entry in &check.inconsistent {
+                println!("  {}: {} ({})", entry.snapshot, entry.path, entry.reason);
+            }
+        }
+        if check.is_clean() {
+            println!("No inconsistencies found.");
+        }
+    }
+
+    if !check.is_clean() {
+        anyhow::bail!(
+            "Found {} hash(es) with conflicting sizes and {} inconsistent entr{} across snapshots",
+            check.conflicts.len(),
+            check.inconsistent.len(),
+            if check.inconsistent.len() == 1 { "y" } else { "ies" }
+        );
+    }
+
+    Ok(())
+}
+
+/// Print the human-readable verification of one snapshot, failing on the first problem
+fn verify_manifest(root: &std::path::Path, manifest: &Manifest, deep: bool, check_root: bool) -> Result<()> {
+    println!("Verifying snapshot '{}' ({} files)...", manifest.name, manifest.file_count);
+
+    if check_root {
+        match manifest.content_root_matches() {
+            Some(true) => println!("Content root matches."),
+            Some(false) => anyhow::bail!(
+                "Content root mismatch: the file list of snapshot '{}' was modified after it was saved",
+                manifest.name
+            ),
+            None => anyhow::bail!(
+                "Snapshot '{}' has no content root (saved by an older kibo version)",
+                manifest.name
+            ),
+        }
+        if !deep {
+            return Ok(());
+        }
+    }
+
+    let store = Store::open(root, manifest.store_group.as_deref())?;
+    verify_snapshot(manifest, &store)?;
+    println!("All blobs present in store.");
+
+    if deep {
+        let rehashed = verify_snapshot_contents(manifest, &store)?;
+        println!("All {} blobs match their recorded hashes.", rehashed);
+
+        let assumed = manifest.assumed_count();
+        if assumed > 0 {
+            println!("Skipped {} assume_unchanged entries (not content-hashed).", assumed);
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts of what a prune pass removed
+struct PruneStats {
+    blobs_removed: usize,
+    blobs_freed: u64,
+    db_dumps_removed: usize,
+    db_dumps_freed: u64,
+    /// Unreferenced blobs kept because they are still within `--keep-unreferenced-for`
+    blobs_kept: usize,
+    /// Result of rehashing the kept blobs, with the snapshots referencing each blob
+    integrity: Option<(BlobCheck, HashMap<String, BTreeSet<String>>)>,
+}
+
+/// Store groups to collect garbage in: the default store (None), every group directory in
+/// the store and every group a snapshot refers to
+fn store_groups(root: &std::path::Path, snapshots: &[Manifest]) -> Result<Vec<Option<String>>> {
+    let mut groups: BTreeSet<Option<String>> = BTreeSet::from([None]);
+    groups.extend(Store::group_names(root)?.into_iter().map(Some));
+    groups.extend(snapshots.iter().map(|s| s.store_group.clone()));
+    Ok(groups.into_iter().collect())
+}
+
+/// How `prune` collects unreferenced blobs
+#[derive(Default)]
+struct Collect<'a> {
+    /// Rehash every blob that is kept and report damaged ones
+    check: bool,
+    /// Only collect garbage in this store group
+    group: Option<&'a str>,
+    /// Threads scanning the store for unreferenced blobs (None = one per CPU)
+    concurrency: Option<usize>,
+    /// Keep blobs first found unreferenced less than this long ago, e.g. "24h"
+    keep_unreferenced_for: Option<&'a str>,
+}
+
+impl Collect<'_> {
+    /// The parsed `keep_unreferenced_for`
+    fn grace_period(&self) -> Result<Option<chrono::Duration>> {
+        self.keep_unreferenced_for.map(parse_retention).transpose()
+    }
+}
+
+/// Remove blobs and database dumps not referenced by any snapshot, optionally
+/// rehashing the blobs that are kept. Each store group only keeps the blobs of its own
+/// snapshots; `collect.group` limits blob collection to one group. With a grace period,
+/// blobs first found unreferenced by an earlier prune less than that long ago are kept.
+fn prune_unreferenced(
+    root: &std::path::Path,
+    verbose: bool,
+    collect: &Collect,
+    show_progress: bool,
+) -> Result<PruneStats> {
+    let Collect { check, group, concurrency, .. } = *collect;
+    let mut unreferenced_blobs = match collect.grace_period()? {
+        Some(keep_for) => Some(kibo::UnreferencedBlobs::load(root, keep_for)?),
+        None => None,
+    };
+    let mut kept = 0;
+
+    let snapshots = list_snapshots(root)?;
+    let referenced_db_dumps = kibo::referenced_db_dumps(&snapshots);
+    let mut blob_snapshots: HashMap<String, BTreeSet<String>> = HashMap::new();
+    let mut blob_check = BlobCheck::default();
+    let mut removed = 0;
+    let mut freed = 0u64;
+    
+    if verbose {
+        eprintln!("Found {} snapshot(s)", snapshots.len());
+    }
+    
+    let groups = store_groups(root, &snapshots)?;
+    if let Some(group) = group
+        && !groups.iter().any(|g| g.as_deref() == Some(group))
+    {
+        anyhow::bail!("Store group '{}' does not exist", group);
+    }
+
+    for store_group in groups.iter().filter(|g| group.is_none() || g.as_deref() == group) {
+        let mut referenced_hashes = HashSet::new();
+        let mut group_blobs: HashMap<String, BTreeSet<String>> = HashMap::new();
+
+        if verbose && let Some(name) = store_group {
+            eprintln!("Store group '{}':", name);
+        }
+
+        for snapshot in snapshots.iter().filter(|s| &s.store_group == store_group) {
+            if verbose {
+                eprintln!("  Scanning: {} ({} files)", snapshot.name, snapshot.file_count);
+            }
+            for entry in snapshot.files.values() {
+                referenced_hashes.insert(entry.hash.clone());
+                // Assumed-unchanged hashes are not derived from content, so they cannot be rehashed
+                if check && !entry.assumed {
+                    group_blobs.entry(entry.hash.clone()).or_default().insert(snapshot.name.clone());
+                }
+            }
+        }
+
+        if verbose {
+            eprintln!("Found {} unique referenced blob(s)", referenced_hashes.len());
+        }
+
+        let store = Store::open_or_empty(root, store_group.as_deref())?;
+
+        if check {
+            if verbose {
+                eprintln!("Rehashing {} referenced blob(s)", group_blobs.len());
+            }
+            let hashes: Vec<&str> = group_blobs.keys().map(String::as_str).collect();
+            let group_check = store.check_blobs(&hashes);
+            blob_check.checked += group_check.checked;
+            blob_check.corrupt.extend(group_check.corrupt);
+            blob_check.missing.extend(group_check.missing);
+            for (hash, names) in group_blobs {
+                blob_snapshots.entry(hash).or_default().extend(names);
+            }
+        }
+
+        if let Some(ref mut unreferenced_blobs) = unreferenced_blobs {
+            let recent = unreferenced_blobs.protect_recent(&store, store_group.as_deref(), &mut referenced_hashes)?;
+            if verbose {
+                eprintln!("Keeping {} unreferenced blob(s) within the grace period", recent);
+            }
+            kept += recent;
+        }
+
+        let (group_removed, group_freed) = store.garbage_collect(&referenced_hashes, show_progress, concurrency)?;
+        removed += group_removed;
+        freed += group_freed;
+    }
+    if let Some(unreferenced_blobs) = unreferenced_blobs {
+        unreferenced_blobs.save()?;
+    }
+
+    if verbose {
+        eprintln!("Found {} referenced database dump(s)", referenced_db_dumps.len());
+    }
+
+    let integrity = if check {
+        blob_check.corrupt.sort();
+        blob_check.missing.sort();
+        Some((blob_check, blob_snapshots))
+    }
+    else {
+        None
+    };
+
+    // Prune unreferenced database dumps
+    let mut db_removed = 0;
+    let mut db_freed = 0u64;
+
+    if verbose {
+        eprintln!("Scanning database dumps directory...");
+    }
+    for dump in kibo::find_orphaned_db_dumps(root, &referenced_db_dumps)? {
+        if verbose {
+            eprintln!("  Removing unreferenced database dump: {}", dump.filename);
+        }
+
+        std::fs::remove_file(&dump.path)?;
+        db_removed += 1;
+        db_freed += dump.size;
+    }
+
+    Ok(PruneStats {
+        blobs_removed: removed,
+        blobs_freed: freed,
+        db_dumps_removed: db_removed,
+        db_dumps_freed: db_freed,
+        blobs_kept: kept,
+        integrity,
+    })
+}
+
+/// Which expired snapshots `prune --expire` may remove
+struct Expiry {
+    /// Snapshots kept for this run even if they have expired
+    protect: Vec<String>,
+    /// Also remove expired snapshots marked with `kibo protect`
+    force: bool,
+}
+
+/// Prune unreferenced blobs from the store
+fn cmd_prune(
+    root: &std::path::Path,
+    verbose: bool,
+    expiry: Option<Expiry>,
+    collect: &Collect,
+    progress_config: ProgressConfig,
+) -> Result<()> {
+    let timer = Timer::new();
+    // Reject a bad duration before expired snapshots are removed
+    collect.grace_period()?;
+    
+    if let Some(ref expiry) = expiry {
+        for name in &expiry.protect {
+            if !Manifest::exists(root, name) {
+                eprintln!("Warning: Snapshot '{}' given to --protect does not exist", name);
+            }
+        }
+
+        let now = chrono::Utc::now();
+        let (kept, expired): (Vec<Manifest>, Vec<Manifest>) = list_snapshots(root)?
+            .into_iter()
+            .filter(|snapshot| snapshot.is_expired(now))
+            .partition(|snapshot| (snapshot.protected && !expiry.force) || expiry.protect.contains(&snapshot.name));
+
+        for snapshot in &kept {
+            println!("Keeping expired snapshot '{}' (protected)", snapshot.name);
+        }
+        if expired.is_empty() {
+            println!("No expired snapshots found");
+        }
+        for snapshot in &expired {
+            println!(
+                "Removing expired snapshot '{}' (expired {})",
+                snapshot.name,
+                snapshot.expire_at.unwrap_or(now).format("%Y-%m-%d %H:%M:%S")
+            );
+            // The snapshot's database dump becomes unreferenced and is pruned below
+            Manifest::delete(root, &snapshot.name)?;
+        }
+    }
+
+    println!("Scanning snapshots for referenced blobs and database dumps...");
+    println!("Pruning unreferenced blobs...");
+
+    let stats = prune_unreferenced(root, verbose, collect, progress_config.should_show_progress())?;
+
+    if stats.blobs_removed > 0 || stats.db_dumps_removed > 0 {
+        println!(
+            "\nPruned {} unreferenced blob(s) (freed {}) and {} database dump(s) (freed {}) in {}",
+            stats.blobs_removed,
+            format_size(stats.blobs_freed),
+            stats.db_dumps_removed,
+            format_size(stats.db_dumps_freed),
+            timer.elapsed_string()
+        );
+    }
+    else {
+        println!("\nNo unreferenced blobs or database dumps found in {}", timer.elapsed_string());
+    }
+    if stats.blobs_kept > 0 {
+        println!(
+            "Kept {} unreferenced blob(s) first found less than {} ago",
+            stats.blobs_kept,
+            collect.keep_unreferenced_for.unwrap_or_default()
+        );
+    }
+
+    let mut flags = Vec::new();
+    if verbose { flags.push("--verbose".to_string()); }
+    if let Some(ref expiry) = expiry {
+        flags.push("--expire".to_string());
+        for name in &expiry.protect {
+            flags.push(format!("--protect={}", name));
+        }
+        if expiry.force { flags.push("--force".to_string()); }
+    }
+    if collect.check { flags.push("--check".to_string()); }
+    if let Some(group) = collect.group { flags.push(format!("--group={}", group)); }
+    if let Some(threads) = collect.concurrency { flags.push(format!("--concurrency={}", threads)); }
+    if let Some(keep_for) = collect.keep_unreferenced_for { flags.push(format!("--keep-unreferenced-for={}", keep_for)); }
+    let entry = HistoryEntry::new("PRUNE", None, flags);
+    log_entry(root, &entry);
+
+    if let Some((blob_check, blob_snapshots)) = stats.integrity {
+        let damaged = print_integrity_report(&blob_check, &blob_snapshots);
+        if damaged > 0 {
+            anyhow::bail!("Store integrity check failed: {} damaged blob(s)", damaged);
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the result of `prune --check`, returning the number of damaged blobs
+fn print_integrity_report(blob_check: &BlobCheck, blob_snapshots: &HashMap<String, BTreeSet<String>>) -> usize {
+    println!("\nIntegrity: rehashed {} blob(s)", blob_check.checked);
+
+    let damaged = blob_check.corrupt.len() + blob_check.missing.len();
+    if damaged == 0 {
+        println!("  All blobs match their hashes");
+        return 0;
+    }
+
+    let problems = blob_check.corrupt.iter().map(|hash| (hash, "corrupt"))
+        .chain(blob_check.missing.iter().map(|hash| (hash, "missing")));
+    for (hash, problem) in problems {
+        let snapshots: Vec<&str> = blob_snapshots
+            .get(hash)
+            .map(|names| names.iter().map(String::as_str).collect())
+            .unwrap_or_default();
+        println!("  {} ({}): referenced by {}", hash, problem, snapshots.join(", "));
+    }
+    println!(
+        "  {} corrupt, {} missing; damaged blobs were kept for recovery",
+        blob_check.corrupt.len(),
+        blob_check.missing.len()
+    );
+
+    damaged
+}
+
+/// Remove empty directories left behind in the store
+fn cmd_store_tidy(root: &std::path::Path) -> Result<()> {
+    let removed = Store::new(root).prune_empty_dirs()?;
+
+    if removed == 0 {
+        println!("Store is already tidy.");
+    }
+    else {
+        println!("Removed {} empty store director{}", removed, if removed == 1 { "y" } else { "ies" });
+    }
+
+    Ok(())
+}
+
+/// List blobs that have become writable in every store, optionally making them read-only again
+fn cmd_store_verify_permissions(root: &std::path::Path, fix: bool) -> Result<()> {
+    let groups = Store::group_names(root)?;
+    let mut writable = Store::new(root).writable_blobs()?;
+    for group in &groups {
+        writable.extend(Store::new(root).in_group(Some(group)).writable_blobs()?);
+    }
+
+    if writable.is_empty() {
+        println!("All blobs are read-only.");
+        return Ok(());
+    }
+
+    for path in &writable {
+        if fix {
+            kibo::make_read_only(path)?;
+            println!("Made read-only: {}", path.display());
+        }
+        else {
+            println!("Writable: {}", path.display());
+        }
+    }
+
+    if !fix {
+        anyhow::bail!("{} writable blob(s) found; run 'kibo store verify-permissions --fix' to make them read-only", writable.len());
+    }
+    println!("\nMade {} blob(s) read-only. Run 'kibo verify --deep' to check that their content is intact.", writable.len());
+
+    Ok(())
+}
+
+/// Compress the uncompressed blobs referenced by the given snapshots, or by all of them
+fn cmd_store_recompress(
+    root: &std::path::Path,
+    config: &Config,
+    names: &[String],
+    all: bool,
+    level: Option<u32>,
+) -> Result<()> {
+    let effort = if level.is_none() { config.compression_effort } else { None };
+    let level = level.unwrap_or_else(|| config.effective_compression_level());
+    if level == 0 {
+        anyhow::bail!("Compression is disabled (compression_level = 0); set compression_level or pass --level");
+    }
+
+    // A snapshot's recorded level only covers the blobs it added, not the ones it reused, so
+    // every blob is checked whatever level its snapshots were saved at
+    let snapshots = if all {
+        list_snapshots(root)?
+    }
+    else {
+        names.iter().map(|name| Manifest::load(root, name)).collect::<Result<Vec<_>>>()?
+    };
+
+    let mut total = kibo::Recompressed::default();
+    for store_group in store_groups(root, &snapshots)? {
+        let hashes: BTreeSet<&str> = snapshots
+            .iter()
+            .filter(|snapshot| snapshot.store_group == store_group)
+            .flat_map(|snapshot| snapshot.files.values().map(|entry| entry.hash.as_str()))
+            .collect();
+        if hashes.is_empty() {
+            continue;
+        }
+
+        // Report a missing or malformed store before touching any blob
+        Store::open(root, store_group.as_deref())?;
+        let mut store = Store::with_compression(root, level).in_group(store_group.as_deref());
+        if let Some(effort) = effort {
+            store = store.with_compression_effort(effort);
+        }
+        if config.durable_writes {
+            store = store.with_durable_writes();
+        }
+        let hashes: Vec<&str> = hashes.into_iter().collect();
+        let recompressed = store.recompress_blobs(&hashes)?;
+        total.blobs += recompressed.blobs;
+        total.bytes_before += recompressed.bytes_before;
+        total.bytes_after += recompressed.bytes_after;
+    }
+
+    // Only raw blobs were compressed; blobs already compressed keep their level, so only
+    // snapshots recorded without compression now match the level they are recorded at
+    for mut snapshot in snapshots {
+        let uncompressed = snapshot.compression_level.is_none_or(|recorded| recorded == 0)
+            || snapshot.compression_algorithm.as_deref() == Some("none");
+        if snapshot.hash_only || !uncompressed {
+            continue;
+        }
+        snapshot.compression_level = Some(level);
+        snapshot.compression_effort = effort;
+        snapshot.compression_algorithm = Some("zstd".to_string());
+        snapshot.save(root)?;
+    }
+
+    if total.blobs == 0 {
+        println!("No uncompressed blobs found.");
+    }
+    else {
+        println!(
+            "Compressed {} blob(s) at level {}: {} -> {}",
+            total.blobs,
+            level,
+            format_size(total.bytes_before),
+            format_size(total.bytes_after)
+        );
+    }
+
+    Ok(())
+}
+
+/// Seed the store with the content of a directory of loose files
+fn cmd_store_import_blobs(
+    root: &std::path::Path,
+    config: &Config,
+    dir: &std::path::Path,
+    group: Option<&str>,
+) -> Result<()> {
+    if !dir.is_dir() {
+        anyhow::bail!("Not a directory: {}", dir.display());
+    }
+    if let Some(group) = group {
+        kibo::validate_store_group(group)?;
+    }
+
+    let mut store = Store::with_compression(root, config.effective_compression_level()).in_group(group);
+    if let Some(effort) = config.compression_effort {
+        store = store.with_compression_effort(effort);
+    }
+    if config.pack_small_blobs {
+        store = store.with_pack_threshold(config.pack_threshold_kb * 1024);
+    }
+    if config.durable_writes {
+        store = store.with_durable_writes();
+    }
+    store.init()?;
+
+    let imported = store.import_dir(dir)?;
+    println!(
+        "Imported {} file{}: {} new blob{} ({}), {} already stored",
+        imported.files,
+        if imported.files == 1 { "" } else { "s" },
+        imported.new_blobs,
+        if imported.new_blobs == 1 { "" } else { "s" },
+        format_size(imported.new_bytes),
+        imported.reused_blobs
+    );
+    if imported.new_blobs > 0 {
+        println!("Note: 'kibo prune' removes these blobs until a snapshot references them.");
+    }
+
+    Ok(())
+}
+
+/// Rebuild a snapshot manifest from a file list whose blobs are in the store
+fn cmd_store_fsck(
+    root: &std::path::Path,
+    config: &Config,
+    name: &str,
+    file_list: &std::path::Path,
+    group: Option<&str>,
+    force: bool,
+) -> Result<()> {
+    validate_snapshot_name(name)?;
+    if Manifest::get_path(root, name).exists() && !force {
+        anyhow::bail!("Snapshot '{}' already has a manifest; use --force to replace it", name);
+    }
+
+    let content = std::fs::read_to_string(file_list)
+        .with_context(|| format!("Failed to read file list: {}", file_list.display()))?;
+    let files = kibo::parse_file_list(&content)
+        .with_context(|| format!("Invalid file list: {}", file_list.display()))?;
+
+    let group = group.or(config.store_group.as_deref());
+    let store = Store::open(root, group)?;
+    let mut config = config.clone();
+    config.store_group = group.map(str::to_string);
+
+    let manifest = kibo::rebuild_manifest(name, &files, &store, &config)?;
+    manifest.save(root)?;
+
+    println!(
+        "Rebuilt manifest for '{}': {} file{}, {}",
+        name,
+        manifest.file_count,
+        if manifest.file_count == 1 { "" } else { "s" },
+        format_size(manifest.total_size)
+    );
+    println!("Note: modes and modification times are defaults (644/755, now); symlinks are restored as regular files.");
+
+    let mut flags = vec![format!("--file-list={}", file_list.display())];
+    if let Some(group) = group {
+        flags.push(format!("--group={}", group));
+    }
+    log_entry(root, &HistoryEntry::new("FSCK", Some(name), flags));
+
+    Ok(())
+}
+
+/// The store's size compared with its limit, after any automatic pruning
+struct StoreLimitCheck {
+    limit: u64,
+    size: u64,
+    /// Snapshots removed to bring the store under its limit
+    removed: Vec<String>,
+    freed: u64,
+    /// Set when the grace period kept a removal's blobs, which stops further removals
+    stalled: bool,
+}
+
+impl StoreLimitCheck {
+    fn is_over(&self) -> bool {
+        self.size > self.limit
+    }
+}
+
+/// Order in which `auto_prune` removes snapshots: expired ones first, soonest expiry first,
+/// then the oldest. Protected snapshots and `keep` are never removed.
+fn auto_prune_order(snapshots: Vec<Manifest>, keep: Option<&str>, now: chrono::DateTime<chrono::Utc>) -> Vec<Manifest> {
+    let mut candidates: Vec<Manifest> = snapshots
+        .into_iter()
+        .filter(|snapshot| !snapshot.protected && Some(snapshot.name.as_str()) != keep)
+        .collect();
+    candidates.sort_by_key(|snapshot| {
+        let expired = snapshot.expire_at.filter(|_| snapshot.is_expired(now));
+        (expired.is_none(), expired, snapshot.created_at)
+    });
+    candidates
+}
+
+/// Compare the store's size with a limit in GB. With `auto_prune`, remove snapshots in
+/// `auto_prune_order` and their unreferenced blobs until the store fits.
+fn enforce_store_limit(
+    root: &std::path::Path,
+    limit_gb: f64,
+    auto_prune: bool,
+    keep: Option<&str>,
+    keep_unreferenced_for: Option<&str>,
+    verbose: bool,
+) -> Result<StoreLimitCheck> {
+    let store = Store::new(root);
+    let mut check = StoreLimitCheck {
+        limit: (limit_gb * 1024.0 * 1024.0 * 1024.0) as u64,
+        size: store.total_size()?,
+        removed: Vec::new(),
+        freed: 0,
+        stalled: false,
+    };
+    if !auto_prune || !check.is_over() {
+        return Ok(check);
+    }
+
+    for snapshot in auto_prune_order(list_snapshots(root)?, keep, chrono::Utc::now()) {
+        if !check.is_over() {
+            break;
+        }
+        println!(
+            "Store is over its {} limit; removing snapshot '{}' (created {})",
+            format_size(check.limit),
+            snapshot.name,
+            snapshot.created_at.format("%Y-%m-%d %H:%M:%S")
+        );
+        Manifest::delete(root, &snapshot.name)?;
+        let collect = Collect { keep_unreferenced_for, ..Default::default() };
+        let stats = prune_unreferenced(root, verbose, &collect, false)?;
+        let freed = stats.blobs_freed + stats.db_dumps_freed;
+        check.freed += freed;
+        check.size = store.total_size()?;
+        log_entry(root, &HistoryEntry::new("RM", Some(&snapshot.name), vec!["--auto-prune".to_string()]));
+        check.removed.push(snapshot.name);
+
+        // Blobs within keep_unreferenced_for stay in the store, and so would those of every
+        // further snapshot removed. Blobs shared with the remaining snapshots are freed once
+        // the next snapshots go, so those do not stop the loop.
+        if stats.blobs_kept > 0 && check.is_over() {
+            println!(
+                "Kept {} unreferenced blob(s) within keep_unreferenced_for; not removing any more snapshots",
+                stats.blobs_kept
+            );
+            check.stalled = true;
+            break;
+        }
+    }
+
+    Ok(check)
+}
+
+/// Check the store against its size limit, failing if it stays over the limit
+fn cmd_store_check_space(root: &std::path::Path, config: &Config, max_size_gb: Option<f64>, auto_prune: bool) -> Result<()> {
+    let Some(limit_gb) = max_size_gb.or(config.store_max_size_gb) else {
+        anyhow::bail!("No store size limit set; set store_max_size_gb in {} or pass --max-size-gb", CONFIG_FILENAME);
+    };
+    if limit_gb.is_nan() || limit_gb <= 0.0 {
+        anyhow::bail!("Store size limit must be positive, got {}", limit_gb);
+    }
+
+    let check = enforce_store_limit(root, limit_gb, auto_prune || config.auto_prune, None, config.keep_unreferenced_for.as_deref(), false)?;
+    if !check.removed.is_empty() {
+        println!("Removed {} snapshot(s), freed {}", check.removed.len(), format_size(check.freed));
+    }
+    println!("Store: {} of {} limit", format_size(check.size), format_size(check.limit));
+
+    if check.is_over() {
+        anyhow::bail!(
+            "Store exceeds its size limit by {}{}",
+            format_size(check.size - check.limit),
+            if check.stalled {
+                "; removed snapshots' blobs are kept by keep_unreferenced_for"
+            }
+            else if auto_prune || config.auto_prune {
+                " with no unprotected snapshots left to remove"
+            }
+            else {
+                "; run with --auto-prune to remove the oldest snapshots"
+            }
+        );
+    }
+    println!("Store is within its size limit.");
+
+    Ok(())
+}
+
+/// Print the store's size on disk, optionally with what each snapshot costs
+fn cmd_store_size(root: &std::path::Path, per_snapshot: bool, histogram: bool) -> Result<()> {
+    let store = Store::new(root);
+    println!("Store: {} blobs, {} on disk", count_all_blobs(root)?, format_size(store.total_size()?));
+    if histogram {
+        print_blob_size_histogram(root)?;
+    }
+    if !per_snapshot {
+        return Ok(());
+    }
+
+    let snapshots = list_snapshots(root)?;
+    if snapshots.is_empty() {
+        println!("No snapshots found.");
+        return Ok(());
     }
-    // Default: keep order from list_snapshots (newest first by creation time)
-
-    println!("Snapshots ({}):\n", snapshots.len());
-    println!(
-        "{:<20} {:<20} {:>10} {:>8}",
-        "NAME", "CREATED", "SIZE", "FILES"
-    );
-    println!("{}", "-".repeat(60));
 
-    for snapshot in &snapshots {
+    let stores: HashMap<Option<String>, Store> = store_groups(root, &snapshots)?
+        .into_iter()
+        .map(|group| {
+            let store = Store::new(root).in_group(group.as_deref());
+            (group, store)
+        })
+        .collect();
+    let footprints = kibo::snapshot_footprints(&snapshots, |group, hash| {
+        stores[&group.map(str::to_string)].stored_size(hash).ok().flatten()
+    });
+
+    println!();
+    println!("{:<20} {:>10} {:>10} {:>10}", "NAME", "LOGICAL", "EXCLUSIVE", "SHARED");
+    println!("{}", "-".repeat(53));
+    for footprint in &footprints {
         println!(
-            "{:<20} {:<20} {:>10} {:>8}",
-            snapshot.name,
-            snapshot.created_at.format("%Y-%m-%d %H:%M:%S"),
-            snapshot.human_size(),
-            snapshot.file_count
+            "{:<20} {:>10} {:>10} {:>10}",
+            footprint.name,
+            format_size(footprint.logical_bytes),
+            format_size(footprint.exclusive_bytes),
+            format_size(footprint.shared_bytes)
         );
     }
-
-    let store = Store::new(root);
-    if let Ok(total_size) = store.total_size() {
-        if let Ok(blob_count) = store.blob_count() {
-            println!("\nStore: {} blobs, {} on disk", blob_count, format_size(total_size));
-        }
-    }
+    println!("\nEXCLUSIVE is freed by removing that snapshot alone; SHARED stays as long as another snapshot uses it.");
 
     Ok(())
 }
 
-/// Prune unreferenced blobs from the store
-fn cmd_prune(root: &std::path::Path, verbose: bool, progress_config: ProgressConfig) -> Result<()> {
-    use std::collections::HashSet;
-
-    let timer = Timer::new();
-    
-    println!("Scanning snapshots for referenced blobs and database dumps...");
+/// Number of blobs in the default store and every store group, matching `Store::total_size`,
+/// which measures them all
+fn count_all_blobs(root: &std::path::Path) -> Result<usize> {
+    let mut count = Store::new(root).blob_count()?;
+    for group in Store::group_names(root)? {
+        count += Store::new(root).in_group(Some(&group)).blob_count()?;
+    }
+    Ok(count)
+}
 
-    let snapshots = list_snapshots(root)?;
-    let mut referenced_hashes = HashSet::new();
-    let mut referenced_db_dumps = HashSet::new();
-    
-    if verbose {
-        eprintln!("Found {} snapshot(s)", snapshots.len());
+/// Print how the blobs of all store groups are spread over size ranges
+fn print_blob_size_histogram(root: &std::path::Path) -> Result<()> {
+    let mut sizes = Store::new(root).blob_sizes()?;
+    for group in Store::group_names(root)? {
+        sizes.extend(Store::new(root).in_group(Some(&group)).blob_sizes()?);
     }
-    
-    for snapshot in &snapshots {
-        if verbose {
-            eprintln!("  Scanning: {} ({} files)", snapshot.name, snapshot.file_count);
-        }
-        for entry in snapshot.files.values() {
-            referenced_hashes.insert(entry.hash.clone());
-        }
-        
-        // Track referenced database dumps
-        if let Some(ref db_filename) = snapshot.db_dump_filename {
-            referenced_db_dumps.insert(db_filename.clone());
-        }
+    let total_count = sizes.len().max(1);
+    let total_bytes: u64 = sizes.iter().sum::<u64>().max(1);
+
+    println!();
+    println!("{:<12} {:>8} {:>6} {:>10} {:>6}", "SIZE", "BLOBS", "%", "BYTES", "%");
+    println!("{}", "-".repeat(46));
+    for bucket in kibo::size_histogram(sizes) {
+        println!(
+            "{:<12} {:>8} {:>5.1}% {:>10} {:>5.1}%",
+            bucket.label,
+            bucket.count,
+            bucket.count as f64 * 100.0 / total_count as f64,
+            format_size(bucket.bytes),
+            bucket.bytes as f64 * 100.0 / total_bytes as f64
+        );
     }
+    Ok(())
+}
 
-    if verbose {
-        eprintln!("Found {} unique referenced blob(s)", referenced_hashes.len());
-        eprintln!("Found {} referenced database dump(s)", referenced_db_dumps.len());
+/// Report how much content is shared between snapshots
+fn cmd_store_dedup_report(root: &std::path::Path, top: usize) -> Result<()> {
+    let snapshots = list_snapshots(root)?;
+    if snapshots.is_empty() {
+        println!("No snapshots found.");
+        return Ok(());
     }
 
-    println!("Pruning unreferenced blobs...");
+    let report = dedup_report(&snapshots, top);
+    let store_size = Store::new(root).total_size().unwrap_or(0);
 
-    let store = Store::new(root);
-    let (removed, freed) = store.garbage_collect(&referenced_hashes, progress_config.should_show_progress())?;
+    println!("Snapshots:       {}", report.snapshot_count);
+    println!("Logical size:    {}", format_size(report.logical_bytes));
+    println!("Unique content:  {} ({} blobs)", format_size(report.unique_bytes), report.unique_blobs);
+    println!(
+        "Saved by dedup:  {} ({:.2}x)",
+        format_size(report.saved_bytes()),
+        report.ratio()
+    );
+    println!("Store on disk:   {}", format_size(store_size));
 
-    // Prune unreferenced database dumps
-    let db_dumps_dir = root.join(".kibo").join("db_snapshots");
-    let mut db_removed = 0;
-    let mut db_freed = 0u64;
-    
-    if db_dumps_dir.exists() {
-        if verbose {
-            eprintln!("Scanning database dumps directory...");
-        }
-        
-        for entry in std::fs::read_dir(&db_dumps_dir)? {
-            let entry = entry?;
-            if entry.file_type()?.is_file() {
-                let filename = entry.file_name().to_string_lossy().to_string();
-                
-                // Only remove .sql files that aren't referenced
-                if filename.ends_with(".sql") && !referenced_db_dumps.contains(&filename) {
-                    let metadata = entry.metadata()?;
-                    let size = metadata.len();
-                    
-                    if verbose {
-                        eprintln!("  Removing unreferenced database dump: {}", filename);
-                    }
-                    
-                    std::fs::remove_file(entry.path())?;
-                    db_removed += 1;
-                    db_freed += size;
-                }
-            }
-        }
+    if report.top_shared.is_empty() {
+        println!("\nNo blobs are shared between files or snapshots.");
+        return Ok(());
     }
 
-    if removed > 0 || db_removed > 0 {
+    println!("\nMost shared blobs:\n");
+    println!("{:<16} {:>10} {:>10} {:>6}  PATH", "HASH", "SIZE", "SNAPSHOTS", "REFS");
+    for blob in &report.top_shared {
         println!(
-            "\nPruned {} unreferenced blob(s) (freed {}) and {} database dump(s) (freed {}) in {}",
-            removed,
-            format_size(freed),
-            db_removed,
-            format_size(db_freed),
-            timer.elapsed_string()
+            "{:<16} {:>10} {:>10} {:>6}  {}",
+            &blob.hash[..blob.hash.len().min(16)],
+            format_size(blob.size),
+            blob.snapshot_count,
+            blob.reference_count,
+            blob.example_path
         );
     }
-    else {
-        println!("\nNo unreferenced blobs or database dumps found in {}", timer.elapsed_string());
-    }
-
-    let mut flags = Vec::new();
-    if verbose { flags.push("--verbose".to_string()); }
-    let entry = HistoryEntry::new("PRUNE", None, flags);
-    log_entry(root, &entry);
 
     Ok(())
 }
 
 /// Remove one or more snapshots by name
-fn cmd_remove(root: &std::path::Path, names: &[String], progress_config: ProgressConfig) -> Result<()> {
+fn cmd_remove(
+    root: &std::path::Path,
+    names: &[String],
+    force: bool,
+    keep_unreferenced_for: Option<&str>,
+    progress_config: ProgressConfig,
+) -> Result<()> {
     if names.is_empty() {
         anyhow::bail!("No snapshot names provided");
     }
+    let grace_period = keep_unreferenced_for.map(parse_retention).transpose().context("Invalid keep_unreferenced_for")?;
+
+    // Refuse before deleting anything, so a protected name does not leave the list half removed
+    if !force {
+        let protected: Vec<&str> = names
+            .iter()
+            .filter(|name| Manifest::load(root, name).is_ok_and(|manifest| manifest.protected))
+            .map(|name| name.as_str())
+            .collect();
+        if !protected.is_empty() {
+            anyhow::bail!(
+                "Snapshot(s) {} are protected; run 'kibo unprotect' first or pass --force",
+                protected.iter().map(|name| format!("'{}'", name)).collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
 
     let timer = Timer::new();
     
@@ -642,16 +2596,33 @@ fn cmd_remove(root: &std::path::Path, names: &[String], progress_config: Progres
     progress.finish();
 
     let remaining_snapshots = list_snapshots(root)?;
-    let mut referenced_hashes = HashSet::new();
-    
-    for snapshot in &remaining_snapshots {
-        for entry in snapshot.files.values() {
-            referenced_hashes.insert(entry.hash.clone());
+    let mut removed = 0;
+    let mut freed = 0u64;
+    let mut kept = 0;
+    let mut unreferenced_blobs = match grace_period {
+        Some(keep_for) => Some(kibo::UnreferencedBlobs::load(root, keep_for)?),
+        None => None,
+    };
+
+    // Blobs are only shared within a store group, so each group keeps what its own snapshots use
+    for store_group in store_groups(root, &remaining_snapshots)? {
+        let mut referenced_hashes: HashSet<String> = remaining_snapshots
+            .iter()
+            .filter(|s| s.store_group == store_group)
+            .flat_map(|s| s.files.values().map(|entry| entry.hash.clone()))
+            .collect();
+
+        let store = Store::new(root).in_group(store_group.as_deref());
+        if let Some(ref mut unreferenced_blobs) = unreferenced_blobs {
+            kept += unreferenced_blobs.protect_recent(&store, store_group.as_deref(), &mut referenced_hashes)?;
         }
+        let (group_removed, group_freed) = store.garbage_collect(&referenced_hashes, progress_config.should_show_progress(), None)?;
+        removed += group_removed;
+        freed += group_freed;
+    }
+    if let Some(unreferenced_blobs) = unreferenced_blobs {
+        unreferenced_blobs.save()?;
     }
-
-    let store = Store::new(root);
-    let (removed, freed) = store.garbage_collect(&referenced_hashes, progress_config.should_show_progress())?;
 
     let mut msg = format!(
         "\nRemoved {} snapshot(s)",
@@ -670,16 +2641,126 @@ fn cmd_remove(root: &std::path::Path, names: &[String], progress_config: Progres
     ));
     
     println!("{}", msg);
+    if kept > 0 {
+        println!("Kept {} unreferenced blob(s) for keep_unreferenced_for = \"{}\"", kept, keep_unreferenced_for.unwrap_or_default());
+    }
 
     for (name, _) in &snapshots_to_delete {
-        let entry = HistoryEntry::new("RM", Some(name), Vec::new());
+        let flags = if force { vec!["--force".to_string()] } else { Vec::new() };
+        let entry = HistoryEntry::new("RM", Some(name), flags);
+        log_entry(root, &entry);
+    }
+
+    Ok(())
+}
+
+/// Mark snapshots as protected from `rm` and `prune --expire`, or lift the protection
+fn cmd_protect(root: &std::path::Path, names: &[String], protect: bool) -> Result<()> {
+    // Load every snapshot first, so a misspelled name does not leave the list half applied
+    let manifests = names.iter().map(|name| Manifest::load(root, name)).collect::<Result<Vec<_>>>()?;
+
+    for mut manifest in manifests {
+        if manifest.protected == protect {
+            println!("Snapshot '{}' is already {}", manifest.name, if protect { "protected" } else { "unprotected" });
+            continue;
+        }
+
+        manifest.protected = protect;
+        manifest.save(root)?;
+        println!("{} snapshot '{}'", if protect { "Protected" } else { "Unprotected" }, manifest.name);
+
+        let entry = HistoryEntry::new(if protect { "PROTECT" } else { "UNPROTECT" }, Some(&manifest.name), Vec::new());
         log_entry(root, &entry);
     }
 
     Ok(())
 }
 
-/// Dump MySQL database to SQL file with auto-generated filename
+/// The client command that dumps a database as SQL to stdout, for the configured database type
+fn database_dump_command(db_name: &str, db_config: &DatabaseConfig) -> Result<std::process::Command> {
+    use std::process::Command;
+
+    match db_config.db_type.as_str() {
+        "mysql" => {
+            let mut cmd = Command::new("mysqldump");
+            cmd.arg(format!("--user={}", db_config.user))
+                .arg(format!("--host={}", db_config.host))
+                .arg(format!("--port={}", db_config.port))
+                .arg("--databases")
+                .arg(db_name)
+                .arg("--routines")
+                .arg("--triggers")
+                .arg("--events");
+
+            if !db_config.password.is_empty() {
+                cmd.arg(format!("--password={}", db_config.password));
+            }
+
+            if db_config.single_transaction {
+                cmd.arg("--single-transaction");
+            }
+            Ok(cmd)
+        },
+        "postgres" => {
+            // pg_dump always reads from a single consistent snapshot
+            let mut cmd = Command::new("pg_dump");
+            cmd.arg(format!("--username={}", db_config.user))
+                .arg(format!("--host={}", db_config.host))
+                .arg(format!("--port={}", db_config.port))
+                .arg(format!("--dbname={}", db_name))
+                .arg("--clean")
+                .arg("--if-exists");
+
+            if !db_config.password.is_empty() {
+                cmd.env("PGPASSWORD", &db_config.password);
+            }
+            Ok(cmd)
+        },
+        other => anyhow::bail!("Unsupported database type '{}' (expected \"mysql\" or \"postgres\")", other),
+    }
+}
+
+/// The client command that loads an SQL dump of `db_name` from stdin, for the configured
+/// database type. MySQL dumps select their database themselves, PostgreSQL ones do not.
+fn database_load_command(db_name: &str, db_config: &DatabaseConfig) -> Result<std::process::Command> {
+    use std::process::Command;
+
+    match db_config.db_type.as_str() {
+        "mysql" => {
+            let mut cmd = Command::new("mysql");
+            cmd.arg(format!("--user={}", db_config.user))
+                .arg(format!("--host={}", db_config.host))
+                .arg(format!("--port={}", db_config.port));
+
+            if !db_config.password.is_empty() {
+                cmd.arg(format!("--password={}", db_config.password));
+            }
+            Ok(cmd)
+        },
+        "postgres" => {
+            let mut cmd = Command::new("psql");
+            cmd.arg(format!("--username={}", db_config.user))
+                .arg(format!("--host={}", db_config.host))
+                .arg(format!("--port={}", db_config.port))
+                .arg(format!("--dbname={}", db_name))
+                .arg("--no-psqlrc")
+                .arg("--quiet")
+                .arg("--set=ON_ERROR_STOP=1");
+
+            if db_config.single_transaction {
+                cmd.arg("--single-transaction");
+            }
+
+            if !db_config.password.is_empty() {
+                cmd.env("PGPASSWORD", &db_config.password);
+            }
+            Ok(cmd)
+        },
+        other => anyhow::bail!("Unsupported database type '{}' (expected \"mysql\" or \"postgres\")", other),
+    }
+}
+
+/// Dump a MySQL or PostgreSQL database to SQL file with auto-generated filename
 fn dump_database(
     root: &std::path::Path,
     snapshot_name: &str,
@@ -687,12 +2768,10 @@ fn dump_database(
     db_config: &DatabaseConfig,
     verbose: bool,
 ) -> Result<String> {
-    use std::process::Command;
     use chrono::Utc;
 
-    if db_config.db_type != "mysql" {
-        anyhow::bail!("Only MySQL databases are currently supported");
-    }
+    let mut cmd = database_dump_command(db_name, db_config)?;
+    let program = cmd.get_program().to_string_lossy().to_string();
 
     println!("Dumping database '{}'", db_name);
 
@@ -705,35 +2784,17 @@ fn dump_database(
     
     let dump_path = db_dumps_dir.join(&dump_filename);
 
-    let mut cmd = Command::new("mysqldump");
-    cmd.arg(format!("--user={}", db_config.user))
-        .arg(format!("--host={}", db_config.host))
-        .arg(format!("--port={}", db_config.port))
-        .arg("--databases")
-        .arg(db_name)
-        .arg("--routines")
-        .arg("--triggers")
-        .arg("--events");
-
-    if !db_config.password.is_empty() {
-        cmd.arg(format!("--password={}", db_config.password));
-    }
-
-    if db_config.single_transaction {
-        cmd.arg("--single-transaction");
-    }
-
     cmd.stdout(std::fs::File::create(&dump_path)?);
 
     if verbose {
-        eprintln!("Running: mysqldump to {}", dump_path.display());
+        eprintln!("Running: {} to {}", program, dump_path.display());
     }
 
     let status = cmd.status()
-        .with_context(|| "Failed to execute mysqldump. Is MySQL client installed?")?;
+        .with_context(|| format!("Failed to execute {}. Is the {} client installed?", program, db_config.db_type))?;
 
     if !status.success() {
-        anyhow::bail!("mysqldump failed with exit code: {}", status);
+        anyhow::bail!("{} failed with exit code: {}", program, status);
     }
 
     println!("Database dumped to {}", dump_path.display());
@@ -741,7 +2802,13 @@ fn dump_database(
     Ok(dump_filename)
 }
 
-/// Load MySQL database from SQL file using manifest metadata
+/// The database a snapshot's dump is loaded into: the one it was dumped from, which
+/// `--include-db=<name>` may have chosen over the configured one
+fn database_to_load<'a>(manifest: &'a Manifest, db_config: &'a DatabaseConfig) -> &'a str {
+    manifest.db_name.as_deref().unwrap_or(&db_config.name)
+}
+
+/// Load a MySQL or PostgreSQL database from SQL file using manifest metadata
 /// Returns Ok(true) if database was loaded, Ok(false) if skipped/not available
 fn load_database(
     root: &std::path::Path,
@@ -749,12 +2816,11 @@ fn load_database(
     db_config: &DatabaseConfig,
     verbose: bool,
 ) -> Result<bool> {
-    use std::process::Command;
     use std::io::{self, Write};
 
-    if db_config.db_type != "mysql" {
-        anyhow::bail!("Only MySQL databases are currently supported");
-    }
+    let db_name = database_to_load(manifest, db_config);
+    let mut cmd = database_load_command(db_name, db_config)?;
+    let program = cmd.get_program().to_string_lossy().to_string();
 
     let dump_filename = match &manifest.db_dump_filename {
         Some(filename) => filename,
@@ -774,7 +2840,7 @@ fn load_database(
     }
 
     // Prompt user before loading database
-    print!("\n   Loading database from snapshot. This will overwrite the current database. Continue? [y/N] ");
+    print!("\n   Loading database '{}' from snapshot. This will overwrite its current contents. Continue? [y/N] ", db_name);
     io::stdout().flush()?;
 
     let mut input = String::new();
@@ -788,26 +2854,17 @@ fn load_database(
 
     println!("Loading database from {}", dump_filename);
 
-    let mut cmd = Command::new("mysql");
-    cmd.arg(format!("--user={}", db_config.user))
-        .arg(format!("--host={}", db_config.host))
-        .arg(format!("--port={}", db_config.port));
-
-    if !db_config.password.is_empty() {
-        cmd.arg(format!("--password={}", db_config.password));
-    }
-
     cmd.stdin(std::fs::File::open(&dump_path)?);
 
     if verbose {
-        eprintln!("Running: mysql < {}", dump_path.display());
+        eprintln!("Running: {} < {}", program, dump_path.display());
     }
 
     let status = cmd.status()
-        .with_context(|| "Failed to execute mysql. Is MySQL client installed?")?;
+        .with_context(|| format!("Failed to execute {}. Is the {} client installed?", program, db_config.db_type))?;
 
     if !status.success() {
-        anyhow::bail!("mysql load failed with exit code: {}", status);
+        anyhow::bail!("{} load failed with exit code: {}", program, status);
     }
 
     Ok(true)
@@ -839,7 +2896,20 @@ fn validate_snapshot_name(name: &str) -> Result<()> {
     Ok(())
 }
 
-/// Open the .kibo.toml configuration file in vim
+/// The editor command line to run: `$VISUAL`, then `$EDITOR`, then vim, split on whitespace
+/// so values with arguments like `code --wait` work
+fn editor_command(visual: Option<String>, editor: Option<String>) -> Vec<String> {
+    [visual, editor]
+        .into_iter()
+        .flatten()
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "vim".to_string())
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
+}
+
+/// Open the .kibo.toml configuration file in the user's editor
 fn cmd_config(root: &std::path::Path) -> Result<()> {
     let config_path = root.join(CONFIG_FILENAME);
     
@@ -849,22 +2919,83 @@ fn cmd_config(root: &std::path::Path) -> Result<()> {
         anyhow::bail!("Configuration file does not exist");
     }
 
-    println!("Opening {} with vim", CONFIG_FILENAME);
+    let command = editor_command(std::env::var("VISUAL").ok(), std::env::var("EDITOR").ok());
+    let (program, args) = command.split_first().expect("editor command is never empty");
 
-    let status = std::process::Command::new("vim")
+    println!("Opening {} with {}", CONFIG_FILENAME, program);
+
+    let status = std::process::Command::new(program)
+        .args(args)
         .arg(&config_path)
         .status()
-        .with_context(|| "Failed to launch vim")?;
+        .with_context(|| format!("Failed to launch editor '{}' (set $VISUAL or $EDITOR to choose another)", program))?;
 
     if !status.success() {
-        anyhow::bail!("vim exited with error");
+        anyhow::bail!("{} exited with error", program);
+    }
+
+    Ok(())
+}
+
+/// Check .kibo.toml strictly, including for unknown keys
+fn cmd_config_validate(root: &std::path::Path) -> Result<()> {
+    Config::load_strict(&root.join(CONFIG_FILENAME))?;
+    println!("{} is valid", CONFIG_FILENAME);
+    Ok(())
+}
+
+/// Rewrite .kibo.toml with all keys and their comments, keeping the previous file as .kibo.toml.bak
+fn cmd_config_migrate(root: &std::path::Path, dry_run: bool) -> Result<()> {
+    let config_path = root.join(CONFIG_FILENAME);
+    let content = std::fs::read_to_string(&config_path)
+        .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
+    let config = Config::load(&config_path)?;
+
+    for key in kibo::unknown_config_keys(&content)? {
+        eprintln!("Warning: unknown key '{}' is ignored by kibo and will be dropped", key);
+    }
+
+    let migrated = config.to_commented_toml()?;
+    if dry_run {
+        print!("{}", migrated);
+        return Ok(());
+    }
+    if migrated == content {
+        println!("{} is already up to date", CONFIG_FILENAME);
+        return Ok(());
+    }
+
+    let backup_path = root.join(format!("{}.bak", CONFIG_FILENAME));
+    std::fs::copy(&config_path, &backup_path)
+        .with_context(|| format!("Failed to back up config file to: {}", backup_path.display()))?;
+    config.save(&config_path)?;
+    println!("Migrated {} (previous version kept as {})", CONFIG_FILENAME, backup_path.display());
+
+    Ok(())
+}
+
+/// Number of snapshots and days listed by `history --stats`
+const HISTORY_STATS_TOP: usize = 5;
+
+/// Write the whole history log in a structured format, to a file or standard output
+fn cmd_history_export(root: &std::path::Path, format: ExportFormat, output: Option<&std::path::Path>) -> Result<()> {
+    let entries = read_history(root)?;
+    let exported = kibo::export_history(&entries, format)?;
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, exported)
+                .with_context(|| format!("Failed to write history export: {}", path.display()))?;
+            eprintln!("Exported {} history entr{} to {}", entries.len(), if entries.len() == 1 { "y" } else { "ies" }, path.display());
+        },
+        None => print!("{}", exported),
     }
 
     Ok(())
 }
 
 /// Display command history
-fn cmd_history(root: &std::path::Path, last: Option<usize>, snapshot_filter: Option<String>, json: bool) -> Result<()> {
+fn cmd_history(root: &std::path::Path, last: Option<usize>, snapshot_filter: Option<String>, stats: bool, json: bool) -> Result<()> {
     let mut entries = read_history(root)?;
 
     if entries.is_empty() {
@@ -884,6 +3015,10 @@ fn cmd_history(root: &std::path::Path, last: Option<usize>, snapshot_filter: Opt
         entries = take_last(entries, n);
     }
 
+    if stats {
+        return print_history_stats(&history_stats(&entries, HISTORY_STATS_TOP), json);
+    }
+
     if json {
         let json_output = serde_json::to_string_pretty(&entries)?;
         println!("{}", json_output);
@@ -901,10 +3036,273 @@ fn cmd_history(root: &std::path::Path, last: Option<usize>, snapshot_filter: Opt
     Ok(())
 }
 
+/// Print the summary shown by `history --stats`
+fn print_history_stats(stats: &HistoryStats, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(stats)?);
+        return Ok(());
+    }
+
+    let format_time = |time: Option<chrono::DateTime<chrono::Utc>>| {
+        time.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_else(|| "-".to_string())
+    };
+
+    println!("History Summary:\n");
+    println!("  Entries: {}", stats.total);
+    println!("  First:   {}", format_time(stats.first));
+    println!("  Last:    {}", format_time(stats.last));
+
+    let sections = [
+        ("Commands", &stats.commands),
+        ("Most saved snapshots", &stats.top_snapshots),
+        ("Busiest days (UTC)", &stats.busiest_days),
+    ];
+    for (title, tallies) in sections {
+        println!("\n{}:", title);
+        if tallies.is_empty() {
+            println!("  (none)");
+        }
+        for tally in tallies {
+            println!("  {:<24} {}", tally.name, tally.count);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the .kibo directory for inconsistencies and optionally repair them
+fn cmd_doctor(root: &std::path::Path, fix: bool) -> Result<()> {
+    println!("Checking {} for problems...", root.join(".kibo").display());
+
+    let issues = diagnose(root)?;
+
+    if issues.is_empty() {
+        println!("\nNo problems found.");
+        return Ok(());
+    }
+
+    println!("\nFound {} problem(s):", issues.len());
+    for issue in &issues {
+        println!("  - {}", issue.describe());
+    }
+
+    let fixable = issues.iter().filter(|i| i.is_fixable()).count();
+    if !fix {
+        if fixable > 0 {
+            println!("\nRun 'kibo doctor --fix' to repair {} of them.", fixable);
+        }
+        anyhow::bail!("{} problem(s) found", issues.len());
+    }
+
+    println!("\nRepairing...");
+    let mut remaining = 0;
+    for issue in &issues {
+        if !issue.is_fixable() {
+            remaining += 1;
+            continue;
+        }
+        match issue.fix(root) {
+            Ok(action) => println!("  {}", action),
+            Err(e) => {
+                eprintln!("  Failed: {:#}", e);
+                remaining += 1;
+            }
+        }
+    }
+
+    let entry = HistoryEntry::new("DOCTOR", None, vec!["--fix".to_string()]);
+    log_entry(root, &entry);
+
+    if remaining > 0 {
+        anyhow::bail!("{} problem(s) could not be repaired automatically", remaining);
+    }
+
+    println!("\nAll problems repaired.");
+    Ok(())
+}
+
+/// Re-run save commands recorded in the history log
+fn cmd_replay(
+    root: &std::path::Path,
+    last: Option<usize>,
+    since: Option<String>,
+    until: Option<String>,
+    yes: bool,
+    config: &Config,
+) -> Result<()> {
+    let since = since.as_deref().map(parse_time_bound).transpose()?;
+    let until = until.as_deref().map(parse_time_bound).transpose()?;
+
+    let mut entries = filter_by_command(read_history(root)?, "SAVE");
+
+    if since.is_some() || until.is_some() {
+        entries = filter_by_time_range(entries, since, until);
+    }
+    else if last.is_none() {
+        entries = take_last(entries, 1);
+    }
+
+    if let Some(n) = last {
+        entries = take_last(entries, n);
+    }
+
+    if entries.is_empty() {
+        println!("No logged save commands to replay.");
+        return Ok(());
+    }
+
+    let mut saves = Vec::new();
+    for entry in &entries {
+        let Some(ref name) = entry.snapshot else {
+            eprintln!("Warning: Skipping save logged at {} without a snapshot name", entry.timestamp);
+            continue;
+        };
+
+        let (args, unknown) = match SaveArgs::from_history(name, &entry.flags) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("Warning: Skipping save logged at {}: {}", entry.timestamp, e);
+                continue;
+            }
+        };
+
+        if !unknown.is_empty() {
+            eprintln!(
+                "Warning: Ignoring unrecognized flags for '{}': {}",
+                name,
+                unknown.join(" ")
+            );
+        }
+
+        saves.push(args);
+    }
+
+    if saves.is_empty() {
+        println!("No logged save commands to replay.");
+        return Ok(());
+    }
+
+    println!("Commands to replay:");
+    for args in &saves {
+        let flags = args.history_flags();
+        if flags.is_empty() {
+            println!("  kibo save {}", args.name);
+        }
+        else {
+            println!("  kibo save {} {}", args.name, flags.join(" "));
+        }
+    }
+
+    if !yes {
+        use std::io::{self, Write};
+        print!("\nRun {} save command(s)? [y/N] ", saves.len());
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        let input = input.trim().to_lowercase();
+
+        if input != "y" && input != "yes" {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    for mut args in saves {
+        println!();
+        // Replaying with --yes also skips the overwrite prompt for existing snapshots
+        args.yes |= yes;
+        let progress_config = ProgressConfig::from_flags(args.progress, args.no_progress, config.progress);
+        let effective_config = effective_save_config(config, &args)?;
+        cmd_save(root, &args, &effective_config, progress_config, config)?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_database_commands() {
+        let mysql = DatabaseConfig { password: "secret".to_string(), ..Default::default() };
+        let dump = database_dump_command("app", &mysql).unwrap();
+        assert_eq!(dump.get_program(), "mysqldump");
+        assert!(dump.get_args().any(|arg| arg == "--password=secret"));
+        assert!(dump.get_args().any(|arg| arg == "--single-transaction"));
+
+        let postgres = DatabaseConfig { db_type: "postgres".to_string(), port: 5432, ..mysql.clone() };
+        let dump = database_dump_command("app", &postgres).unwrap();
+        assert_eq!(dump.get_program(), "pg_dump");
+        assert!(dump.get_args().any(|arg| arg == "--dbname=app"));
+        assert!(!dump.get_args().any(|arg| arg.to_string_lossy().contains("secret")));
+        assert!(dump.get_envs().any(|(key, value)| key == "PGPASSWORD" && value == Some("secret".as_ref())));
+
+        let load = database_load_command("mydb", &postgres).unwrap();
+        assert_eq!(load.get_program(), "psql");
+        assert!(load.get_args().any(|arg| arg == "--dbname=mydb"));
+        assert!(load.get_args().any(|arg| arg == "--single-transaction"));
+
+        // A dump of another database than the configured one (`--include-db=<name>`) goes back there
+        let mut manifest = Manifest::new("snap".to_string());
+        assert_eq!(database_to_load(&manifest, &postgres), "mydb");
+        manifest.db_name = Some("otherdb".to_string());
+        let load = database_load_command(database_to_load(&manifest, &postgres), &postgres).unwrap();
+        assert!(load.get_args().any(|arg| arg == "--dbname=otherdb"));
+        assert!(!load.get_args().any(|arg| arg == "--dbname=mydb"));
+
+        let sqlite = DatabaseConfig { db_type: "sqlite".to_string(), ..Default::default() };
+        assert!(database_dump_command("app", &sqlite).is_err());
+        assert!(database_load_command("app", &sqlite).is_err());
+    }
+
+    #[test]
+    fn test_editor_command() {
+        let some = |value: &str| Some(value.to_string());
+        assert_eq!(editor_command(some("code --wait"), some("nano")), vec!["code", "--wait"]);
+        assert_eq!(editor_command(None, some("nano")), vec!["nano"]);
+        assert_eq!(editor_command(some(" "), some("nano")), vec!["nano"]);
+        assert_eq!(editor_command(None, None), vec!["vim"]);
+    }
+
+    #[test]
+    fn test_render_ignore_list() {
+        let list = render_ignore_list(&["*.log".to_string(), "say \"hi\"".to_string()]);
+        let config: Config = toml::from_str(&list).unwrap();
+        assert_eq!(config.ignore, vec!["*.log", "say \"hi\""]);
+        assert!(render_ignore_list(&[]).ends_with("ignore = [\n]\n"));
+    }
+
+    #[test]
+    fn test_auto_prune_order() {
+        let now = chrono::Utc::now();
+        let snapshot = |name: &str, age_days: i64, expired: bool| {
+            let mut manifest = Manifest::new(name.to_string());
+            manifest.created_at = now - chrono::Duration::days(age_days);
+            if expired {
+                manifest.expire_at = Some(now - chrono::Duration::days(age_days) + chrono::Duration::hours(1));
+            }
+            manifest
+        };
+        let mut protected = snapshot("protected", 30, true);
+        protected.protected = true;
+        let snapshots = vec![
+            snapshot("recent", 1, false),
+            snapshot("old", 20, false),
+            snapshot("expired-recently", 2, true),
+            snapshot("expired-long-ago", 10, true),
+            protected,
+            snapshot("just-saved", 40, false),
+        ];
+
+        let order: Vec<String> = auto_prune_order(snapshots, Some("just-saved"), now)
+            .into_iter()
+            .map(|snapshot| snapshot.name)
+            .collect();
+        assert_eq!(order, ["expired-long-ago", "expired-recently", "old", "recent"]);
+    }
+
     #[test]
     fn test_validate_snapshot_name_valid() {
         assert!(validate_snapshot_name("my-snapshot").is_ok());
@@ -974,6 +3372,25 @@ mod tests {
         assert!(validate_snapshot_name("snapshot_with_underscore").is_ok());
         assert!(validate_snapshot_name("snapshot.with.dots").is_ok());
     }
+
+    #[test]
+    fn test_count_all_blobs_includes_store_groups() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let source = root.join("source.txt");
+        std::fs::write(&source, b"content").unwrap();
+
+        let store = Store::new(root);
+        store.init().unwrap();
+        store.store_file(&source, "aa11").unwrap();
+        let group = Store::new(root).in_group(Some("ci"));
+        group.init().unwrap();
+        group.store_file(&source, "bb22").unwrap();
+        group.store_file(&source, "cc33").unwrap();
+
+        assert_eq!(store.blob_count().unwrap(), 1);
+        assert_eq!(count_all_blobs(root).unwrap(), 3);
+    }
 }
 
 
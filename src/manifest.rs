@@ -1,13 +1,14 @@
 use anyhow::{Context, Result, bail};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use glob;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use crate::config::get_manifests_dir;
+use crate::config::{CompressionEffort, MtimePrecision, SymlinkPolicy, SymlinkTargetMode, get_manifests_dir};
 use crate::fs_utils;
+use crate::store::STORE_FORMAT_VERSION;
 
 /// File entry in a manifest
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -37,8 +38,20 @@ pub struct FileEntry {
     /// Modification time nanoseconds component
     #[serde(default)]
     pub mtime_nanos: u32,
+
+    /// Whether the hash was derived from path, size and mtime instead of the file content
+    #[serde(default)]
+    pub assumed: bool,
+
+    /// Whether the file looked like text when it was saved (no null bytes, valid UTF-8).
+    /// False for symlinks, assumed-unchanged files and manifests from older versions.
+    #[serde(default)]
+    pub is_text: bool,
 }
 
+/// Permissions of the directories a sparse manifest leaves out
+pub const IMPLIED_DIRECTORY_MODE: u32 = 0o755;
+
 /// Directory entry in a manifest
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct DirectoryEntry {
@@ -74,11 +87,58 @@ pub struct Manifest {
     #[serde(default)]
     pub ignored_patterns: Vec<String>,
     
-    /// Mapping of relative paths to directory entries
+    /// How symlinks were captured when the snapshot was created
+    #[serde(default)]
+    pub symlink_policy: SymlinkPolicy,
+
+    /// How symlink targets were normalized when the snapshot was created
+    #[serde(default, skip_serializing_if = "SymlinkTargetMode::is_preserve")]
+    pub symlink_target_mode: SymlinkTargetMode,
+    
+    /// Precision of the recorded mtimes, which load uses when comparing workspace files
+    #[serde(default, skip_serializing_if = "MtimePrecision::is_full")]
+    pub mtime_precision: MtimePrecision,
+    
+    /// Whether mount points below the workspace root were skipped when the snapshot was created
+    #[serde(default)]
+    pub one_file_system: bool,
+    
+    /// Whether version control metadata directories were skipped
     #[serde(default)]
+    pub exclude_vcs: bool,
+
+    /// Whether hidden files and directories inside tracked directories were skipped
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub exclude_hidden: bool,
+
+    /// Whether directories with default permissions that hold files were left out, so load
+    /// recreates them from the file paths with `IMPLIED_DIRECTORY_MODE`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sparse_directories: bool,
+    
+    /// Whether the workspace's .gitignore patterns were added to the ignore list
+    #[serde(default)]
+    pub gitignore_applied: bool,
+    
+    /// Files last modified before this time were left out by `--include-newer-than`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_after: Option<DateTime<Utc>>,
+    
+    /// Whether zero-byte files were left out by `--exclude-empty-files`
+    #[serde(default)]
+    pub exclude_empty_files: bool,
+    
+    /// Workspace root the snapshot was saved from, recorded when it tracks directories
+    /// outside the root. Paths of files there start with `..` relative to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outside_base: Option<String>,
+    
+    /// Mapping of relative paths to directory entries
+    #[serde(default, serialize_with = "serialize_sorted")]
     pub directories: HashMap<String, DirectoryEntry>,
     
     /// Mapping of relative paths to file entries
+    #[serde(serialize_with = "serialize_sorted")]
     pub files: HashMap<String, FileEntry>,
     
     /// Total size of all files in bytes
@@ -87,6 +147,22 @@ pub struct Manifest {
     /// Number of files in the snapshot
     pub file_count: usize,
     
+    /// Free-form provenance recorded with `save --metadata key=value` (build number, git SHA, ...)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty", serialize_with = "serialize_sorted")]
+    pub metadata: HashMap<String, String>,
+    
+    /// Git commit checked out when the snapshot was saved with `include_git_sha`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vcs_revision: Option<String>,
+    
+    /// Whether tracked files had uncommitted changes at `vcs_revision`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub vcs_dirty: bool,
+    
+    /// Environment variables captured with `capture_env`; variables that were not set are absent
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub environment: BTreeMap<String, String>,
+    
     /// Optional toolchain/environment info
     #[serde(skip_serializing_if = "Option::is_none")]
     pub toolchain_info: Option<String>,
@@ -94,9 +170,63 @@ pub struct Manifest {
     /// Version of kibo that created this manifest
     pub kibo_version: String,
     
+    /// Blob store format the snapshot's blobs were written in (manifests without one predate versioning)
+    #[serde(default = "initial_store_format_version")]
+    pub store_format_version: u32,
+    
     /// Optional database dump filename for this snapshot
     #[serde(skip_serializing_if = "Option::is_none")]
     pub db_dump_filename: Option<String>,
+
+    /// Database the dump was taken from, which loading it restores (dumps of manifests
+    /// without one are loaded into the configured database)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub db_name: Option<String>,
+    
+    /// When this snapshot expires and becomes eligible for `prune --expire`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expire_at: Option<DateTime<Utc>>,
+    
+    /// Saved with `--hash-only`: the files were hashed but their blobs were not stored
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hash_only: bool,
+    
+    /// Saved with `--link-identical`: `load` hard-links files with the same content instead of copying each
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub link_identical: bool,
+
+    /// Compression level new blobs were stored at (0 = uncompressed), raised by `store recompress`.
+    /// None for hash-only snapshots and snapshots saved before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<u32>,
+
+    /// Compression new blobs were stored with (`none`, `zstd` or `zstd-dict`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_algorithm: Option<String>,
+
+    /// Compression effort preset new blobs were stored with, if one picked the level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_effort: Option<CompressionEffort>,
+    
+    /// Set with `kibo protect`: `rm` and `prune --expire` leave the snapshot alone unless forced
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub protected: bool,
+    
+    /// Hash over the sorted (path, hash) pairs of all files, recorded at save time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_root: Option<String>,
+    
+    /// Store group holding this snapshot's blobs (None = the default store)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store_group: Option<String>,
+
+    /// Content root of the snapshot this one overwrote
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_content_root: Option<String>,
+
+    /// Creation time of the snapshot this one overwrote
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_created_at: Option<DateTime<Utc>>,
 }
 
 impl Manifest {
@@ -108,13 +238,41 @@ impl Manifest {
             tracked_directories: Vec::new(),
             tracked_files: Vec::new(),
             ignored_patterns: Vec::new(),
+            symlink_policy: SymlinkPolicy::Store,
+            symlink_target_mode: SymlinkTargetMode::Preserve,
+            mtime_precision: MtimePrecision::Full,
+            one_file_system: false,
+            exclude_vcs: false,
+            exclude_hidden: false,
+            sparse_directories: false,
+            gitignore_applied: false,
+            modified_after: None,
+            exclude_empty_files: false,
+            outside_base: None,
             directories: HashMap::new(),
             files: HashMap::new(),
             total_size: 0,
             file_count: 0,
+            metadata: HashMap::new(),
+            vcs_revision: None,
+            vcs_dirty: false,
+            environment: BTreeMap::new(),
             toolchain_info: None,
             kibo_version: env!("CARGO_PKG_VERSION").to_string(),
+            store_format_version: STORE_FORMAT_VERSION,
             db_dump_filename: None,
+            db_name: None,
+            expire_at: None,
+            hash_only: false,
+            link_identical: false,
+            compression_level: None,
+            compression_algorithm: None,
+            compression_effort: None,
+            protected: false,
+            content_root: None,
+            store_group: None,
+            previous_content_root: None,
+            previous_created_at: None,
         }
     }
     
@@ -154,7 +312,12 @@ impl Manifest {
             bail!("Snapshot '{}' not found", name);
         }
 
-        let content = fs::read_to_string(&manifest_path)
+        Self::load_from_path(&manifest_path)
+    }
+
+    /// Load a manifest from an arbitrary JSON file, e.g. one imported from elsewhere
+    pub fn load_from_path(manifest_path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(manifest_path)
             .with_context(|| format!("Failed to read manifest: {}", manifest_path.display()))?;
 
         let manifest: Manifest = serde_json::from_str(&content)
@@ -169,11 +332,15 @@ impl Manifest {
         fs::create_dir_all(&manifests_dir)
             .with_context(|| format!("Failed to create manifests directory: {}", manifests_dir.display()))?;
 
-        let manifest_path = Self::get_path(root, &self.name);
+        self.save_to_path(&Self::get_path(root, &self.name))
+    }
+
+    /// Write the manifest to an arbitrary JSON file, e.g. a copy kept with build logs
+    pub fn save_to_path(&self, manifest_path: &Path) -> Result<()> {
         let content = serde_json::to_string_pretty(self)
             .context("Failed to serialize manifest")?;
 
-        fs_utils::atomic_write(&manifest_path, content.as_bytes())
+        fs_utils::atomic_write(manifest_path, content.as_bytes())
             .with_context(|| format!("Failed to write manifest: {}", manifest_path.display()))?;
 
         Ok(())
@@ -200,6 +367,112 @@ impl Manifest {
     pub fn human_size(&self) -> String {
         format_size(self.total_size)
     }
+
+    /// Number of files whose hash was assumed from metadata rather than content
+    pub fn assumed_count(&self) -> usize {
+        self.files.values().filter(|e| e.assumed).count()
+    }
+
+    /// Hash of hashes over the files' (path, hash) pairs in path order. Each pair is hashed
+    /// on its own and the root is the hash of those leaf hashes.
+    pub fn compute_content_root(&self) -> String {
+        let mut paths: Vec<&String> = self.files.keys().collect();
+        paths.sort();
+
+        let mut root = blake3::Hasher::new();
+        for path in paths {
+            let mut leaf = blake3::Hasher::new();
+            leaf.update(path.as_bytes());
+            leaf.update(b"\0");
+            leaf.update(self.files[path].hash.as_bytes());
+            root.update(leaf.finalize().as_bytes());
+        }
+        root.finalize().to_hex().to_string()
+    }
+
+    /// Record the content root of the current file list
+    pub fn set_content_root(&mut self) {
+        self.content_root = Some(self.compute_content_root());
+    }
+
+    /// Whether the file list still matches the recorded content root, or None if none was recorded
+    pub fn content_root_matches(&self) -> Option<bool> {
+        self.content_root.as_ref().map(|root| *root == self.compute_content_root())
+    }
+
+    /// Record the snapshot this one overwrites. Manifests saved before content roots were
+    /// recorded get theirs computed from the file list.
+    pub fn record_replaced(&mut self, previous: &Manifest) {
+        self.previous_content_root = Some(
+            previous.content_root.clone().unwrap_or_else(|| previous.compute_content_root())
+        );
+        self.previous_created_at = Some(previous.created_at);
+    }
+
+    /// Clear the metadata that differs between two saves of the same content, for
+    /// `save --deterministic`: the creation time becomes `created_at`, all file and
+    /// directory mtimes become 0 and the toolchain info and overwritten snapshot are dropped.
+    /// Values captured with `capture_env` are kept as pinned inputs of the build, since they
+    /// were asked for explicitly; saves only match if those values do.
+    pub fn normalize_volatile(&mut self, created_at: DateTime<Utc>) {
+        self.created_at = created_at;
+        self.toolchain_info = None;
+        self.previous_content_root = None;
+        self.previous_created_at = None;
+        for entry in self.files.values_mut() {
+            entry.mtime_secs = 0;
+            entry.mtime_nanos = 0;
+        }
+        for entry in self.directories.values_mut() {
+            entry.mtime_secs = 0;
+            entry.mtime_nanos = 0;
+        }
+    }
+
+    /// Set the expiry to a retention period after the snapshot's creation time
+    pub fn set_retention(&mut self, retention: Duration) {
+        self.expire_at = Some(self.created_at + retention);
+    }
+
+    /// Time left until the snapshot expires, negative once it has expired
+    pub fn expires_in(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.expire_at.map(|expire_at| expire_at - now)
+    }
+
+    /// Check if the snapshot's retention period has passed
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expire_at.is_some_and(|expire_at| expire_at <= now)
+    }
+
+    /// Build a summary of this snapshot without the file listing
+    pub fn summary(&self) -> SnapshotSummary {
+        SnapshotSummary {
+            name: self.name.clone(),
+            created_at: self.created_at,
+            total_size: self.total_size,
+            human_size: self.human_size(),
+            file_count: self.file_count,
+            db_dump_filename: self.db_dump_filename.clone(),
+            expire_at: self.expire_at,
+            protected: self.protected,
+            store_group: self.store_group.clone(),
+            metadata: self.metadata.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            vcs_revision: self.vcs_revision.clone(),
+            vcs_dirty: self.vcs_dirty,
+            environment: self.environment.clone(),
+        }
+    }
+
+    /// Check if every `(key, value)` filter matches an entry of the snapshot's metadata.
+    /// Unless set as metadata, `vcs_revision` matches the recorded git commit or a prefix of it.
+    pub fn matches_metadata(&self, filters: &[(String, String)]) -> bool {
+        filters.iter().all(|(key, value)| match self.metadata.get(key) {
+            None if key == "vcs_revision" => {
+                !value.is_empty() && self.vcs_revision.as_deref().is_some_and(|revision| revision.starts_with(value.as_str()))
+            }
+            recorded => recorded == Some(value),
+        })
+    }
     
     /// Check if a path should be ignored based on manifest's ignore patterns
     pub fn should_ignore(&self, relative_path: &Path) -> bool {
@@ -232,6 +505,33 @@ impl Manifest {
     }
 }
 
+/// Summary of a snapshot for machine-readable listings
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotSummary {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// Total size of all files in bytes
+    pub total_size: u64,
+    pub human_size: String,
+    pub file_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub db_dump_filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub protected: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store_group: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vcs_revision: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub vcs_dirty: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub environment: BTreeMap<String, String>,
+}
+
 /// List all available snapshots
 pub fn list_snapshots(root: &Path) -> Result<Vec<Manifest>> {
     let manifests_dir = get_manifests_dir(root);
@@ -265,6 +565,74 @@ pub fn list_snapshots(root: &Path) -> Result<Vec<Manifest>> {
     Ok(snapshots)
 }
 
+/// Store format of manifests saved before the format was recorded
+fn initial_store_format_version() -> u32 {
+    1
+}
+
+/// Write a path map in key order, so that manifests of the same content serialize identically
+fn serialize_sorted<S, V>(map: &HashMap<String, V>, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    V: Serialize,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+/// Parse a `key=value` metadata pair, as given to `save --metadata` and `list --where`
+pub fn parse_metadata(value: &str) -> Result<(String, String)> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+        _ => bail!("Invalid metadata '{}': expected KEY=VALUE (e.g. git_sha=1a2b3c)", value),
+    }
+}
+
+/// Parse a retention period such as "90m", "12h", "30d" or "2w"
+pub fn parse_retention(value: &str) -> Result<Duration> {
+    let invalid = || format!("Invalid duration '{}': expected a number followed by m, h, d or w (e.g. 30d)", value);
+
+    let unit_start = value.find(|c: char| !c.is_ascii_digit()).with_context(invalid)?;
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: i64 = amount.parse().with_context(invalid)?;
+
+    let retention = match unit {
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => None,
+    };
+
+    match retention {
+        Some(retention) if amount > 0 => Ok(retention),
+        _ => bail!(invalid()),
+    }
+}
+
+/// Format the time left until expiry, e.g. "29d 23h", "5h 10m" or "expired"
+pub fn format_lifetime(remaining: Duration) -> String {
+    if remaining <= Duration::zero() {
+        return "expired".to_string();
+    }
+
+    let days = remaining.num_days();
+    let hours = remaining.num_hours() % 24;
+    let minutes = remaining.num_minutes() % 60;
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    }
+    else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    }
+    else if minutes > 0 {
+        format!("{}m", minutes)
+    }
+    else {
+        "<1m".to_string()
+    }
+}
+
 /// Format a byte size into human-readable format
 pub fn format_size(bytes: u64) -> String {
     const KB: u64 = 1024;
@@ -304,7 +672,14 @@ mod tests {
         assert_eq!(manifest.total_size, 0);
         assert_eq!(manifest.file_count, 0);
         assert_eq!(manifest.kibo_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(manifest.store_format_version, STORE_FORMAT_VERSION);
         assert!(manifest.db_dump_filename.is_none());
+
+        // Manifests saved before the store format was recorded read as version 1
+        let mut value = serde_json::to_value(&manifest).unwrap();
+        value.as_object_mut().unwrap().remove("store_format_version");
+        let old: Manifest = serde_json::from_value(value).unwrap();
+        assert_eq!(old.store_format_version, 1);
     }
 
     #[test]
@@ -392,6 +767,8 @@ mod tests {
             symlink_target: None,
             mtime_secs: 1234567890,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         
         manifest.add_file("test.txt".to_string(), file_entry.clone());
@@ -416,6 +793,8 @@ mod tests {
                 symlink_target: None,
                 mtime_secs: 1234567890,
                 mtime_nanos: 0,
+                assumed: false,
+                is_text: false,
             };
             
             manifest.add_file(format!("file{}.txt", i), file_entry);
@@ -442,6 +821,8 @@ mod tests {
                 symlink_target: None,
                 mtime_secs: 1234567890,
                 mtime_nanos: 0,
+                assumed: false,
+                is_text: false,
             },
         );
         
@@ -516,18 +897,62 @@ mod tests {
             tracked_directories: vec![],
             tracked_files: vec![],
             ignored_patterns: vec![],
+            symlink_policy: SymlinkPolicy::Store,
+            symlink_target_mode: SymlinkTargetMode::Preserve,
+            mtime_precision: MtimePrecision::Full,
+            one_file_system: false,
+            exclude_vcs: false,
+            exclude_hidden: false,
+            sparse_directories: false,
+            gitignore_applied: false,
+            modified_after: None,
+            exclude_empty_files: false,
+            outside_base: None,
             directories: HashMap::new(),
             files: HashMap::new(),
             total_size: 1024 * 1024, // 1 MB
             file_count: 10,
+            metadata: HashMap::new(),
+            vcs_revision: None,
+            vcs_dirty: false,
+            environment: BTreeMap::new(),
             toolchain_info: None,
             kibo_version: "1.0.0".to_string(),
+            store_format_version: 1,
             db_dump_filename: None,
+            db_name: None,
+            expire_at: None,
+            hash_only: false,
+            link_identical: false,
+            compression_level: None,
+            compression_algorithm: None,
+            compression_effort: None,
+            protected: false,
+            content_root: None,
+            store_group: None,
+            previous_content_root: None,
+            previous_created_at: None,
         };
         
         assert_eq!(manifest.human_size(), "1.00 MB");
     }
 
+    #[test]
+    fn test_manifest_summary_json() {
+        let mut manifest = Manifest::new("test".to_string());
+        manifest.total_size = 1024 * 1024;
+        manifest.file_count = 10;
+
+        let json: serde_json::Value = serde_json::to_value(manifest.summary()).unwrap();
+
+        assert_eq!(json["name"], "test");
+        assert_eq!(json["total_size"], 1024 * 1024);
+        assert_eq!(json["human_size"], "1.00 MB");
+        assert_eq!(json["file_count"], 10);
+        assert!(DateTime::parse_from_rfc3339(json["created_at"].as_str().unwrap()).is_ok());
+        assert!(json.get("db_dump_filename").is_none());
+    }
+
     #[test]
     fn test_list_snapshots_empty() {
         let temp_dir = TempDir::new().unwrap();
@@ -619,6 +1044,8 @@ mod tests {
             symlink_target: Some("/path/to/target".to_string()),
             mtime_secs: 1234567890,
             mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
         };
         
         assert!(entry.is_symlink);
@@ -650,4 +1077,219 @@ mod tests {
             Some("snapshot-mydb-20260101.sql".to_string())
         );
     }
+
+    #[test]
+    fn test_parse_retention() {
+        assert_eq!(parse_retention("90m").unwrap(), Duration::minutes(90));
+        assert_eq!(parse_retention("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_retention("30d").unwrap(), Duration::days(30));
+        assert_eq!(parse_retention("2w").unwrap(), Duration::weeks(2));
+
+        assert!(parse_retention("").is_err());
+        assert!(parse_retention("30").is_err());
+        assert!(parse_retention("d").is_err());
+        assert!(parse_retention("0d").is_err());
+        assert!(parse_retention("30y").is_err());
+        assert!(parse_retention("-1d").is_err());
+    }
+
+    #[test]
+    fn test_format_lifetime() {
+        assert_eq!(format_lifetime(Duration::days(30) - Duration::hours(1)), "29d 23h");
+        assert_eq!(format_lifetime(Duration::minutes(310)), "5h 10m");
+        assert_eq!(format_lifetime(Duration::minutes(45)), "45m");
+        assert_eq!(format_lifetime(Duration::seconds(30)), "<1m");
+        assert_eq!(format_lifetime(Duration::zero()), "expired");
+        assert_eq!(format_lifetime(Duration::days(-1)), "expired");
+    }
+
+    #[test]
+    fn test_manifest_content_root() {
+        let entry = |hash: &str| FileEntry {
+            hash: hash.to_string(),
+            size: 1,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
+        };
+
+        let mut manifest = Manifest::new("test".to_string());
+        assert_eq!(manifest.content_root_matches(), None);
+
+        manifest.add_file("a.o".to_string(), entry("h1"));
+        manifest.add_file("b.o".to_string(), entry("h2"));
+        manifest.set_content_root();
+        assert_eq!(manifest.content_root_matches(), Some(true));
+
+        // Metadata outside the (path, hash) pairs is not covered
+        manifest.files.get_mut("a.o").unwrap().size = 99;
+        assert_eq!(manifest.content_root_matches(), Some(true));
+
+        manifest.files.get_mut("a.o").unwrap().hash = "tampered".to_string();
+        assert_eq!(manifest.content_root_matches(), Some(false));
+
+        manifest.files.get_mut("a.o").unwrap().hash = "h1".to_string();
+        let renamed = manifest.files.remove("b.o").unwrap();
+        manifest.files.insert("c.o".to_string(), renamed);
+        assert_eq!(manifest.content_root_matches(), Some(false));
+    }
+
+    #[test]
+    fn test_manifest_normalize_volatile() {
+        let entry = |mtime_secs| FileEntry {
+            hash: "abc123".to_string(),
+            size: 3,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs,
+            mtime_nanos: 42,
+            assumed: false,
+            is_text: false,
+        };
+        let epoch = DateTime::<Utc>::UNIX_EPOCH;
+
+        let mut m1 = Manifest::new("build".to_string());
+        m1.add_file("b.o".to_string(), entry(100));
+        m1.add_file("a.o".to_string(), entry(200));
+        m1.normalize_volatile(epoch);
+
+        let mut m2 = Manifest::new("build".to_string());
+        m2.add_file("a.o".to_string(), entry(300));
+        m2.add_file("b.o".to_string(), entry(400));
+        m2.toolchain_info = Some("rustc 1.85".to_string());
+        m2.record_replaced(&m1);
+        m2.normalize_volatile(epoch);
+
+        assert_eq!(m1.created_at, epoch);
+        assert_eq!(m1.files["a.o"].mtime_secs, 0);
+        assert_eq!(m1.files["a.o"].mtime_nanos, 0);
+        assert_eq!(serde_json::to_string_pretty(&m1).unwrap(), serde_json::to_string_pretty(&m2).unwrap());
+
+        // Captured environment values are pinned inputs, not volatile metadata
+        m1.environment.insert("CC".to_string(), "clang".to_string());
+        m1.normalize_volatile(epoch);
+        assert_eq!(m1.environment["CC"], "clang");
+    }
+
+    #[test]
+    fn test_manifest_record_replaced() {
+        let mut old = Manifest::new("build".to_string());
+        old.add_file("a.o".to_string(), FileEntry {
+            hash: "abc123".to_string(),
+            size: 3,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
+        });
+
+        // Older manifests without a recorded root get one computed
+        let mut new = Manifest::new("build".to_string());
+        new.record_replaced(&old);
+        assert_eq!(new.previous_content_root, Some(old.compute_content_root()));
+        assert_eq!(new.previous_created_at, Some(old.created_at));
+
+        old.content_root = Some("recorded".to_string());
+        new.record_replaced(&old);
+        assert_eq!(new.previous_content_root.as_deref(), Some("recorded"));
+    }
+
+    #[test]
+    fn test_manifest_metadata() {
+        assert_eq!(parse_metadata("branch=main").unwrap(), ("branch".to_string(), "main".to_string()));
+        assert_eq!(parse_metadata("url=a=b").unwrap(), ("url".to_string(), "a=b".to_string()));
+        assert_eq!(parse_metadata("empty=").unwrap(), ("empty".to_string(), String::new()));
+        assert!(parse_metadata("novalue").is_err());
+        assert!(parse_metadata("=value").is_err());
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut manifest = Manifest::new("ci".to_string());
+        manifest.metadata.insert("build".to_string(), "42".to_string());
+        manifest.metadata.insert("branch".to_string(), "main".to_string());
+        manifest.save(temp_dir.path()).unwrap();
+
+        let loaded = Manifest::load(temp_dir.path(), "ci").unwrap();
+        assert_eq!(loaded.metadata, manifest.metadata);
+        assert!(loaded.matches_metadata(&[]));
+        assert!(loaded.matches_metadata(&[("build".to_string(), "42".to_string())]));
+        assert!(!loaded.matches_metadata(&[
+            ("build".to_string(), "42".to_string()),
+            ("branch".to_string(), "dev".to_string()),
+        ]));
+        assert_eq!(serde_json::to_value(loaded.summary()).unwrap()["metadata"]["branch"], "main");
+    }
+
+    #[test]
+    fn test_manifest_matches_vcs_revision() {
+        let mut manifest = Manifest::new("ci".to_string());
+        let filter = |value: &str| [("vcs_revision".to_string(), value.to_string())];
+        assert!(!manifest.matches_metadata(&filter("1a2b3c")));
+
+        manifest.vcs_revision = Some("1a2b3c4d5e".to_string());
+        assert!(manifest.matches_metadata(&filter("1a2b3c4d5e")));
+        assert!(manifest.matches_metadata(&filter("1a2b3c")));
+        assert!(!manifest.matches_metadata(&filter("2b3c")));
+        assert!(!manifest.matches_metadata(&filter("")));
+        assert_eq!(serde_json::to_value(manifest.summary()).unwrap()["vcs_revision"], "1a2b3c4d5e");
+
+        // Metadata recorded under the same key takes precedence
+        manifest.metadata.insert("vcs_revision".to_string(), "manual".to_string());
+        assert!(manifest.matches_metadata(&filter("manual")));
+        assert!(!manifest.matches_metadata(&filter("1a2b3c")));
+    }
+
+    #[test]
+    fn test_manifest_retention() {
+        let mut manifest = Manifest::new("test".to_string());
+        assert!(!manifest.is_expired(manifest.created_at + Duration::weeks(520)));
+
+        manifest.set_retention(Duration::days(30));
+        assert_eq!(manifest.expire_at, Some(manifest.created_at + Duration::days(30)));
+        assert_eq!(manifest.expires_in(manifest.created_at), Some(Duration::days(30)));
+        assert!(!manifest.is_expired(manifest.created_at + Duration::days(29)));
+        assert!(manifest.is_expired(manifest.created_at + Duration::days(30)));
+    }
+
+    #[test]
+    fn test_manifest_save_to_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manifest = Manifest::new("ci".to_string());
+        manifest.metadata.insert("build".to_string(), "42".to_string());
+        manifest.save(temp_dir.path()).unwrap();
+
+        let copy = temp_dir.path().join("logs/manifest.json");
+        fs::create_dir_all(copy.parent().unwrap()).unwrap();
+        manifest.save_to_path(&copy).unwrap();
+        assert_eq!(
+            fs::read(&copy).unwrap(),
+            fs::read(Manifest::get_path(temp_dir.path(), "ci")).unwrap()
+        );
+        assert_eq!(Manifest::load_from_path(&copy).unwrap().metadata, manifest.metadata);
+        assert!(Manifest::exists(temp_dir.path(), "ci"));
+    }
+
+    #[test]
+    fn test_manifest_protected() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut manifest = Manifest::new("pinned".to_string());
+        manifest.save(temp_dir.path()).unwrap();
+        let json = fs::read_to_string(Manifest::get_path(temp_dir.path(), "pinned")).unwrap();
+        assert!(!json.contains("protected"));
+
+        manifest.protected = true;
+        manifest.save(temp_dir.path()).unwrap();
+        assert!(Manifest::load(temp_dir.path(), "pinned").unwrap().protected);
+        assert_eq!(serde_json::to_value(manifest.summary()).unwrap()["protected"], true);
+    }
 }
@@ -0,0 +1,408 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::fs_utils;
+
+pub const PACKS_DIR: &str = "packs";
+const PACK_INDEX_FILE: &str = "index.json";
+const PACK_EXTENSION: &str = "kbp";
+/// Pack files are rolled over once they grow past this size (64MB)
+const PACK_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Location of a packed blob
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackEntry {
+    pub pack: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// Index mapping blob hashes to their location inside pack files
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PackIndex {
+    entries: HashMap<String, PackEntry>,
+}
+
+/// Pack files holding small blobs appended back to back, plus their index
+pub struct PackStore {
+    packs_dir: PathBuf,
+    index: PackIndex,
+    dirty: bool,
+    /// Pack currently being appended to: name, open handle and current length
+    current: Option<(String, File, u64)>,
+}
+
+impl PackStore {
+    /// Load the pack index from the store directory (empty if no packs exist yet)
+    pub fn load(store_dir: &Path) -> Result<Self> {
+        let packs_dir = store_dir.join(PACKS_DIR);
+        let index_path = packs_dir.join(PACK_INDEX_FILE);
+
+        let index = if index_path.exists() {
+            let content = fs::read_to_string(&index_path)
+                .with_context(|| format!("Failed to read pack index: {}", index_path.display()))?;
+            serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse pack index: {}", index_path.display()))?
+        }
+        else {
+            PackIndex::default()
+        };
+
+        Ok(Self {
+            packs_dir,
+            index,
+            dirty: false,
+            current: None,
+        })
+    }
+
+    /// Check if a blob is stored in a pack
+    pub fn contains(&self, hash: &str) -> bool {
+        self.index.entries.contains_key(hash)
+    }
+
+    /// Look up where a packed blob is stored
+    pub fn get(&self, hash: &str) -> Option<PackEntry> {
+        self.index.entries.get(hash).cloned()
+    }
+
+    /// Number of blobs stored in packs
+    pub fn len(&self) -> usize {
+        self.index.entries.len()
+    }
+
+    /// Hashes of all packed blobs
+    pub fn hashes(&self) -> impl Iterator<Item = &String> + '_ {
+        self.index.entries.keys()
+    }
+
+    /// Lengths of all packed blobs as stored in the packs
+    pub fn lengths(&self) -> impl Iterator<Item = u64> + '_ {
+        self.index.entries.values().map(|entry| entry.length)
+    }
+
+    /// Append a blob to the current pack, starting a new pack when it is full
+    pub fn append(&mut self, hash: &str, data: &[u8]) -> Result<()> {
+        let needs_new_pack = match self.current {
+            Some((_, _, len)) => len >= PACK_MAX_SIZE,
+            None => true,
+        };
+
+        if needs_new_pack {
+            self.current = Some(self.open_pack_for_append()?);
+        }
+
+        let (name, file, len) = self.current.as_mut().expect("current pack was just opened");
+        file.write_all(data)
+            .with_context(|| format!("Failed to append blob to pack: {}", name))?;
+
+        self.index.entries.insert(hash.to_string(), PackEntry {
+            pack: name.clone(),
+            offset: *len,
+            length: data.len() as u64,
+        });
+        *len += data.len() as u64;
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    /// Write the index to disk if it has changed
+    pub fn save(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if let Some((_, ref file, _)) = self.current {
+            file.sync_all().context("Failed to sync pack file")?;
+        }
+
+        fs_utils::ensure_dir(&self.packs_dir)?;
+        let index_path = self.packs_dir.join(PACK_INDEX_FILE);
+        let content = serde_json::to_string(&self.index)
+            .context("Failed to serialize pack index")?;
+
+        fs_utils::atomic_write(&index_path, content.as_bytes())
+            .with_context(|| format!("Failed to write pack index: {}", index_path.display()))?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Rewrite packs keeping only referenced blobs, and remove pack files the index doesn't use
+    /// Returns the number of packed blobs dropped and bytes freed
+    pub fn repack(&mut self, referenced_hashes: &HashSet<String>) -> Result<(usize, u64)> {
+        let orphan_bytes = self.remove_orphan_packs()?;
+        let dead: Vec<String> = self.index.entries
+            .keys()
+            .filter(|hash| !referenced_hashes.contains(*hash))
+            .cloned()
+            .collect();
+
+        if dead.is_empty() {
+            return Ok((0, orphan_bytes));
+        }
+
+        let old_packs = self.pack_names()?;
+        let old_index = std::mem::take(&mut self.index.entries);
+        let freed_bytes: u64 = orphan_bytes + dead.iter().map(|hash| old_index[hash].length).sum::<u64>();
+
+        // Start from a fresh pack so live blobs never share a file with the old ones
+        self.current = None;
+        let mut live: Vec<(&String, &PackEntry)> = old_index
+            .iter()
+            .filter(|(hash, _)| referenced_hashes.contains(*hash))
+            .collect();
+        live.sort_by(|a, b| (&a.1.pack, a.1.offset).cmp(&(&b.1.pack, b.1.offset)));
+
+        for (hash, entry) in live {
+            let data = read_entry(&self.packs_dir, entry)?;
+            self.append(hash, &data)?;
+        }
+
+        self.dirty = true;
+        self.save()?;
+        self.current = None;
+
+        for name in old_packs {
+            fs::remove_file(self.packs_dir.join(&name))
+                .with_context(|| format!("Failed to remove old pack: {}", name))?;
+        }
+
+        Ok((dead.len(), freed_bytes))
+    }
+
+    /// Remove pack files no index entry points into, such as those left by an interrupted save
+    /// Returns the bytes freed
+    fn remove_orphan_packs(&self) -> Result<u64> {
+        let in_use: HashSet<&str> = self.index.entries.values().map(|e| e.pack.as_str()).collect();
+        let current = self.current.as_ref().map(|(name, _, _)| name.as_str());
+
+        let mut freed_bytes = 0;
+        for name in self.pack_names()? {
+            if in_use.contains(name.as_str()) || current == Some(name.as_str()) {
+                continue;
+            }
+            let path = self.packs_dir.join(&name);
+            freed_bytes += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove orphaned pack: {}", name))?;
+        }
+
+        Ok(freed_bytes)
+    }
+
+    /// List pack file names in sequence order
+    fn pack_names(&self) -> Result<Vec<String>> {
+        if !self.packs_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.packs_dir)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if pack_sequence(&name).is_some() {
+                names.push(name);
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// Open the newest pack for appending, or create the next one if it is full
+    fn open_pack_for_append(&self) -> Result<(String, File, u64)> {
+        fs_utils::ensure_dir(&self.packs_dir)?;
+
+        let names = self.pack_names()?;
+        let in_use: HashSet<&str> = self.index.entries.values().map(|e| e.pack.as_str()).collect();
+
+        let reusable = names.last().and_then(|name| {
+            let len = fs::metadata(self.packs_dir.join(name)).ok()?.len();
+            (len < PACK_MAX_SIZE && (in_use.contains(name.as_str()) || len == 0))
+                .then(|| (name.clone(), len))
+        });
+
+        let (name, len) = match reusable {
+            Some(found) => found,
+            None => {
+                let next = names.last().and_then(|n| pack_sequence(n)).map_or(1, |n| n + 1);
+                (format!("pack-{:06}.{}", next, PACK_EXTENSION), 0)
+            }
+        };
+
+        let path = self.packs_dir.join(&name);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open pack file: {}", path.display()))?;
+
+        // Drop any bytes past the last indexed blob, left behind by an interrupted save
+        let indexed_end = self.index.entries
+            .values()
+            .filter(|e| e.pack == name)
+            .map(|e| e.offset + e.length)
+            .max()
+            .unwrap_or(0);
+        if indexed_end < len {
+            file.set_len(indexed_end)?;
+        }
+
+        Ok((name, file, indexed_end))
+    }
+}
+
+/// Read a packed blob's bytes from its pack file
+pub fn read_entry(packs_dir: &Path, entry: &PackEntry) -> Result<Vec<u8>> {
+    let path = packs_dir.join(&entry.pack);
+    let mut file = File::open(&path)
+        .with_context(|| format!("Failed to open pack file: {}", path.display()))?;
+
+    file.seek(SeekFrom::Start(entry.offset))?;
+
+    let mut data = vec![0u8; entry.length as usize];
+    if file.read_exact(&mut data).is_err() {
+        bail!("Pack file {} is truncated", entry.pack);
+    }
+
+    Ok(data)
+}
+
+/// Parse the sequence number from a pack file name (pack-000001.kbp)
+fn pack_sequence(name: &str) -> Option<u32> {
+    name.strip_prefix("pack-")?
+        .strip_suffix(&format!(".{}", PACK_EXTENSION))?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_pack_sequence() {
+        assert_eq!(pack_sequence("pack-000001.kbp"), Some(1));
+        assert_eq!(pack_sequence("pack-000042.kbp"), Some(42));
+        assert_eq!(pack_sequence("index.json"), None);
+        assert_eq!(pack_sequence("pack-abc.kbp"), None);
+    }
+
+    #[test]
+    fn test_append_and_read() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut packs = PackStore::load(temp_dir.path()).unwrap();
+
+        packs.append("hash1", b"first").unwrap();
+        packs.append("hash2", b"second").unwrap();
+
+        let entry = packs.get("hash2").unwrap();
+        assert_eq!(entry.offset, 5);
+        assert_eq!(read_entry(&packs.packs_dir, &entry).unwrap(), b"second");
+        assert_eq!(packs.len(), 2);
+    }
+
+    #[test]
+    fn test_save_and_reload() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut packs = PackStore::load(temp_dir.path()).unwrap();
+        packs.append("hash1", b"data").unwrap();
+        packs.save().unwrap();
+
+        let reloaded = PackStore::load(temp_dir.path()).unwrap();
+        assert!(reloaded.contains("hash1"));
+        let entry = reloaded.get("hash1").unwrap();
+        assert_eq!(read_entry(&reloaded.packs_dir, &entry).unwrap(), b"data");
+    }
+
+    #[test]
+    fn test_append_after_reload_continues_pack() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut packs = PackStore::load(temp_dir.path()).unwrap();
+        packs.append("hash1", b"data").unwrap();
+        packs.save().unwrap();
+
+        let mut reloaded = PackStore::load(temp_dir.path()).unwrap();
+        reloaded.append("hash2", b"more").unwrap();
+
+        let first = reloaded.get("hash1").unwrap();
+        let second = reloaded.get("hash2").unwrap();
+        assert_eq!(first.pack, second.pack);
+        assert_eq!(second.offset, 4);
+    }
+
+    #[test]
+    fn test_unsaved_bytes_are_discarded() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut packs = PackStore::load(temp_dir.path()).unwrap();
+        packs.append("hash1", b"data").unwrap();
+        packs.save().unwrap();
+        packs.append("lost", b"never indexed").unwrap();
+        drop(packs);
+
+        let mut reloaded = PackStore::load(temp_dir.path()).unwrap();
+        assert!(!reloaded.contains("lost"));
+        reloaded.append("hash2", b"more").unwrap();
+        assert_eq!(reloaded.get("hash2").unwrap().offset, 4);
+    }
+
+    #[test]
+    fn test_repack_drops_unreferenced() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut packs = PackStore::load(temp_dir.path()).unwrap();
+        packs.append("keep", b"keep me").unwrap();
+        packs.append("drop", b"drop me!").unwrap();
+        packs.save().unwrap();
+
+        let referenced: HashSet<String> = ["keep".to_string()].into_iter().collect();
+        let (removed, freed) = packs.repack(&referenced).unwrap();
+
+        assert_eq!(removed, 1);
+        assert_eq!(freed, 8);
+        assert!(!packs.contains("drop"));
+
+        let reloaded = PackStore::load(temp_dir.path()).unwrap();
+        let entry = reloaded.get("keep").unwrap();
+        assert_eq!(entry.offset, 0);
+        assert_eq!(read_entry(&reloaded.packs_dir, &entry).unwrap(), b"keep me");
+        assert_eq!(reloaded.pack_names().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_repack_nothing_to_drop() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut packs = PackStore::load(temp_dir.path()).unwrap();
+        packs.append("keep", b"keep me").unwrap();
+        packs.save().unwrap();
+
+        let referenced: HashSet<String> = ["keep".to_string()].into_iter().collect();
+        assert_eq!(packs.repack(&referenced).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_repack_removes_orphaned_packs() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut packs = PackStore::load(temp_dir.path()).unwrap();
+        packs.append("keep", b"keep me").unwrap();
+        packs.save().unwrap();
+        // A pack whose index was never written, e.g. from an interrupted save
+        fs::write(packs.packs_dir.join("pack-000002.kbp"), b"unindexed").unwrap();
+
+        let referenced: HashSet<String> = ["keep".to_string()].into_iter().collect();
+        assert_eq!(packs.repack(&referenced).unwrap(), (0, 9));
+        assert_eq!(packs.pack_names().unwrap(), ["pack-000001.kbp"]);
+        assert_eq!(read_entry(&packs.packs_dir, &packs.get("keep").unwrap()).unwrap(), b"keep me");
+
+        // With an empty index every pack is an orphan
+        let mut unindexed = PackStore::load(temp_dir.path()).unwrap();
+        unindexed.index.entries.clear();
+        assert_eq!(unindexed.repack(&referenced).unwrap(), (0, 7));
+        assert!(unindexed.pack_names().unwrap().is_empty());
+    }
+}
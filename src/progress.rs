@@ -1,4 +1,10 @@
+use anyhow::{Context, Result};
 use indicatif::{ProgressBar, ProgressStyle, ProgressDrawTarget};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 /// Progress configuration for determining whether to show progress bars
@@ -58,6 +64,11 @@ impl Timer {
         }
     }
 
+    /// Get the time elapsed since the timer was started
+    pub fn elapsed(&self) -> std::time::Duration {
+        self.start.elapsed()
+    }
+
     /// Get elapsed time in seconds as a formatted string
     pub fn elapsed_string(&self) -> String {
         let elapsed = self.start.elapsed();
@@ -65,12 +76,83 @@ impl Timer {
     }
 }
 
+/// A machine-readable progress event, written as one JSON object per line
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ProgressEvent<'a> {
+    Start { total_bytes: u64 },
+    Progress { done_bytes: u64 },
+    File { path: &'a str },
+    Done { elapsed: f64 },
+}
+
+/// Sink for newline-delimited JSON progress events, for frontends wrapping the CLI.
+/// Events are written regardless of whether a progress bar is shown.
+#[derive(Debug, Clone)]
+pub struct ProgressEvents {
+    writer: Arc<Mutex<File>>,
+    start: Instant,
+}
+
+impl ProgressEvents {
+    /// Open an event sink on a file descriptor number (e.g. "3") or a file path
+    pub fn open(target: &str) -> Result<Self> {
+        let file = if let Ok(fd) = target.parse::<u32>() {
+            open_fd(fd)?
+        }
+        else {
+            File::create(target)
+                .with_context(|| format!("Failed to create progress event file: {}", target))?
+        };
+
+        Ok(Self {
+            writer: Arc::new(Mutex::new(file)),
+            start: Instant::now(),
+        })
+    }
+
+    fn emit(&self, event: ProgressEvent) {
+        let Ok(mut line) = serde_json::to_string(&event) else { return };
+        line.push('\n');
+
+        // Progress reporting must never fail the operation itself, e.g. if the reader went away
+        let mut writer = self.writer.lock().expect("Progress event writer mutex poisoned");
+        let _ = writer.write_all(line.as_bytes());
+    }
+
+    /// Report that a file was processed
+    pub fn file(&self, path: &str) {
+        self.emit(ProgressEvent::File { path });
+    }
+
+    /// Report completion with the time elapsed since the sink was opened
+    pub fn done(&self) {
+        self.emit(ProgressEvent::Done { elapsed: self.start.elapsed().as_secs_f64() });
+    }
+}
+
+#[cfg(unix)]
+fn open_fd(fd: u32) -> Result<File> {
+    OpenOptions::new()
+        .append(true)
+        .open(format!("/dev/fd/{}", fd))
+        .with_context(|| format!("Failed to open file descriptor {} for progress events", fd))
+}
+
+#[cfg(not(unix))]
+fn open_fd(fd: u32) -> Result<File> {
+    anyhow::bail!("Writing progress events to file descriptor {} is only supported on Unix; pass a file path instead", fd)
+}
+
 /// Progress tracker for byte-based operations
 #[derive(Debug, Clone)]
 pub struct ByteProgress {
     bar: Option<ProgressBar>,
     timer: Timer,
     enabled: bool,
+    events: Option<ProgressEvents>,
+    done_bytes: Arc<AtomicU64>,
+    skipped: Arc<AtomicU64>,
 }
 
 impl ByteProgress {
@@ -83,7 +165,7 @@ impl ByteProgress {
             pb.set_draw_target(ProgressDrawTarget::stderr());
             pb.set_style(
                 ProgressStyle::default_bar()
-                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}")
                     .expect("Invalid progress template")
                     .progress_chars("#>-")
             );
@@ -97,7 +179,19 @@ impl ByteProgress {
             bar,
             timer: Timer::new(),
             enabled,
+            events: None,
+            done_bytes: Arc::new(AtomicU64::new(0)),
+            skipped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Also report progress as JSON events, starting with the total byte count
+    pub fn with_events(mut self, events: Option<&ProgressEvents>, total_bytes: u64) -> Self {
+        if let Some(events) = events {
+            events.emit(ProgressEvent::Start { total_bytes });
         }
+        self.events = events.cloned();
+        self
     }
 
     /// Update progress by adding bytes processed
@@ -105,6 +199,31 @@ impl ByteProgress {
         if let Some(ref bar) = self.bar {
             bar.inc(bytes);
         }
+        if let Some(ref events) = self.events {
+            let done_bytes = self.done_bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+            events.emit(ProgressEvent::Progress { done_bytes });
+        }
+    }
+
+    /// Count a file that needed no work, without moving the bar, so that the ETA only
+    /// reflects the bytes still to be processed
+    pub fn skip(&self) {
+        let skipped = self.skipped.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(ref bar) = self.bar {
+            bar.set_message(format!("{} unchanged", skipped));
+        }
+    }
+
+    /// Number of files counted with `skip`
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Report a processed file to the JSON event sink, if any
+    pub fn file(&self, path: &str) {
+        if let Some(ref events) = self.events {
+            events.file(path);
+        }
     }
 
     /// Finish the progress bar and return elapsed time
@@ -310,6 +429,15 @@ mod tests {
         // Should not panic even when disabled
     }
 
+    #[test]
+    fn test_byte_progress_skip() {
+        let progress = ByteProgress::new(1000, ProgressConfig::ForceEnable);
+        progress.skip();
+        progress.skip();
+        assert_eq!(progress.skipped(), 2);
+        assert_eq!(progress.bar.as_ref().unwrap().position(), 0);
+    }
+
     #[test]
     fn test_byte_progress_finish() {
         let progress = ByteProgress::new(1000, ProgressConfig::ForceDisable);
@@ -397,6 +525,35 @@ mod tests {
         let _ = progress.finish();
     }
 
+    #[test]
+    fn test_progress_events() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("events.ndjson");
+        let events = ProgressEvents::open(path.to_str().unwrap()).unwrap();
+
+        let progress = ByteProgress::new(30, ProgressConfig::ForceDisable).with_events(Some(&events), 30);
+        progress.file("build/a.o");
+        progress.inc(10);
+        progress.file("build/b.o");
+        progress.inc(20);
+        progress.finish();
+        events.done();
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], serde_json::json!({"type": "start", "total_bytes": 30}));
+        assert_eq!(lines[1], serde_json::json!({"type": "file", "path": "build/a.o"}));
+        assert_eq!(lines[2], serde_json::json!({"type": "progress", "done_bytes": 10}));
+        assert_eq!(lines[4], serde_json::json!({"type": "progress", "done_bytes": 30}));
+        assert_eq!(lines[5]["type"], "done");
+        assert!(lines[5]["elapsed"].is_f64());
+    }
+
     #[test]
     fn test_item_progress_custom_name() {
         let progress1 = ItemProgress::new(10, ProgressConfig::ForceEnable, "files");
@@ -0,0 +1,209 @@
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Component, Path};
+
+use crate::config::Config;
+use crate::file_hash;
+use crate::manifest::{DirectoryEntry, FileEntry, Manifest};
+use crate::store::Store;
+
+/// Permissions given to rebuilt entries, whose real ones are unknown
+#[cfg(unix)]
+const DEFAULT_FILE_MODE: u32 = 0o100644;
+#[cfg(unix)]
+const DEFAULT_DIR_MODE: u32 = 0o040755;
+
+/// Parse a list of file paths and content hashes, either as `<hash>  <path>` lines like
+/// `b3sum` prints, or as a JSON object mapping paths to hashes. Returns (path, hash) pairs.
+pub fn parse_file_list(content: &str) -> Result<Vec<(String, String)>> {
+    let entries: Vec<(String, String)> = if content.trim_start().starts_with('{') {
+        let map: BTreeMap<String, String> = serde_json::from_str(content).context("Invalid JSON file list")?;
+        map.into_iter().collect()
+    }
+    else {
+        let mut entries = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim_end();
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((hash, path)) = line.split_once(char::is_whitespace) else {
+                bail!("Line {}: expected '<hash>  <path>'", number + 1);
+            };
+            entries.push((path.trim_start().to_string(), hash.to_string()));
+        }
+        entries
+    };
+
+    let mut seen = BTreeSet::new();
+    let mut normalized = Vec::with_capacity(entries.len());
+    for (path, hash) in entries {
+        let path = path.strip_prefix("./").unwrap_or(&path).to_string();
+        let is_relative = Path::new(&path).components().all(|component| matches!(component, Component::Normal(_)));
+        if path.is_empty() || !is_relative {
+            bail!("Invalid path in file list: '{}' (expected a relative path inside the workspace)", path);
+        }
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("Invalid hash for '{}' in file list: '{}'", path, hash);
+        }
+        if !seen.insert(path.clone()) {
+            bail!("Path listed twice in file list: {}", path);
+        }
+        normalized.push((path, hash.to_ascii_lowercase()));
+    }
+    Ok(normalized)
+}
+
+/// Counts the bytes written to it, hashes them, and keeps the start for text detection
+struct BlobProbe {
+    size: u64,
+    start: Vec<u8>,
+    hasher: blake3::Hasher,
+}
+
+impl Write for BlobProbe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.size += buf.len() as u64;
+        let wanted = file_hash::TEXT_PROBE_SIZE.saturating_sub(self.start.len());
+        self.start.extend_from_slice(&buf[..buf.len().min(wanted)]);
+        self.hasher.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reconstruct a manifest from (path, hash) pairs whose blobs are in the store. Each blob
+/// is read back to recover the file's size and check that its content matches the hash.
+/// What the list cannot supply is filled in: regular files with mode 644 in directories
+/// with mode 755, modified now, tracked under the paths configured in `config`.
+pub fn rebuild_manifest(name: &str, files: &[(String, String)], store: &Store, config: &Config) -> Result<Manifest> {
+    if files.is_empty() {
+        bail!("The file list is empty");
+    }
+
+    let missing: Vec<&str> = files
+        .iter()
+        .filter(|(_, hash)| !store.has_blob(hash))
+        .map(|(path, _)| path.as_str())
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "{} listed file(s) have no blob in the store:\n  {}",
+            missing.len(),
+            missing.join("\n  ")
+        );
+    }
+
+    let probes = files
+        .par_iter()
+        .map(|(path, hash)| {
+            let mut probe = BlobProbe { size: 0, start: Vec::new(), hasher: blake3::Hasher::new() };
+            store.copy_blob_to_writer(hash, &mut probe)
+                .with_context(|| format!("Failed to read blob for: {}", path))?;
+            if probe.hasher.finalize().to_hex().as_str() != hash {
+                bail!("Blob for '{}' does not match its hash {}", path, hash);
+            }
+            Ok(probe)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let now = Utc::now();
+    let mut manifest = Manifest::new(name.to_string());
+    manifest.set_tracked_paths(config.directories.clone(), config.files.clone());
+    manifest.ignored_patterns = config.ignore.clone();
+    manifest.store_group = config.store_group.clone();
+
+    let mut directories = BTreeSet::new();
+    for ((path, hash), probe) in files.iter().zip(probes) {
+        directories.extend(
+            Path::new(path)
+                .ancestors()
+                .skip(1)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .map(|dir| dir.to_string_lossy().to_string()),
+        );
+        manifest.add_file(path.clone(), FileEntry {
+            hash: hash.clone(),
+            size: probe.size,
+            #[cfg(unix)]
+            mode: DEFAULT_FILE_MODE,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: now.timestamp(),
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: file_hash::is_text(&probe.start),
+        });
+    }
+    for dir in directories {
+        manifest.add_directory(dir, DirectoryEntry {
+            #[cfg(unix)]
+            mode: DEFAULT_DIR_MODE,
+            mtime_secs: now.timestamp(),
+            mtime_nanos: 0,
+        });
+    }
+
+    manifest.set_content_root();
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_file_list() {
+        let hash = "a".repeat(64);
+        let lines = format!("# from b3sum\n{}  ./build/app\n{}  build/lib file.a\n", hash, hash.to_uppercase());
+        assert_eq!(
+            parse_file_list(&lines).unwrap(),
+            vec![("build/app".to_string(), hash.clone()), ("build/lib file.a".to_string(), hash.clone())]
+        );
+
+        let json = format!("{{\"build/app\": \"{}\"}}", hash);
+        assert_eq!(parse_file_list(&json).unwrap(), vec![("build/app".to_string(), hash.clone())]);
+
+        assert!(parse_file_list(&format!("{}  ../outside", hash)).is_err());
+        assert!(parse_file_list("abc  build/app").is_err());
+        assert!(parse_file_list(&format!("{}  build/app\n{}  build/app", hash, hash)).is_err());
+    }
+
+    #[test]
+    fn test_rebuild_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let store = Store::with_compression(root, 3);
+        store.init().unwrap();
+
+        let source = root.join("source");
+        fs::write(&source, b"hello text").unwrap();
+        let hash = file_hash::hash_file(&source).unwrap();
+        store.store_file(&source, &hash).unwrap();
+
+        let config = Config { directories: vec!["build".to_string()], ..Default::default() };
+        let files = vec![("build/sub/readme.txt".to_string(), hash.clone())];
+        let manifest = rebuild_manifest("recovered", &files, &store, &config).unwrap();
+
+        let entry = &manifest.files["build/sub/readme.txt"];
+        assert_eq!(entry.size, 10);
+        assert!(entry.is_text);
+        assert_eq!(manifest.file_count, 1);
+        assert!(manifest.directories.contains_key("build"));
+        assert!(manifest.directories.contains_key("build/sub"));
+        assert_eq!(manifest.tracked_directories, vec!["build"]);
+        assert!(manifest.content_root.is_some());
+
+        let missing = vec![("build/gone".to_string(), "b".repeat(64))];
+        let err = rebuild_manifest("recovered", &missing, &store, &config).unwrap_err();
+        assert!(err.to_string().contains("build/gone"), "{}", err);
+    }
+}
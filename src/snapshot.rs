@@ -1,18 +1,31 @@
 use anyhow::{Context, Result};
-use glob::glob;
+use chrono::{DateTime, Utc};
 use rayon::prelude::*;
-use std::collections::HashSet;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use walkdir::WalkDir;
 
-use crate::config::Config;
+use crate::config::{Config, MtimePrecision, SymlinkPolicy, SymlinkTargetMode, get_store_dir};
 use crate::file_hash::{self, HashCache};
-use crate::manifest::{FileEntry, DirectoryEntry, Manifest};
-use crate::progress::{ProgressConfig, Spinner};
+use crate::manifest::{FileEntry, DirectoryEntry, Manifest, format_size, parse_retention};
+use crate::progress::{ByteProgress, ProgressConfig, ProgressEvents, Spinner, Timer};
 use crate::store::Store;
 
+/// Maximum number of discovered paths queued for hashing at once
+const SCAN_QUEUE_CAPACITY: usize = 1024;
+/// Files up to this size are used as samples when training a compression dictionary
+const DICT_SAMPLE_MAX_FILE_SIZE: u64 = 64 * 1024;
+/// Upper bound on the sample data read for dictionary training
+const DICT_SAMPLE_MAX_TOTAL: u64 = 16 * 1024 * 1024;
+/// Fewer samples than this are not worth training a dictionary on
+const DICT_MIN_SAMPLES: usize = 16;
+
 /// Result of scanning a single file
 struct ScanResult {
     relative_path: String,
@@ -20,6 +33,82 @@ struct ScanResult {
     entry: FileEntry,
 }
 
+/// Where the time of a save went, for `save --timing`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SaveTiming {
+    /// Wall time of walking the tracked paths and hashing the files found
+    pub scan: Duration,
+    /// Time spent reading and hashing files, summed across worker threads
+    pub hashing: Duration,
+    /// Wall time of writing blobs to the store
+    pub store: Duration,
+    /// Part of the store time spent compressing, summed across threads
+    pub compression: Duration,
+    /// Number of worker threads hashing files
+    pub threads: usize,
+}
+
+/// How the files of a save relate to the blobs already in the store
+#[derive(Debug, Default, Clone, PartialEq)]
+struct BlobCounts {
+    /// Files whose content was stored for the first time
+    new: usize,
+    /// Files whose content an earlier snapshot already stored
+    reused: usize,
+    /// Files with the same content as another file earlier in this save
+    duplicates: usize,
+    duplicate_bytes: u64,
+    seen: HashSet<String>,
+}
+
+impl BlobCounts {
+    fn record(&mut self, hash: &str, size: u64, was_new: bool) {
+        if !self.seen.insert(hash.to_string()) {
+            self.duplicates += 1;
+            self.duplicate_bytes += size;
+        }
+        else if was_new {
+            self.new += 1;
+        }
+        else {
+            self.reused += 1;
+        }
+    }
+}
+
+/// Time budget of a save, from `max_duration_secs`
+struct Deadline {
+    timer: Timer,
+    limit: Option<Duration>,
+}
+
+impl Deadline {
+    fn new(limit_secs: Option<u64>) -> Self {
+        Self {
+            timer: Timer::new(),
+            limit: limit_secs.map(Duration::from_secs),
+        }
+    }
+
+    fn exceeded(&self) -> bool {
+        self.limit.is_some_and(|limit| self.timer.elapsed() > limit)
+    }
+
+    /// Fail once the save has run longer than its limit
+    fn check(&self) -> Result<()> {
+        if let Some(limit) = self.limit
+            && self.exceeded()
+        {
+            anyhow::bail!(
+                "Save aborted after exceeding the maximum duration of {}s (max_duration_secs); \
+                 blobs stored so far are unreferenced and removed by 'kibo prune'",
+                limit.as_secs()
+            );
+        }
+        Ok(())
+    }
+}
+
 /// Create a snapshot of the tracked directories
 pub fn create_snapshot(
     root: &Path,
@@ -27,9 +116,34 @@ pub fn create_snapshot(
     config: &Config,
     verbose: bool,
     progress_config: ProgressConfig,
+    progress_events: Option<&ProgressEvents>,
+    timing: Option<&mut SaveTiming>,
 ) -> Result<Manifest> {
+    let deadline = Deadline::new(config.max_duration_secs);
+    let mut config = config.clone();
+    let gitignore_applied = config.apply_vcs_ignores(root)?;
+    let config = &config;
+
     let effective_level = config.effective_compression_level();
-    let store = Store::with_compression(root, effective_level);
+    let mut store = Store::with_compression(root, effective_level).in_group(config.store_group.as_deref());
+    if let Some(effort) = config.compression_effort {
+        store = store.with_compression_effort(effort);
+    }
+    if config.compression_dict && effective_level == 0 {
+        eprintln!("Warning: compression dictionary ignored because compression is disabled (compression_level = 0)");
+    }
+    if config.recompress_reused && effective_level == 0 {
+        eprintln!("Warning: --recompress-reused ignored because compression is disabled (compression_level = 0)");
+    }
+    if config.pack_small_blobs {
+        store = store.with_pack_threshold(config.pack_threshold_kb * 1024);
+    }
+    if config.durable_writes {
+        store = store.with_durable_writes();
+    }
+    if timing.is_some() {
+        store = store.with_compression_timing();
+    }
     store.init()?;
 
     if verbose && effective_level > 0 {
@@ -38,29 +152,126 @@ pub fn create_snapshot(
 
     let spinner = Spinner::new(progress_config, &format!("Preparing snapshot '{}'", name));
 
-    let hash_cache = HashCache::load(root).unwrap_or_else(|_| HashCache::new());
+    let hash_cache = HashCache::load(root)
+        .unwrap_or_else(|_| HashCache::new())
+        .with_mtime_precision(config.mtime_precision);
 
-    let files_to_process = collect_files(root, config, verbose)?;
-
-    let file_count = files_to_process.len();
-    
     let directories_to_save = collect_directories(root, config, verbose)?;
-    let dir_count = directories_to_save.len();
 
     spinner.finish();
-    
-    eprintln!("Found {} files and {} directories to snapshot", file_count, dir_count);
-    
-    let spinner = Spinner::new(progress_config, &format!("Processing {} files", file_count));
+
+    let spinner = Spinner::new(progress_config, "Scanning and processing files");
 
     let hash_cache = Arc::new(Mutex::new(hash_cache));
+    let assume_unchanged = crate::fs_utils::compile_patterns(&config.assume_unchanged)
+        .context("Invalid assume_unchanged pattern")?;
+
+    // Stream paths from the directory walk straight into the hashing pool so work starts
+    // immediately and the number of queued paths stays bounded, however large the tree
+    let (sender, receiver) = mpsc::sync_channel::<(String, PathBuf)>(SCAN_QUEUE_CAPACITY);
+    let symlink_policy = config.symlink_policy;
+    let mut skipped_symlinks = Vec::new();
+    let modified_after = mtime_cutoff(config)?;
+    let cutoff = modified_after.map(SystemTime::from);
+    let mut skipped_old = 0usize;
+    let exclude_empty_files = config.exclude_empty_files;
+    let mut skipped_empty = 0usize;
+    let measure = timing.is_some();
+    let hashing_nanos = AtomicU64::new(0);
+    let scan_started = Instant::now();
+
+    let (results, file_count) = thread::scope(|scope| -> Result<(Vec<Result<ScanResult>>, usize)> {
+        let skipped = &mut skipped_symlinks;
+        let skipped_old = &mut skipped_old;
+        let skipped_empty = &mut skipped_empty;
+        let deadline = &deadline;
+        let producer = scope.spawn(move || {
+            walk_files(root, config, verbose, |relative_path, absolute_path| {
+                // Past the deadline the walk only drains, since the save is going to fail
+                if deadline.exceeded() {
+                    return;
+                }
+                if symlink_policy == SymlinkPolicy::Skip && absolute_path.is_symlink() {
+                    skipped.push(relative_path);
+                    return;
+                }
+                if cutoff.is_some_and(|cutoff| crate::fs_utils::modified_before(&absolute_path, cutoff)) {
+                    *skipped_old += 1;
+                    return;
+                }
+                if exclude_empty_files && crate::fs_utils::is_empty_file(&absolute_path) {
+                    *skipped_empty += 1;
+                    return;
+                }
+                // Only fails if the consumer has gone away, in which case nothing is left to do
+                let _ = sender.send((relative_path, absolute_path));
+            })
+        });
 
-    let results: Vec<Result<ScanResult>> = files_to_process
-        .into_par_iter()
-        .map(|(relative_path, absolute_path)| {
-            process_file(&absolute_path, &relative_path, hash_cache.clone())
-        })
-        .collect();
+        let results = receiver
+            .into_iter()
+            .par_bridge()
+            .map(|(relative_path, absolute_path)| {
+                deadline.check()?;
+                let assumed = crate::fs_utils::matches_any_pattern(Path::new(&relative_path), &assume_unchanged);
+                let started = measure.then(Instant::now);
+                let result = process_file(&absolute_path, &relative_path, assumed, symlink_policy, config.mtime_precision, hash_cache.clone())
+                    .map(|mut result| {
+                        normalize_symlink_target(&mut result.entry, &result.relative_path, root, config.symlink_target_mode);
+                        result
+                    });
+                if let Some(started) = started {
+                    hashing_nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                }
+                result
+            })
+            .collect();
+
+        let file_count = producer.join().expect("File scanner thread panicked")?;
+        Ok((results, file_count))
+    })?;
+
+    spinner.finish();
+    deadline.check()?;
+    let scan_time = scan_started.elapsed();
+
+    let file_count = file_count - skipped_symlinks.len() - skipped_old - skipped_empty;
+
+    let directories_to_save = if config.include_empty_dirs {
+        directories_to_save
+    }
+    else {
+        let file_paths = results.iter().flatten().map(|r| r.relative_path.as_str());
+        retain_directories_with_files(directories_to_save, file_paths)
+    };
+    let directories_to_save = if config.sparse_directories {
+        let file_paths = results.iter().flatten().map(|r| r.relative_path.as_str());
+        omit_implied_directories(directories_to_save, file_paths)
+    }
+    else {
+        directories_to_save
+    };
+    let dir_count = directories_to_save.len();
+
+    eprintln!("Found {} files and {} directories to snapshot", file_count, dir_count);
+
+    if !skipped_symlinks.is_empty() {
+        eprintln!("Skipped {} symlink(s) (symlink policy: skip)", skipped_symlinks.len());
+        if verbose {
+            skipped_symlinks.sort();
+            for path in &skipped_symlinks {
+                eprintln!("  Skipped symlink: {}", path);
+            }
+        }
+    }
+
+    if skipped_old > 0 {
+        eprintln!(
+            "Skipped {} file(s) last modified more than {} ago",
+            skipped_old,
+            config.include_newer_than.as_deref().unwrap_or_default()
+        );
+    }
 
     let mut scan_results = Vec::new();
     for result in results {
@@ -72,6 +283,16 @@ pub fn create_snapshot(
         }
     }
 
+    if skipped_empty > 0 {
+        eprintln!("Skipped {} empty file(s)", skipped_empty);
+    }
+    else {
+        let empty_files = scan_results.iter().filter(|r| !r.entry.is_symlink && r.entry.size == 0).count();
+        if empty_files > 0 {
+            eprintln!("Included {} empty file(s) (leave them out with --exclude-empty-files)", empty_files);
+        }
+    }
+
     let mut manifest = Manifest::new(name.to_string());
     
     manifest.set_tracked_paths(
@@ -80,39 +301,115 @@ pub fn create_snapshot(
     );
     
     manifest.set_ignored_patterns(config.ignore.clone());
+    manifest.symlink_policy = symlink_policy;
+    manifest.symlink_target_mode = config.symlink_target_mode;
+    manifest.mtime_precision = config.mtime_precision;
+    manifest.one_file_system = config.one_file_system;
+    manifest.exclude_vcs = config.exclude_vcs;
+    manifest.exclude_hidden = config.exclude_hidden;
+    manifest.sparse_directories = config.sparse_directories;
+    manifest.gitignore_applied = gitignore_applied;
+    manifest.modified_after = modified_after;
+    manifest.exclude_empty_files = exclude_empty_files;
+    manifest.store_group = config.store_group.clone();
+    if config.include_git_sha {
+        match git_revision(root) {
+            Some((revision, dirty)) => {
+                manifest.vcs_revision = Some(revision);
+                manifest.vcs_dirty = dirty;
+            }
+            None => eprintln!(
+                "Warning: include_git_sha is set but {} is not in a git work tree with a commit (or git is not installed); no revision recorded",
+                root.display()
+            ),
+        }
+    }
+    manifest.environment = capture_environment(&config.capture_env, |name| std::env::var(name).ok());
+    if config.outside_directories().next().is_some() {
+        manifest.outside_base = Some(root.to_string_lossy().to_string());
+    }
     
     for (relative_path, entry) in directories_to_save {
         manifest.add_directory(relative_path, entry);
     }
 
-    let mut new_blobs = 0usize;
-    let mut reused_blobs = 0usize;
+    manifest.hash_only = config.hash_only;
+    manifest.link_identical = config.link_identical;
+    if (config.check_free_space || config.strict_space) && !config.hash_only {
+        check_free_space(&store, &scan_results, config.strict_space)?;
+    }
+
+    if config.compression_dict && effective_level > 0 && !config.hash_only {
+        train_dictionary(&mut store, &scan_results, verbose)?;
+    }
+
+    let mut blob_counts = BlobCounts::default();
+    let mut reused_hashes = HashSet::new();
+    let mut unstored_hashes = HashSet::new();
+
+    let total_bytes = scan_results.iter().map(|r| r.entry.size).sum();
+    let progress = ByteProgress::new(total_bytes, progress_config).with_events(progress_events, total_bytes);
+    let store_started = Instant::now();
 
     for scan_result in scan_results {
-        if scan_result.entry.is_symlink {
+        deadline.check()?;
+        if config.hash_only {
+            if !store.has_blob(&scan_result.entry.hash) {
+                unstored_hashes.insert(scan_result.entry.hash.clone());
+            }
+        }
+        else if scan_result.entry.is_symlink {
             if let Some(ref target) = scan_result.entry.symlink_target {
                 let was_new = store.store_symlink(Path::new(target), &scan_result.entry.hash)?;
-                if was_new {
-                    new_blobs += 1;
-                }
-                else {
-                    reused_blobs += 1;
-                }
+                blob_counts.record(&scan_result.entry.hash, scan_result.entry.size, was_new);
             }
         }
         else {
             let was_new = store.store_file(&scan_result.absolute_path, &scan_result.entry.hash)?;
-            if was_new {
-                new_blobs += 1;
-            }
-            else {
-                reused_blobs += 1;
+            blob_counts.record(&scan_result.entry.hash, scan_result.entry.size, was_new);
+            if !was_new && config.recompress_reused {
+                reused_hashes.insert(scan_result.entry.hash.clone());
             }
         }
 
+        progress.file(&scan_result.relative_path);
+        progress.inc(scan_result.entry.size);
         manifest.add_file(scan_result.relative_path, scan_result.entry);
     }
 
+    store.flush()?;
+    progress.finish();
+    if !config.hash_only {
+        manifest.compression_level = Some(effective_level);
+        manifest.compression_algorithm = Some(store.compression_algorithm().to_string());
+        manifest.compression_effort = config.compression_effort;
+    }
+
+    if let Some(timing) = timing {
+        *timing = SaveTiming {
+            scan: scan_time,
+            hashing: Duration::from_nanos(hashing_nanos.load(Ordering::Relaxed)),
+            store: store_started.elapsed(),
+            compression: store.compression_time().unwrap_or_default(),
+            threads: rayon::current_num_threads(),
+        };
+    }
+
+    if !reused_hashes.is_empty() {
+        let hashes: Vec<&str> = reused_hashes.iter().map(String::as_str).collect();
+        let recompressed = store.recompress_blobs(&hashes)?;
+        if recompressed.blobs > 0 {
+            eprintln!(
+                "Recompressed {} reused blob(s) stored uncompressed ({} -> {})",
+                recompressed.blobs,
+                format_size(recompressed.bytes_before),
+                format_size(recompressed.bytes_after)
+            );
+        }
+    }
+
+    manifest.set_content_root();
+
     // Note: manifest is not saved here - caller must save it after setting optional fields
     // like db_dump_filename
 
@@ -122,8 +419,6 @@ pub fn create_snapshot(
         .expect("Hash cache mutex poisoned");
     hash_cache.save(root)?;
 
-    spinner.finish();
-
     if verbose {
         eprintln!(
             "Snapshot '{}' created: {} files, {} total",
@@ -131,7 +426,37 @@ pub fn create_snapshot(
             manifest.file_count,
             manifest.human_size()
         );
-        eprintln!("  New blobs: {}, Reused: {}", new_blobs, reused_blobs);
+        if !config.hash_only {
+            eprintln!(
+                "  New blobs: {}, Reused from earlier snapshots: {}, Duplicates within snapshot: {}",
+                blob_counts.new,
+                blob_counts.reused,
+                blob_counts.duplicates
+            );
+        }
+    }
+
+    if config.dedup_within_run && !config.hash_only {
+        eprintln!(
+            "Duplicates within this snapshot: {} file(s) ({}) share content with another file in it; \
+             {} blob(s) reused from earlier snapshots, {} new",
+            blob_counts.duplicates,
+            format_size(blob_counts.duplicate_bytes),
+            blob_counts.reused,
+            blob_counts.new
+        );
+        if blob_counts.duplicates > 0 && !config.link_identical {
+            eprintln!("  Save with --link-identical to restore them as hard links on load");
+        }
+    }
+
+    if !unstored_hashes.is_empty() {
+        eprintln!(
+            "Warning: {} blob(s) of hash-only snapshot '{}' are not in the store yet; it cannot be loaded \
+             until they are stored, e.g. by a full save or 'kibo store import-blobs'",
+            unstored_hashes.len(),
+            name
+        );
     }
 
     if let Some(max_size_gb) = config.max_snapshot_size_gb {
@@ -145,25 +470,231 @@ pub fn create_snapshot(
         }
     }
 
+    // Only a warning, so failing to count must not lose a save whose blobs are already stored
+    if let Some(max_blobs) = config.max_blob_count {
+        match store.blob_count() {
+            Ok(blob_count) if blob_count > max_blobs => {
+                eprintln!(
+                    "Warning: Store contains {} blobs, exceeding configured maximum ({})",
+                    blob_count,
+                    max_blobs
+                );
+                eprintln!("  Large blob counts can exhaust filesystem inodes. Run 'kibo prune' to remove unreferenced blobs.");
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Warning: Could not count the blobs in the store: {:#}", e),
+        }
+    }
+
     Ok(manifest)
 }
 
-/// Collect all files from tracked directories and file patterns
-fn collect_files(
+/// Values of the named environment variables, as returned by `lookup`, leaving out those
+/// that are not set
+fn capture_environment(names: &[String], lookup: impl Fn(&str) -> Option<String>) -> BTreeMap<String, String> {
+    names
+        .iter()
+        .filter_map(|name| lookup(name).map(|value| (name.clone(), value)))
+        .collect()
+}
+
+/// Commit checked out in the git work tree containing `root`, and whether tracked files have
+/// uncommitted changes. Untracked files, such as build outputs, do not count as changes.
+fn git_revision(root: &Path) -> Option<(String, bool)> {
+    let git = |args: &[&str]| {
+        std::process::Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    let revision = git(&["rev-parse", "--verify", "HEAD"])?;
+    let dirty = !git(&["status", "--porcelain", "--untracked-files=no"])?.is_empty();
+    Some((revision, dirty))
+}
+
+/// Compare the size of the blobs a save would add with the free space on the store's
+/// filesystem, warning or (when `strict`) failing if they may not fit. Content already in
+/// the store is not counted, and the uncompressed size is used as an upper bound.
+fn check_free_space(store: &Store, scan_results: &[ScanResult], strict: bool) -> Result<()> {
+    let needed = new_blob_bytes(store, scan_results);
+    let Some(available) = store.available_space()? else {
+        eprintln!("Warning: free space cannot be checked on this platform");
+        return Ok(());
+    };
+    if needed <= available {
+        return Ok(());
+    }
+
+    let message = format!(
+        "New blobs need up to {} uncompressed, but only {} is free on the store's filesystem",
+        format_size(needed),
+        format_size(available)
+    );
+    if strict {
+        anyhow::bail!("{}; free up space or run 'kibo prune' (strict_space)", message);
+    }
+    eprintln!("Warning: {}; the save may run out of space", message);
+    Ok(())
+}
+
+/// Uncompressed size of the distinct blobs that are not in the store yet
+fn new_blob_bytes(store: &Store, scan_results: &[ScanResult]) -> u64 {
+    let mut seen = HashSet::new();
+    scan_results
+        .iter()
+        .filter(|result| seen.insert(result.entry.hash.as_str()) && !store.has_blob(&result.entry.hash))
+        .map(|result| match result.entry.symlink_target {
+            Some(ref target) => target.len() as u64,
+            None => result.entry.size,
+        })
+        .sum()
+}
+
+/// A file that `save --dry-run` would capture
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlannedFile {
+    pub path: String,
+    pub size: u64,
+}
+
+/// What `save` would capture, computed without hashing or writing anything
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotPlan {
+    pub would_create: String,
+    pub files: Vec<PlannedFile>,
+    pub directories: usize,
+    pub total_size: u64,
+}
+
+/// Walk the tracked paths like `create_snapshot` does, but only collect paths and sizes
+pub fn plan_snapshot(root: &Path, name: &str, config: &Config, verbose: bool) -> Result<SnapshotPlan> {
+    let mut config = config.clone();
+    config.apply_vcs_ignores(root)?;
+    let config = &config;
+
+    let directories = collect_directories(root, config, verbose)?;
+    let cutoff = mtime_cutoff(config)?.map(SystemTime::from);
+
+    let mut files = Vec::new();
+    walk_files(root, config, verbose, |relative_path, absolute_path| {
+        let Ok(metadata) = fs::symlink_metadata(&absolute_path) else { return };
+        if cutoff.is_some_and(|cutoff| metadata.modified().is_ok_and(|mtime| mtime < cutoff)) {
+            return;
+        }
+        if config.exclude_empty_files && metadata.is_file() && metadata.len() == 0 {
+            return;
+        }
+
+        let size = if !metadata.file_type().is_symlink() {
+            metadata.len()
+        }
+        else {
+            match config.symlink_policy {
+                SymlinkPolicy::Skip => return,
+                SymlinkPolicy::Follow => fs::metadata(&absolute_path)
+                    .ok()
+                    .filter(|target| target.is_file())
+                    .map_or(0, |target| target.len()),
+                SymlinkPolicy::Store => 0,
+            }
+        };
+        files.push(PlannedFile { path: relative_path, size });
+    })?;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let directories = if config.include_empty_dirs {
+        directories
+    }
+    else {
+        retain_directories_with_files(directories, files.iter().map(|f| f.path.as_str()))
+    };
+
+    Ok(SnapshotPlan {
+        would_create: name.to_string(),
+        directories: directories.len(),
+        total_size: files.iter().map(|f| f.size).sum(),
+        files,
+    })
+}
+
+/// Oldest modification time a file may have to be captured with `include_newer_than`
+fn mtime_cutoff(config: &Config) -> Result<Option<DateTime<Utc>>> {
+    config
+        .include_newer_than
+        .as_deref()
+        .map(|age| parse_retention(age).map(|age| Utc::now() - age))
+        .transpose()
+}
+
+/// Keeps directory walks on the workspace root's filesystem when `one_file_system` is set
+struct MountFilter {
+    root_device: Option<u64>,
+    verbose: bool,
+    reported: HashSet<PathBuf>,
+}
+
+impl MountFilter {
+    fn new(root: &Path, config: &Config, verbose: bool) -> Self {
+        let root_device = if config.one_file_system {
+            crate::fs_utils::device_id(root)
+        }
+        else {
+            None
+        };
+
+        Self {
+            root_device,
+            verbose,
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Check if a path is on the root's filesystem, reporting each skipped mount point once
+    fn allows(&mut self, root: &Path, path: &Path) -> bool {
+        if crate::fs_utils::on_device(path, self.root_device) {
+            return true;
+        }
+
+        if self.verbose && self.reported.insert(path.to_path_buf()) {
+            let rel_path = path.strip_prefix(root).unwrap_or(path);
+            eprintln!("Skipping mount point: {}", rel_path.display());
+        }
+        false
+    }
+}
+
+/// Walk tracked directories and file patterns, passing each file to `emit` as it is found
+/// Returns the number of files emitted
+fn walk_files(
     root: &Path,
     config: &Config,
     verbose: bool,
-) -> Result<Vec<(String, PathBuf)>> {
-    let mut files = Vec::new();
+    mut emit: impl FnMut(String, PathBuf),
+) -> Result<usize> {
+    let mut file_count = 0usize;
     let mut found_dirs = Vec::new();
     let mut seen_paths: HashSet<PathBuf> = HashSet::new();
+    let mut mounts = MountFilter::new(root, config, verbose);
+    let mut hidden_skipped = 0usize;
+    // Skipped by path as well as by the .kibo name, so blobs are never captured into a snapshot
+    // even when a tracked directory contains the store
+    let store_dir = get_store_dir(root);
 
     for tracked_dir_name in &config.directories {
+        if Path::new(tracked_dir_name).is_absolute() {
+            continue;
+        }
+
         for entry in WalkDir::new(root)
             .follow_links(false)
             .into_iter()
             .filter_entry(|e| {
-                if e.file_name() == ".kibo" {
+                if e.file_name() == ".kibo" || e.path() == store_dir {
                     return false;
                 }
 
@@ -172,7 +703,7 @@ fn collect_files(
                         return false;
                     }
                 }
-                true
+                mounts.allows(root, e.path())
             })
             .filter_map(|e| e.ok())
         {
@@ -192,6 +723,18 @@ fn collect_files(
         }
     }
 
+    for outside_dir in config.outside_directories() {
+        if outside_dir.is_dir() {
+            found_dirs.push(outside_dir.to_path_buf());
+            if verbose {
+                eprintln!("Found tracked directory outside the workspace: {}", outside_dir.display());
+            }
+        }
+        else if verbose {
+            eprintln!("Warning: Tracked directory not found: {}", outside_dir.display());
+        }
+    }
+
     if found_dirs.is_empty() && config.files.is_empty() && verbose {
         eprintln!("Warning: No directories matching tracked names found");
     }
@@ -201,12 +744,17 @@ fn collect_files(
             .follow_links(false)
             .into_iter()
             .filter_entry(|e| {
-                if let Ok(rel_path) = e.path().strip_prefix(root) {
-                    if config.should_ignore(rel_path) {
-                        return false;
-                    }
+                if e.path() == store_dir {
+                    return false;
+                }
+                if config.should_ignore(&crate::fs_utils::relative_to(root, e.path())) {
+                    return false;
                 }
-                true
+                if config.exclude_hidden && crate::fs_utils::is_hidden_entry(e) {
+                    hidden_skipped += 1;
+                    return false;
+                }
+                mounts.allows(root, e.path())
             })
             .filter_map(|e| e.ok())
         {
@@ -216,20 +764,21 @@ fn collect_files(
                 continue;
             }
 
-            let relative_path = path
-                .strip_prefix(root)
-                .with_context(|| format!("Failed to get relative path: {}", path.display()))?
+            // Files in directories outside the workspace get paths starting with `..`
+            let relative_path = crate::fs_utils::relative_to(root, path)
                 .to_string_lossy()
                 .to_string();
 
             // Use the actual path for deduplication to preserve symlinks
             // Don't use canonicalize() as it resolves symlinks to their target
             if seen_paths.insert(path.to_path_buf()) {
-                files.push((relative_path, path.to_path_buf()));
+                emit(relative_path, path.to_path_buf());
+                file_count += 1;
             }
         }
     }
 
+    let mut loops = BTreeSet::new();
     for pattern in &config.files {
         let full_pattern = if pattern.starts_with("./") {
             let pattern_without_prefix = &pattern[2..];
@@ -256,14 +805,20 @@ fn collect_files(
             eprintln!("Searching for files matching: {} -> {}", pattern, full_pattern);
         }
 
-        match glob(&full_pattern) {
+        let matched = crate::fs_utils::match_files(&full_pattern, &mut loops, |e| {
+            if e.file_name() == ".kibo" || e.path() == store_dir {
+                return false;
+            }
+            if config.exclude_hidden && crate::fs_utils::is_hidden_entry(e) {
+                hidden_skipped += 1;
+                return false;
+            }
+            true
+        });
+        match matched {
             Ok(paths) => {
-                for entry in paths.filter_map(Result::ok) {
-                    if entry.is_dir() {
-                        continue;
-                    }
-
-                    if entry.starts_with(root.join(".kibo")) {
+                for entry in paths {
+                    if entry.starts_with(root.join(".kibo")) || entry.starts_with(&store_dir) {
                         continue;
                     }
 
@@ -276,13 +831,18 @@ fn collect_files(
                         continue;
                     }
 
+                    if !mounts.allows(root, &entry) {
+                        continue;
+                    }
+
                     // Use the actual path for deduplication to preserve symlinks
                     // Don't use canonicalize() as it resolves symlinks to their target
                     if seen_paths.insert(entry.clone()) {
                         // if verbose {
                         //     eprintln!("  Found: {}", relative_path);
                         // }
-                        files.push((relative_path, entry));
+                        emit(relative_path, entry);
+                        file_count += 1;
                     }
                 }
             }
@@ -292,7 +852,110 @@ fn collect_files(
         }
     }
 
-    Ok(files)
+    if hidden_skipped > 0 {
+        eprintln!(
+            "Skipped {} hidden entr{} in tracked directories and files patterns",
+            hidden_skipped,
+            if hidden_skipped == 1 { "y" } else { "ies" }
+        );
+    }
+
+    if !loops.is_empty() {
+        eprintln!("Warning: Skipped files reached through {} symlink loop(s)", loops.len());
+        if verbose {
+            for link in &loops {
+                eprintln!("  Symlink loop: {}", crate::fs_utils::relative_to(root, link).display());
+            }
+        }
+    }
+
+    Ok(file_count)
+}
+
+/// Train a compression dictionary on a sample of the snapshot's small files so new blobs are
+/// compressed with it. Too few samples, or a failed training, leave compression unchanged.
+fn train_dictionary(store: &mut Store, scan_results: &[ScanResult], verbose: bool) -> Result<()> {
+    let mut samples = Vec::new();
+    let mut sample_bytes = 0u64;
+
+    for result in scan_results {
+        let size = result.entry.size;
+        if result.entry.is_symlink || size == 0 || size > DICT_SAMPLE_MAX_FILE_SIZE {
+            continue;
+        }
+        if sample_bytes + size > DICT_SAMPLE_MAX_TOTAL {
+            break;
+        }
+        if let Ok(data) = fs::read(&result.absolute_path) {
+            sample_bytes += data.len() as u64;
+            samples.push(data);
+        }
+    }
+
+    if samples.len() < DICT_MIN_SAMPLES {
+        if verbose {
+            eprintln!("Not training a compression dictionary: only {} small file(s)", samples.len());
+        }
+        return Ok(());
+    }
+
+    match store.train_dictionary(&samples) {
+        Ok(id) => {
+            eprintln!("Trained compression dictionary from {} small files", samples.len());
+            if verbose {
+                eprintln!("  Dictionary: {:08x}", id);
+            }
+        }
+        Err(e) => eprintln!("Warning: {:#}", e),
+    }
+
+    Ok(())
+}
+
+/// Directories that hold at least one of the files, directly or further down
+fn directories_holding<'a>(file_paths: impl Iterator<Item = &'a str>) -> HashSet<&'a Path> {
+    let mut non_empty: HashSet<&Path> = HashSet::new();
+    for file_path in file_paths {
+        non_empty.extend(Path::new(file_path).ancestors().skip(1));
+    }
+    non_empty
+}
+
+/// Drop directory entries that have no files below them
+fn retain_directories_with_files<'a>(
+    directories: Vec<(String, DirectoryEntry)>,
+    file_paths: impl Iterator<Item = &'a str>,
+) -> Vec<(String, DirectoryEntry)> {
+    let non_empty = directories_holding(file_paths);
+
+    directories
+        .into_iter()
+        .filter(|(relative_path, _)| non_empty.contains(Path::new(relative_path)))
+        .collect()
+}
+
+/// Drop directories that load can recreate from the file paths alone: those holding files
+/// whose permissions are `IMPLIED_DIRECTORY_MODE`
+fn omit_implied_directories<'a>(
+    directories: Vec<(String, DirectoryEntry)>,
+    file_paths: impl Iterator<Item = &'a str>,
+) -> Vec<(String, DirectoryEntry)> {
+    let non_empty = directories_holding(file_paths);
+
+    directories
+        .into_iter()
+        .filter(|(relative_path, entry)| !(non_empty.contains(Path::new(relative_path)) && has_implied_mode(entry)))
+        .collect()
+}
+
+#[cfg(unix)]
+fn has_implied_mode(entry: &DirectoryEntry) -> bool {
+    entry.mode & 0o7777 == crate::manifest::IMPLIED_DIRECTORY_MODE
+}
+
+#[cfg(not(unix))]
+fn has_implied_mode(_entry: &DirectoryEntry) -> bool {
+    true
 }
 
 /// Collect all directories from tracked directory patterns
@@ -304,13 +967,20 @@ fn collect_directories(
     let mut directories = Vec::new();
     let mut seen_paths: HashSet<PathBuf> = HashSet::new();
     let mut found_dir_roots = Vec::new();
+    // Mount points are reported while walking files
+    let mut mounts = MountFilter::new(root, config, false);
+    let store_dir = get_store_dir(root);
 
     for tracked_dir_name in &config.directories {
+        if Path::new(tracked_dir_name).is_absolute() {
+            continue;
+        }
+
         for entry in WalkDir::new(root)
             .follow_links(false)
             .into_iter()
             .filter_entry(|e| {
-                if e.file_name() == ".kibo" {
+                if e.file_name() == ".kibo" || e.path() == store_dir {
                     return false;
                 }
 
@@ -319,7 +989,7 @@ fn collect_directories(
                         return false;
                     }
                 }
-                true
+                mounts.allows(root, e.path())
             })
             .filter_map(|e| e.ok())
         {
@@ -339,10 +1009,21 @@ fn collect_directories(
         }
     }
 
+    found_dir_roots.extend(
+        config.outside_directories()
+            .filter(|dir| dir.is_dir())
+            .map(Path::to_path_buf)
+    );
+
     for dir_path in found_dir_roots {
         for entry in WalkDir::new(&dir_path)
             .follow_links(false)
             .into_iter()
+            .filter_entry(|e| {
+                e.path() != store_dir
+                    && !(config.exclude_hidden && crate::fs_utils::is_hidden_entry(e))
+                    && mounts.allows(root, e.path())
+            })
             .filter_map(|e| e.ok())
         {
             let path = entry.path();
@@ -351,9 +1032,7 @@ fn collect_directories(
                 continue;
             }
 
-            let relative_path = path
-                .strip_prefix(root)
-                .with_context(|| format!("Failed to get relative path: {}", path.display()))?
+            let relative_path = crate::fs_utils::relative_to(root, path)
                 .to_string_lossy()
                 .to_string();
 
@@ -371,7 +1050,7 @@ fn collect_directories(
                     use std::time::UNIX_EPOCH;
                     let duration = mtime.duration_since(UNIX_EPOCH)
                         .unwrap_or_else(|_| std::time::Duration::from_secs(0));
-                    (duration.as_secs() as i64, duration.subsec_nanos())
+                    (duration.as_secs() as i64, config.mtime_precision.nanos(duration.subsec_nanos()))
                 };
                 
                 #[cfg(unix)]
@@ -399,50 +1078,137 @@ fn collect_directories(
     Ok(directories)
 }
 
+/// Rewrite a stored symlink's target as `mode` asks, and rehash the entry to match. Only links
+/// inside the workspace are changed, and `relative` only rewrites targets inside it.
+fn normalize_symlink_target(entry: &mut FileEntry, relative_path: &str, root: &Path, mode: SymlinkTargetMode) {
+    let Some(ref target) = entry.symlink_target else { return };
+    let link = Path::new(relative_path);
+    if !link.components().all(|c| matches!(c, std::path::Component::Normal(_))) {
+        return;
+    }
+    let link_dir = link.parent().unwrap_or(Path::new(""));
+    let target = Path::new(target);
+
+    let normalized = match mode {
+        SymlinkTargetMode::Relative if target.is_absolute() => {
+            let canonical_root = fs::canonicalize(root).ok();
+            let inside = target
+                .strip_prefix(root)
+                .ok()
+                .or_else(|| canonical_root.as_deref().and_then(|root| target.strip_prefix(root).ok()))
+                .and_then(lexically_normalize);
+            let Some(inside) = inside else { return };
+
+            let mut relative: PathBuf = link_dir.components().map(|_| "..").collect();
+            relative.push(inside);
+            if relative.as_os_str().is_empty() {
+                relative.push(".");
+            }
+            relative
+        },
+        SymlinkTargetMode::Absolute if target.is_relative() => {
+            let Some(resolved) = lexically_normalize(&link_dir.join(target)) else { return };
+            root.join(resolved)
+        },
+        _ => return,
+    };
+
+    let normalized = normalized.to_string_lossy().to_string();
+    entry.hash = file_hash::hash_symlink_target(&normalized);
+    entry.symlink_target = Some(normalized);
+}
+
+/// Resolve `.` and `..` in a relative path without touching the filesystem, or None if it
+/// climbs above its start
+fn lexically_normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::Normal(part) => normalized.push(part),
+            std::path::Component::CurDir => {},
+            std::path::Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            },
+            _ => return None,
+        }
+    }
+    Some(normalized)
+}
+
 /// Process a single file: compute hash and build entry
 fn process_file(
     absolute_path: &Path,
     relative_path: &str,
+    assume_unchanged: bool,
+    symlink_policy: SymlinkPolicy,
+    mtime_precision: MtimePrecision,
     hash_cache: Arc<Mutex<HashCache>>,
 ) -> Result<ScanResult> {
-    let metadata = fs::symlink_metadata(absolute_path)
+    let mut metadata = fs::symlink_metadata(absolute_path)
         .with_context(|| format!("Failed to get metadata: {}", absolute_path.display()))?;
 
-    let is_symlink = metadata.file_type().is_symlink();
+    let mut is_symlink = metadata.file_type().is_symlink();
+
+    // When following, a link to a regular file is captured as that file. Broken links and
+    // links to directories have no file contents to follow, so the link itself is kept.
+    if is_symlink
+        && symlink_policy == SymlinkPolicy::Follow
+        && let Ok(target) = fs::metadata(absolute_path)
+        && target.is_file()
+    {
+        metadata = target;
+        is_symlink = false;
+    }
 
     let mtime = metadata.modified()?;
     let (mtime_secs, mtime_nanos) = {
         use std::time::UNIX_EPOCH;
         let duration = mtime.duration_since(UNIX_EPOCH)
             .unwrap_or_else(|_| std::time::Duration::from_secs(0));
-        (duration.as_secs() as i64, duration.subsec_nanos())
+        (duration.as_secs() as i64, mtime_precision.nanos(duration.subsec_nanos()))
     };
 
-    let (hash, size, symlink_target) = if is_symlink {
+    let assumed = assume_unchanged && !is_symlink;
+
+    let (hash, size, symlink_target, is_text) = if is_symlink {
         let target = fs::read_link(absolute_path)?;
         let hash = file_hash::hash_symlink(absolute_path)?;
-        (hash, 0, Some(target.to_string_lossy().to_string()))
+        (hash, 0, Some(target.to_string_lossy().to_string()), false)
+    }
+    else if assumed {
+        // Skip reading the content; the blob is only copied the first time this hash is seen
+        let size = metadata.len();
+        (file_hash::hash_assumed(relative_path, size, mtime_secs, mtime_nanos), size, None, false)
     }
     else {
         let size = metadata.len();
 
-        let hash = {
+        let cached = {
             let cache = hash_cache.lock().expect("Hash cache mutex poisoned");
-            
-            if let Some(cached_hash) = cache.get(absolute_path, size, mtime) {
-                cached_hash
-            }
-            else {
-                drop(cache);
-                let hash = file_hash::hash_file(absolute_path)?;
-                
+            cache.get(absolute_path, size, mtime).map(|hash| (hash, cache.get_text(absolute_path, size, mtime)))
+        };
+
+        let (hash, is_text) = match cached {
+            Some((hash, Some(is_text))) => (hash, is_text),
+            Some((hash, None)) => {
+                // Cached before text detection; the flag only helps diff, so a failed read is not fatal
+                let is_text = file_hash::file_is_text(absolute_path).unwrap_or(false);
+                hash_cache.lock().expect("Hash cache mutex poisoned").set_text(absolute_path, is_text);
+                (hash, is_text)
+            },
+            None => {
+                let (hash, is_text) = file_hash::hash_file_and_probe(absolute_path)?;
+
                 let mut cache = hash_cache.lock().expect("Hash cache mutex poisoned");
                 cache.insert(absolute_path, size, mtime, hash.clone());
-                hash
-            }
+                cache.set_text(absolute_path, is_text);
+                (hash, is_text)
+            },
         };
 
-        (hash, size, None)
+        (hash, size, None, is_text)
     };
 
     #[cfg(unix)]
@@ -460,6 +1226,8 @@ fn process_file(
         symlink_target,
         mtime_secs,
         mtime_nanos,
+        assumed,
+        is_text,
     };
 
     Ok(ScanResult {
@@ -477,6 +1245,124 @@ mod tests {
     use tempfile::TempDir;
     use std::sync::{Arc, Mutex};
 
+    fn collect_files(root: &Path, config: &Config, verbose: bool) -> Result<Vec<(String, PathBuf)>> {
+        let mut files = Vec::new();
+        walk_files(root, config, verbose, |relative_path, absolute_path| {
+            files.push((relative_path, absolute_path));
+        })?;
+        Ok(files)
+    }
+
+    #[test]
+    fn test_plan_snapshot() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build/obj")).unwrap();
+        fs::create_dir_all(root.join("build/empty")).unwrap();
+        File::create(root.join("build/app.bin")).unwrap().write_all(b"binary").unwrap();
+        File::create(root.join("build/obj/a.o")).unwrap().write_all(b"obj").unwrap();
+
+        let config = Config {
+            directories: vec!["build".to_string()],
+            include_empty_dirs: false,
+            ..Default::default()
+        };
+
+        let plan = plan_snapshot(&root, "snap", &config, false).unwrap();
+
+        assert_eq!(plan.would_create, "snap");
+        assert_eq!(plan.files, vec![
+            PlannedFile { path: "build/app.bin".to_string(), size: 6 },
+            PlannedFile { path: "build/obj/a.o".to_string(), size: 3 },
+        ]);
+        assert_eq!(plan.total_size, 9);
+        assert_eq!(plan.directories, 2, "build and build/obj, but not the empty directory");
+        assert!(!root.join(".kibo").exists(), "Planning must not touch the store");
+    }
+
+    #[test]
+    fn test_blob_counts_separate_duplicates_from_reuse() {
+        let mut counts = BlobCounts::default();
+        counts.record("a", 10, true);
+        counts.record("b", 20, false);
+        counts.record("a", 10, false);
+        counts.record("b", 20, false);
+        counts.record("c", 5, true);
+
+        assert_eq!(counts.new, 2);
+        assert_eq!(counts.reused, 1);
+        assert_eq!(counts.duplicates, 2);
+        assert_eq!(counts.duplicate_bytes, 30);
+    }
+
+    #[test]
+    fn test_capture_environment() {
+        let names = ["CC".to_string(), "CFLAGS".to_string(), "TARGET".to_string()];
+        let lookup = |name: &str| match name {
+            "CC" => Some("clang".to_string()),
+            "CFLAGS" => Some(String::new()),
+            _ => None,
+        };
+
+        let environment = capture_environment(&names, lookup);
+        assert_eq!(environment.len(), 2);
+        assert_eq!(environment["CC"], "clang");
+        assert_eq!(environment["CFLAGS"], "", "Variables set to an empty value are kept");
+        assert!(!environment.contains_key("TARGET"));
+    }
+
+    #[test]
+    fn test_git_revision() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .args(["-c", "user.name=kibo", "-c", "user.email=kibo@example.com", "-C"])
+                .arg(root)
+                .args(args)
+                .output()
+                .is_ok_and(|output| output.status.success())
+        };
+        if !git(&["init", "-q"]) {
+            return; // git is not installed
+        }
+        // A repository without commits has no revision
+        assert_eq!(git_revision(root), None);
+
+        fs::write(root.join("tracked.txt"), "v1").unwrap();
+        assert!(git(&["add", "tracked.txt"]));
+        assert!(git(&["commit", "-q", "-m", "initial"]));
+        let (revision, dirty) = git_revision(root).unwrap();
+        assert_eq!(revision.len(), 40);
+        assert!(!dirty);
+
+        // Untracked build outputs do not make the tree dirty, modified tracked files do
+        fs::write(root.join("output.o"), "object").unwrap();
+        assert_eq!(git_revision(root), Some((revision.clone(), false)));
+        fs::write(root.join("tracked.txt"), "v2").unwrap();
+        assert_eq!(git_revision(root), Some((revision, true)));
+    }
+
+    #[test]
+    fn test_check_free_space() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let store = Store::new(root);
+        store.init().unwrap();
+
+        let hash_cache = Arc::new(Mutex::new(HashCache::new()));
+        let mut results = Vec::new();
+        for (name, content) in [("stored.o", "stored"), ("new.o", "new content"), ("copy.o", "new content")] {
+            fs::write(root.join(name), content).unwrap();
+            results.push(process_file(&root.join(name), name, false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache.clone()).unwrap());
+        }
+        store.store_file(&results[0].absolute_path, &results[0].entry.hash).unwrap();
+
+        // Only the one blob not yet in the store counts, once
+        assert_eq!(new_blob_bytes(&store, &results), 11);
+        assert!(check_free_space(&store, &results, true).is_ok());
+    }
+
     #[test]
     fn test_process_file_regular_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -484,7 +1370,7 @@ mod tests {
         File::create(&file_path).unwrap().write_all(b"Hello").unwrap();
         
         let hash_cache = Arc::new(Mutex::new(HashCache::new()));
-        let result = process_file(&file_path, "test.txt", hash_cache).unwrap();
+        let result = process_file(&file_path, "test.txt", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache).unwrap();
         
         assert_eq!(result.relative_path, "test.txt");
         assert_eq!(result.entry.size, 5);
@@ -493,6 +1379,42 @@ mod tests {
         assert!(!result.entry.hash.is_empty());
     }
 
+    #[test]
+    fn test_process_file_assume_unchanged() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("big.iso");
+        File::create(&file_path).unwrap().write_all(b"Hello").unwrap();
+        
+        let hash_cache = Arc::new(Mutex::new(HashCache::new()));
+        let result = process_file(&file_path, "big.iso", true, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache.clone()).unwrap();
+        
+        assert!(result.entry.assumed);
+        assert_eq!(result.entry.size, 5);
+        assert_eq!(
+            result.entry.hash,
+            file_hash::hash_assumed("big.iso", 5, result.entry.mtime_secs, result.entry.mtime_nanos)
+        );
+        assert!(hash_cache.lock().unwrap().get(&file_path, 5, fs::metadata(&file_path).unwrap().modified().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_process_file_second_mtime_precision() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("lib.a");
+        File::create(&file_path).unwrap().write_all(b"Hello").unwrap();
+        let mtime = filetime::FileTime::from_unix_time(1_700_000_000, 123_456_789);
+        filetime::set_file_mtime(&file_path, mtime).unwrap();
+
+        let hash_cache = Arc::new(Mutex::new(HashCache::new()));
+        let full = process_file(&file_path, "lib.a", true, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache.clone()).unwrap();
+        let second = process_file(&file_path, "lib.a", true, SymlinkPolicy::Store, MtimePrecision::Second, hash_cache).unwrap();
+
+        assert_eq!(full.entry.mtime_nanos, 123_456_789);
+        assert_eq!(second.entry.mtime_secs, 1_700_000_000);
+        assert_eq!(second.entry.mtime_nanos, 0);
+        assert_eq!(second.entry.hash, file_hash::hash_assumed("lib.a", 5, 1_700_000_000, 0));
+    }
+
     #[test]
     fn test_process_file_empty_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -500,12 +1422,42 @@ mod tests {
         File::create(&file_path).unwrap();
         
         let hash_cache = Arc::new(Mutex::new(HashCache::new()));
-        let result = process_file(&file_path, "empty.txt", hash_cache).unwrap();
+        let result = process_file(&file_path, "empty.txt", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache).unwrap();
         
         assert_eq!(result.entry.size, 0);
         assert!(!result.entry.is_symlink);
     }
 
+    #[test]
+    fn test_normalize_symlink_target() {
+        let root = Path::new("/work/repo");
+        let link = |target: &str| FileEntry {
+            hash: file_hash::hash_symlink_target(target),
+            size: 0,
+            #[cfg(unix)]
+            mode: 0o777,
+            is_symlink: true,
+            symlink_target: Some(target.to_string()),
+            mtime_secs: 0,
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
+        };
+        let normalize = |target: &str, mode: SymlinkTargetMode| {
+            let mut entry = link(target);
+            normalize_symlink_target(&mut entry, "build/out/app", root, mode);
+            assert_eq!(entry.hash, file_hash::hash_symlink_target(entry.symlink_target.as_deref().unwrap()));
+            entry.symlink_target.unwrap()
+        };
+
+        assert_eq!(normalize("/work/repo/lib/app.so", SymlinkTargetMode::Relative), "../../lib/app.so");
+        assert_eq!(normalize("/usr/lib/app.so", SymlinkTargetMode::Relative), "/usr/lib/app.so");
+        assert_eq!(normalize("app.so", SymlinkTargetMode::Relative), "app.so");
+        assert_eq!(normalize("../lib/./app.so", SymlinkTargetMode::Absolute), "/work/repo/build/lib/app.so");
+        assert_eq!(normalize("../../../../etc", SymlinkTargetMode::Absolute), "../../../../etc");
+        assert_eq!(normalize("/work/repo/lib", SymlinkTargetMode::Preserve), "/work/repo/lib");
+    }
+
     #[test]
     #[cfg(unix)]
     fn test_process_file_symlink() {
@@ -517,13 +1469,276 @@ mod tests {
         std::os::unix::fs::symlink(&target_path, &link_path).unwrap();
         
         let hash_cache = Arc::new(Mutex::new(HashCache::new()));
-        let result = process_file(&link_path, "link.txt", hash_cache).unwrap();
+        let result = process_file(&link_path, "link.txt", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache).unwrap();
         
         assert!(result.entry.is_symlink);
         assert_eq!(result.entry.size, 0);
         assert!(result.entry.symlink_target.is_some());
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_process_file_symlink_follow() {
+        let temp_dir = TempDir::new().unwrap();
+        let target_path = temp_dir.path().join("target.txt");
+        let link_path = temp_dir.path().join("link.txt");
+        let dir_link_path = temp_dir.path().join("dir_link");
+        
+        File::create(&target_path).unwrap().write_all(b"Target").unwrap();
+        std::os::unix::fs::symlink(&target_path, &link_path).unwrap();
+        std::os::unix::fs::symlink(temp_dir.path(), &dir_link_path).unwrap();
+        
+        let hash_cache = Arc::new(Mutex::new(HashCache::new()));
+        let result = process_file(&link_path, "link.txt", false, SymlinkPolicy::Follow, MtimePrecision::Full, hash_cache.clone()).unwrap();
+        
+        assert!(!result.entry.is_symlink);
+        assert_eq!(result.entry.size, 6);
+        assert_eq!(result.entry.hash, file_hash::hash_file(&target_path).unwrap());
+
+        // Directory links have no contents to follow and stay links
+        let result = process_file(&dir_link_path, "dir_link", false, SymlinkPolicy::Follow, MtimePrecision::Full, hash_cache).unwrap();
+        assert!(result.entry.is_symlink);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_create_snapshot_skip_symlinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build")).unwrap();
+        File::create(root.join("build/real.o")).unwrap().write_all(b"object").unwrap();
+        std::os::unix::fs::symlink("real.o", root.join("build/alias.o")).unwrap();
+
+        let config = Config {
+            directories: vec!["build".to_string()],
+            symlink_policy: SymlinkPolicy::Skip,
+            ..Default::default()
+        };
+
+        let manifest = create_snapshot(&root, "snap", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        assert_eq!(manifest.file_count, 1);
+        assert!(manifest.files.contains_key("build/real.o"));
+        assert_eq!(manifest.symlink_policy, SymlinkPolicy::Skip);
+    }
+
+    #[test]
+    fn test_create_snapshot_include_newer_than() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build")).unwrap();
+        File::create(root.join("build/new.o")).unwrap().write_all(b"new").unwrap();
+        File::create(root.join("build/old.o")).unwrap().write_all(b"old").unwrap();
+        let two_days_ago = filetime::FileTime::from_unix_time(Utc::now().timestamp() - 2 * 86400, 0);
+        filetime::set_file_mtime(root.join("build/old.o"), two_days_ago).unwrap();
+
+        let config = Config {
+            directories: vec!["build".to_string()],
+            include_newer_than: Some("1d".to_string()),
+            ..Default::default()
+        };
+
+        let plan = plan_snapshot(&root, "snap", &config, false).unwrap();
+        let planned: Vec<&str> = plan.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(planned, vec!["build/new.o"]);
+
+        let manifest = create_snapshot(&root, "snap", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        assert_eq!(manifest.file_count, 1);
+        assert!(manifest.files.contains_key("build/new.o"));
+        assert!(manifest.modified_after.is_some_and(|cutoff| cutoff < manifest.created_at));
+    }
+
+    #[test]
+    fn test_create_snapshot_max_duration() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build")).unwrap();
+        File::create(root.join("build/app.bin")).unwrap().write_all(b"binary").unwrap();
+
+        let mut config = Config {
+            directories: vec!["build".to_string()],
+            max_duration_secs: Some(60),
+            ..Default::default()
+        };
+        assert!(create_snapshot(&root, "quick", &config, false, ProgressConfig::ForceDisable, None, None).is_ok());
+
+        config.max_duration_secs = Some(0);
+        let err = create_snapshot(&root, "slow", &config, false, ProgressConfig::ForceDisable, None, None).unwrap_err();
+        assert!(err.to_string().contains("maximum duration of 0s"), "{}", err);
+        assert!(!Deadline::new(None).exceeded());
+    }
+
+    #[test]
+    fn test_create_snapshot_timing() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build")).unwrap();
+        File::create(root.join("build/app.bin")).unwrap().write_all(b"binary").unwrap();
+
+        let config = Config {
+            directories: vec!["build".to_string()],
+            compression_level: 3,
+            ..Default::default()
+        };
+        let mut timing = SaveTiming::default();
+        create_snapshot(&root, "snap", &config, false, ProgressConfig::ForceDisable, None, Some(&mut timing)).unwrap();
+
+        assert!(timing.threads > 0);
+        assert!(timing.hashing > Duration::ZERO);
+        assert!(timing.compression > Duration::ZERO);
+        assert!(timing.compression <= timing.store);
+    }
+
+    #[test]
+    fn test_create_snapshot_records_compression() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build")).unwrap();
+        File::create(root.join("build/app.bin")).unwrap().write_all(b"binary").unwrap();
+
+        let mut config = Config {
+            directories: vec!["build".to_string()],
+            compression_level: 6,
+            ..Default::default()
+        };
+        let manifest = create_snapshot(&root, "compressed", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        assert_eq!(manifest.compression_level, Some(6));
+        assert_eq!(manifest.compression_algorithm.as_deref(), Some("zstd"));
+
+        config.compression_level = 0;
+        let manifest = create_snapshot(&root, "plain", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        assert_eq!(manifest.compression_level, Some(0));
+        assert_eq!(manifest.compression_algorithm.as_deref(), Some("none"));
+
+        config.hash_only = true;
+        let manifest = create_snapshot(&root, "light", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        assert_eq!(manifest.compression_level, None);
+    }
+
+    #[test]
+    fn test_create_snapshot_hash_only() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build")).unwrap();
+        File::create(root.join("build/app.bin")).unwrap().write_all(b"binary").unwrap();
+
+        let mut config = Config {
+            directories: vec!["build".to_string()],
+            hash_only: true,
+            ..Default::default()
+        };
+        let manifest = create_snapshot(&root, "light", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        let hash = &manifest.files["build/app.bin"].hash;
+        assert!(manifest.hash_only);
+        assert!(manifest.content_root.is_some());
+
+        let store = Store::new(&root);
+        assert!(!store.has_blob(hash));
+        assert_eq!(store.blob_count().unwrap(), 0);
+
+        config.hash_only = false;
+        let full = create_snapshot(&root, "full", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        assert!(!full.hash_only);
+        assert_eq!(full.content_root, manifest.content_root);
+        assert!(store.has_blob(hash));
+    }
+
+    #[test]
+    fn test_create_snapshot_exclude_empty_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build")).unwrap();
+        File::create(root.join("build/app.bin")).unwrap().write_all(b"binary").unwrap();
+        File::create(root.join("build/.stamp")).unwrap();
+
+        let mut config = Config {
+            directories: vec!["build".to_string()],
+            ..Default::default()
+        };
+
+        let manifest = create_snapshot(&root, "all", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        assert_eq!(manifest.file_count, 2);
+
+        config.exclude_empty_files = true;
+        let plan = plan_snapshot(&root, "trimmed", &config, false).unwrap();
+        assert_eq!(plan.files.len(), 1);
+
+        let manifest = create_snapshot(&root, "trimmed", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        assert_eq!(manifest.file_count, 1);
+        assert!(manifest.files.contains_key("build/app.bin"));
+        assert!(manifest.exclude_empty_files);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_create_snapshot_skips_symlink_loops() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub/app.cfg"), b"config").unwrap();
+        // Two links back up would branch at every level of a walk that followed them
+        std::os::unix::fs::symlink("..", root.join("sub/up")).unwrap();
+        std::os::unix::fs::symlink("..", root.join("sub/up2")).unwrap();
+
+        let config = Config {
+            files: vec!["*.cfg".to_string()],
+            ..Default::default()
+        };
+        let manifest = create_snapshot(&root, "snap", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        assert_eq!(manifest.files.keys().collect::<Vec<_>>(), vec!["sub/app.cfg"]);
+    }
+
+    #[test]
+    fn test_create_snapshot_outside_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        let outside = temp_dir.path().join("out");
+        fs::create_dir_all(root.join("build")).unwrap();
+        fs::create_dir_all(outside.join("obj")).unwrap();
+        File::create(root.join("build/app.bin")).unwrap().write_all(b"binary").unwrap();
+        File::create(outside.join("obj/a.o")).unwrap().write_all(b"obj").unwrap();
+
+        let config = Config {
+            directories: vec!["build".to_string(), outside.to_string_lossy().to_string()],
+            allow_outside_root: true,
+            ..Default::default()
+        };
+
+        let manifest = create_snapshot(&root, "snap", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+
+        let mut paths: Vec<&str> = manifest.files.keys().map(String::as_str).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["../out/obj/a.o", "build/app.bin"]);
+        assert!(manifest.directories.contains_key("../out/obj"));
+        assert_eq!(manifest.outside_base.as_deref(), Some(root.to_string_lossy().as_ref()));
+    }
+
+    #[test]
+    fn test_process_file_detects_text() {
+        let temp_dir = TempDir::new().unwrap();
+        let text_path = temp_dir.path().join("notes.txt");
+        File::create(&text_path).unwrap().write_all(b"plain text\n").unwrap();
+        let binary_path = temp_dir.path().join("app.bin");
+        File::create(&binary_path).unwrap().write_all(b"\x7fELF\x00\x00").unwrap();
+        
+        let hash_cache = Arc::new(Mutex::new(HashCache::new()));
+        
+        let text = process_file(&text_path, "notes.txt", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache.clone()).unwrap();
+        let binary = process_file(&binary_path, "app.bin", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache.clone()).unwrap();
+        let assumed = process_file(&text_path, "notes.txt", true, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache.clone()).unwrap();
+        
+        assert!(text.entry.is_text);
+        assert!(!binary.entry.is_text);
+        assert!(!assumed.entry.is_text, "Assumed-unchanged files are not read");
+
+        // A cache hit takes the flag from the cache instead of reading the file again
+        let mtime = fs::metadata(&text_path).unwrap().modified().unwrap();
+        File::create(&text_path).unwrap().write_all(b"plain\0text\n").unwrap();
+        fs::File::options().write(true).open(&text_path).unwrap().set_modified(mtime).unwrap();
+        let cached = process_file(&text_path, "notes.txt", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache).unwrap();
+        assert_eq!(cached.entry.hash, text.entry.hash);
+        assert!(cached.entry.is_text);
+    }
+
     #[test]
     fn test_process_file_uses_cache() {
         let temp_dir = TempDir::new().unwrap();
@@ -533,10 +1748,10 @@ mod tests {
         let hash_cache = Arc::new(Mutex::new(HashCache::new()));
         
         // First call - should compute hash
-        let result1 = process_file(&file_path, "cached.txt", hash_cache.clone()).unwrap();
+        let result1 = process_file(&file_path, "cached.txt", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache.clone()).unwrap();
         
         // Second call - should use cached hash
-        let result2 = process_file(&file_path, "cached.txt", hash_cache.clone()).unwrap();
+        let result2 = process_file(&file_path, "cached.txt", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache.clone()).unwrap();
         
         assert_eq!(result1.entry.hash, result2.entry.hash);
         
@@ -553,7 +1768,7 @@ mod tests {
         let file_path = temp_dir.path().join("nonexistent.txt");
         
         let hash_cache = Arc::new(Mutex::new(HashCache::new()));
-        let result = process_file(&file_path, "nonexistent.txt", hash_cache);
+        let result = process_file(&file_path, "nonexistent.txt", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache);
         
         assert!(result.is_err());
     }
@@ -571,7 +1786,7 @@ mod tests {
         std::fs::set_permissions(&file_path, perms).unwrap();
         
         let hash_cache = Arc::new(Mutex::new(HashCache::new()));
-        let result = process_file(&file_path, "perms.txt", hash_cache).unwrap();
+        let result = process_file(&file_path, "perms.txt", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache).unwrap();
         
         assert_eq!(result.entry.mode & 0o777, 0o755);
     }
@@ -701,6 +1916,219 @@ mod tests {
     // Note: collect_files searches the entire workspace tree for directories
     // whose NAME matches tracked directories. It requires complex integration testing.
 
+    #[test]
+    fn test_walk_files_deduplicates_across_directories_and_patterns() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build")).unwrap();
+        File::create(root.join("build/app.bin")).unwrap().write_all(b"binary").unwrap();
+        File::create(root.join("build/notes.txt")).unwrap().write_all(b"notes").unwrap();
+
+        let config = Config {
+            directories: vec!["build".to_string()],
+            files: vec!["*.txt".to_string()],
+            ..Default::default()
+        };
+
+        let mut emitted = Vec::new();
+        let count = walk_files(&root, &config, false, |relative_path, _| emitted.push(relative_path)).unwrap();
+
+        emitted.sort();
+        assert_eq!(count, 2);
+        assert_eq!(emitted, vec!["build/app.bin", "build/notes.txt"]);
+    }
+
+    #[test]
+    fn test_walk_files_never_captures_the_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build")).unwrap();
+        File::create(root.join("build/app.bin")).unwrap().write_all(b"binary").unwrap();
+
+        let store = Store::new(&root);
+        store.init().unwrap();
+        store.store_file(&root.join("build/app.bin"), "abcdef").unwrap();
+
+        // A tracked directory that contains the store is walked without the .kibo name filter
+        let config = Config {
+            directories: vec![root.to_string_lossy().to_string()],
+            files: vec!["**/*".to_string()],
+            allow_outside_root: true,
+            ..Default::default()
+        };
+
+        let files = collect_files(&root, &config, false).unwrap();
+        let paths: Vec<&str> = files.iter().map(|(rel_path, _)| rel_path.as_str()).collect();
+        assert!(paths.contains(&"build/app.bin"));
+        assert!(paths.iter().all(|rel_path| !Path::new(rel_path).starts_with(".kibo/store")), "{:?}", paths);
+
+        let directories = collect_directories(&root, &config, false).unwrap();
+        assert!(directories.iter().any(|(rel_path, _)| rel_path == "build"));
+        assert!(directories.iter().all(|(rel_path, _)| !Path::new(rel_path).starts_with(".kibo/store")));
+    }
+
+    #[test]
+    fn test_walk_files_one_file_system_keeps_same_device() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build/sub")).unwrap();
+        File::create(root.join("build/sub/app.bin")).unwrap().write_all(b"binary").unwrap();
+
+        let config = Config {
+            directories: vec!["build".to_string()],
+            one_file_system: true,
+            ..Default::default()
+        };
+
+        let count = walk_files(&root, &config, false, |_, _| {}).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(collect_directories(&root, &config, false).unwrap().len(), 2);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_walk_files_one_file_system_skips_other_device() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("conf")).unwrap();
+        File::create(root.join("conf/app.cfg")).unwrap().write_all(b"app").unwrap();
+
+        // /dev/shm is a tmpfs of its own; a pattern match reached through a symlink into it
+        // lies on another device
+        let Ok(other) = TempDir::new_in("/dev/shm") else {
+            return;
+        };
+        if crate::fs_utils::device_id(other.path()) == crate::fs_utils::device_id(&root) {
+            return;
+        }
+        File::create(other.path().join("mounted.cfg")).unwrap().write_all(b"mounted").unwrap();
+        std::os::unix::fs::symlink(other.path(), root.join("conf/mnt")).unwrap();
+
+        let mut config = Config {
+            files: vec!["conf/**/*.cfg".to_string()],
+            one_file_system: true,
+            ..Default::default()
+        };
+
+        let mut files = Vec::new();
+        walk_files(&root, &config, false, |relative_path, _| files.push(relative_path)).unwrap();
+        assert_eq!(files, vec!["conf/app.cfg"]);
+
+        config.one_file_system = false;
+        assert_eq!(walk_files(&root, &config, false, |_, _| {}).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_walk_files_exclude_hidden() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join(".build/.deps")).unwrap();
+        fs::create_dir_all(root.join(".build/obj")).unwrap();
+        File::create(root.join(".build/obj/app.o")).unwrap().write_all(b"obj").unwrap();
+        File::create(root.join(".build/.stamp")).unwrap().write_all(b"stamp").unwrap();
+        File::create(root.join(".build/.deps/app.d")).unwrap().write_all(b"deps").unwrap();
+
+        let mut config = Config {
+            directories: vec![".build".to_string()],
+            exclude_hidden: true,
+            ..Default::default()
+        };
+
+        let mut files = Vec::new();
+        walk_files(&root, &config, false, |relative_path, _| files.push(relative_path)).unwrap();
+        assert_eq!(files, vec![".build/obj/app.o"], "The tracked root is walked even though it is hidden");
+        let directories: Vec<String> = collect_directories(&root, &config, false).unwrap().into_iter().map(|(path, _)| path).collect();
+        assert!(!directories.contains(&".build/.deps".to_string()));
+        assert!(directories.contains(&".build/obj".to_string()));
+
+        config.exclude_hidden = false;
+        assert_eq!(walk_files(&root, &config, false, |_, _| {}).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_walk_files_exclude_hidden_applies_to_files_patterns() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("conf/.local")).unwrap();
+        File::create(root.join("conf/app.cfg")).unwrap().write_all(b"app").unwrap();
+        File::create(root.join("conf/.hidden.cfg")).unwrap().write_all(b"hidden").unwrap();
+        File::create(root.join("conf/.local/user.cfg")).unwrap().write_all(b"user").unwrap();
+
+        let mut config = Config {
+            files: vec!["**/*.cfg".to_string()],
+            exclude_hidden: true,
+            ..Default::default()
+        };
+
+        let mut files = Vec::new();
+        walk_files(&root, &config, false, |relative_path, _| files.push(relative_path)).unwrap();
+        assert_eq!(files, vec!["conf/app.cfg"]);
+
+        config.exclude_hidden = false;
+        assert_eq!(walk_files(&root, &config, false, |_, _| {}).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_retain_directories_with_files() {
+        let dir = |path: &str| (path.to_string(), DirectoryEntry {
+            #[cfg(unix)]
+            mode: 0o755,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+        });
+        let directories = vec![dir("build"), dir("build/obj"), dir("build/empty"), dir("build/empty/nested")];
+
+        let kept = retain_directories_with_files(directories, ["build/obj/a.o"].into_iter());
+
+        let kept: Vec<&str> = kept.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(kept, vec!["build", "build/obj"]);
+    }
+
+    #[test]
+    fn test_omit_implied_directories() {
+        let dir = |path: &str, _mode: u32| (path.to_string(), DirectoryEntry {
+            #[cfg(unix)]
+            mode: _mode,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+        });
+        let directories = vec![
+            dir("build", 0o040755),
+            dir("build/obj", 0o040755),
+            dir("build/private", 0o040700),
+            dir("build/empty", 0o040755),
+        ];
+
+        let kept = omit_implied_directories(directories, ["build/obj/a.o", "build/private/key"].into_iter());
+
+        let kept: Vec<&str> = kept.iter().map(|(path, _)| path.as_str()).collect();
+        #[cfg(unix)]
+        assert_eq!(kept, vec!["build/private", "build/empty"]);
+        #[cfg(not(unix))]
+        assert_eq!(kept, vec!["build/empty"]);
+    }
+
+    #[test]
+    fn test_create_snapshot_streams_more_files_than_queue_capacity() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("workspace");
+        fs::create_dir_all(root.join("build")).unwrap();
+
+        let total = SCAN_QUEUE_CAPACITY * 2 + 7;
+        for i in 0..total {
+            File::create(root.join(format!("build/f{}.o", i))).unwrap().write_all(i.to_string().as_bytes()).unwrap();
+        }
+
+        let config = Config {
+            directories: vec!["build".to_string()],
+            ..Default::default()
+        };
+
+        let manifest = create_snapshot(&root, "snap", &config, false, ProgressConfig::ForceDisable, None, None).unwrap();
+        assert_eq!(manifest.files.len(), total);
+        assert!(manifest.files.contains_key(&format!("build/f{}.o", total - 1)));
+    }
+
     #[test]
     fn test_collect_directories_empty_config() {
         let temp_dir = TempDir::new().unwrap();
@@ -773,8 +2201,8 @@ mod tests {
         
         let hash_cache = Arc::new(Mutex::new(HashCache::new()));
         
-        let target_result = process_file(&target, "target.txt", hash_cache.clone()).unwrap();
-        let link_result = process_file(&link, "link.txt", hash_cache).unwrap();
+        let target_result = process_file(&target, "target.txt", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache.clone()).unwrap();
+        let link_result = process_file(&link, "link.txt", false, SymlinkPolicy::Store, MtimePrecision::Full, hash_cache).unwrap();
         
         assert!(!target_result.entry.is_symlink);
         assert_eq!(target_result.entry.symlink_target, None);
@@ -1,14 +1,68 @@
 use anyhow::{Context, Result, bail};
+use rayon::prelude::*;
 use std::fs::{self, File};
+use std::collections::HashMap;
 use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::config::get_store_dir;
+use crate::config::{CompressionEffort, get_store_dir};
 use crate::fs_utils;
+use crate::pack::{PackStore, PACKS_DIR, read_entry};
 
 const BUFFER_SIZE: usize = 64 * 1024;
 const COMPRESSION_MAGIC: &[u8; 4] = b"KBCP"; // "KBCP" = KiBo ComPressed
+const DICT_COMPRESSION_MAGIC: &[u8; 4] = b"KBCD"; // "KBCD" = KiBo Compressed with Dictionary, followed by the dictionary ID
 const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+/// Target size of a trained dictionary, matching the zstd CLI default
+const DICTIONARY_SIZE: usize = 110 * 1024;
+
+/// Subdirectory of the store holding trained compression dictionaries
+pub const DICTS_DIR: &str = "dicts";
+
+/// Subdirectory of the store holding one independent store per named group
+pub const GROUPS_DIR: &str = "groups";
+
+/// Newest blob store format this binary reads and writes, recorded in every manifest.
+/// Bump it when blobs gain an encoding older versions would misread.
+pub const STORE_FORMAT_VERSION: u32 = 1;
+
+/// Outcome of rehashing a set of blobs
+#[derive(Debug, Default, PartialEq)]
+pub struct BlobCheck {
+    /// Number of blobs rehashed
+    pub checked: usize,
+    /// Blobs whose content no longer matches their hash, or that could not be read
+    pub corrupt: Vec<String>,
+    /// Blobs not present in the store at all
+    pub missing: Vec<String>,
+}
+
+/// Outcome of compressing blobs that were stored uncompressed
+#[derive(Debug, Default, PartialEq)]
+pub struct Recompressed {
+    /// Number of blobs rewritten compressed
+    pub blobs: usize,
+    /// Size of those blobs before they were compressed
+    pub bytes_before: u64,
+    /// Size of those blobs after they were compressed
+    pub bytes_after: u64,
+}
+
+/// Outcome of importing a directory of loose files into the store
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportedBlobs {
+    /// Number of regular files hashed
+    pub files: usize,
+    /// Number of distinct blobs that were not yet in the store
+    pub new_blobs: usize,
+    /// Number of distinct blobs the store already had
+    pub reused_blobs: usize,
+    /// Uncompressed size of the new blobs
+    pub new_bytes: u64,
+}
 
 /// Content-addressed store for file blobs
 pub struct Store {
@@ -16,6 +70,20 @@ pub struct Store {
     store_dir: PathBuf,
     /// Compression level (0 = no compression, 1-10 = zstd levels)
     compression_level: u32,
+    /// Blobs smaller than this many bytes are appended to pack files (None = packing disabled)
+    pack_threshold: Option<u64>,
+    /// Pack index, loaded on first use
+    packs: Mutex<Option<PackStore>>,
+    /// Window log for zstd long-distance matching (None = disabled)
+    long_distance_window: Option<u32>,
+    /// Dictionary new blobs are compressed with, as (zstd dictionary ID, dictionary)
+    dictionary: Option<(u32, Vec<u8>)>,
+    /// Dictionaries loaded for decompression, by ID
+    loaded_dictionaries: Mutex<HashMap<u32, Arc<Vec<u8>>>>,
+    /// Fsync each loose blob and its directory once written
+    durable_writes: bool,
+    /// Nanoseconds spent compressing, summed across threads (None = not measured)
+    compression_nanos: Option<AtomicU64>,
 }
 
 impl Store {
@@ -24,7 +92,67 @@ impl Store {
         Self {
             store_dir: get_store_dir(repo_root),
             compression_level: 0,
+            pack_threshold: None,
+            packs: Mutex::new(None),
+            long_distance_window: None,
+            dictionary: None,
+            loaded_dictionaries: Mutex::new(HashMap::new()),
+            durable_writes: false,
+            compression_nanos: None,
+        }
+    }
+
+    /// Open an existing store, or the store of a named group, for commands that need its blobs.
+    /// Fails upfront when the store was never created (nothing has been saved yet) or when
+    /// it does not look like a store, instead of on the first missing blob mid-operation.
+    pub fn open(repo_root: &Path, group: Option<&str>) -> Result<Self> {
+        let store = Self::new(repo_root).in_group(group);
+        let store_dir = &store.store_dir;
+
+        if !store_dir.exists() {
+            match group {
+                Some(group) => bail!(
+                    "Store group '{}' is not initialized ({} does not exist); save a snapshot with `kibo save --into {}` first",
+                    group,
+                    store_dir.display(),
+                    group
+                ),
+                None => bail!(
+                    "Store is not initialized ({} does not exist); save a snapshot with `kibo save` first",
+                    store_dir.display()
+                ),
+            }
+        }
+        if !store_dir.is_dir() {
+            bail!("Store is malformed: {} is not a directory; run `kibo doctor` to inspect it", store_dir.display());
+        }
+
+        let entries = fs::read_dir(store_dir)
+            .with_context(|| format!("Failed to read store directory: {}", store_dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            let expected = entry.file_type()?.is_dir() && (is_reserved_dir(&name) || is_prefix_dir(&name));
+            if !expected {
+                bail!(
+                    "Store is malformed: unexpected entry '{}' in {}; run `kibo doctor` to inspect it",
+                    name.to_string_lossy(),
+                    store_dir.display()
+                );
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Like `open`, but a store that was never created is treated as empty, for commands
+    /// such as prune that have nothing to do before the first save
+    pub fn open_or_empty(repo_root: &Path, group: Option<&str>) -> Result<Self> {
+        let store = Self::new(repo_root).in_group(group);
+        if !store.store_dir.exists() {
+            return Ok(store);
         }
+        Self::open(repo_root, group)
     }
 
     /// Create a new store instance with compression
@@ -32,9 +160,141 @@ impl Store {
         Self {
             store_dir: get_store_dir(repo_root),
             compression_level,
+            pack_threshold: None,
+            packs: Mutex::new(None),
+            long_distance_window: None,
+            dictionary: None,
+            loaded_dictionaries: Mutex::new(HashMap::new()),
+            durable_writes: false,
+            compression_nanos: None,
         }
     }
 
+    /// Compress new blobs at the level of a compression effort preset, with long-distance
+    /// matching if the preset enables it
+    pub fn with_compression_effort(mut self, effort: CompressionEffort) -> Self {
+        self.compression_level = effort.level();
+        self.long_distance_window = effort.long_distance_window();
+        self
+    }
+
+    /// Enable packing of blobs smaller than `threshold` bytes into shared pack files
+    pub fn with_pack_threshold(mut self, threshold: u64) -> Self {
+        self.pack_threshold = Some(threshold);
+        self
+    }
+
+    /// Fsync every loose blob and the directory it is renamed into, so a blob a manifest
+    /// references cannot be lost or truncated by a crash right after the save
+    pub fn with_durable_writes(mut self) -> Self {
+        self.durable_writes = true;
+        self
+    }
+
+    /// Measure the time spent compressing blobs, for `save --timing`
+    pub fn with_compression_timing(mut self) -> Self {
+        self.compression_nanos = Some(AtomicU64::new(0));
+        self
+    }
+
+    /// Time spent compressing blobs so far, if measured
+    pub fn compression_time(&self) -> Option<Duration> {
+        self.compression_nanos
+            .as_ref()
+            .map(|nanos| Duration::from_nanos(nanos.load(Ordering::Relaxed)))
+    }
+
+    /// Run a compression step, adding its duration to the measured compression time
+    fn timed_compression<T>(&self, compress: impl FnOnce() -> Result<T>) -> Result<T> {
+        let Some(ref nanos) = self.compression_nanos else {
+            return compress();
+        };
+        let started = Instant::now();
+        let result = compress();
+        nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        result
+    }
+
+    /// Use the store of a named group (`.kibo/store/groups/<group>`) instead of the default
+    /// one. Groups share no blobs, so collecting garbage in one leaves the others alone.
+    pub fn in_group(mut self, group: Option<&str>) -> Self {
+        if let Some(group) = group {
+            self.store_dir = self.store_dir.join(GROUPS_DIR).join(group);
+        }
+        self
+    }
+
+    /// Names of the store groups that exist on disk, sorted
+    pub fn group_names(repo_root: &Path) -> Result<Vec<String>> {
+        let groups_dir = get_store_dir(repo_root).join(GROUPS_DIR);
+        if !groups_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&groups_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Train a zstd dictionary from sample file contents, save it in the store and compress
+    /// blobs stored from now on with it. Has no effect when compression is disabled.
+    /// Returns the dictionary's ID, which zstd derives from its content.
+    pub fn train_dictionary(&mut self, samples: &[Vec<u8>]) -> Result<u32> {
+        let dictionary = zstd::dict::from_samples(samples, DICTIONARY_SIZE)
+            .context("Failed to train compression dictionary")?;
+        let Some(id) = zstd::zstd_safe::get_dict_id_from_dict(&dictionary) else {
+            bail!("Trained compression dictionary has no ID");
+        };
+        let id = id.get();
+
+        let dict_path = self.dictionary_path(id);
+        if !dict_path.exists() {
+            fs_utils::ensure_dir(&self.store_dir.join(DICTS_DIR))?;
+            fs_utils::atomic_write(&dict_path, &dictionary)
+                .with_context(|| format!("Failed to store compression dictionary: {:08x}", id))?;
+        }
+        else if fs::read(&dict_path)? != dictionary {
+            bail!("A different compression dictionary with ID {:08x} is already stored", id);
+        }
+
+        self.dictionary = Some((id, dictionary));
+        Ok(id)
+    }
+
+    /// Name of the compression new blobs are stored with: `none`, `zstd`, or `zstd-dict` when
+    /// a trained dictionary is used
+    pub fn compression_algorithm(&self) -> &'static str {
+        match (self.compression_level, &self.dictionary) {
+            (0, _) => "none",
+            (_, Some(_)) => "zstd-dict",
+            (_, None) => "zstd",
+        }
+    }
+
+    /// Path of the stored dictionary with the given ID
+    pub fn dictionary_path(&self, id: u32) -> PathBuf {
+        self.store_dir.join(DICTS_DIR).join(format!("{:08x}", id))
+    }
+
+    /// Load a dictionary referenced by a compressed blob, caching it for later blobs
+    fn load_dictionary(&self, id: u32) -> Result<Arc<Vec<u8>>> {
+        if let Some(dictionary) = self.loaded_dictionaries.lock().unwrap().get(&id) {
+            return Ok(dictionary.clone());
+        }
+
+        let dictionary = Arc::new(fs::read(self.dictionary_path(id))
+            .with_context(|| format!("Compression dictionary missing from store: {:08x}", id))?);
+
+        self.loaded_dictionaries.lock().unwrap().insert(id, dictionary.clone());
+        Ok(dictionary)
+    }
+
     /// Ensure the store directory exists
     pub fn init(&self) -> Result<()> {
         fs_utils::ensure_dir(&self.store_dir)
@@ -49,9 +309,57 @@ impl Store {
         self.store_dir.join(prefix).join(rest)
     }
 
+    /// Bytes free on the store's filesystem, or None where this cannot be determined
+    pub fn available_space(&self) -> Result<Option<u64>> {
+        fs_utils::available_space(&self.store_dir)
+    }
+
     /// Check if a blob exists in the store
+    /// The pack index is only consulted when the store has packs
     pub fn has_blob(&self, hash: &str) -> bool {
         self.blob_path(hash).exists()
+            || (self.store_dir.join(PACKS_DIR).is_dir()
+                && self.with_packs(|packs| Ok(packs.contains(hash))).unwrap_or(false))
+    }
+
+    /// Size a blob takes up on disk (compressed, if it is), or None if it is not stored
+    pub fn stored_size(&self, hash: &str) -> Result<Option<u64>> {
+        let blob_path = self.blob_path(hash);
+        match fs::symlink_metadata(&blob_path) {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.with_packs(|packs| Ok(packs.get(hash).map(|entry| entry.length)))
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to read metadata: {}", blob_path.display())),
+        }
+    }
+
+    /// Run a closure against the pack index, loading it on first use
+    fn with_packs<T>(&self, f: impl FnOnce(&mut PackStore) -> Result<T>) -> Result<T> {
+        let mut guard = self.packs.lock().unwrap();
+        if guard.is_none() {
+            *guard = Some(PackStore::load(&self.store_dir)?);
+        }
+        f(guard.as_mut().expect("pack index was just loaded"))
+    }
+
+    /// Read the raw (possibly compressed) bytes of a packed blob, if the blob is packed
+    fn read_packed_blob(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let Some(entry) = self.with_packs(|packs| Ok(packs.get(hash)))? else {
+            return Ok(None);
+        };
+        // Read outside the lock so parallel loads don't serialize on the index
+        Ok(Some(read_entry(&self.store_dir.join(PACKS_DIR), &entry)?))
+    }
+
+    /// Write any pending pack index changes to disk
+    /// Must be called after storing blobs when packing is enabled
+    pub fn flush(&self) -> Result<()> {
+        let mut guard = self.packs.lock().unwrap();
+        match guard.as_mut() {
+            Some(packs) => packs.save(),
+            None => Ok(()),
+        }
     }
 
     /// Store a file in the content addressed store
@@ -59,69 +367,145 @@ impl Store {
     pub fn store_file(&self, src_path: &Path, hash: &str) -> Result<bool> {
         let blob_path = self.blob_path(hash);
 
-        if blob_path.exists() {
+        if self.has_blob(hash) {
             return Ok(false);
         }
 
+        if let Some(threshold) = self.pack_threshold {
+            let size = fs::metadata(src_path)
+                .with_context(|| format!("Failed to read metadata: {}", src_path.display()))?
+                .len();
+
+            if size < threshold {
+                let data = fs::read(src_path)
+                    .with_context(|| format!("Failed to read file: {}", src_path.display()))?;
+                let data = if self.compression_level > 0 {
+                    self.compress_bytes(&data)?
+                }
+                else {
+                    data
+                };
+                self.with_packs(|packs| packs.append(hash, &data))?;
+                return Ok(true);
+            }
+        }
+
+        let new_prefix = blob_path.parent().is_some_and(|parent| !parent.exists());
         if let Some(parent) = blob_path.parent() {
             fs::create_dir_all(parent)
                 .with_context(|| format!("Failed to create store subdirectory: {}", parent.display()))?;
         }
 
         let temp_path = blob_path.with_extension("tmp");
-        
+        if let Err(e) = self.write_blob_file(src_path, &temp_path, &blob_path, hash) {
+            let _ = fs::remove_file(&temp_path);
+            // Don't leave the prefix directory created for this blob behind empty
+            if new_prefix {
+                let _ = self.prune_empty_dirs();
+            }
+            return Err(e);
+        }
+        self.sync_blob_dir(&blob_path, new_prefix)?;
+
+        Ok(true)
+    }
+
+    /// Copy or compress a file into `temp_path`, then rename it into place as `blob_path`
+    fn write_blob_file(&self, src_path: &Path, temp_path: &Path, blob_path: &Path, hash: &str) -> Result<()> {
         if self.compression_level > 0 {
-            self.compress_file_to_blob(src_path, &temp_path)?;
+            self.compress_file_to_blob(src_path, temp_path)?;
         }
         else {
-            copy_file(src_path, &temp_path)
+            copy_file(src_path, temp_path)
                 .with_context(|| format!("Failed to copy file to store: {}", src_path.display()))?;
         }
+        if self.durable_writes {
+            fs_utils::sync_file(temp_path)?;
+        }
 
-        fs::rename(&temp_path, &blob_path).with_context(|| {
-            let _ = fs::remove_file(&temp_path);
-            format!("Failed to finalize blob in store: {}", hash)
-        })?;
+        fs::rename(temp_path, blob_path)
+            .with_context(|| format!("Failed to finalize blob in store: {}", hash))?;
 
         #[cfg(unix)]
         {
             use std::os::unix::fs::PermissionsExt;
             let permissions = fs::Permissions::from_mode(0o444);
-            let _ = fs::set_permissions(&blob_path, permissions);
+            let _ = fs::set_permissions(blob_path, permissions);
         }
-
-        Ok(true)
+        Ok(())
     }
 
     /// Store symlink target in the content addressed store
     pub fn store_symlink(&self, target: &Path, hash: &str) -> Result<bool> {
         let blob_path = self.blob_path(hash);
 
-        if blob_path.exists() {
+        if self.has_blob(hash) {
             return Ok(false);
         }
 
+        let target_str = target.to_string_lossy();
+
+        if self.pack_threshold.is_some() {
+            self.with_packs(|packs| packs.append(hash, target_str.as_bytes()))?;
+            return Ok(true);
+        }
+
+        let new_prefix = blob_path.parent().is_some_and(|parent| !parent.exists());
         if let Some(parent) = blob_path.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        let target_str = target.to_string_lossy();
-        fs_utils::atomic_write(&blob_path, target_str.as_bytes())
-            .with_context(|| format!("Failed to store symlink target: {}", hash))?;
+        if let Err(e) = fs_utils::atomic_write(&blob_path, target_str.as_bytes()) {
+            if new_prefix {
+                let _ = self.prune_empty_dirs();
+            }
+            return Err(e).with_context(|| format!("Failed to store symlink target: {}", hash));
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&blob_path, fs::Permissions::from_mode(0o444));
+        }
+        self.sync_blob_dir(&blob_path, new_prefix)?;
 
         Ok(true)
     }
 
+    /// With durable writes, fsync the prefix directory a blob was renamed into, and the
+    /// store directory too when the prefix directory was created for it
+    fn sync_blob_dir(&self, blob_path: &Path, new_prefix: bool) -> Result<()> {
+        if !self.durable_writes {
+            return Ok(());
+        }
+        if let Some(parent) = blob_path.parent() {
+            fs_utils::sync_dir(parent)?;
+        }
+        if new_prefix {
+            fs_utils::sync_dir(&self.store_dir)?;
+        }
+        Ok(())
+    }
+
     /// Retrieve a symlink target from the store
     pub fn retrieve_symlink_target(&self, hash: &str) -> Result<PathBuf> {
         let blob_path = self.blob_path(hash);
 
         if !blob_path.exists() {
+            if let Some(data) = self.read_packed_blob(hash)? {
+                let target_str = String::from_utf8(data)
+                    .with_context(|| format!("Invalid symlink target in store: {}", hash))?;
+                return Ok(PathBuf::from(target_str));
+            }
             bail!("Symlink blob not found in store: {}", hash);
         }
 
-        let target_str = fs::read_to_string(&blob_path)
+        // A file with the same content as the target may have stored the blob compressed
+        let mut target = Vec::new();
+        self.copy_blob_to_writer(hash, &mut target)
             .with_context(|| format!("Failed to read symlink target from store: {}", hash))?;
+        let target_str = String::from_utf8(target)
+            .with_context(|| format!("Invalid symlink target in store: {}", hash))?;
 
         Ok(PathBuf::from(target_str))
     }
@@ -148,88 +532,262 @@ impl Store {
         Ok(total)
     }
 
-    /// Get the number of blobs in the store
+    /// Get the number of blobs in the store. Blobs of store groups are not included.
     pub fn blob_count(&self) -> Result<usize> {
         if !self.store_dir.exists() {
             return Ok(0);
         }
 
-        let count = walkdir::WalkDir::new(&self.store_dir)
+        let skipped = [PACKS_DIR, DICTS_DIR, GROUPS_DIR].map(|dir| self.store_dir.join(dir));
+        let loose_count = walkdir::WalkDir::new(&self.store_dir)
             .into_iter()
+            .filter_entry(|e| !skipped.iter().any(|dir| e.path() == dir))
             .filter_map(|e| e.ok())
             .filter(|e| e.file_type().is_file())
             .count();
 
-        Ok(count)
+        let packed_count = self.with_packs(|packs| Ok(packs.len()))?;
+
+        Ok(loose_count + packed_count)
     }
 
-    /// Remove blobs not referenced by any manifest
-    /// Returns the number of blobs removed and bytes freed
-    pub fn garbage_collect(&self, referenced_hashes: &std::collections::HashSet<String>, show_progress: bool) -> Result<(usize, u64)> {
+    /// Size each blob takes up on disk, loose or packed. Blobs of store groups are not included.
+    pub fn blob_sizes(&self) -> Result<Vec<u64>> {
         if !self.store_dir.exists() {
-            return Ok((0, 0));
+            return Ok(Vec::new());
         }
 
-        let spinner = if show_progress {
-            Some(crate::progress::Spinner::new(
-                crate::progress::ProgressConfig::Auto,
-                "Garbage collecting unreferenced blobs"
-            ))
+        let skipped = [PACKS_DIR, DICTS_DIR, GROUPS_DIR].map(|dir| self.store_dir.join(dir));
+        let mut sizes = Vec::new();
+        for entry in walkdir::WalkDir::new(&self.store_dir)
+            .into_iter()
+            .filter_entry(|e| !skipped.iter().any(|dir| e.path() == dir))
+        {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                sizes.push(entry.metadata()?.len());
+            }
         }
-        else {
-            None
-        };
 
-        let mut removed_count = 0;
-        let mut freed_bytes = 0u64;
+        self.with_packs(|packs| {
+            sizes.extend(packs.lengths());
+            Ok(())
+        })?;
+        Ok(sizes)
+    }
 
+    /// Hashes of all blobs in the store, loose or packed. Blobs of store groups are not included.
+    pub fn blob_hashes(&self) -> Result<Vec<String>> {
+        if !self.store_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut hashes = Vec::new();
         for prefix_entry in fs::read_dir(&self.store_dir)? {
             let prefix_entry = prefix_entry?;
-            if !prefix_entry.file_type()?.is_dir() {
+            let prefix = prefix_entry.file_name();
+            if !prefix_entry.file_type()?.is_dir() || !is_prefix_dir(&prefix) {
                 continue;
             }
-
-            let prefix = prefix_entry.file_name().to_string_lossy().to_string();
-
             for blob_entry in fs::read_dir(prefix_entry.path())? {
                 let blob_entry = blob_entry?;
-                if !blob_entry.file_type()?.is_file() {
-                    continue;
+                if blob_entry.file_type()?.is_file() {
+                    hashes.push(format!("{}{}", prefix.to_string_lossy(), blob_entry.file_name().to_string_lossy()));
                 }
+            }
+        }
 
-                let blob_name = blob_entry.file_name().to_string_lossy().to_string();
-                let hash = format!("{}{}", prefix, blob_name);
-
-                if !referenced_hashes.contains(&hash) {
-                    let metadata = blob_entry.metadata()?;
-                    freed_bytes += metadata.len();
-                    
-                    #[cfg(unix)]
-                    {
-                        use std::os::unix::fs::PermissionsExt;
-                        let _ = fs::set_permissions(
-                            blob_entry.path(),
-                            fs::Permissions::from_mode(0o644),
-                        );
-                    }
-                    
-                    fs::remove_file(blob_entry.path())?;
-                    removed_count += 1;
-                }
+        self.with_packs(|packs| {
+            hashes.extend(packs.hashes().cloned());
+            Ok(())
+        })?;
+        Ok(hashes)
+    }
+
+    /// Remove blobs not referenced by any manifest. Prefix directories are scanned in
+    /// parallel on `concurrency` threads (None = one per CPU).
+    /// Returns the number of blobs removed and bytes freed
+    pub fn garbage_collect(
+        &self,
+        referenced_hashes: &std::collections::HashSet<String>,
+        show_progress: bool,
+        concurrency: Option<usize>,
+    ) -> Result<(usize, u64)> {
+        if !self.store_dir.exists() {
+            return Ok((0, 0));
+        }
+
+        let mut prefix_dirs = Vec::new();
+        for prefix_entry in fs::read_dir(&self.store_dir)? {
+            let prefix_entry = prefix_entry?;
+            if prefix_entry.file_type()?.is_dir() && !is_reserved_dir(&prefix_entry.file_name()) {
+                prefix_dirs.push(prefix_entry.path());
             }
+        }
+
+        let progress_config = if show_progress {
+            crate::progress::ProgressConfig::Auto
+        }
+        else {
+            crate::progress::ProgressConfig::ForceDisable
+        };
+        let progress = crate::progress::ItemProgress::new(prefix_dirs.len() as u64, progress_config, "prefixes scanned");
+
+        let mut pool = rayon::ThreadPoolBuilder::new();
+        if let Some(threads) = concurrency {
+            pool = pool.num_threads(threads);
+        }
+        let pool = pool.build().context("Failed to start garbage collection threads")?;
+
+        let (mut removed_count, mut freed_bytes) = pool.install(|| {
+            prefix_dirs
+                .par_iter()
+                .map(|prefix_dir| {
+                    let counts = Self::collect_prefix(prefix_dir, referenced_hashes);
+                    progress.inc(1);
+                    counts
+                })
+                .try_reduce(|| (0, 0), |a, b| Ok((a.0 + b.0, a.1 + b.1)))
+        })?;
+
+        progress.finish();
+
+        let (packed_removed, packed_freed) = self.with_packs(|packs| packs.repack(referenced_hashes))?;
+        removed_count += packed_removed;
+        freed_bytes += packed_freed;
+        freed_bytes += self.collect_dictionaries()?;
 
-            if fs::read_dir(prefix_entry.path())?.next().is_none() {
-                let _ = fs::remove_dir(prefix_entry.path());
+        self.prune_empty_dirs()?;
+
+        Ok((removed_count, freed_bytes))
+    }
+
+    /// Remove the trained dictionaries that no remaining blob was compressed with, since
+    /// each save with a dictionary trains a new one. Returns the bytes freed.
+    fn collect_dictionaries(&self) -> Result<u64> {
+        let dicts_dir = self.store_dir.join(DICTS_DIR);
+        if !dicts_dir.is_dir() {
+            return Ok(0);
+        }
+
+        let mut unused: HashMap<u32, PathBuf> = HashMap::new();
+        for entry in fs::read_dir(&dicts_dir)? {
+            let path = entry?.path();
+            if let Some(id) = path.file_name().and_then(|name| u32::from_str_radix(&name.to_string_lossy(), 16).ok()) {
+                unused.insert(id, path);
+            }
+        }
+        for hash in self.blob_hashes()? {
+            if unused.is_empty() {
+                break;
+            }
+            if let Some(id) = self.blob_dictionary_id(&hash)? {
+                unused.remove(&id);
             }
         }
 
-        if let Some(sp) = spinner {
-            sp.finish();
+        let mut freed_bytes = 0;
+        for path in unused.values() {
+            freed_bytes += fs::metadata(path)?.len();
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove unused compression dictionary: {}", path.display()))?;
+        }
+        Ok(freed_bytes)
+    }
+
+    /// ID of the dictionary a blob was compressed with, if any
+    fn blob_dictionary_id(&self, hash: &str) -> Result<Option<u32>> {
+        let blob_path = self.blob_path(hash);
+        let header = if blob_path.exists() {
+            let mut header = Vec::with_capacity(8);
+            File::open(&blob_path)?.take(8).read_to_end(&mut header)?;
+            header
+        }
+        else {
+            self.read_packed_blob(hash)?.unwrap_or_default()
+        };
+
+        Ok(header
+            .strip_prefix(DICT_COMPRESSION_MAGIC.as_slice())
+            .and_then(|rest| rest.first_chunk())
+            .map(|id| u32::from_le_bytes(*id)))
+    }
+
+    /// Remove the unreferenced blobs in one prefix directory
+    /// Returns the number of blobs removed and bytes freed
+    fn collect_prefix(prefix_dir: &Path, referenced_hashes: &std::collections::HashSet<String>) -> Result<(usize, u64)> {
+        let prefix = prefix_dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let mut removed_count = 0;
+        let mut freed_bytes = 0u64;
+
+        for blob_entry in fs::read_dir(prefix_dir)? {
+            let blob_entry = blob_entry?;
+            if !blob_entry.file_type()?.is_file() {
+                continue;
+            }
+
+            let blob_name = blob_entry.file_name().to_string_lossy().to_string();
+            let hash = format!("{}{}", prefix, blob_name);
+
+            if !referenced_hashes.contains(&hash) {
+                let metadata = blob_entry.metadata()?;
+                freed_bytes += metadata.len();
+                
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    let _ = fs::set_permissions(
+                        blob_entry.path(),
+                        fs::Permissions::from_mode(0o644),
+                    );
+                }
+                
+                fs::remove_file(blob_entry.path())?;
+                removed_count += 1;
+            }
         }
 
         Ok((removed_count, freed_bytes))
     }
 
+    /// Remove empty prefix directories from the store, e.g. after garbage collection or a
+    /// failed store. A directory that a concurrent save removes or fills meanwhile is skipped.
+    /// Returns the number of directories removed
+    pub fn prune_empty_dirs(&self) -> Result<usize> {
+        if !self.store_dir.exists() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+
+        for prefix_entry in fs::read_dir(&self.store_dir)? {
+            let prefix_entry = prefix_entry?;
+            if !prefix_entry.file_type()?.is_dir() || is_reserved_dir(&prefix_entry.file_name()) {
+                continue;
+            }
+
+            let is_empty = match fs::read_dir(prefix_entry.path()) {
+                Ok(mut entries) => entries.next().is_none(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+                Err(e) => return Err(e.into()),
+            };
+            if !is_empty {
+                continue;
+            }
+            match fs::remove_dir(prefix_entry.path()) {
+                Ok(()) => removed += 1,
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::DirectoryNotEmpty) => {}
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("Failed to remove empty store directory: {}", prefix_entry.path().display())
+                    });
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
     /// Compress a file and write it to blob storage
     fn compress_file_to_blob(&self, src: &Path, dst: &Path) -> Result<()> {
         if let Some(parent) = dst.parent() {
@@ -245,9 +803,74 @@ impl Store {
         let mut reader = BufReader::with_capacity(BUFFER_SIZE, src_file);
         let mut writer = BufWriter::with_capacity(BUFFER_SIZE, dst_file);
 
-        writer.write_all(COMPRESSION_MAGIC)?;
+        // Streaming, so the measured time includes reading and writing the blob
+        self.timed_compression(|| {
+            let mut encoder = self.compressed_writer(&mut writer)?;
+            std::io::copy(&mut reader, &mut encoder)?;
+            encoder.finish()?;
+            Ok(())
+        })
+    }
+
+    /// Compress an in-memory blob, prefixed with the compression magic
+    fn compress_bytes(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.timed_compression(|| {
+            let mut encoder = self.compressed_writer(Vec::new())?;
+            encoder.write_all(data)?;
+            Ok(encoder.finish()?)
+        })
+    }
+
+    /// Write the compression header to `writer` and return an encoder for the blob content,
+    /// with long-distance matching when the compression effort enables it
+    fn compressed_writer<W: Write>(&self, writer: W) -> Result<zstd::Encoder<'static, W>> {
+        let mut encoder = self.zstd_encoder(writer)?;
+        if let Some(window_log) = self.long_distance_window {
+            encoder.long_distance_matching(true)?;
+            encoder.window_log(window_log)?;
+        }
+        Ok(encoder)
+    }
+
+    /// Write the header for the blob's compression and create its encoder, using the
+    /// trained dictionary when there is one
+    fn zstd_encoder<W: Write>(&self, mut writer: W) -> Result<zstd::Encoder<'static, W>> {
+        match &self.dictionary {
+            Some((id, dictionary)) => {
+                writer.write_all(DICT_COMPRESSION_MAGIC)?;
+                writer.write_all(&id.to_le_bytes())?;
+                // The ID is already in our header, so leave it out of the zstd frame
+                let mut encoder = zstd::Encoder::with_dictionary(writer, self.zstd_level(), dictionary)?;
+                encoder.include_dictid(false)?;
+                Ok(encoder)
+            }
+            None => {
+                writer.write_all(COMPRESSION_MAGIC)?;
+                Ok(zstd::Encoder::new(writer, self.zstd_level())?)
+            }
+        }
+    }
 
-        let compression_level = if self.compression_level > 22 {
+    /// Decompress blob content that follows the magic bytes, loading the blob's
+    /// dictionary if it was compressed with one
+    fn decode_to_writer(&self, magic: &[u8; 4], mut reader: impl std::io::BufRead, writer: &mut impl Write) -> Result<()> {
+        if magic == DICT_COMPRESSION_MAGIC {
+            let mut id = [0u8; 4];
+            reader.read_exact(&mut id)?;
+            let dictionary = self.load_dictionary(u32::from_le_bytes(id))?;
+
+            let mut decoder = zstd::Decoder::with_dictionary(reader, &dictionary)?;
+            std::io::copy(&mut decoder, writer)?;
+        }
+        else {
+            zstd::stream::copy_decode(reader, writer)?;
+        }
+        Ok(())
+    }
+
+    /// Map the configured compression level to a zstd level
+    fn zstd_level(&self) -> i32 {
+        if self.compression_level > 22 {
             22 // Max zstd level
         }
         else if self.compression_level == 0 {
@@ -255,20 +878,15 @@ impl Store {
         }
         else {
             self.compression_level as i32
-        };
-
-        let mut encoder = zstd::Encoder::new(&mut writer, compression_level)?;
-        std::io::copy(&mut reader, &mut encoder)?;
-        encoder.finish()?;
-
-        Ok(())
+        }
     }
 
     /// Check if a blob is compressed by reading magic bytes
     pub fn is_blob_compressed(&self, hash: &str) -> Result<bool> {
         let blob_path = self.blob_path(hash);
         if !blob_path.exists() {
-            return Ok(false);
+            return Ok(self.read_packed_blob(hash)?
+                .is_some_and(|data| data.first_chunk().is_some_and(is_compression_magic)));
         }
 
         let mut file = File::open(&blob_path)?;
@@ -278,7 +896,205 @@ impl Store {
             return Ok(false);
         }
 
-        Ok(&magic == COMPRESSION_MAGIC)
+        Ok(is_compression_magic(&magic))
+    }
+
+    /// Hash a blob's decompressed content, for checking it against the hash it is stored under
+    pub fn content_hash(&self, hash: &str) -> Result<String> {
+        let mut hasher = blake3::Hasher::new();
+        self.copy_blob_to_writer(hash, &mut hasher)?;
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    /// Rehash blobs in parallel and report the ones that are damaged. Nothing is removed,
+    /// so damaged blobs stay available for recovery.
+    pub fn check_blobs(&self, hashes: &[&str]) -> BlobCheck {
+        let (present, missing): (Vec<&str>, Vec<&str>) = hashes.iter().partition(|hash| self.has_blob(hash));
+
+        let mut corrupt: Vec<String> = present
+            .par_iter()
+            .filter(|hash| !matches!(self.content_hash(hash), Ok(ref actual) if actual == *hash))
+            .map(|hash| hash.to_string())
+            .collect();
+        corrupt.sort();
+
+        let mut missing: Vec<String> = missing.into_iter().map(String::from).collect();
+        missing.sort();
+
+        BlobCheck {
+            checked: present.len(),
+            corrupt,
+            missing,
+        }
+    }
+
+    /// Make a loose blob read-only again if it has become writable, returning whether it
+    /// had. Blobs are stored as 0o444 so that nothing edits them in place. Packed blobs live
+    /// in append-only pack files and are not checked.
+    pub fn ensure_read_only(&self, hash: &str) -> Result<bool> {
+        let blob_path = self.blob_path(hash);
+        if !is_writable_blob(&blob_path) {
+            return Ok(false);
+        }
+        make_read_only(&blob_path)?;
+        Ok(true)
+    }
+
+    /// Find loose blobs that have become writable, sorted by path
+    pub fn writable_blobs(&self) -> Result<Vec<PathBuf>> {
+        if !self.store_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut writable = Vec::new();
+        for prefix_entry in fs::read_dir(&self.store_dir)? {
+            let prefix_entry = prefix_entry?;
+            if !prefix_entry.file_type()?.is_dir() || is_reserved_dir(&prefix_entry.file_name()) {
+                continue;
+            }
+            for blob_entry in fs::read_dir(prefix_entry.path())? {
+                let blob_path = blob_entry?.path();
+                // Temporary files of interrupted writes are not blobs yet
+                if blob_path.extension().is_some_and(|ext| ext == "tmp") {
+                    continue;
+                }
+                if is_writable_blob(&blob_path) {
+                    writable.push(blob_path);
+                }
+            }
+        }
+        writable.sort();
+        Ok(writable)
+    }
+
+    /// Compress a loose blob that was stored uncompressed (by a save at level 0) at this
+    /// store's compression level, returning its size before and after. Blobs that are
+    /// already compressed, packed or missing are left alone, as is every blob when
+    /// compression is disabled.
+    pub fn recompress_blob(&self, hash: &str) -> Result<Option<(u64, u64)>> {
+        let blob_path = self.blob_path(hash);
+        if self.compression_level == 0 || !blob_path.is_file() || self.is_blob_compressed(hash)? {
+            return Ok(None);
+        }
+
+        let bytes_before = fs::metadata(&blob_path)?.len();
+        let temp_path = blob_path.with_extension("tmp");
+        self.compress_file_to_blob(&blob_path, &temp_path)
+            .with_context(|| format!("Failed to recompress blob: {}", hash))?;
+        if self.durable_writes {
+            fs_utils::sync_file(&temp_path)?;
+        }
+        fs::rename(&temp_path, &blob_path).with_context(|| {
+            let _ = fs::remove_file(&temp_path);
+            format!("Failed to replace recompressed blob: {}", hash)
+        })?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&blob_path, fs::Permissions::from_mode(0o444));
+        }
+        self.sync_blob_dir(&blob_path, false)?;
+
+        Ok(Some((bytes_before, fs::metadata(&blob_path)?.len())))
+    }
+
+    /// Compress uncompressed blobs in parallel, see `recompress_blob`
+    pub fn recompress_blobs(&self, hashes: &[&str]) -> Result<Recompressed> {
+        let results = hashes
+            .par_iter()
+            .map(|hash| self.recompress_blob(hash))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut recompressed = Recompressed::default();
+        for (bytes_before, bytes_after) in results.into_iter().flatten() {
+            recompressed.blobs += 1;
+            recompressed.bytes_before += bytes_before;
+            recompressed.bytes_after += bytes_after;
+        }
+        Ok(recompressed)
+    }
+
+    /// Hash every regular file under a directory and store its content, without creating a
+    /// snapshot. Symlinks are not followed and `.kibo` directories are skipped.
+    pub fn import_dir(&self, dir: &Path) -> Result<ImportedBlobs> {
+        let mut paths = Vec::new();
+        let walker = walkdir::WalkDir::new(dir)
+            .follow_links(false)
+            .into_iter()
+            .filter_entry(|entry| !(entry.file_type().is_dir() && entry.file_name() == crate::config::KIBO_DIR));
+        for entry in walker {
+            let entry = entry.with_context(|| format!("Failed to walk directory: {}", dir.display()))?;
+            if entry.file_type().is_file() {
+                paths.push(entry.into_path());
+            }
+        }
+
+        let hashed = paths
+            .par_iter()
+            .map(|path| Ok((crate::file_hash::hash_file(path)?, path)))
+            .collect::<Result<HashMap<String, &PathBuf>>>()?;
+
+        // Store each distinct content once so identical files are not counted as new twice
+        let results = hashed
+            .par_iter()
+            .map(|(hash, path)| {
+                let size = fs::metadata(path)
+                    .with_context(|| format!("Failed to read metadata: {}", path.display()))?
+                    .len();
+                Ok((self.store_file(path, hash)?, size))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.flush()?;
+
+        let mut imported = ImportedBlobs { files: paths.len(), ..Default::default() };
+        for (stored, size) in results {
+            if stored {
+                imported.new_blobs += 1;
+                imported.new_bytes += size;
+            }
+            else {
+                imported.reused_blobs += 1;
+            }
+        }
+
+        Ok(imported)
+    }
+
+    /// Write a blob's decompressed content to a writer
+    pub fn copy_blob_to_writer(&self, hash: &str, writer: &mut impl Write) -> Result<()> {
+        let blob_path = self.blob_path(hash);
+
+        if !blob_path.exists() {
+            let Some(data) = self.read_packed_blob(hash)? else {
+                bail!("Blob not found: {}", hash);
+            };
+            match data.split_first_chunk() {
+                Some((magic, compressed)) if is_compression_magic(magic) => {
+                    self.decode_to_writer(magic, compressed, writer)
+                        .with_context(|| format!("Failed to decompress packed blob: {}", hash))?;
+                }
+                _ => writer.write_all(&data)?,
+            }
+            return Ok(());
+        }
+
+        let file = File::open(&blob_path)
+            .with_context(|| format!("Failed to open blob: {}", blob_path.display()))?;
+        let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
+
+        if self.is_blob_compressed(hash)? {
+            let mut magic = [0u8; 4];
+            reader.read_exact(&mut magic)?;
+            self.decode_to_writer(&magic, reader, writer)
+                .with_context(|| format!("Failed to decompress blob: {}", blob_path.display()))?;
+        }
+        else {
+            std::io::copy(&mut reader, writer)
+                .with_context(|| format!("Failed to read blob: {}", blob_path.display()))?;
+        }
+
+        Ok(())
     }
 
     /// Decompress a blob to a destination file
@@ -299,12 +1115,11 @@ impl Store {
         let mut magic = [0u8; 4];
         reader.read_exact(&mut magic)?;
         
-        if &magic != COMPRESSION_MAGIC {
+        if !is_compression_magic(&magic) {
             bail!("Blob is not compressed (missing magic bytes)");
         }
 
-        let mut decoder = zstd::Decoder::new(reader)?;
-        std::io::copy(&mut decoder, &mut writer)?;
+        self.decode_to_writer(&magic, reader, &mut writer)?;
         writer.flush()?;
 
         Ok(())
@@ -315,7 +1130,24 @@ impl Store {
         let blob_path = self.blob_path(hash);
         
         if !blob_path.exists() {
-            bail!("Blob not found: {}", hash);
+            let Some(data) = self.read_packed_blob(hash)? else {
+                bail!("Blob not found: {}", hash);
+            };
+            let data = match data.split_first_chunk() {
+                Some((magic, compressed)) if is_compression_magic(magic) => {
+                    let mut decoded = Vec::new();
+                    self.decode_to_writer(magic, compressed, &mut decoded)
+                        .with_context(|| format!("Failed to decompress packed blob: {}", hash))?;
+                    decoded
+                }
+                _ => data,
+            };
+            if let Some(parent) = dst.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(dst, data)
+                .with_context(|| format!("Failed to create destination file: {}", dst.display()))?;
+            return Ok(());
         }
 
         if self.is_blob_compressed(hash)? {
@@ -325,6 +1157,90 @@ impl Store {
             copy_file(&blob_path, dst)
         }
     }
+
+    /// Copy a blob to destination like `copy_blob_to_file`, hashing the content as it is
+    /// written and failing if it does not match the blob's hash. A corrupt copy is removed.
+    pub fn copy_blob_to_file_verified(&self, hash: &str, dst: &Path) -> Result<()> {
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let dst_file = File::create(dst)
+            .with_context(|| format!("Failed to create destination file: {}", dst.display()))?;
+
+        let mut writer = HashingWriter {
+            inner: BufWriter::with_capacity(BUFFER_SIZE, dst_file),
+            hasher: blake3::Hasher::new(),
+        };
+        self.copy_blob_to_writer(hash, &mut writer)?;
+        writer.inner.flush()?;
+
+        let actual = writer.hasher.finalize().to_hex();
+        if actual.as_str() != hash {
+            let _ = fs::remove_file(dst);
+            bail!("Blob {} is corrupt: its content hashes to {}", hash, actual);
+        }
+        Ok(())
+    }
+}
+
+/// Writes through to `inner` while hashing everything written
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: blake3::Hasher,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Whether a blob starts with one of the compressed blob headers
+fn is_compression_magic(magic: &[u8; 4]) -> bool {
+    magic == COMPRESSION_MAGIC || magic == DICT_COMPRESSION_MAGIC
+}
+
+/// Whether a store subdirectory holds something other than loose blobs
+fn is_reserved_dir(name: &std::ffi::OsStr) -> bool {
+    name == PACKS_DIR || name == DICTS_DIR || name == GROUPS_DIR
+}
+
+/// Whether a store subdirectory is named like a blob prefix (two lowercase hex digits)
+fn is_prefix_dir(name: &std::ffi::OsStr) -> bool {
+    name.to_str().is_some_and(|name| {
+        name.len() == 2 && name.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    })
+}
+
+/// Whether a loose blob file grants write permission to anyone
+#[cfg(unix)]
+fn is_writable_blob(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.mode() & 0o222 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_writable_blob(_path: &Path) -> bool {
+    false
+}
+
+/// Restore the read-only permissions a blob is stored with
+pub fn make_read_only(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o444))
+            .with_context(|| format!("Failed to make blob read-only: {}", path.display()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
 }
 
 /// Copy a file efficiently using buffered I/O
@@ -352,9 +1268,44 @@ fn copy_file(src: &Path, dst: &Path) -> Result<()> {
         writer.write_all(&buffer[..bytes_read])?;
     }
 
-    writer.flush()?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Blobs whose size falls into one range of a size histogram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeBucket {
+    pub label: &'static str,
+    /// Exclusive upper bound of the range, None for the last bucket
+    pub below: Option<u64>,
+    pub count: usize,
+    pub bytes: u64,
+}
 
-    Ok(())
+/// Count blobs and their bytes in the ranges <1KB, 1-16KB, 16-256KB, 256KB-4MB and >4MB
+pub fn size_histogram(sizes: impl IntoIterator<Item = u64>) -> Vec<SizeBucket> {
+    const KB: u64 = 1024;
+    let mut buckets: Vec<SizeBucket> = [
+        ("< 1 KB", Some(KB)),
+        ("1-16 KB", Some(16 * KB)),
+        ("16-256 KB", Some(256 * KB)),
+        ("256 KB-4 MB", Some(4096 * KB)),
+        (">= 4 MB", None),
+    ]
+    .into_iter()
+    .map(|(label, below)| SizeBucket { label, below, count: 0, bytes: 0 })
+    .collect();
+
+    for size in sizes {
+        let bucket = buckets
+            .iter_mut()
+            .find(|bucket| bucket.below.is_none_or(|below| size < below))
+            .expect("the last bucket has no upper bound");
+        bucket.count += 1;
+        bucket.bytes += size;
+    }
+    buckets
 }
 
 #[cfg(test)]
@@ -380,6 +1331,42 @@ mod tests {
         assert_eq!(store.compression_level, 5);
     }
 
+    #[test]
+    fn test_store_durable_writes() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::with_compression(temp_dir.path(), 3).with_durable_writes();
+        assert!(store.durable_writes);
+        store.init().unwrap();
+
+        let src = temp_dir.path().join("source.txt");
+        fs::write(&src, b"durable content").unwrap();
+        let hash = crate::file_hash::hash_file(&src).unwrap();
+        assert!(store.store_file(&src, &hash).unwrap());
+        assert!(store.store_symlink(Path::new("target"), "ab12").unwrap());
+
+        let dest = temp_dir.path().join("dest.txt");
+        store.copy_blob_to_file(&hash, &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"durable content");
+        assert_eq!(store.retrieve_symlink_target("ab12").unwrap(), PathBuf::from("target"));
+    }
+
+    #[test]
+    fn test_store_compression_timing() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("source.txt");
+        fs::write(&src, b"timed content").unwrap();
+        let hash = crate::file_hash::hash_file(&src).unwrap();
+
+        let untimed = Store::with_compression(temp_dir.path(), 3);
+        assert_eq!(untimed.compression_time(), None);
+
+        let store = Store::with_compression(temp_dir.path(), 3).with_compression_timing();
+        store.init().unwrap();
+        assert_eq!(store.compression_time(), Some(Duration::ZERO));
+        store.store_file(&src, &hash).unwrap();
+        assert!(store.compression_time().unwrap() > Duration::ZERO);
+    }
+
     #[test]
     fn test_store_init() {
         let temp_dir = TempDir::new().unwrap();
@@ -423,6 +1410,16 @@ mod tests {
         assert!(!store.has_blob("nonexistent"));
     }
 
+    #[test]
+    fn test_has_blob_skips_pack_index_without_packs() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::new(temp_dir.path());
+        store.init().unwrap();
+
+        assert!(!store.has_blob("nonexistent"));
+        assert!(store.packs.lock().unwrap().is_none());
+    }
+
     #[test]
     fn test_store_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -557,6 +1554,42 @@ mod tests {
         
         let count = store.blob_count().unwrap();
         assert_eq!(count, 2);
+
+        // Grouped blobs are counted by their own store only
+        let group = Store::new(temp_dir.path()).in_group(Some("debug"));
+        group.init().unwrap();
+        group.store_file(&test_file, "hash3").unwrap();
+        assert_eq!(store.blob_count().unwrap(), 2);
+        assert_eq!(group.blob_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_blob_sizes_include_packed_blobs() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::new(temp_dir.path()).with_pack_threshold(8);
+        store.init().unwrap();
+
+        let small = temp_dir.path().join("small.txt");
+        fs::write(&small, b"tiny").unwrap();
+        let large = temp_dir.path().join("large.txt");
+        fs::write(&large, b"Hello, World!").unwrap();
+        store.store_file(&small, "packed1").unwrap();
+        store.store_file(&large, "loose1").unwrap();
+        store.flush().unwrap();
+        Store::new(temp_dir.path()).in_group(Some("other")).store_file(&large, "grouped1").unwrap();
+
+        let mut sizes = store.blob_sizes().unwrap();
+        sizes.sort();
+        assert_eq!(sizes, vec![4, 13]);
+    }
+
+    #[test]
+    fn test_size_histogram() {
+        let buckets = size_histogram([0, 1023, 1024, 300 * 1024, 4 * 1024 * 1024]);
+        let counts: Vec<usize> = buckets.iter().map(|bucket| bucket.count).collect();
+        assert_eq!(counts, vec![2, 1, 0, 1, 1]);
+        assert_eq!(buckets[0].bytes, 1023);
+        assert_eq!(buckets[4].bytes, 4 * 1024 * 1024);
     }
 
     #[test]
@@ -566,12 +1599,91 @@ mod tests {
         store.init().unwrap();
         
         let referenced = HashSet::new();
-        let (removed, freed) = store.garbage_collect(&referenced, false).unwrap();
+        let (removed, freed) = store.garbage_collect(&referenced, false, None).unwrap();
         
         assert_eq!(removed, 0);
         assert_eq!(freed, 0);
     }
 
+    #[test]
+    fn test_content_hash_matches_source() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("test.txt");
+        fs::write(&test_file, b"Hello, World!").unwrap();
+        let hash = crate::file_hash::hash_file(&test_file).unwrap();
+        
+        for store in [
+            Store::new(temp_dir.path()),
+            Store::with_compression(temp_dir.path(), 3),
+            Store::with_compression(temp_dir.path(), 3).with_pack_threshold(1024),
+        ] {
+            let _ = store.garbage_collect(&HashSet::new(), false, None);
+            store.store_file(&test_file, &hash).unwrap();
+            store.flush().unwrap();
+            assert_eq!(store.content_hash(&hash).unwrap(), hash);
+        }
+        
+        assert!(Store::new(temp_dir.path()).content_hash("missing").is_err());
+    }
+
+    #[test]
+    fn test_check_blobs() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::new(temp_dir.path());
+        store.init().unwrap();
+
+        let good_file = temp_dir.path().join("good.txt");
+        fs::write(&good_file, b"good").unwrap();
+        let good = crate::file_hash::hash_file(&good_file).unwrap();
+        store.store_file(&good_file, &good).unwrap();
+
+        let bad_file = temp_dir.path().join("bad.txt");
+        fs::write(&bad_file, b"bad").unwrap();
+        let bad = crate::file_hash::hash_file(&bad_file).unwrap();
+        store.store_file(&bad_file, &bad).unwrap();
+        let bad_blob = store.blob_path(&bad);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&bad_blob, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        fs::write(&bad_blob, b"tampered").unwrap();
+
+        let check = store.check_blobs(&[good.as_str(), bad.as_str(), "missing"]);
+
+        assert_eq!(check, BlobCheck {
+            checked: 2,
+            corrupt: vec![bad.clone()],
+            missing: vec!["missing".to_string()],
+        });
+        assert!(bad_blob.exists(), "Corrupt blobs must not be removed");
+    }
+
+    #[test]
+    fn test_prune_empty_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::new(temp_dir.path());
+        store.init().unwrap();
+        
+        let test_file = temp_dir.path().join("test.txt");
+        fs::write(&test_file, b"Hello, World!").unwrap();
+        store.store_file(&test_file, "hash1").unwrap();
+        
+        let store_dir = get_store_dir(temp_dir.path());
+        fs::create_dir_all(store_dir.join("ab")).unwrap();
+        fs::create_dir_all(store_dir.join(PACKS_DIR)).unwrap();
+        
+        assert_eq!(store.prune_empty_dirs().unwrap(), 1);
+        assert!(!store_dir.join("ab").exists());
+        assert!(store_dir.join(PACKS_DIR).exists());
+        assert!(store.has_blob("hash1"));
+        assert_eq!(store.prune_empty_dirs().unwrap(), 0);
+
+        // A failed store leaves no empty prefix directory behind
+        assert!(store.store_file(&temp_dir.path().join("missing.txt"), "cd1234").is_err());
+        assert!(!store_dir.join("cd").exists());
+    }
+
     #[test]
     fn test_garbage_collect_all_referenced() {
         let temp_dir = TempDir::new().unwrap();
@@ -587,7 +1699,7 @@ mod tests {
         let mut referenced = HashSet::new();
         referenced.insert(hash.to_string());
         
-        let (removed, freed) = store.garbage_collect(&referenced, false).unwrap();
+        let (removed, freed) = store.garbage_collect(&referenced, false, None).unwrap();
         
         assert_eq!(removed, 0);
         assert_eq!(freed, 0);
@@ -607,13 +1719,137 @@ mod tests {
         store.store_file(&test_file, hash).unwrap();
         
         let referenced = HashSet::new();
-        let (removed, freed) = store.garbage_collect(&referenced, false).unwrap();
+        let (removed, freed) = store.garbage_collect(&referenced, false, None).unwrap();
         
         assert_eq!(removed, 1);
         assert!(freed > 0);
         assert!(!store.has_blob(hash));
     }
 
+    #[test]
+    fn test_blob_hashes() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::new(temp_dir.path()).with_pack_threshold(8);
+        store.init().unwrap();
+
+        let large = temp_dir.path().join("large");
+        let small = temp_dir.path().join("small");
+        fs::write(&large, b"Hello, World!").unwrap();
+        fs::write(&small, b"hi").unwrap();
+        store.store_file(&large, "ab12").unwrap();
+        store.store_file(&small, "cd34").unwrap();
+        store.flush().unwrap();
+
+        let mut hashes = store.blob_hashes().unwrap();
+        hashes.sort();
+        assert_eq!(hashes, vec!["ab12", "cd34"]);
+    }
+
+    #[test]
+    fn test_open_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        let err = Store::open(root, None).err().unwrap();
+        assert!(err.to_string().contains("not initialized"), "{}", err);
+        let empty = Store::open_or_empty(root, None).unwrap();
+        assert_eq!(empty.garbage_collect(&HashSet::new(), false, None).unwrap(), (0, 0));
+
+        let store = Store::new(root);
+        store.init().unwrap();
+        let test_file = root.join("test.txt");
+        fs::write(&test_file, b"content").unwrap();
+        store.store_file(&test_file, "ab12cd").unwrap();
+        fs::create_dir_all(get_store_dir(root).join(PACKS_DIR)).unwrap();
+        assert!(Store::open(root, None).unwrap().has_blob("ab12cd"));
+
+        let err = Store::open(root, Some("debug")).err().unwrap();
+        assert!(err.to_string().contains("Store group 'debug' is not initialized"), "{}", err);
+        Store::new(root).in_group(Some("debug")).init().unwrap();
+        assert!(Store::open(root, Some("debug")).is_ok());
+
+        fs::create_dir_all(get_store_dir(root).join("not-a-prefix")).unwrap();
+        let err = Store::open(root, None).err().unwrap();
+        assert!(err.to_string().contains("malformed"), "{}", err);
+        assert!(err.to_string().contains("not-a-prefix"), "{}", err);
+        assert!(Store::open_or_empty(root, None).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_writable_blobs() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::new(temp_dir.path());
+        store.init().unwrap();
+
+        let test_file = temp_dir.path().join("test.txt");
+        fs::write(&test_file, b"content").unwrap();
+        store.store_file(&test_file, "ab12").unwrap();
+        store.store_file(&test_file, "cd34").unwrap();
+        store.store_symlink(Path::new("target"), "ef56").unwrap();
+        assert!(store.writable_blobs().unwrap().is_empty());
+
+        let blob_path = store.blob_path("cd34");
+        fs::set_permissions(&blob_path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(store.writable_blobs().unwrap(), vec![blob_path.clone()]);
+
+        assert!(!store.ensure_read_only("ab12").unwrap());
+        assert!(store.ensure_read_only("cd34").unwrap());
+        assert_eq!(fs::metadata(&blob_path).unwrap().permissions().mode() & 0o777, 0o444);
+        assert!(store.writable_blobs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_store_groups_are_independent() {
+        let temp_dir = TempDir::new().unwrap();
+        let default_store = Store::new(temp_dir.path());
+        let debug_store = Store::new(temp_dir.path()).in_group(Some("debug"));
+        default_store.init().unwrap();
+        debug_store.init().unwrap();
+
+        let test_file = temp_dir.path().join("test.txt");
+        fs::write(&test_file, b"blob").unwrap();
+        default_store.store_file(&test_file, "aahash").unwrap();
+        debug_store.store_file(&test_file, "bbhash").unwrap();
+
+        assert!(!default_store.has_blob("bbhash"));
+        assert_eq!(Store::group_names(temp_dir.path()).unwrap(), vec!["debug"]);
+
+        // Collecting the default store must not descend into the groups
+        let (removed, _) = default_store.garbage_collect(&HashSet::new(), false, None).unwrap();
+        assert_eq!(removed, 1);
+        assert!(debug_store.has_blob("bbhash"));
+    }
+
+    #[test]
+    fn test_garbage_collect_concurrent_prefixes() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::new(temp_dir.path());
+        store.init().unwrap();
+
+        let test_file = temp_dir.path().join("test.txt");
+        fs::write(&test_file, b"blob").unwrap();
+
+        let mut referenced = HashSet::new();
+        for i in 0..32 {
+            let hash = format!("{:02x}hash", i);
+            store.store_file(&test_file, &hash).unwrap();
+            if i % 4 == 0 {
+                referenced.insert(hash);
+            }
+        }
+
+        let (removed, freed) = store.garbage_collect(&referenced, false, Some(4)).unwrap();
+
+        assert_eq!(removed, 24);
+        assert_eq!(freed, 24 * 4);
+        assert_eq!(store.blob_count().unwrap(), 8);
+        assert!(store.has_blob("00hash"));
+        assert!(!store.has_blob("01hash"));
+    }
+
     #[test]
     fn test_store_file_with_compression() {
         let temp_dir = TempDir::new().unwrap();
@@ -630,6 +1866,82 @@ mod tests {
         assert!(store.is_blob_compressed(hash).unwrap());
     }
 
+    #[test]
+    fn test_recompress_blobs() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("test.txt");
+        fs::write(&test_file, "compressible ".repeat(1000)).unwrap();
+
+        let raw_store = Store::new(temp_dir.path());
+        raw_store.init().unwrap();
+        raw_store.store_file(&test_file, "raw1").unwrap();
+        assert_eq!(raw_store.recompress_blobs(&["raw1"]).unwrap(), Recompressed::default());
+
+        let store = Store::with_compression(temp_dir.path(), 3);
+        assert!(!store.store_file(&test_file, "raw1").unwrap());
+        assert!(!store.is_blob_compressed("raw1").unwrap());
+
+        let recompressed = store.recompress_blobs(&["raw1", "missing"]).unwrap();
+        assert_eq!(recompressed.blobs, 1);
+        assert_eq!(recompressed.bytes_before, 13000);
+        assert!(recompressed.bytes_after < recompressed.bytes_before);
+        assert!(store.is_blob_compressed("raw1").unwrap());
+
+        let restored = temp_dir.path().join("restored.txt");
+        store.copy_blob_to_file("raw1", &restored).unwrap();
+        assert_eq!(fs::read(&restored).unwrap(), fs::read(&test_file).unwrap());
+
+        assert_eq!(store.recompress_blobs(&["raw1"]).unwrap().blobs, 0);
+    }
+
+    #[test]
+    fn test_stored_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let small = temp_dir.path().join("small.txt");
+        let large = temp_dir.path().join("large.txt");
+        fs::write(&small, "small").unwrap();
+        fs::write(&large, "compressible ".repeat(1000)).unwrap();
+
+        let store = Store::with_compression(temp_dir.path(), 3).with_pack_threshold(100);
+        store.init().unwrap();
+        store.store_file(&small, "small").unwrap();
+        store.store_file(&large, "large").unwrap();
+        store.flush().unwrap();
+
+        let packed = store.stored_size("small").unwrap().unwrap();
+        assert!(packed > 0);
+        let loose = store.stored_size("large").unwrap().unwrap();
+        assert_eq!(loose, fs::metadata(store.blob_path("large")).unwrap().len());
+        assert!(loose < 13000);
+        assert_eq!(store.stored_size("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_import_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let artifacts = temp_dir.path().join("artifacts");
+        fs::create_dir_all(artifacts.join("nested")).unwrap();
+        fs::create_dir_all(artifacts.join(".kibo")).unwrap();
+        fs::write(artifacts.join("a.o"), "alpha").unwrap();
+        fs::write(artifacts.join("nested/b.o"), "beta").unwrap();
+        fs::write(artifacts.join("nested/copy.o"), "alpha").unwrap();
+        fs::write(artifacts.join(".kibo/skipped"), "skipped").unwrap();
+
+        let store = Store::with_compression(temp_dir.path(), 3);
+        store.init().unwrap();
+        let imported = store.import_dir(&artifacts).unwrap();
+        assert_eq!(imported, ImportedBlobs { files: 3, new_blobs: 2, reused_blobs: 0, new_bytes: 9 });
+
+        let hash = crate::file_hash::hash_file(&artifacts.join("a.o")).unwrap();
+        let restored = temp_dir.path().join("restored.o");
+        store.copy_blob_to_file(&hash, &restored).unwrap();
+        assert_eq!(fs::read(&restored).unwrap(), b"alpha");
+
+        fs::write(artifacts.join("c.o"), "gamma").unwrap();
+        let imported = store.import_dir(&artifacts).unwrap();
+        assert_eq!(imported, ImportedBlobs { files: 4, new_blobs: 1, reused_blobs: 2, new_bytes: 5 });
+    }
+
     #[test]
     fn test_is_blob_compressed_false() {
         let temp_dir = TempDir::new().unwrap();
@@ -685,6 +1997,54 @@ mod tests {
         assert_eq!(restored_content, content.as_slice());
     }
 
+    #[test]
+    fn test_copy_blob_to_file_compression_effort() {
+        let temp_dir = TempDir::new().unwrap();
+        for effort in [CompressionEffort::Fast, CompressionEffort::Max] {
+            let store = Store::new(temp_dir.path()).with_compression_effort(effort).with_pack_threshold(1024);
+            store.init().unwrap();
+            assert_eq!(store.compression_level, effort.level());
+
+            let content: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+            for (name, content) in [("large", content.as_slice()), ("small", &content[..100])] {
+                let test_file = temp_dir.path().join(name);
+                fs::write(&test_file, content).unwrap();
+                let hash = format!("{}-{}", effort.as_str(), name);
+                store.store_file(&test_file, &hash).unwrap();
+
+                let dst = temp_dir.path().join("restored");
+                store.copy_blob_to_file(&hash, &dst).unwrap();
+                assert_eq!(fs::read(&dst).unwrap(), content);
+            }
+            assert!(store.is_blob_compressed(&format!("{}-large", effort.as_str())).unwrap());
+            store.flush().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_copy_blob_to_file_verified() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::with_compression(temp_dir.path(), 3);
+        store.init().unwrap();
+
+        let test_file = temp_dir.path().join("test.txt");
+        let content = b"Hello, World! ".repeat(100);
+        fs::write(&test_file, &content).unwrap();
+        let hash = blake3::hash(&content).to_hex().to_string();
+        store.store_file(&test_file, &hash).unwrap();
+
+        let dst = temp_dir.path().join("out/restored.txt");
+        store.copy_blob_to_file_verified(&hash, &dst).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), content);
+
+        // Stored under a hash its content does not have, as a corrupt blob would be
+        let wrong = "0".repeat(64);
+        store.store_file(&test_file, &wrong).unwrap();
+        let err = store.copy_blob_to_file_verified(&wrong, &dst).unwrap_err();
+        assert!(err.to_string().contains("corrupt"), "{}", err);
+        assert!(!dst.exists());
+    }
+
     #[test]
     fn test_copy_blob_to_file_not_found() {
         let temp_dir = TempDir::new().unwrap();
@@ -724,4 +2084,183 @@ mod tests {
         
         assert!(dst.exists());
     }
+
+    #[test]
+    fn test_packed_store_and_copy() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::new(temp_dir.path()).with_pack_threshold(1024);
+        store.init().unwrap();
+        
+        let test_file = temp_dir.path().join("test.txt");
+        fs::write(&test_file, b"Hello, World!").unwrap();
+        
+        let hash = "packed123";
+        assert!(store.store_file(&test_file, hash).unwrap());
+        assert!(!store.store_file(&test_file, hash).unwrap());
+        store.flush().unwrap();
+        
+        assert!(!store.blob_path(hash).exists());
+        
+        // A fresh store instance reads the packed blob through the saved index
+        let reader = Store::new(temp_dir.path());
+        assert!(reader.has_blob(hash));
+        assert_eq!(reader.blob_count().unwrap(), 1);
+        
+        let dst = temp_dir.path().join("restored.txt");
+        reader.copy_blob_to_file(hash, &dst).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), b"Hello, World!");
+    }
+
+    #[test]
+    fn test_packed_store_compressed() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::with_compression(temp_dir.path(), 3).with_pack_threshold(4096);
+        store.init().unwrap();
+        
+        let test_file = temp_dir.path().join("test.txt");
+        let content = b"Hello, World! ".repeat(100);
+        fs::write(&test_file, &content).unwrap();
+        
+        let hash = "packedcompressed";
+        store.store_file(&test_file, hash).unwrap();
+        
+        assert!(store.is_blob_compressed(hash).unwrap());
+        
+        let dst = temp_dir.path().join("restored.txt");
+        store.copy_blob_to_file(hash, &dst).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), content);
+    }
+
+    #[test]
+    fn test_packed_store_large_file_stays_loose() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::new(temp_dir.path()).with_pack_threshold(4);
+        store.init().unwrap();
+        
+        let test_file = temp_dir.path().join("test.txt");
+        fs::write(&test_file, b"Hello, World!").unwrap();
+        
+        let hash = "loose123";
+        store.store_file(&test_file, hash).unwrap();
+        
+        assert!(store.blob_path(hash).exists());
+    }
+
+    #[test]
+    fn test_packed_symlink() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::new(temp_dir.path()).with_pack_threshold(1024);
+        store.init().unwrap();
+        
+        let target = Path::new("/path/to/target");
+        let hash = "symlink123";
+        
+        store.store_symlink(target, hash).unwrap();
+        
+        assert!(!store.blob_path(hash).exists());
+        assert_eq!(store.retrieve_symlink_target(hash).unwrap(), target);
+    }
+
+    #[test]
+    fn test_garbage_collect_repacks() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Store::new(temp_dir.path()).with_pack_threshold(1024);
+        store.init().unwrap();
+        
+        let test_file = temp_dir.path().join("test.txt");
+        fs::write(&test_file, b"Hello, World!").unwrap();
+        
+        store.store_file(&test_file, "keep").unwrap();
+        store.store_file(&test_file, "drop").unwrap();
+        store.flush().unwrap();
+        
+        let mut referenced = HashSet::new();
+        referenced.insert("keep".to_string());
+        
+        let (removed, freed) = store.garbage_collect(&referenced, false, None).unwrap();
+        
+        assert_eq!(removed, 1);
+        assert_eq!(freed, 13);
+        assert!(store.has_blob("keep"));
+        assert!(!store.has_blob("drop"));
+        assert!(store.store_dir.join(PACKS_DIR).exists());
+    }
+
+    fn dictionary_samples() -> Vec<Vec<u8>> {
+        (0..200)
+            .map(|i| format!("build/obj/file{i}.o: src/file{i}.c include/common.h include/config.h\n").into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_store_with_dictionary() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut store = Store::with_compression(temp_dir.path(), 3);
+        store.init().unwrap();
+
+        let dict_id = store.train_dictionary(&dictionary_samples()).unwrap();
+        assert!(store.dictionary_path(dict_id).exists());
+
+        let test_file = temp_dir.path().join("file7.d");
+        let content = b"build/obj/file7.o: src/file7.c include/common.h include/config.h\n";
+        fs::write(&test_file, content).unwrap();
+        store.store_file(&test_file, "d1c7b10b").unwrap();
+
+        let raw = fs::read(store.blob_path("d1c7b10b")).unwrap();
+        assert!(raw.starts_with(DICT_COMPRESSION_MAGIC));
+        assert!(store.is_blob_compressed("d1c7b10b").unwrap());
+
+        // A fresh store has to load the dictionary from disk
+        let store = Store::new(temp_dir.path());
+        let dst = temp_dir.path().join("restored.d");
+        store.copy_blob_to_file("d1c7b10b", &dst).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), content);
+        assert_eq!(store.blob_count().unwrap(), 1);
+
+        store.garbage_collect(&HashSet::from(["d1c7b10b".to_string()]), false, None).unwrap();
+        assert!(store.dictionary_path(dict_id).exists());
+
+        // Once no blob uses the dictionary it is collected too
+        let dict_size = fs::metadata(store.dictionary_path(dict_id)).unwrap().len();
+        let (removed, freed) = store.garbage_collect(&HashSet::new(), false, None).unwrap();
+        assert_eq!(removed, 1);
+        assert!(freed > dict_size);
+        assert!(!store.dictionary_path(dict_id).exists());
+    }
+
+    #[test]
+    fn test_packed_store_with_dictionary() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut store = Store::with_compression(temp_dir.path(), 3).with_pack_threshold(4096);
+        store.init().unwrap();
+        store.train_dictionary(&dictionary_samples()).unwrap();
+
+        let test_file = temp_dir.path().join("file3.d");
+        let content = b"build/obj/file3.o: src/file3.c include/common.h\n";
+        fs::write(&test_file, content).unwrap();
+        store.store_file(&test_file, "packeddict").unwrap();
+        store.flush().unwrap();
+
+        let store = Store::new(temp_dir.path());
+        let mut restored = Vec::new();
+        store.copy_blob_to_writer("packeddict", &mut restored).unwrap();
+        assert_eq!(restored, content);
+    }
+
+    #[test]
+    fn test_missing_dictionary_is_an_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut store = Store::with_compression(temp_dir.path(), 3);
+        store.init().unwrap();
+        let dict_id = store.train_dictionary(&dictionary_samples()).unwrap();
+
+        let test_file = temp_dir.path().join("file1.d");
+        fs::write(&test_file, b"build/obj/file1.o: src/file1.c\n").unwrap();
+        store.store_file(&test_file, "orphan").unwrap();
+        fs::remove_file(store.dictionary_path(dict_id)).unwrap();
+
+        let store = Store::new(temp_dir.path());
+        let err = store.copy_blob_to_file("orphan", &temp_dir.path().join("out")).unwrap_err();
+        assert!(format!("{:#}", err).contains("dictionary missing"));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::manifest::{FileEntry, Manifest, format_size};
+
+/// A directory or file in the tree reconstructed from a manifest
+#[derive(Default)]
+struct TreeNode<'a> {
+    children: BTreeMap<String, TreeNode<'a>>,
+    file: Option<&'a FileEntry>,
+    /// Size of the file, or of all files below a directory
+    size: u64,
+}
+
+impl<'a> TreeNode<'a> {
+    /// Walk down to the node for a relative path, creating missing nodes along the way
+    fn node_mut(&mut self, relative_path: &str, size: u64) -> &mut TreeNode<'a> {
+        let mut node = self;
+        node.size += size;
+
+        for component in Path::new(relative_path).components() {
+            let name = component.as_os_str().to_string_lossy().to_string();
+            node = node.children.entry(name).or_default();
+            node.size += size;
+        }
+
+        node
+    }
+}
+
+/// Render a snapshot's files and directories as an indented tree, with file sizes and
+/// rolled-up directory sizes. Symlinks are shown as `name -> target`.
+pub fn render_tree(manifest: &Manifest) -> Vec<String> {
+    let mut root = TreeNode::default();
+
+    for relative_path in manifest.directories.keys() {
+        root.node_mut(relative_path, 0);
+    }
+
+    for (relative_path, entry) in &manifest.files {
+        root.node_mut(relative_path, entry.size).file = Some(entry);
+    }
+
+    let mut lines = vec![format!(". ({})", format_size(root.size))];
+    render_children(&root, "", &mut lines);
+    lines
+}
+
+fn render_children(node: &TreeNode, prefix: &str, lines: &mut Vec<String>) {
+    let count = node.children.len();
+
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let last = i + 1 == count;
+        let branch = if last { "└── " } else { "├── " };
+
+        let label = match child.file {
+            Some(entry) if entry.is_symlink => format!(
+                "{} -> {}",
+                name,
+                entry.symlink_target.as_deref().unwrap_or("?")
+            ),
+            Some(_) => format!("{} ({})", name, format_size(child.size)),
+            None => format!("{}/ ({})", name, format_size(child.size)),
+        };
+        lines.push(format!("{}{}{}", prefix, branch, label));
+
+        let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        render_children(child, &child_prefix, lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::DirectoryEntry;
+
+    fn file(size: u64) -> FileEntry {
+        FileEntry {
+            hash: "hash".to_string(),
+            size,
+            #[cfg(unix)]
+            mode: 0o644,
+            is_symlink: false,
+            symlink_target: None,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+            assumed: false,
+            is_text: false,
+        }
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let mut manifest = Manifest::new("test".to_string());
+        manifest.add_file("build/app.bin".to_string(), file(2048));
+        manifest.add_file("build/obj/a.o".to_string(), file(100));
+        manifest.add_file("build/obj/b.o".to_string(), file(200));
+        manifest.add_file("build/current".to_string(), FileEntry {
+            is_symlink: true,
+            symlink_target: Some("app.bin".to_string()),
+            ..file(0)
+        });
+        manifest.add_directory("build/empty".to_string(), DirectoryEntry {
+            #[cfg(unix)]
+            mode: 0o755,
+            mtime_secs: 0,
+            mtime_nanos: 0,
+        });
+
+        let lines = render_tree(&manifest);
+
+        assert_eq!(lines, vec![
+            ". (2.29 KB)",
+            "└── build/ (2.29 KB)",
+            "    ├── app.bin (2.00 KB)",
+            "    ├── current -> app.bin",
+            "    ├── empty/ (0 B)",
+            "    └── obj/ (300 B)",
+            "        ├── a.o (100 B)",
+            "        └── b.o (200 B)",
+        ]);
+    }
+
+    #[test]
+    fn test_render_tree_empty() {
+        let manifest = Manifest::new("test".to_string());
+        assert_eq!(render_tree(&manifest), vec![". (0 B)"]);
+    }
+}
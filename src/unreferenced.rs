@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::get_unreferenced_path;
+use crate::fs_utils;
+use crate::store::Store;
+
+/// When each unreferenced blob was first found unreferenced, by store group ("" for the
+/// default store), kept in `.kibo/unreferenced.json` so garbage collection can give blobs
+/// a grace period before removing them
+#[derive(Debug)]
+pub struct UnreferencedBlobs {
+    path: PathBuf,
+    groups: BTreeMap<String, BTreeMap<String, DateTime<Utc>>>,
+    keep_for: Duration,
+    now: DateTime<Utc>,
+}
+
+impl UnreferencedBlobs {
+    /// Read the recorded times for a grace period of `keep_for`, or start empty if none
+    /// were recorded yet
+    pub fn load(root: &Path, keep_for: Duration) -> Result<Self> {
+        let path = get_unreferenced_path(root);
+        let groups = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse unreferenced blob times: {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read unreferenced blob times: {}", path.display())),
+        };
+        Ok(Self { path, groups, keep_for, now: Utc::now() })
+    }
+
+    /// Add the blobs of a store group's store that are unreferenced but still within the
+    /// grace period to `referenced_hashes`, so garbage collection keeps them. Returns how
+    /// many were added.
+    pub fn protect_recent(&mut self, store: &Store, group: Option<&str>, referenced_hashes: &mut HashSet<String>) -> Result<usize> {
+        let unreferenced: Vec<String> = store
+            .blob_hashes()?
+            .into_iter()
+            .filter(|hash| !referenced_hashes.contains(hash))
+            .collect();
+        let recent = self.keep_recent(group, unreferenced);
+        let count = recent.len();
+        referenced_hashes.extend(recent);
+        Ok(count)
+    }
+
+    /// Take the blobs of a store group that are unreferenced now and return those first
+    /// found unreferenced less than the grace period ago. Blobs seen for the first time
+    /// start their grace period now. Blobs that are referenced again or past their grace
+    /// period are forgotten.
+    fn keep_recent(&mut self, group: Option<&str>, unreferenced: Vec<String>) -> HashSet<String> {
+        let key = group.unwrap_or_default();
+        let previous = self.groups.remove(key).unwrap_or_default();
+
+        let recent: BTreeMap<String, DateTime<Utc>> = unreferenced
+            .into_iter()
+            .map(|hash| {
+                let since = previous.get(&hash).copied().unwrap_or(self.now);
+                (hash, since)
+            })
+            .filter(|(_, since)| self.now - *since < self.keep_for)
+            .collect();
+
+        let hashes = recent.keys().cloned().collect();
+        if !recent.is_empty() {
+            self.groups.insert(key.to_string(), recent);
+        }
+        hashes
+    }
+
+    /// Write the recorded times back
+    pub fn save(&self) -> Result<()> {
+        let content = serde_json::to_string_pretty(&self.groups)?;
+        fs_utils::atomic_write(&self.path, content.as_bytes())
+            .with_context(|| format!("Failed to write unreferenced blob times: {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_keep_recent() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join(crate::config::KIBO_DIR)).unwrap();
+        let day = Duration::days(1);
+        let blobs = |hashes: &[&str]| hashes.iter().map(|hash| hash.to_string()).collect::<Vec<_>>();
+        let set = |hashes: &[&str]| hashes.iter().map(|hash| hash.to_string()).collect::<HashSet<_>>();
+
+        let mut unreferenced = UnreferencedBlobs::load(root, day).unwrap();
+        let start = unreferenced.now;
+        assert_eq!(unreferenced.keep_recent(None, blobs(&["aa", "bb"])), set(&["aa", "bb"]));
+        unreferenced.save().unwrap();
+
+        // "bb" is referenced again, "cc" just lost its last reference
+        let mut unreferenced = UnreferencedBlobs::load(root, day).unwrap();
+        unreferenced.now = start + Duration::hours(12);
+        assert_eq!(unreferenced.keep_recent(None, blobs(&["aa", "cc"])), set(&["aa", "cc"]));
+
+        // A day after it was first found, "aa" may go, while "cc" is still kept
+        unreferenced.now = start + day;
+        assert_eq!(unreferenced.keep_recent(None, blobs(&["aa", "cc"])), set(&["cc"]));
+
+        // Groups are tracked separately
+        assert!(unreferenced.keep_recent(Some("ci"), Vec::new()).is_empty());
+        assert_eq!(unreferenced.groups.len(), 1);
+    }
+
+    #[test]
+    fn test_protect_recent() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let store = Store::new(root);
+        store.init().unwrap();
+        let file = root.join("file");
+        fs::write(&file, b"content").unwrap();
+        store.store_file(&file, "ab12").unwrap();
+        store.store_file(&file, "cd34").unwrap();
+
+        let mut unreferenced = UnreferencedBlobs::load(root, Duration::hours(1)).unwrap();
+        let mut referenced = HashSet::from(["ab12".to_string()]);
+        assert_eq!(unreferenced.protect_recent(&store, None, &mut referenced).unwrap(), 1);
+        assert!(referenced.contains("cd34"));
+
+        store.garbage_collect(&referenced, false, None).unwrap();
+        assert!(store.has_blob("cd34"));
+    }
+}